@@ -0,0 +1,17 @@
+#![no_main]
+
+use kmum_common::{KmMessage, KmMessageRef, KmReplyMessage, UmSendMessage};
+use libfuzzer_sys::fuzz_target;
+
+// Every message that crosses the port must fail cleanly on garbage
+fuzz_target!(|data: &[u8]| {
+    let _ = postcard::from_bytes::<KmMessage>(data);
+    let _ = postcard::from_bytes::<KmReplyMessage>(data);
+    let _ = postcard::from_bytes::<UmSendMessage>(data);
+
+    // A view that decodes must also decode as an owned message
+    if let Ok((view, _)) = KmMessageRef::take_from_bytes(data) {
+        let _ = format!("{:?}", view);
+        assert!(view.to_message().is_some());
+    }
+});
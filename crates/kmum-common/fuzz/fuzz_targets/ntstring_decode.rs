@@ -0,0 +1,26 @@
+#![no_main]
+
+use kmum_common::serializable_ntstring::{NtStringSeed, SerializableNtString};
+use libfuzzer_sys::fuzz_target;
+use serde::de::DeserializeSeed;
+
+// First byte picks the limit, the rest is the encoded string
+fuzz_target!(|data: &[u8]| {
+    let Some((&limit, input)) = data.split_first() else {
+        return;
+    };
+
+    let mut deserializer = postcard::Deserializer::from_bytes(input);
+    let Ok(string) = NtStringSeed::new(limit as usize * 16).deserialize(&mut deserializer) else {
+        return;
+    };
+
+    if string.is_empty() {
+        return;
+    }
+    assert!(string.as_slice().len() <= limit as usize * 16);
+
+    let encoded = postcard::to_allocvec(&string).unwrap();
+    let decoded: SerializableNtString = postcard::from_bytes(&encoded).unwrap();
+    assert_eq!(decoded.as_slice(), string.as_slice());
+});
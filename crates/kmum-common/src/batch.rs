@@ -0,0 +1,163 @@
+use crate::KmMessage;
+
+/// "PMBT" in little endian, first field of every batch
+pub const BATCH_MAGIC: u32 = u32::from_le_bytes(*b"PMBT");
+
+///
+/// Fixed size header placed in front of every batch of serialized `KmMessage`s.
+///
+/// Encoded by hand instead of through postcard so the sender can reserve
+/// its space upfront and fill it once the payload is known
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchHeader {
+    pub worker: u16,
+    /// Incremented by one for every batch a worker produces
+    pub sequence: u64,
+    /// Total number of events the worker had to drop since it started
+    pub dropped: u64,
+    pub event_count: u32,
+    pub payload_length: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchError {
+    TooSmall,
+    InvalidMagic(u32),
+    PayloadLength { expected: u32, actual: u32 },
+}
+
+impl BatchHeader {
+    pub const SIZE: usize = 32;
+
+    pub fn write(&self, buffer: &mut [u8; Self::SIZE]) {
+        buffer[0..4].copy_from_slice(&BATCH_MAGIC.to_le_bytes());
+        buffer[4..6].copy_from_slice(&self.worker.to_le_bytes());
+        buffer[6..8].copy_from_slice(&0u16.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.sequence.to_le_bytes());
+        buffer[16..24].copy_from_slice(&self.dropped.to_le_bytes());
+        buffer[24..28].copy_from_slice(&self.event_count.to_le_bytes());
+        buffer[28..32].copy_from_slice(&self.payload_length.to_le_bytes());
+    }
+
+    pub fn read(buffer: &[u8; Self::SIZE]) -> Result<Self, BatchError> {
+        let u16_at = |offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+        let u32_at = |offset: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&buffer[offset..offset + 4]);
+            u32::from_le_bytes(bytes)
+        };
+        let u64_at = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&buffer[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+
+        let magic = u32_at(0);
+        if magic != BATCH_MAGIC {
+            return Err(BatchError::InvalidMagic(magic));
+        }
+
+        Ok(Self {
+            worker: u16_at(4),
+            sequence: u64_at(8),
+            dropped: u64_at(16),
+            event_count: u32_at(24),
+            payload_length: u32_at(28),
+        })
+    }
+
+    ///
+    /// Splits a received batch into its header and payload
+    ///
+    pub fn split(batch: &[u8]) -> Result<(Self, &[u8]), BatchError> {
+        if batch.len() < Self::SIZE {
+            return Err(BatchError::TooSmall);
+        }
+
+        let (header, payload) = batch.split_at(Self::SIZE);
+        let header = Self::read(header.try_into().unwrap())?;
+
+        if header.payload_length as usize != payload.len() {
+            return Err(BatchError::PayloadLength {
+                expected: header.payload_length,
+                actual: payload.len() as _,
+            });
+        }
+
+        Ok((header, payload))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchFull;
+
+///
+/// Serializes messages into a caller owned buffer, leaving room for the header
+///
+pub struct BatchBuilder {
+    offset: usize,
+    event_count: u32,
+}
+
+impl Default for BatchBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchBuilder {
+    pub const fn new() -> Self {
+        Self {
+            offset: BatchHeader::SIZE,
+            event_count: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.event_count == 0
+    }
+
+    pub fn event_count(&self) -> u32 {
+        self.event_count
+    }
+
+    pub fn try_push(&mut self, buffer: &mut [u8], message: &KmMessage) -> Result<(), BatchFull> {
+        if buffer.len() <= self.offset {
+            return Err(BatchFull);
+        }
+
+        let serialized =
+            postcard::to_slice(message, &mut buffer[self.offset..]).map_err(|_| BatchFull)?;
+
+        self.offset += serialized.len();
+        self.event_count += 1;
+        Ok(())
+    }
+
+    ///
+    /// Writes the header and returns the complete batch, the builder is reset
+    /// and can be reused for the next batch on the same buffer
+    ///
+    pub fn finish<'a>(
+        &mut self,
+        buffer: &'a mut [u8],
+        worker: u16,
+        sequence: u64,
+        dropped: u64,
+    ) -> &'a [u8] {
+        let header = BatchHeader {
+            worker,
+            sequence,
+            dropped,
+            event_count: self.event_count,
+            payload_length: (self.offset - BatchHeader::SIZE) as _,
+        };
+        header.write((&mut buffer[..BatchHeader::SIZE]).try_into().unwrap());
+
+        let length = self.offset;
+        *self = Self::new();
+
+        &buffer[..length]
+    }
+}
@@ -1,26 +1,35 @@
-use super::{EventFileSystemOperation, EventProcessOperation, EventRegistryOperation};
+use super::{
+    EventFileSystemOperation, EventNetworkOperation, EventProcessOperation, EventRegistryOperation,
+    EventSessionOperation,
+};
 use crate::serializable_ntstring::SerializableNtString;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum EventClass {
-    Process(EventProcessOperation),
-    FileSystem(EventFileSystemOperation),
-    Registry(EventRegistryOperation),
+///
+/// Generic over the string type so received events can also be decoded
+/// as borrowed views, see `KmMessageRef`
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EventClass<S = SerializableNtString> {
+    Process(EventProcessOperation<S>),
+    FileSystem(EventFileSystemOperation<S>),
+    Registry(EventRegistryOperation<S>),
+    Network(EventNetworkOperation),
+    Session(EventSessionOperation),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleProcessDetails {
     pub pid: u64,
     pub unique_id: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct EventCompoent {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventCompoent<S = SerializableNtString> {
     pub date: u64,
     pub thread: u64,
-    pub operation: EventClass,
+    pub operation: EventClass<S>,
     pub result: i32,
-    pub path: SerializableNtString,
+    pub path: S,
     pub duration: u64,
 }
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use crate::serializable_ntstring::SerializableNtString;
+
+///
+/// Information classes that are decoded when set, everything else is reported as `Other`
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FileSetInformation<S = SerializableNtString> {
+    Basic {
+        creation_time: i64,
+        last_access_time: i64,
+        last_write_time: i64,
+        change_time: i64,
+        attributes: u32,
+    },
+    Rename {
+        target: S,
+        replace_if_exists: bool,
+    },
+    Disposition {
+        delete: bool,
+    },
+    EndOfFile {
+        end_of_file: i64,
+    },
+    Allocation {
+        allocation_size: i64,
+    },
+    Other {
+        information_class: u32,
+        length: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileLockOperation {
+    Lock,
+    UnlockSingle,
+    UnlockAll,
+    UnlockAllByKey,
+    Unknown(u8),
+}
+
+impl FileLockOperation {
+    pub fn from_minor_function(minor: u8) -> Self {
+        match minor {
+            1 => Self::Lock,
+            2 => Self::UnlockSingle,
+            3 => Self::UnlockAll,
+            4 => Self::UnlockAllByKey,
+            other => Self::Unknown(other),
+        }
+    }
+}
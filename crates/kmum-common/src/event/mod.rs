@@ -1,7 +1,15 @@
 mod events;
+mod filesystem;
+mod network;
 mod operation;
+mod registry;
+mod session;
 mod stack;
 
 pub use events::*;
+pub use filesystem::*;
+pub use network::*;
 pub use operation::*;
+pub use registry::*;
+pub use session::*;
 pub use stack::*;
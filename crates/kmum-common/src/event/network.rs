@@ -0,0 +1,52 @@
+use core::{
+    fmt::{self, Display},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IpAddress {
+    V4([u8; 4]),
+    V6([u8; 16]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketAddress {
+    pub ip: IpAddress,
+    pub port: u16,
+}
+
+///
+/// Both ends of a connection as seen from the process that owns the socket
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkEndpoints {
+    pub local: SocketAddress,
+    pub remote: SocketAddress,
+}
+
+impl Display for IpAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpAddress::V4(octets) => Display::fmt(&Ipv4Addr::from(*octets), f),
+            //Same text as std, zero runs compressed the way users type them
+            IpAddress::V6(octets) => Display::fmt(&Ipv6Addr::from(*octets), f),
+        }
+    }
+}
+
+impl Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip {
+            IpAddress::V4(_) => write!(f, "{}:{}", self.ip, self.port),
+            IpAddress::V6(_) => write!(f, "[{}]:{}", self.ip, self.port),
+        }
+    }
+}
+
+impl Display for NetworkEndpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.local, self.remote)
+    }
+}
@@ -2,26 +2,181 @@ use serde::{Deserialize, Serialize};
 
 use crate::serializable_ntstring::SerializableNtString;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum EventProcessOperation {
+use super::{
+    FileLockOperation, FileSetInformation, NetworkEndpoints, RegistryDataPreview, RegistryValueType,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EventProcessOperation<S = SerializableNtString> {
     ProcessCreate {
         pid: u64,
-        cmd: Option<SerializableNtString>,
+        cmd: Option<S>,
     },
     ProcessDestroy {
         pid: u64,
+        exit_status: i32,
+    },
+    ThreadCreate {
+        tid: u64,
+        start_address: u64,
+    },
+    ThreadExit {
+        tid: u64,
+    },
+    /// The image path is the event path
+    ImageLoad {
+        base: u64,
+        size: u64,
     },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum EventFileSystemOperation {
-    Create { attribute: u16 },
-    Read { length: u64, offset: i64 },
-    Write { length: u64, offset: i64 },
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EventFileSystemOperation<S = SerializableNtString> {
+    Create {
+        desired_access: u32,
+        share_mode: u16,
+        /// FILE_SUPERSEDE up to FILE_OVERWRITE_IF
+        disposition: u32,
+        options: u32,
+        attribute: u16,
+        /// IoStatus.Information of the completed create, FILE_OPENED, FILE_CREATED...
+        open_action: u32,
+    },
+    Read {
+        length: u64,
+        offset: i64,
+    },
+    Write {
+        length: u64,
+        offset: i64,
+    },
     Close {},
+    QueryInformation {
+        information_class: u32,
+        length: u32,
+    },
+    SetInformation {
+        information: FileSetInformation<S>,
+    },
+    QueryDirectory {
+        pattern: S,
+        information_class: u32,
+        length: u32,
+    },
+    FileSystemControl {
+        control_code: u32,
+        input_length: u32,
+        output_length: u32,
+    },
+    LockControl {
+        operation: FileLockOperation,
+        offset: i64,
+        length: i64,
+        exclusive: bool,
+    },
+    Cleanup {},
+    FlushBuffers {},
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum EventRegistryOperation {
-    Open(),
+///
+/// The key itself is the event path, value names are relative to it
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EventRegistryOperation<S = SerializableNtString> {
+    CreateKey {
+        desired_access: u32,
+        /// REG_CREATED_NEW_KEY or REG_OPENED_EXISTING_KEY
+        disposition: u32,
+    },
+    OpenKey {
+        desired_access: u32,
+    },
+    QueryKey {
+        information_class: u32,
+        length: u32,
+    },
+    SetValue {
+        value_name: S,
+        value_type: RegistryValueType,
+        data_size: u32,
+        preview: RegistryDataPreview,
+    },
+    QueryValue {
+        value_name: S,
+        information_class: u32,
+        length: u32,
+    },
+    DeleteKey {},
+    DeleteValue {
+        value_name: S,
+    },
+    EnumerateKey {
+        index: u32,
+        information_class: u32,
+    },
+    EnumerateValue {
+        index: u32,
+        information_class: u32,
+    },
+    RenameKey {
+        new_name: S,
+    },
+    Flush {},
+}
+
+///
+/// The owning process is the one the event is reported for
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EventNetworkOperation {
+    TcpConnect {
+        endpoints: NetworkEndpoints,
+    },
+    TcpAccept {
+        endpoints: NetworkEndpoints,
+    },
+    TcpSend {
+        endpoints: NetworkEndpoints,
+        length: u32,
+    },
+    TcpReceive {
+        endpoints: NetworkEndpoints,
+        length: u32,
+    },
+    TcpDisconnect {
+        endpoints: NetworkEndpoints,
+    },
+    UdpSend {
+        endpoints: NetworkEndpoints,
+        length: u32,
+    },
+    UdpReceive {
+        endpoints: NetworkEndpoints,
+        length: u32,
+    },
+}
+
+impl EventNetworkOperation {
+    pub fn endpoints(&self) -> &NetworkEndpoints {
+        match self {
+            EventNetworkOperation::TcpConnect { endpoints }
+            | EventNetworkOperation::TcpAccept { endpoints }
+            | EventNetworkOperation::TcpSend { endpoints, .. }
+            | EventNetworkOperation::TcpReceive { endpoints, .. }
+            | EventNetworkOperation::TcpDisconnect { endpoints }
+            | EventNetworkOperation::UdpSend { endpoints, .. }
+            | EventNetworkOperation::UdpReceive { endpoints, .. } => endpoints,
+        }
+    }
+
+    /// Bytes transferred, zero for connection state changes
+    pub fn length(&self) -> u32 {
+        match self {
+            EventNetworkOperation::TcpSend { length, .. }
+            | EventNetworkOperation::TcpReceive { length, .. }
+            | EventNetworkOperation::UdpSend { length, .. }
+            | EventNetworkOperation::UdpReceive { length, .. } => *length,
+            _ => 0,
+        }
+    }
 }
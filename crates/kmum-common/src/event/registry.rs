@@ -0,0 +1,148 @@
+use core::fmt;
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// Only the start of a value is sent, `SetValue` keeps the full size separately
+pub const MAX_REGISTRY_PREVIEW_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryValueType {
+    None,
+    String,
+    ExpandString,
+    Binary,
+    Dword,
+    DwordBigEndian,
+    Link,
+    MultiString,
+    ResourceList,
+    FullResourceDescriptor,
+    ResourceRequirementsList,
+    Qword,
+    Unknown(u32),
+}
+
+impl RegistryValueType {
+    pub fn from_raw(value_type: u32) -> Self {
+        match value_type {
+            0 => Self::None,
+            1 => Self::String,
+            2 => Self::ExpandString,
+            3 => Self::Binary,
+            4 => Self::Dword,
+            5 => Self::DwordBigEndian,
+            6 => Self::Link,
+            7 => Self::MultiString,
+            8 => Self::ResourceList,
+            9 => Self::FullResourceDescriptor,
+            10 => Self::ResourceRequirementsList,
+            11 => Self::Qword,
+            other => Self::Unknown(other),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "REG_NONE",
+            Self::String => "REG_SZ",
+            Self::ExpandString => "REG_EXPAND_SZ",
+            Self::Binary => "REG_BINARY",
+            Self::Dword => "REG_DWORD",
+            Self::DwordBigEndian => "REG_DWORD_BIG_ENDIAN",
+            Self::Link => "REG_LINK",
+            Self::MultiString => "REG_MULTI_SZ",
+            Self::ResourceList => "REG_RESOURCE_LIST",
+            Self::FullResourceDescriptor => "REG_FULL_RESOURCE_DESCRIPTOR",
+            Self::ResourceRequirementsList => "REG_RESOURCE_REQUIREMENTS_LIST",
+            Self::Qword => "REG_QWORD",
+            Self::Unknown(_) => "REG_UNKNOWN",
+        }
+    }
+}
+
+///
+/// First bytes of a registry value, at most `MAX_REGISTRY_PREVIEW_SIZE`.
+///
+/// Stored inline so decoding an event never allocates for it
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegistryDataPreview {
+    bytes: [u8; MAX_REGISTRY_PREVIEW_SIZE],
+    len: u8,
+}
+
+impl RegistryDataPreview {
+    /// Copies the start of `data`
+    pub fn new(data: &[u8]) -> Self {
+        let len = data.len().min(MAX_REGISTRY_PREVIEW_SIZE);
+
+        let mut bytes = [0u8; MAX_REGISTRY_PREVIEW_SIZE];
+        bytes[..len].copy_from_slice(&data[..len]);
+
+        Self {
+            bytes,
+            len: len as _,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl Serialize for RegistryDataPreview {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for RegistryDataPreview {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PreviewVisitor;
+
+        impl<'de> Visitor<'de> for PreviewVisitor {
+            type Value = RegistryDataPreview;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("at most 32 bytes of registry data")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                if bytes.len() > MAX_REGISTRY_PREVIEW_SIZE {
+                    return Err(E::invalid_length(bytes.len(), &self));
+                }
+
+                Ok(RegistryDataPreview::new(bytes))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let mut bytes = [0u8; MAX_REGISTRY_PREVIEW_SIZE];
+                let mut len = 0;
+
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    if len == MAX_REGISTRY_PREVIEW_SIZE {
+                        return Err(de::Error::invalid_length(len + 1, &self));
+                    }
+                    bytes[len] = byte;
+                    len += 1;
+                }
+
+                Ok(RegistryDataPreview::new(&bytes[..len]))
+            }
+        }
+
+        deserializer.deserialize_bytes(PreviewVisitor)
+    }
+}
@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+///
+/// Recorded by the client about its own connection, the driver never sends these
+///
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum EventSessionOperation {
+    /// The event starts when the connection was lost and lasts until it was back
+    Disconnected { attempts: u32 },
+}
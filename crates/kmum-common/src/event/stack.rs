@@ -1,10 +1,369 @@
-use serde::{Deserialize, Serialize};
+use alloc::vec::Vec;
+use core::fmt;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct EventStack {}
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// Frames past this limit are not captured
+pub const MAX_STACK_FRAMES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrameMode {
+    User,
+    Kernel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StackFrame {
+    pub address: u64,
+    pub mode: FrameMode,
+}
+
+///
+/// Return addresses of the thread that caused an event, innermost frame first.
+///
+/// On the wire every frame is a single varint, either the delta from the previous
+/// frame of the same mode or a back reference to an identical frame already encoded
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventStack {
+    frames: Vec<StackFrame>,
+}
+
+const TAG_USER: u128 = 0;
+const TAG_KERNEL: u128 = 1;
+const TAG_REFERENCE: u128 = 2;
+const TAG_BITS: u32 = 2;
+const TAG_MASK: u128 = (1 << TAG_BITS) - 1;
+
+//A 64 bit delta with the tag fits in 10 varint bytes
+const MAX_ENCODED_STACK_SIZE: usize = MAX_STACK_FRAMES * 10;
 
 impl EventStack {
     pub fn new() -> Self {
-        Self {}
+        Self { frames: Vec::new() }
+    }
+
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    ///
+    /// Returns false once the stack is full or the frame could not be allocated
+    ///
+    pub fn try_push(&mut self, frame: StackFrame) -> bool {
+        if self.frames.len() >= MAX_STACK_FRAMES || self.frames.try_reserve(1).is_err() {
+            false
+        } else {
+            self.frames.push(frame);
+            true
+        }
+    }
+
+    fn encode<F: FnMut(u8)>(&self, mut emit: F) {
+        let mut previous = [0u64; 2];
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            let mode_index = frame.mode as usize;
+
+            let value = match self.frames[..index].iter().position(|f| f == frame) {
+                Some(reference) => ((reference as u128) << TAG_BITS) | TAG_REFERENCE,
+                None => {
+                    let delta = frame.address.wrapping_sub(previous[mode_index]) as i64;
+                    let tag = match frame.mode {
+                        FrameMode::User => TAG_USER,
+                        FrameMode::Kernel => TAG_KERNEL,
+                    };
+                    ((zigzag(delta) as u128) << TAG_BITS) | tag
+                }
+            };
+            previous[mode_index] = frame.address;
+
+            write_varint(value, &mut emit);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut stack = Self::new();
+        let mut decoder = FrameDecoder::new(bytes);
+
+        while let Some(frame) = decoder.next_frame()? {
+            if !stack.try_push(frame) {
+                return Err("too many stack frames");
+            }
+        }
+
+        Ok(stack)
+    }
+}
+
+///
+/// Encoded stack borrowed from a received buffer, frames are decoded on demand.
+///
+/// The encoding is validated when deserialized so iterating it can not fail
+///
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventStackRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> EventStackRef<'a> {
+    pub fn from_encoded(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        let mut decoder = FrameDecoder::new(bytes);
+        while decoder.next_frame()?.is_some() {}
+
+        Ok(Self { bytes })
+    }
+
+    pub fn frames(&self) -> StackFrames<'a> {
+        StackFrames {
+            decoder: FrameDecoder::new(self.bytes),
+        }
+    }
+
+    /// Every frame ends with exactly one byte that has the continuation bit clear
+    pub fn len(&self) -> usize {
+        self.bytes.iter().filter(|byte| *byte & 0x80 == 0).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn to_stack(&self) -> EventStack {
+        let mut stack = EventStack::new();
+        self.frames().for_each(|frame| {
+            stack.try_push(frame);
+        });
+        stack
+    }
+}
+
+impl fmt::Debug for EventStackRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.frames()).finish()
+    }
+}
+
+pub struct StackFrames<'a> {
+    decoder: FrameDecoder<'a>,
+}
+
+impl Iterator for StackFrames<'_> {
+    type Item = StackFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decoder.next_frame().ok().flatten()
+    }
+}
+
+///
+/// Back references point at earlier frames, so the decoded ones are kept inline
+///
+struct FrameDecoder<'a> {
+    bytes: &'a [u8],
+    frames: [StackFrame; MAX_STACK_FRAMES],
+    count: usize,
+    previous: [u64; 2],
+}
+
+impl<'a> FrameDecoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            frames: [StackFrame {
+                address: 0,
+                mode: FrameMode::User,
+            }; MAX_STACK_FRAMES],
+            count: 0,
+            previous: [0; 2],
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Option<StackFrame>, &'static str> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+
+        if self.count == MAX_STACK_FRAMES {
+            return Err("too many stack frames");
+        }
+
+        let value = read_varint(&mut self.bytes).ok_or("truncated stack frame")?;
+        let payload = value >> TAG_BITS;
+
+        let frame = match value & TAG_MASK {
+            TAG_REFERENCE => *usize::try_from(payload)
+                .ok()
+                .and_then(|index| self.frames[..self.count].get(index))
+                .ok_or("stack frame reference out of bounds")?,
+            tag @ (TAG_USER | TAG_KERNEL) => {
+                let mode = if tag == TAG_USER {
+                    FrameMode::User
+                } else {
+                    FrameMode::Kernel
+                };
+                let delta = unzigzag(u64::try_from(payload).map_err(|_| "invalid frame delta")?);
+
+                StackFrame {
+                    address: self.previous[mode as usize].wrapping_add(delta as u64),
+                    mode,
+                }
+            }
+            _ => return Err("invalid stack frame tag"),
+        };
+        self.previous[frame.mode as usize] = frame.address;
+
+        self.frames[self.count] = frame;
+        self.count += 1;
+
+        Ok(Some(frame))
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint<F: FnMut(u8)>(mut value: u128, emit: &mut F) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            emit(byte);
+            break;
+        }
+        emit(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u128> {
+    let mut value = 0u128;
+
+    for shift in (0..u128::BITS).step_by(7) {
+        let (byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+
+        value |= ((byte & 0x7F) as u128) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+impl Serialize for EventStack {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            return serializer.collect_seq(self.frames.iter());
+        }
+
+        let mut buffer = [0u8; MAX_ENCODED_STACK_SIZE];
+        let mut len = 0;
+        self.encode(|byte| {
+            buffer[len] = byte;
+            len += 1;
+        });
+
+        serializer.serialize_bytes(&buffer[..len])
+    }
+}
+
+impl<'de> Deserialize<'de> for EventStack {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct EventStackVisitor;
+
+        impl<'de> Visitor<'de> for EventStackVisitor {
+            type Value = EventStack;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an encoded list of stack frames")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                EventStack::decode(bytes).map_err(E::custom)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let mut stack = EventStack::new();
+
+                while let Some(frame) = seq.next_element::<StackFrame>()? {
+                    if !stack.try_push(frame) {
+                        return Err(de::Error::custom("too many stack frames"));
+                    }
+                }
+
+                Ok(stack)
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_seq(EventStackVisitor)
+        } else {
+            deserializer.deserialize_bytes(EventStackVisitor)
+        }
+    }
+}
+
+impl Serialize for EventStackRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(self.frames())
+        } else {
+            serializer.serialize_bytes(self.bytes)
+        }
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for EventStackRef<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct EventStackRefVisitor;
+
+        impl<'de> Visitor<'de> for EventStackRefVisitor {
+            type Value = EventStackRef<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("borrowed bytes of encoded stack frames")
+            }
+
+            fn visit_borrowed_bytes<E: de::Error>(
+                self,
+                bytes: &'de [u8],
+            ) -> Result<Self::Value, E> {
+                EventStackRef::from_encoded(bytes).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_bytes(EventStackRefVisitor)
     }
 }
@@ -0,0 +1,161 @@
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::EventClass, ClientConnectMessage, MAX_KM_MESSAGE_RECEIVE_SIZE,
+    MAX_UM_REPLY_MESSAGE_SIZE, MAX_UM_SEND_MESSAGE_BUFFER_SIZE,
+};
+
+/// "PMON" in little endian, first field of every connect message
+pub const PROTOCOL_MAGIC: u32 = u32::from_le_bytes(*b"PMON");
+
+/// Must be bumped every time the wire format of any message changes
+pub const PROTOCOL_VERSION: u32 = 10;
+
+bitflags! {
+    /// Event classes a peer is able to produce or understand
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub struct EventCapabilities: u32 {
+        const PROCESS = 1 << 0;
+        const FILE_SYSTEM = 1 << 1;
+        const REGISTRY = 1 << 2;
+        const NETWORK = 1 << 3;
+    }
+}
+
+impl EventClass {
+    pub fn capability(&self) -> EventCapabilities {
+        match self {
+            EventClass::Process(_) => EventCapabilities::PROCESS,
+            EventClass::FileSystem(_) => EventCapabilities::FILE_SYSTEM,
+            EventClass::Registry(_) => EventCapabilities::REGISTRY,
+            EventClass::Network(_) => EventCapabilities::NETWORK,
+            //Never negotiated, the client records them itself
+            EventClass::Session(_) => EventCapabilities::empty(),
+        }
+    }
+}
+
+///
+/// Always serialized first so a peer from a different build
+/// can still read it before decoding the rest of the message
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolPreamble {
+    pub magic: u32,
+    pub version: u32,
+}
+
+impl ProtocolPreamble {
+    pub const fn current() -> Self {
+        Self {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), HandshakeError> {
+        if self.magic != PROTOCOL_MAGIC {
+            Err(HandshakeError::InvalidMagic(self.magic))
+        } else if self.version != PROTOCOL_VERSION {
+            Err(HandshakeError::VersionMismatch {
+                client: self.version,
+                driver: PROTOCOL_VERSION,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferSizes {
+    pub km_message: u32,
+    pub um_reply: u32,
+    pub um_send: u32,
+}
+
+impl BufferSizes {
+    pub const fn current() -> Self {
+        Self {
+            km_message: MAX_KM_MESSAGE_RECEIVE_SIZE as _,
+            um_reply: MAX_UM_REPLY_MESSAGE_SIZE as _,
+            um_send: MAX_UM_SEND_MESSAGE_BUFFER_SIZE as _,
+        }
+    }
+}
+
+/// Driver answer to a `ClientConnectMessage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DriverHandshake {
+    pub preamble: ProtocolPreamble,
+    /// Intersection between what the client asked for and what the driver supports
+    pub capabilities: EventCapabilities,
+    pub buffers: BufferSizes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandshakeError {
+    Malformed,
+    InvalidMagic(u32),
+    VersionMismatch { client: u32, driver: u32 },
+    NoCommonCapabilities,
+    InvalidBufferSizes,
+    Unanswered,
+}
+
+///
+/// Driver side of the handshake
+///
+pub fn negotiate(
+    request: &ClientConnectMessage,
+    supported: EventCapabilities,
+    buffers: BufferSizes,
+) -> Result<DriverHandshake, HandshakeError> {
+    request.preamble.check()?;
+
+    let capabilities = request.capabilities & supported;
+    if capabilities.is_empty() {
+        return Err(HandshakeError::NoCommonCapabilities);
+    }
+
+    Ok(DriverHandshake {
+        preamble: ProtocolPreamble::current(),
+        capabilities,
+        buffers,
+    })
+}
+
+impl DriverHandshake {
+    ///
+    /// Client side of the handshake, checks the driver reply against what was requested
+    ///
+    pub fn validate(&self, request: &ClientConnectMessage) -> Result<(), HandshakeError> {
+        if self.preamble.magic != PROTOCOL_MAGIC {
+            return Err(HandshakeError::InvalidMagic(self.preamble.magic));
+        }
+
+        if self.preamble.version != request.preamble.version {
+            return Err(HandshakeError::VersionMismatch {
+                client: request.preamble.version,
+                driver: self.preamble.version,
+            });
+        }
+
+        if self.capabilities.is_empty() || !request.capabilities.contains(self.capabilities) {
+            return Err(HandshakeError::NoCommonCapabilities);
+        }
+
+        let BufferSizes {
+            km_message,
+            um_reply,
+            um_send,
+        } = self.buffers;
+        if km_message == 0 || um_reply == 0 || um_send == 0 {
+            return Err(HandshakeError::InvalidBufferSizes);
+        }
+
+        Ok(())
+    }
+}
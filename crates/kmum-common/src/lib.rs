@@ -1,14 +1,24 @@
 #![no_std]
 
-use event::{EventCompoent, EventStack, SimpleProcessDetails};
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use event::{EventCompoent, EventStack, EventStackRef, SimpleProcessDetails};
+use handshake::{DriverHandshake, EventCapabilities, HandshakeError, ProtocolPreamble};
 use nt_string::{unicode_string::NtUnicodeString, widestring::U16CStr};
 use process::{ProcessInformation, UniqueProcessId};
 use serde::{Deserialize, Serialize};
 use serializable_ntstring::SerializableNtString;
+use wide_str::WideStr;
 
+pub mod batch;
 pub mod event;
+pub mod handshake;
+pub mod ntstatus;
 pub mod process;
 pub mod serializable_ntstring;
+pub mod wide_str;
 
 pub fn get_communication_port_name() -> &'static U16CStr {
     nt_string::widestring::u16cstr!("\\PROCMONPORT")
@@ -20,7 +30,7 @@ pub const MAX_UM_REPLY_MESSAGE_SIZE: usize = 32 * 1024;
 pub const MAX_UM_SEND_MESSAGE_BUFFER_SIZE: usize = 32 * 1024;
 
 //Km -> Um
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KmMessage {
     pub event: EventCompoent,
     pub process: SimpleProcessDetails,
@@ -30,24 +40,187 @@ pub struct KmMessage {
 unsafe impl Sync for KmMessage {}
 unsafe impl Send for KmMessage {}
 
+///
+/// `KmMessage` decoded in place from a received buffer, strings and the stack
+/// borrow from it so decoding never allocates.
+///
+/// Meant for looking at events before deciding to keep them, `to_message` turns
+/// the ones that are kept into a `KmMessage`
+///
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KmMessageRef<'a> {
+    #[serde(borrow)]
+    pub event: EventCompoent<WideStr<'a>>,
+    pub process: SimpleProcessDetails,
+    #[serde(borrow)]
+    pub stack: EventStackRef<'a>,
+    /// The serialized message, kept to decode the owned copy
+    #[serde(skip)]
+    raw: &'a [u8],
+}
+
+impl<'a> KmMessageRef<'a> {
+    ///
+    /// Decodes the message at the start of `bytes` and returns it with the bytes that follow it
+    ///
+    pub fn take_from_bytes(bytes: &'a [u8]) -> postcard::Result<(Self, &'a [u8])> {
+        let (mut message, remaining) = postcard::take_from_bytes::<KmMessageRef>(bytes)?;
+        message.raw = &bytes[..bytes.len() - remaining.len()];
+
+        Ok((message, remaining))
+    }
+
+    /// Serialized form of the message exactly as it was received
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    ///
+    /// Decodes the owned message from the same bytes, `None` if it can not be allocated
+    ///
+    pub fn to_message(&self) -> Option<KmMessage> {
+        postcard::from_bytes(self.raw).ok()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum UmReplyMessage {}
 
+/// Most uids a single batch query may ask for
+pub const MAX_BATCH_QUERY: usize = 256;
+
 //Um -> Km
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum UmSendMessage {
     GetProcessInfo(UniqueProcessId),
     GetExeName(UniqueProcessId),
+    Handshake,
+    GetProcessInfoBatch(Vec<UniqueProcessId>),
+    GetExeNameBatch(Vec<UniqueProcessId>),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum KmReplyMessage {
     ProcessInfo(ProcessInformation),
     ExeName(SerializableNtString),
+    Handshake(Result<DriverHandshake, HandshakeError>),
+    /// One entry per requested uid in the same order, a reply that would not fit
+    /// its buffer stops early and the rest has to be asked for again
+    ProcessInfoBatch(Vec<Option<ProcessInformation>>),
+    /// Same rules as `ProcessInfoBatch`
+    ExeNameBatch(Vec<Option<SerializableNtString>>),
+}
+
+impl KmReplyMessage {
+    /// Entries of a batch reply, `None` for single replies
+    pub fn batch_len(&self) -> Option<usize> {
+        match self {
+            KmReplyMessage::ProcessInfoBatch(infos) => Some(infos.len()),
+            KmReplyMessage::ExeNameBatch(names) => Some(names.len()),
+            _ => None,
+        }
+    }
+
+    fn truncate_batch(&mut self, len: usize) {
+        match self {
+            KmReplyMessage::ProcessInfoBatch(infos) => infos.truncate(len),
+            KmReplyMessage::ExeNameBatch(names) => names.truncate(len),
+            _ => {}
+        }
+    }
+}
+
+/// Picked by the client, echoed back in the reply to the request
+pub type RequestId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RequestError {
+    /// The request was understood but could not be answered
+    Failed,
+    /// The request could not be decoded past its id
+    Malformed,
 }
 
+//Um -> Km, envelope of every `UmSendMessage`
 #[derive(Debug, Serialize, Deserialize)]
-pub enum ClientConnectMessage {
+pub struct UmRequest<M = UmSendMessage> {
+    pub id: RequestId,
+    pub message: M,
+}
+
+impl UmRequest {
+    ///
+    /// Reads only the id, a request that fails to decode can still be answered
+    ///
+    pub fn peek_id(bytes: &[u8]) -> Option<RequestId> {
+        postcard::take_from_bytes::<RequestId>(bytes)
+            .ok()
+            .map(|(id, _)| id)
+    }
+}
+
+//Km -> Um, answer to the `UmRequest` with the same id
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KmReply {
+    pub id: RequestId,
+    pub result: Result<Option<KmReplyMessage>, RequestError>,
+}
+
+impl KmReply {
+    pub fn new(id: RequestId, result: Result<Option<KmReplyMessage>, RequestError>) -> Self {
+        Self { id, result }
+    }
+
+    ///
+    /// Serializes the reply into `buffer` and returns its length. Batch replies that
+    /// don't fit lose entries from their end, anything else that doesn't fit
+    /// is replaced by `RequestError::Failed`
+    ///
+    pub fn serialize_fitting(&mut self, buffer: &mut [u8]) -> postcard::Result<usize> {
+        loop {
+            match postcard::to_slice(&*self, buffer) {
+                Ok(serialized) => return Ok(serialized.len()),
+                Err(postcard::Error::SerializeBufferFull) => {}
+                Err(e) => return Err(e),
+            }
+
+            match &mut self.result {
+                Ok(Some(message)) if message.batch_len().is_some_and(|len| len > 0) => {
+                    let len = message.batch_len().unwrap_or_default();
+                    message.truncate_batch(len / 2);
+                }
+                Err(_) => return Err(postcard::Error::SerializeBufferFull),
+                result => *result = Err(RequestError::Failed),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ClientConnectMode {
     Any,
     Testing { filter_pid: u64 },
 }
+
+//Um -> Km, sent as the connection context of the port
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ClientConnectMessage {
+    pub preamble: ProtocolPreamble,
+    pub capabilities: EventCapabilities,
+    pub mode: ClientConnectMode,
+}
+
+impl ClientConnectMessage {
+    pub fn new(mode: ClientConnectMode) -> Self {
+        Self {
+            preamble: ProtocolPreamble::current(),
+            capabilities: EventCapabilities::all(),
+            mode,
+        }
+    }
+
+    pub fn with_capabilities(mut self, capabilities: EventCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+}
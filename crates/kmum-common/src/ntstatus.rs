@@ -0,0 +1,211 @@
+use core::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+///
+/// Severity stored in the two high bits of a status
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NtStatusSeverity {
+    Success,
+    Informational,
+    Warning,
+    Error,
+}
+
+impl NtStatusSeverity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Success => "Success",
+            Self::Informational => "Informational",
+            Self::Warning => "Warning",
+            Self::Error => "Error",
+        }
+    }
+}
+
+pub struct NtStatusEntry {
+    pub code: u32,
+    /// Symbolic name as found in ntstatus.h
+    pub name: &'static str,
+    /// Short upper case text shown in the result column
+    pub text: &'static str,
+}
+
+macro_rules! ntstatus_table {
+    ($($code:literal => $name:ident, $text:literal;)*) => {
+        pub const NTSTATUS_TABLE: &[NtStatusEntry] = &[
+            $(NtStatusEntry { code: $code, name: stringify!($name), text: $text },)*
+        ];
+    };
+}
+
+ntstatus_table! {
+    0x0000_0000 => STATUS_SUCCESS, "SUCCESS";
+    0x0000_0102 => STATUS_TIMEOUT, "TIMEOUT";
+    0x0000_0103 => STATUS_PENDING, "PENDING";
+    0x0000_0104 => STATUS_REPARSE, "REPARSE";
+    0x0000_0105 => STATUS_MORE_ENTRIES, "MORE ENTRIES";
+    0x0000_010C => STATUS_NOTIFY_ENUM_DIR, "NOTIFY ENUM DIR";
+    0x0000_0121 => STATUS_OPLOCK_BREAK_IN_PROGRESS, "OPLOCK BREAK IN PROGRESS";
+    0x4000_0000 => STATUS_OBJECT_NAME_EXISTS, "NAME EXISTS";
+    0x8000_0005 => STATUS_BUFFER_OVERFLOW, "BUFFER OVERFLOW";
+    0x8000_0006 => STATUS_NO_MORE_FILES, "NO MORE FILES";
+    0x8000_001A => STATUS_NO_MORE_ENTRIES, "NO MORE ENTRIES";
+    0x8000_002D => STATUS_STOPPED_ON_SYMLINK, "STOPPED ON SYMLINK";
+    0xC000_0001 => STATUS_UNSUCCESSFUL, "UNSUCCESSFUL";
+    0xC000_0002 => STATUS_NOT_IMPLEMENTED, "NOT IMPLEMENTED";
+    0xC000_0003 => STATUS_INVALID_INFO_CLASS, "INVALID INFO CLASS";
+    0xC000_0004 => STATUS_INFO_LENGTH_MISMATCH, "INFO LENGTH MISMATCH";
+    0xC000_0005 => STATUS_ACCESS_VIOLATION, "ACCESS VIOLATION";
+    0xC000_0008 => STATUS_INVALID_HANDLE, "INVALID HANDLE";
+    0xC000_000D => STATUS_INVALID_PARAMETER, "INVALID PARAMETER";
+    0xC000_000E => STATUS_NO_SUCH_DEVICE, "NO SUCH DEVICE";
+    0xC000_000F => STATUS_NO_SUCH_FILE, "NO SUCH FILE";
+    0xC000_0010 => STATUS_INVALID_DEVICE_REQUEST, "INVALID DEVICE REQUEST";
+    0xC000_0011 => STATUS_END_OF_FILE, "END OF FILE";
+    0xC000_0017 => STATUS_NO_MEMORY, "NO MEMORY";
+    0xC000_0022 => STATUS_ACCESS_DENIED, "ACCESS DENIED";
+    0xC000_0023 => STATUS_BUFFER_TOO_SMALL, "BUFFER TOO SMALL";
+    0xC000_0024 => STATUS_OBJECT_TYPE_MISMATCH, "OBJECT TYPE MISMATCH";
+    0xC000_0033 => STATUS_OBJECT_NAME_INVALID, "NAME INVALID";
+    0xC000_0034 => STATUS_OBJECT_NAME_NOT_FOUND, "NAME NOT FOUND";
+    0xC000_0035 => STATUS_OBJECT_NAME_COLLISION, "NAME COLLISION";
+    0xC000_0039 => STATUS_OBJECT_PATH_INVALID, "PATH INVALID";
+    0xC000_003A => STATUS_OBJECT_PATH_NOT_FOUND, "PATH NOT FOUND";
+    0xC000_003B => STATUS_OBJECT_PATH_SYNTAX_BAD, "PATH SYNTAX BAD";
+    0xC000_0043 => STATUS_SHARING_VIOLATION, "SHARING VIOLATION";
+    0xC000_0054 => STATUS_FILE_LOCK_CONFLICT, "FILE LOCK CONFLICT";
+    0xC000_0055 => STATUS_LOCK_NOT_GRANTED, "LOCK NOT GRANTED";
+    0xC000_0056 => STATUS_DELETE_PENDING, "DELETE PENDING";
+    0xC000_0061 => STATUS_PRIVILEGE_NOT_HELD, "PRIVILEGE NOT HELD";
+    0xC000_007F => STATUS_DISK_FULL, "DISK FULL";
+    0xC000_009A => STATUS_INSUFFICIENT_RESOURCES, "INSUFFICIENT RESOURCES";
+    0xC000_00BA => STATUS_FILE_IS_A_DIRECTORY, "IS DIRECTORY";
+    0xC000_00BB => STATUS_NOT_SUPPORTED, "NOT SUPPORTED";
+    0xC000_00C3 => STATUS_INVALID_NETWORK_RESPONSE, "INVALID NETWORK RESPONSE";
+    0xC000_00CC => STATUS_BAD_NETWORK_NAME, "BAD NETWORK NAME";
+    0xC000_00E3 => STATUS_INVALID_PARAMETER_MIX, "INVALID PARAMETER MIX";
+    0xC000_0101 => STATUS_DIRECTORY_NOT_EMPTY, "DIRECTORY NOT EMPTY";
+    0xC000_0103 => STATUS_NOT_A_DIRECTORY, "NOT A DIRECTORY";
+    0xC000_0120 => STATUS_CANCELLED, "CANCELLED";
+    0xC000_0121 => STATUS_CANNOT_DELETE, "CANNOT DELETE";
+    0xC000_0123 => STATUS_FILE_DELETED, "FILE DELETED";
+    0xC000_0128 => STATUS_FILE_CLOSED, "FILE CLOSED";
+    0xC000_0135 => STATUS_DLL_NOT_FOUND, "DLL NOT FOUND";
+    0xC000_013A => STATUS_CONTROL_C_EXIT, "CONTROL C EXIT";
+    0xC000_0189 => STATUS_TOO_LATE, "TOO LATE";
+    0xC000_0225 => STATUS_NOT_FOUND, "NOT FOUND";
+    0xC000_0236 => STATUS_CONNECTION_REFUSED, "CONNECTION REFUSED";
+    0xC000_0241 => STATUS_CONNECTION_ABORTED, "CONNECTION ABORTED";
+    0xC000_023A => STATUS_CONNECTION_INVALID, "CONNECTION INVALID";
+    0xC000_020C => STATUS_CONNECTION_DISCONNECTED, "CONNECTION DISCONNECTED";
+    0xC000_020D => STATUS_CONNECTION_RESET, "CONNECTION RESET";
+    0xC000_0275 => STATUS_NOT_A_REPARSE_POINT, "NOT REPARSE POINT";
+    0xC000_0279 => STATUS_IO_REPARSE_TAG_NOT_HANDLED, "REPARSE TAG NOT HANDLED";
+    0xC000_0388 => STATUS_DOWNGRADE_DETECTED, "DOWNGRADE DETECTED";
+    0xC000_03E6 => STATUS_ACCESS_DISABLED_BY_POLICY_DEFAULT, "ACCESS DISABLED BY POLICY";
+    0xC000_0427 => STATUS_FILE_LOCKED_WITH_ONLY_READERS, "FILE LOCKED WITH ONLY READERS";
+    0xC000_0428 => STATUS_FILE_LOCKED_WITH_WRITERS, "FILE LOCKED WITH WRITERS";
+    0xC000_0467 => STATUS_FILE_IN_USE, "FILE IN USE";
+    0xC000_A203 => STATUS_CONNECTION_TIMEOUT, "CONNECTION TIMEOUT";
+    0xC01C_0004 => STATUS_FLT_DISALLOW_FAST_IO, "FAST IO DISALLOWED";
+}
+
+///
+/// A raw `NTSTATUS` as reported by the driver
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NtStatus(pub i32);
+
+impl NtStatus {
+    pub const SUCCESS: NtStatus = NtStatus(0);
+
+    pub fn code(&self) -> u32 {
+        self.0 as u32
+    }
+
+    pub fn entry(&self) -> Option<&'static NtStatusEntry> {
+        NTSTATUS_TABLE
+            .iter()
+            .find(|entry| entry.code == self.code())
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.entry().map(|entry| entry.name)
+    }
+
+    pub fn text(&self) -> Option<&'static str> {
+        self.entry().map(|entry| entry.text)
+    }
+
+    pub fn severity(&self) -> NtStatusSeverity {
+        match self.code() >> 30 {
+            0 => NtStatusSeverity::Success,
+            1 => NtStatusSeverity::Informational,
+            2 => NtStatusSeverity::Warning,
+            _ => NtStatusSeverity::Error,
+        }
+    }
+
+    /// Same rule as `NT_SUCCESS`, informational statuses are successes
+    pub fn is_success(&self) -> bool {
+        self.0 >= 0
+    }
+
+    ///
+    /// Accepts a numeric status, either hexadecimal with a `0x` prefix or decimal
+    /// (signed or unsigned), the symbolic name with or without the `STATUS_` prefix,
+    /// or the short text shown in the result column. Names are not case sensitive
+    ///
+    pub fn parse(input: &str) -> Option<NtStatus> {
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+
+        if let Some(hex) = input
+            .strip_prefix("0x")
+            .or_else(|| input.strip_prefix("0X"))
+        {
+            return u32::from_str_radix(hex, 16)
+                .ok()
+                .map(|code| NtStatus(code as i32));
+        }
+
+        if let Ok(code) = input.parse::<i32>() {
+            return Some(NtStatus(code));
+        }
+
+        if let Ok(code) = input.parse::<u32>() {
+            return Some(NtStatus(code as i32));
+        }
+
+        NTSTATUS_TABLE
+            .iter()
+            .find(|entry| {
+                entry.name.eq_ignore_ascii_case(input)
+                    || entry.name[7..].eq_ignore_ascii_case(input)
+                    || entry.text.eq_ignore_ascii_case(input)
+            })
+            .map(|entry| NtStatus(entry.code as i32))
+    }
+}
+
+impl From<i32> for NtStatus {
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+///
+/// Short text if the status is known, its hexadecimal value otherwise
+///
+impl Display for NtStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.text() {
+            Some(text) => f.write_str(text),
+            None => write!(f, "0x{:08X}", self.code()),
+        }
+    }
+}
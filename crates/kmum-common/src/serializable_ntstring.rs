@@ -3,8 +3,20 @@ use core::{
     ops::{Deref, DerefMut},
 };
 
+use alloc::vec::Vec;
 use nt_string::unicode_string::NtUnicodeString;
-use serde::{de::Visitor, Deserialize, Serialize};
+use serde::{
+    de::{self, DeserializeSeed, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::wide_str::WideStr;
+
+///
+/// Longest string a `UNICODE_STRING` can hold, in UTF-16 code units.
+/// The length is a byte count stored in 16 bits and one element is kept for the NUL terminator
+///
+pub const MAX_NT_STRING_LENGTH: usize = (u16::MAX as usize / 2) - 1;
 
 pub struct SerializableNtString(pub NtUnicodeString);
 
@@ -67,53 +79,146 @@ impl From<NtUnicodeString> for SerializableNtString {
     }
 }
 
+///
+/// Binary formats get the code units as little endian bytes so the receiver can
+/// borrow them as a `WideStr`, human readable ones get a sequence of code units
+///
 impl Serialize for SerializableNtString {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        if self.0.len() == 0 {
-            let empty_buffer: &[u16] = &[];
-            serializer.collect_seq(empty_buffer.iter())
+        let units: &[u16] = if self.0.is_empty() {
+            &[]
         } else {
-            serializer.collect_seq(self.0.as_slice().iter())
+            self.0.as_slice()
+        };
+
+        if serializer.is_human_readable() {
+            serializer.collect_seq(units.iter())
+        } else {
+            serializer.serialize_bytes(units_as_le_bytes(units))
         }
     }
 }
 
+#[cfg(target_endian = "little")]
+fn units_as_le_bytes(units: &[u16]) -> &[u8] {
+    // SAFETY: u8 has no alignment requirement and the length covers the same memory
+    unsafe { core::slice::from_raw_parts(units.as_ptr() as *const u8, units.len() * 2) }
+}
+
 impl<'de> Deserialize<'de> for SerializableNtString {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
-        D: serde::Deserializer<'de>,
+        D: Deserializer<'de>,
     {
-        struct NtStringVisitor;
+        NtStringSeed::new(MAX_NT_STRING_LENGTH).deserialize(deserializer)
+    }
+}
 
-        impl<'de> Visitor<'de> for NtStringVisitor {
-            type Value = SerializableNtString;
+///
+/// For `#[serde(deserialize_with = "deserialize_bounded::<_, N>")]` on fields
+/// that must be shorter than `MAX_NT_STRING_LENGTH`
+///
+pub fn deserialize_bounded<'de, D, const MAX_LEN: usize>(
+    deserializer: D,
+) -> Result<SerializableNtString, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    NtStringSeed::new(MAX_LEN).deserialize(deserializer)
+}
 
-            fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
-                formatter.write_str("a byte sequence representing UTF-16 encoded data")
-            }
+///
+/// Decodes a string of at most `max_len` UTF-16 elements.
+///
+/// Oversized, truncated or malformed input is reported as a deserialization error,
+/// nothing in here panics since the driver decodes messages coming from user mode
+///
+#[derive(Debug, Clone, Copy)]
+pub struct NtStringSeed {
+    max_len: usize,
+}
+
+impl NtStringSeed {
+    /// `max_len` is clamped to `MAX_NT_STRING_LENGTH`
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len: max_len.min(MAX_NT_STRING_LENGTH),
+        }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for NtStringSeed {
+    type Value = SerializableNtString;
 
-            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
-            where
-                A: serde::de::SeqAccess<'de>,
-            {
-                let size = seq.size_hint().unwrap();
-
-                let mut string = NtUnicodeString::with_capacity(size as _);
-
-                loop {
-                    let result = seq.next_element()?;
-                    if let Some(element) = result {
-                        string.try_push_u16(&[element]).unwrap();
-                    } else {
-                        break Ok(SerializableNtString(string));
-                    }
-                }
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_seq(self)
+        } else {
+            deserializer.deserialize_bytes(self)
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for NtStringSeed {
+    type Value = SerializableNtString;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(formatter, "at most {} UTF-16 code units", self.max_len)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+        if !bytes.len().is_multiple_of(2) || bytes.len() / 2 > self.max_len {
+            return Err(E::invalid_length(bytes.len() / 2, &self));
+        }
+
+        WideStr::from_le_bytes(bytes)
+            .and_then(|string| string.to_nt_string())
+            .ok_or_else(|| E::custom("failed to allocate the string buffer"))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        //The hint comes from the input, it is only trusted up to the limit.
+        //Without one the elements are counted against the limit as they come
+        let hint = seq.size_hint().unwrap_or(0);
+        if hint > self.max_len {
+            return Err(de::Error::invalid_length(hint, &self));
+        }
+
+        let mut buffer: Vec<u16> = Vec::new();
+        buffer
+            .try_reserve_exact(hint)
+            .map_err(|_| de::Error::custom("failed to allocate the string buffer"))?;
+
+        while let Some(element) = seq.next_element::<u16>()? {
+            if buffer.len() == self.max_len {
+                return Err(de::Error::invalid_length(buffer.len() + 1, &self));
             }
+
+            buffer
+                .try_reserve(1)
+                .map_err(|_| de::Error::custom("failed to allocate the string buffer"))?;
+            buffer.push(element);
+        }
+
+        if buffer.is_empty() {
+            return Ok(SerializableNtString::empty());
         }
 
-        deserializer.deserialize_seq(NtStringVisitor)
+        NtUnicodeString::try_from_u16(&buffer)
+            .map(SerializableNtString)
+            .map_err(|_| de::Error::invalid_length(buffer.len(), &self))
     }
 }
@@ -0,0 +1,153 @@
+use core::fmt::{self, Debug, Display, Write};
+
+use nt_string::unicode_string::NtUnicodeString;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::serializable_ntstring::{SerializableNtString, MAX_NT_STRING_LENGTH};
+
+///
+/// UTF-16 string borrowed straight from a received buffer.
+///
+/// The code units are kept as little endian bytes, a batch gives no alignment
+/// guarantee so they can not be handed out as a `&[u16]`
+///
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct WideStr<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> WideStr<'a> {
+    ///
+    /// Returns `None` if `bytes` is not a whole number of code units
+    /// or is longer than `MAX_NT_STRING_LENGTH`
+    ///
+    pub fn from_le_bytes(bytes: &'a [u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(2) || bytes.len() / 2 > MAX_NT_STRING_LENGTH {
+            None
+        } else {
+            Some(Self { bytes })
+        }
+    }
+
+    pub fn as_le_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Length in UTF-16 code units
+    pub fn len(&self) -> usize {
+        self.bytes.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn units(&self) -> impl Iterator<Item = u16> + 'a {
+        self.bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+    }
+
+    /// Unpaired surrogates are replaced by U+FFFD
+    pub fn chars(&self) -> impl Iterator<Item = char> + 'a {
+        char::decode_utf16(self.units()).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    pub fn eq_ignore_ascii_case(&self, other: &str) -> bool {
+        let mut chars = self.chars();
+        other
+            .chars()
+            .all(|c| chars.next().is_some_and(|own| own.eq_ignore_ascii_case(&c)))
+            && chars.next().is_none()
+    }
+
+    ///
+    /// Copies the string out of the buffer, `None` if it can not be allocated
+    ///
+    pub fn to_nt_string(&self) -> Option<SerializableNtString> {
+        if self.is_empty() {
+            return Some(SerializableNtString::empty());
+        }
+
+        let mut string = NtUnicodeString::new();
+        string.try_reserve((self.bytes.len() + 2) as u16).ok()?;
+
+        let mut chunk = [0u16; 64];
+        for units in self.bytes.chunks(chunk.len() * 2) {
+            let units = units.chunks_exact(2).zip(chunk.iter_mut());
+            let mut len = 0;
+            for (bytes, unit) in units {
+                *unit = u16::from_le_bytes([bytes[0], bytes[1]]);
+                len += 1;
+            }
+            string.try_push_u16(&chunk[..len]).ok()?;
+        }
+
+        Some(SerializableNtString::new(string))
+    }
+}
+
+impl Display for WideStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("[[[Empty]]]");
+        }
+
+        self.chars().try_for_each(|c| f.write_char(c))
+    }
+}
+
+impl Debug for WideStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+///
+/// Same wire format as `SerializableNtString`
+///
+impl Serialize for WideStr<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(self.units())
+        } else {
+            serializer.serialize_bytes(self.bytes)
+        }
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for WideStr<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct WideStrVisitor;
+
+        impl<'de> Visitor<'de> for WideStrVisitor {
+            type Value = WideStr<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    formatter,
+                    "borrowed bytes holding at most {} UTF-16 code units",
+                    MAX_NT_STRING_LENGTH
+                )
+            }
+
+            fn visit_borrowed_bytes<E: de::Error>(
+                self,
+                bytes: &'de [u8],
+            ) -> Result<Self::Value, E> {
+                WideStr::from_le_bytes(bytes).ok_or_else(|| E::invalid_length(bytes.len(), &self))
+            }
+        }
+
+        deserializer.deserialize_bytes(WideStrVisitor)
+    }
+}
@@ -0,0 +1,139 @@
+use kmum_common::{
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventProcessOperation, EventStack,
+        FileSetInformation, FrameMode, SimpleProcessDetails, StackFrame,
+    },
+    serializable_ntstring::SerializableNtString,
+    KmMessage, KmMessageRef,
+};
+use nt_string::unicode_string::NtUnicodeString;
+
+fn nt_string(value: &str) -> SerializableNtString {
+    SerializableNtString::new(NtUnicodeString::try_from(value).unwrap())
+}
+
+fn message(operation: EventClass, path: &str) -> KmMessage {
+    let mut stack = EventStack::new();
+    for (address, mode) in [
+        (0xFFFF_F800_0010_0000, FrameMode::Kernel),
+        (0x7FF6_0000_1000, FrameMode::User),
+        (0xFFFF_F800_0010_0000, FrameMode::Kernel),
+    ] {
+        stack.try_push(StackFrame { address, mode });
+    }
+
+    KmMessage {
+        event: EventCompoent {
+            date: 133_000_000_000_000_000,
+            thread: 42,
+            operation,
+            result: 0xC000_0034u32 as i32,
+            path: nt_string(path),
+            duration: 7,
+        },
+        process: SimpleProcessDetails {
+            pid: 1234,
+            unique_id: 99,
+        },
+        stack,
+    }
+}
+
+fn encode(messages: &[KmMessage]) -> Vec<u8> {
+    let mut buffer = vec![0u8; 4096];
+    let mut used = 0;
+    for message in messages {
+        used += postcard::to_slice(message, &mut buffer[used..])
+            .unwrap()
+            .len();
+    }
+    buffer.truncate(used);
+    buffer
+}
+
+#[test]
+fn borrowed_view_matches_owned_message() {
+    let messages = [
+        message(
+            EventClass::Process(EventProcessOperation::ProcessCreate {
+                pid: 1234,
+                cmd: Some(nt_string("cmd.exe /c dir")),
+            }),
+            "\\Device\\HarddiskVolume3\\Windows\\System32\\cmd.exe",
+        ),
+        message(
+            EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+                information: FileSetInformation::Rename {
+                    target: nt_string("\\??\\C:\\renamed.txt"),
+                    replace_if_exists: true,
+                },
+            }),
+            "\\Device\\HarddiskVolume3\\original.txt",
+        ),
+        message(
+            EventClass::FileSystem(EventFileSystemOperation::Cleanup {}),
+            "",
+        ),
+    ];
+    let bytes = encode(&messages);
+
+    let mut remaining = &bytes[..];
+    for expected in &messages {
+        let (view, rest) = KmMessageRef::take_from_bytes(remaining).unwrap();
+        remaining = rest;
+
+        assert_eq!(view.event.path.to_string(), expected.event.path.to_string());
+        assert_eq!(view.event.result, expected.event.result);
+        assert_eq!(view.process.unique_id, expected.process.unique_id);
+        assert_eq!(view.stack.len(), expected.stack.len());
+        assert!(view
+            .stack
+            .frames()
+            .eq(expected.stack.frames().iter().copied()));
+
+        let owned = view.to_message().unwrap();
+        assert_eq!(format!("{:?}", owned), format!("{:?}", expected));
+    }
+    assert!(remaining.is_empty());
+}
+
+#[test]
+fn borrowed_strings_point_into_the_buffer() {
+    let bytes = encode(&[message(
+        EventClass::Process(EventProcessOperation::ProcessCreate {
+            pid: 1,
+            cmd: Some(nt_string("notepad.exe")),
+        }),
+        "\\notepad.exe",
+    )]);
+
+    let (view, _) = KmMessageRef::take_from_bytes(&bytes).unwrap();
+    let range = bytes.as_ptr_range();
+
+    let path = view.event.path.as_le_bytes();
+    assert!(range.contains(&path.as_ptr()));
+    assert!(view.event.path.eq_ignore_ascii_case("\\NOTEPAD.EXE"));
+
+    let EventClass::Process(EventProcessOperation::ProcessCreate { cmd: Some(cmd), .. }) =
+        view.event.operation
+    else {
+        panic!("unexpected operation");
+    };
+    assert!(range.contains(&cmd.as_le_bytes().as_ptr()));
+    assert_eq!(cmd.to_string(), "notepad.exe");
+}
+
+#[test]
+fn truncated_messages_are_rejected() {
+    let bytes = encode(&[message(
+        EventClass::FileSystem(EventFileSystemOperation::Read {
+            length: 10,
+            offset: 0,
+        }),
+        "\\file.txt",
+    )]);
+
+    for cut in 0..bytes.len() {
+        assert!(KmMessageRef::take_from_bytes(&bytes[..cut]).is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use std::net::{Ipv6Addr, SocketAddrV6};
+
+use kmum_common::event::{IpAddress, SocketAddress};
+
+#[test]
+fn ipv6_is_formatted_like_std() {
+    for text in [
+        "2001:db8::1",
+        "::1",
+        "::",
+        "fe80::1:0:0:2",
+        "2001:db8:0:1:1:1:1:1",
+    ] {
+        let ip: Ipv6Addr = text.parse().unwrap();
+        assert_eq!(IpAddress::V6(ip.octets()).to_string(), ip.to_string());
+    }
+
+    let address = SocketAddress {
+        ip: IpAddress::V6(Ipv6Addr::LOCALHOST.octets()),
+        port: 443,
+    };
+    assert_eq!(
+        address.to_string(),
+        SocketAddrV6::new(Ipv6Addr::LOCALHOST, 443, 0, 0).to_string()
+    );
+}
+
+#[test]
+fn ipv4_is_dotted() {
+    assert_eq!(IpAddress::V4([192, 168, 0, 1]).to_string(), "192.168.0.1");
+}
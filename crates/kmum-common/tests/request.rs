@@ -0,0 +1,76 @@
+use kmum_common::{
+    serializable_ntstring::SerializableNtString, KmReply, KmReplyMessage, RequestError, UmRequest,
+    UmSendMessage, MAX_UM_REPLY_MESSAGE_SIZE,
+};
+use nt_string::unicode_string::NtUnicodeString;
+
+fn name(index: usize) -> Option<SerializableNtString> {
+    let name = format!("Process{:04}.exe", index);
+    Some(SerializableNtString::new(
+        NtUnicodeString::try_from(name.as_str()).unwrap(),
+    ))
+}
+
+#[test]
+fn request_id_survives_a_malformed_body() {
+    let request = UmRequest {
+        id: 70_000,
+        message: UmSendMessage::GetExeNameBatch(vec![1, 2, 3]),
+    };
+    let mut buffer = [0u8; 64];
+    let encoded = postcard::to_slice(&request, &mut buffer).unwrap();
+
+    let decoded: UmRequest = postcard::from_bytes(encoded).unwrap();
+    assert_eq!(decoded.id, 70_000);
+    assert!(matches!(decoded.message, UmSendMessage::GetExeNameBatch(uids) if uids == [1, 2, 3]));
+
+    let truncated = &encoded[..encoded.len() - 1];
+    assert!(postcard::from_bytes::<UmRequest>(truncated).is_err());
+    assert_eq!(UmRequest::peek_id(truncated), Some(70_000));
+    assert_eq!(UmRequest::peek_id(&[]), None);
+}
+
+#[test]
+fn batch_reply_is_cut_to_fit() {
+    let names: Vec<_> = (0..4096).map(name).collect();
+    let mut buffer = vec![0u8; MAX_UM_REPLY_MESSAGE_SIZE];
+
+    let mut reply = KmReply::new(7, Ok(Some(KmReplyMessage::ExeNameBatch(names.clone()))));
+    let length = reply.serialize_fitting(&mut buffer).unwrap();
+    assert!(length <= MAX_UM_REPLY_MESSAGE_SIZE);
+
+    let decoded: KmReply = postcard::from_bytes(&buffer[..length]).unwrap();
+    assert_eq!(decoded.id, 7);
+    let Ok(Some(KmReplyMessage::ExeNameBatch(received))) = decoded.result else {
+        panic!("unexpected reply {:?}", decoded.result);
+    };
+
+    //Whatever was kept is the start of the batch, in order
+    assert!(!received.is_empty() && received.len() < names.len());
+    for (index, received) in received.iter().enumerate() {
+        assert_eq!(
+            received.as_ref().unwrap().to_string(),
+            names[index].as_ref().unwrap().to_string()
+        );
+    }
+}
+
+#[test]
+fn oversized_single_reply_fails() {
+    let long = "a".repeat(30_000);
+    let mut buffer = vec![0u8; 1024];
+
+    let mut reply = KmReply::new(
+        3,
+        Ok(Some(KmReplyMessage::ExeName(SerializableNtString::new(
+            NtUnicodeString::try_from(long.as_str()).unwrap(),
+        )))),
+    );
+    let length = reply.serialize_fitting(&mut buffer).unwrap();
+
+    let decoded: KmReply = postcard::from_bytes(&buffer[..length]).unwrap();
+    assert_eq!(decoded.id, 3);
+    assert!(matches!(decoded.result, Err(RequestError::Failed)));
+
+    assert!(reply.serialize_fitting(&mut [0u8; 1]).is_err());
+}
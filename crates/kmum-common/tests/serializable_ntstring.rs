@@ -0,0 +1,207 @@
+use kmum_common::serializable_ntstring::{
+    deserialize_bounded, NtStringSeed, SerializableNtString, MAX_NT_STRING_LENGTH,
+};
+use nt_string::unicode_string::NtUnicodeString;
+use serde::{
+    de::{value::SeqDeserializer, DeserializeSeed},
+    Deserialize,
+};
+
+const ITERATIONS: usize = 2_000;
+
+/// Small xorshift generator so the cases are reproducible without extra dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    fn units(&mut self, len: usize) -> Vec<u16> {
+        (0..len).map(|_| self.next() as u16).collect()
+    }
+}
+
+fn encode(units: &[u16]) -> Vec<u8> {
+    let string = match units.is_empty() {
+        true => SerializableNtString::empty(),
+        false => SerializableNtString::new(NtUnicodeString::try_from_u16(units).unwrap()),
+    };
+
+    let mut buffer = vec![0u8; units.len() * 3 + 16];
+    let used = postcard::to_slice(&string, &mut buffer).unwrap().len();
+    buffer.truncate(used);
+    buffer
+}
+
+fn decode(bytes: &[u8]) -> postcard::Result<SerializableNtString> {
+    postcard::from_bytes(bytes)
+}
+
+fn decode_with_limit(bytes: &[u8], max_len: usize) -> postcard::Result<SerializableNtString> {
+    let mut deserializer = postcard::Deserializer::from_bytes(bytes);
+    NtStringSeed::new(max_len).deserialize(&mut deserializer)
+}
+
+/// An empty string has no buffer, `as_slice` can not be used on it
+fn units_of(string: &SerializableNtString) -> &[u16] {
+    match string.is_empty() {
+        true => &[],
+        false => string.as_slice(),
+    }
+}
+
+/// postcard varint, used to forge length prefixes
+fn varint(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[test]
+fn round_trips_random_strings() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+
+    for _ in 0..ITERATIONS {
+        let len = rng.below(512);
+        let units = rng.units(len);
+
+        let decoded = decode(&encode(&units)).unwrap();
+        assert_eq!(units_of(&decoded), &units[..]);
+    }
+}
+
+#[test]
+fn round_trips_limits() {
+    let empty = decode(&encode(&[])).unwrap();
+    assert!(empty.is_empty());
+
+    let longest = vec![b'A' as u16; MAX_NT_STRING_LENGTH];
+    let decoded = decode(&encode(&longest)).unwrap();
+    assert_eq!(units_of(&decoded).len(), MAX_NT_STRING_LENGTH);
+}
+
+#[test]
+fn rejects_oversized_length_prefix() {
+    for len in [MAX_NT_STRING_LENGTH as u64 + 1, u32::MAX as u64, u64::MAX] {
+        assert!(decode(&varint(len)).is_err());
+    }
+}
+
+#[test]
+fn rejects_strings_over_the_configured_limit() {
+    let units = vec![b'x' as u16; 300];
+    let bytes = encode(&units);
+
+    assert!(decode_with_limit(&bytes, 260).is_err());
+    assert_eq!(
+        units_of(&decode_with_limit(&bytes, 300).unwrap()),
+        &units[..]
+    );
+}
+
+#[test]
+fn limit_is_clamped() {
+    assert_eq!(
+        NtStringSeed::new(usize::MAX).max_len(),
+        MAX_NT_STRING_LENGTH
+    );
+}
+
+#[test]
+fn bounded_field() {
+    #[derive(Deserialize)]
+    struct Named {
+        #[serde(deserialize_with = "deserialize_bounded::<_, 4>")]
+        name: SerializableNtString,
+    }
+
+    let short: Named = postcard::from_bytes(&encode(&[1, 2, 3, 4])).unwrap();
+    assert_eq!(units_of(&short.name), &[1, 2, 3, 4]);
+
+    assert!(postcard::from_bytes::<Named>(&encode(&[1, 2, 3, 4, 5])).is_err());
+}
+
+#[test]
+fn sequences_without_a_length_are_counted() {
+    type Error = serde::de::value::Error;
+
+    //A filtered iterator can not tell its length up front
+    let units = [b'a' as u16, b'b' as u16, b'c' as u16];
+    let unknown = || SeqDeserializer::<_, Error>::new(units.iter().copied().filter(|_| true));
+
+    assert_eq!(
+        units_of(
+            &NtStringSeed::new(MAX_NT_STRING_LENGTH)
+                .deserialize(unknown())
+                .unwrap()
+        ),
+        &units[..]
+    );
+    assert!(NtStringSeed::new(3).deserialize(unknown()).is_ok());
+    assert!(NtStringSeed::new(2).deserialize(unknown()).is_err());
+}
+
+#[test]
+fn round_trips_through_serde_json() {
+    let string =
+        SerializableNtString::new(NtUnicodeString::try_from("C:\\Windows\\ä.txt").unwrap());
+
+    let json = serde_json::to_string(&string).unwrap();
+    assert!(json.starts_with('['));
+    let back: SerializableNtString = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.to_string(), string.to_string());
+
+    let empty: SerializableNtString = serde_json::from_str("[]").unwrap();
+    assert!(empty.is_empty());
+
+    let mut deserializer = serde_json::Deserializer::from_str("[97, 98, 99]");
+    assert!(NtStringSeed::new(2).deserialize(&mut deserializer).is_err());
+}
+
+#[test]
+fn rejects_truncated_and_malformed_input() {
+    let mut rng = Rng(0xD1B5_4A32_D192_ED03);
+
+    for _ in 0..ITERATIONS {
+        let len = 1 + rng.below(64);
+        let units = rng.units(len);
+        let bytes = encode(&units);
+
+        let cut = rng.below(bytes.len());
+        assert!(decode(&bytes[..cut]).is_err());
+    }
+
+    //Odd number of bytes, the last code unit is cut in half
+    let mut bytes = varint(3);
+    bytes.extend([b'a', 0, b'b']);
+    assert!(decode(&bytes).is_err());
+}
+
+#[test]
+fn random_bytes_never_panic() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+
+    for _ in 0..ITERATIONS * 5 {
+        let len = rng.below(64);
+        let bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+
+        if let Ok(string) = decode(&bytes) {
+            assert!(units_of(&string).len() <= MAX_NT_STRING_LENGTH);
+        }
+    }
+}
@@ -1,19 +1,52 @@
-use std::{sync::Arc, time::Duration};
-
-use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use eframe::Frame;
+use egui::Color32;
 use egui_extras::{Column, TableBuilder};
-use kmum_common::event::{
-    EventClass, EventFileSystemOperation, EventProcessOperation, EventRegistryOperation,
+use kmum_common::{
+    event::FrameMode,
+    ntstatus::{NtStatus, NtStatusSeverity},
+    process::UniqueProcessId,
 };
+use procmon_core::query::{Query, QueryError};
 
 use crate::{
-    client_runtime::ClientRuntime, events_storage::EventStorage, process_cache::ProcessCache,
+    client_runtime::ClientRuntime,
+    columns::{
+        event_class_to_str, event_detail, event_operation_to_str, event_path, filetime_to_datetime,
+    },
+    events_storage::EventStorage,
+    export::ExportJob,
+    export_dialog::ExportDialog,
+    filter::{CompiledFilter, FilterSet, FilteredView},
+    filter_dialog::FilterDialog,
+    highlight::{CompiledHighlights, HighlightSet, HighlightedRows},
+    highlight_dialog::HighlightDialog,
+    stack::{ResolvedFrame, StackResolver},
 };
 
 pub struct ProcmonApp {
     runtime: ClientRuntime,
     storage: EventStorage,
+    resolver: StackResolver,
+    selected: Option<usize>,
+    /// Frames of the selected event, resolved once when the selection changes
+    selected_stack: Vec<ResolvedFrame>,
+    selected_process: Option<UniqueProcessId>,
+    filter: FilterSet,
+    compiled_filter: CompiledFilter,
+    view: FilteredView,
+    filter_dialog: FilterDialog,
+    highlights: HighlightSet,
+    compiled_highlights: CompiledHighlights,
+    highlighted: HighlightedRows,
+    highlight_dialog: HighlightDialog,
+    export_dialog: ExportDialog,
+    /// View row the table scrolls to on the next frame
+    scroll_to: Option<usize>,
+    /// What is typed in the search bar, applied on enter
+    query_text: String,
+    query: Option<Query>,
+    /// Why the last query typed was not applied, with the query underlined where it went wrong
+    query_error: Option<(QueryError, String)>,
 }
 
 impl Drop for ProcmonApp {
@@ -23,56 +56,476 @@ impl Drop for ProcmonApp {
 }
 
 impl ProcmonApp {
-    pub fn new(runtime: ClientRuntime, storage: EventStorage) -> Self {
-        Self { runtime, storage }
+    pub fn new(
+        runtime: ClientRuntime,
+        storage: EventStorage,
+        filter: FilterSet,
+        highlights: HighlightSet,
+        query: Option<Query>,
+    ) -> Self {
+        let compiled_filter = CompiledFilter::compile(&filter).unwrap_or_else(|e| {
+            tracing::error!("Filter rule {} ignored: {}", e.rule + 1, e.message);
+            CompiledFilter::default()
+        });
+        let compiled_highlights = CompiledHighlights::compile(&highlights).unwrap_or_else(|e| {
+            tracing::error!("Highlight rule {} ignored: {}", e.rule + 1, e.message);
+            CompiledHighlights::default()
+        });
+
+        Self {
+            resolver: StackResolver::new(runtime.modules().clone(), runtime.is_local()),
+            runtime,
+            storage,
+            selected: None,
+            selected_stack: Vec::new(),
+            selected_process: None,
+            filter,
+            compiled_filter,
+            view: FilteredView::default(),
+            filter_dialog: FilterDialog::default(),
+            highlights,
+            compiled_highlights,
+            highlighted: HighlightedRows::default(),
+            highlight_dialog: HighlightDialog::default(),
+            export_dialog: ExportDialog::default(),
+            scroll_to: None,
+            query_text: query
+                .as_ref()
+                .map_or_else(String::new, |query| query.source().to_string()),
+            query,
+            query_error: None,
+        }
+    }
+
+    /// The rows shown are about to change, rows are looked at again from the start
+    fn reset_view(&mut self) {
+        self.view.reset();
+        self.highlighted.reset();
+    }
+
+    fn apply_query(&mut self) {
+        let text = self.query_text.trim();
+        let query = match text.is_empty() {
+            true => Ok(None),
+            false => Query::parse(text).map(Some),
+        };
+
+        match query {
+            Ok(query) => {
+                self.query = query;
+                self.query_error = None;
+                self.reset_view();
+            }
+            Err(e) => {
+                let annotated = e.annotate(text);
+                self.query_error = Some((e, annotated));
+            }
+        }
+    }
+
+    fn search_bar(&mut self, ui: &mut egui::Ui) {
+        ui.label("Search:");
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut self.query_text)
+                .hint_text(r#"process == "svchost.exe" && op in (Write, SetInformation)"#)
+                .font(egui::TextStyle::Monospace)
+                .desired_width(500.0),
+        );
+
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            self.apply_query();
+        }
+        if ui.button("Clear").clicked() {
+            self.query_text.clear();
+            self.apply_query();
+        }
+
+        if let Some((error, annotated)) = &self.query_error {
+            ui.colored_label(ui.visuals().error_fg_color, &error.message)
+                .on_hover_ui(|ui| {
+                    ui.label(egui::RichText::new(annotated).monospace());
+                });
+        }
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = Some(index);
+        self.selected_stack.clear();
+
+        let mut frames = Vec::new();
+        self.storage.read(index, |event| {
+            frames = self.resolver.resolve(&event.process, &event.stack);
+            self.selected_process = Some(event.process.unique_id);
+        });
+        self.selected_stack = frames;
+    }
+
+    ///
+    /// Failures are highlighted, the symbolic name and raw value are shown on hover
+    ///
+    fn result_label(ui: &mut egui::Ui, status: NtStatus) {
+        let text = egui::RichText::new(status.to_string());
+        let text = match status.severity() {
+            NtStatusSeverity::Success | NtStatusSeverity::Informational => text,
+            NtStatusSeverity::Warning => text.color(ui.visuals().warn_fg_color),
+            NtStatusSeverity::Error => text.color(ui.visuals().error_fg_color),
+        };
+
+        ui.label(text).on_hover_text(format!(
+            "{} (0x{:08X})",
+            status.name().unwrap_or("Unknown status"),
+            status.code()
+        ));
+    }
+
+    ///
+    /// Fills the cell with the colour of the highlight rule its row matched, text stays readable on it
+    ///
+    fn highlight_cell(ui: &mut egui::Ui, color: Option<Color32>) {
+        let Some(color) = color else {
+            return;
+        };
+
+        ui.painter().rect_filled(ui.max_rect(), 0.0, color);
+
+        let [r, g, b, _] = color.to_array();
+        let luminance = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+        ui.visuals_mut().override_text_color = Some(match luminance > 140.0 {
+            true => Color32::BLACK,
+            false => Color32::WHITE,
+        });
+    }
+
+    ///
+    /// Where highlighted rows sit in the whole view, clicking jumps to the closest one
+    ///
+    fn overview_strip(&mut self, ui: &mut egui::Ui) {
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+        let rows = self.view.len();
+        if rows == 0 {
+            return;
+        }
+
+        //Marks closer than a pixel would be drawn over each other
+        let mut last_y = f32::NEG_INFINITY;
+        for (row, color) in self.highlighted.marks() {
+            let y = rect.top() + (*row as f32 + 0.5) / rows as f32 * rect.height();
+            if y - last_y < 1.0 {
+                continue;
+            }
+            last_y = y;
+
+            painter.rect_filled(
+                egui::Rect::from_x_y_ranges(rect.x_range(), y - 1.0..=y + 1.0),
+                0.0,
+                *color,
+            );
+        }
+
+        if response.clicked() {
+            if let Some(position) = response.interact_pointer_pos() {
+                let row = ((position.y - rect.top()) / rect.height() * rows as f32) as usize;
+                let row = row.min(rows - 1);
+                self.scroll_to = Some(self.highlighted.nearest(row).unwrap_or(row));
+            }
+        }
+        response.on_hover_text("Highlighted events, click to jump to the closest one");
+    }
+
+    fn modules_pane(&self, ui: &mut egui::Ui) {
+        let Some(uid) = self.selected_process else {
+            return;
+        };
+
+        self.runtime.modules().read(uid, |modules| {
+            let modules = modules.map_or(&[][..], |modules| modules.modules());
+            if modules.is_empty() {
+                ui.label("No image loads were seen for this process");
+                return;
+            }
+
+            TableBuilder::new(ui)
+                .striped(true)
+                .resizable(true)
+                .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+                .column(Column::auto().at_least(150.0).resizable(true)) //module
+                .column(Column::auto().at_least(150.0).resizable(true)) //base
+                .column(Column::remainder()) //size
+                .header(20.0, |mut header| {
+                    for name in ["MODULE", "BASE", "SIZE"] {
+                        header.col(|ui| {
+                            ui.label(name);
+                        });
+                    }
+                })
+                .body(|body| {
+                    body.rows(20.0, modules.len(), |mut row| {
+                        let module = &modules[row.index()];
+
+                        row.col(|ui| {
+                            ui.label(&module.name);
+                        });
+                        row.col(|ui| {
+                            ui.label(format!("0x{:016x}", module.base));
+                        });
+                        row.col(|ui| {
+                            ui.label(format!("0x{:x}", module.size));
+                        });
+                    });
+                });
+        });
+    }
+
+    fn stack_pane(&self, ui: &mut egui::Ui) {
+        if self.selected.is_none() {
+            ui.label("Select an event to view its stack");
+            return;
+        }
+
+        if self.selected_stack.is_empty() {
+            ui.label("No stack was captured for this event");
+            return;
+        }
+
+        TableBuilder::new(ui)
+            .striped(true)
+            .resizable(true)
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+            .column(Column::auto().at_least(30.0)) //frame
+            .column(Column::auto().at_least(30.0)) //mode
+            .column(Column::auto().at_least(250.0).resizable(true)) //location
+            .column(Column::remainder()) //address
+            .header(20.0, |mut header| {
+                for name in ["FRAME", "MODE", "LOCATION", "ADDRESS"] {
+                    header.col(|ui| {
+                        ui.label(name);
+                    });
+                }
+            })
+            .body(|body| {
+                body.rows(20.0, self.selected_stack.len(), |mut row| {
+                    let frame = &self.selected_stack[row.index()];
+                    let index = row.index();
+
+                    row.col(|ui| {
+                        ui.label(format!("{}", index));
+                    });
+                    row.col(|ui| {
+                        ui.label(match frame.frame.mode {
+                            FrameMode::Kernel => "K",
+                            FrameMode::User => "U",
+                        });
+                    });
+                    row.col(|ui| {
+                        ui.label(format!("{}", frame));
+                    });
+                    row.col(|ui| {
+                        ui.label(format!("0x{:016x}", frame.frame.address));
+                    });
+                });
+            });
     }
 }
 
 impl eframe::App for ProcmonApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
+        if let Some(filter) = self.filter_dialog.show(ctx, &mut self.filter) {
+            self.compiled_filter = filter;
+            self.reset_view();
+        }
+        if let Some(highlights) = self.highlight_dialog.show(ctx, &mut self.highlights) {
+            self.compiled_highlights = highlights;
+            self.highlighted.reset();
+        }
+        self.export_dialog.show(ctx, |path, format| {
+            ExportJob::start(
+                path,
+                format,
+                self.storage.clone(),
+                self.compiled_filter.clone(),
+                self.query.clone(),
+                self.runtime.cache().clone(),
+            )
+        });
+
+        egui::TopBottomPanel::top("toolbar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Filter...").clicked() {
+                    self.filter_dialog.open = true;
+                }
+                match self.filter.active_rules() {
+                    0 => ui.label("No filter"),
+                    rules => ui.label(format!("{} filter rules", rules)),
+                };
+                if ui.button("Highlight...").clicked() {
+                    self.highlight_dialog.open = true;
+                }
+                match self.highlights.active_rules() {
+                    0 => ui.label("No highlight"),
+                    rules => ui.label(format!("{} highlight rules", rules)),
+                };
+                if ui.button("Export...").clicked() {
+                    self.export_dialog.open = true;
+                }
+                ui.separator();
+                self.search_bar(ui);
+            });
+        });
+
+        let dropped = self.view.refresh(
+            &self.storage,
+            &self.compiled_filter,
+            self.query.as_ref(),
+            self.runtime.cache(),
+        );
+        self.highlighted.drop_rows(dropped);
+        self.highlighted.refresh(
+            &self.view,
+            &self.storage,
+            &self.compiled_highlights,
+            self.runtime.cache(),
+        );
+
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            let statistics = self.runtime.statistics();
+
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Showing {} of {} events",
+                    self.view.len(),
+                    self.storage.len() - self.storage.evicted()
+                ));
+                ui.separator();
+                ui.label(format!("Evicted: {}", self.storage.evicted()));
+                ui.separator();
+                ui.label(format!("Batches: {}", statistics.batches));
+                ui.separator();
+                ui.label(format!("Lost batches: {}", statistics.lost_batches));
+                ui.separator();
+                ui.label(format!("Dropped events: {}", statistics.dropped_events));
+                ui.separator();
+                ui.label(format!(
+                    "Truncated batches: {}",
+                    statistics.truncated_batches + statistics.malformed_batches
+                ));
+                ui.separator();
+                ui.label(format!(
+                    "Reordered batches: {}",
+                    statistics.reordered_batches
+                ));
+                ui.separator();
+                ui.label(format!(
+                    "Duplicate batches: {}",
+                    statistics.duplicate_batches
+                ));
+            });
+        });
+
+        egui::TopBottomPanel::bottom("stack_pane")
+            .resizable(true)
+            .default_height(200.0)
+            .show(ctx, |ui| {
+                ui.columns(2, |columns| {
+                    columns[0].push_id("stack", |ui| {
+                        egui::ScrollArea::horizontal().show(ui, |ui| self.stack_pane(ui));
+                    });
+                    columns[1].push_id("modules", |ui| {
+                        egui::ScrollArea::horizontal().show(ui, |ui| self.modules_pane(ui));
+                    });
+                });
+            });
+
+        egui::SidePanel::right("overview")
+            .resizable(false)
+            .exact_width(14.0)
+            .show(ctx, |ui| self.overview_strip(ui));
+
+        let mut clicked = None;
+        let scroll_to = self.scroll_to.take();
+
         egui::CentralPanel::default().show(ctx, |ui| {
-            TableBuilder::new(ui)
+            let mut table = TableBuilder::new(ui);
+            if let Some(row) = scroll_to {
+                table = table.scroll_to_row(row, Some(egui::Align::Center));
+            }
+
+            table
                 .striped(true)
                 .resizable(true)
+                .sense(egui::Sense::click())
                 .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
                 .column(Column::auto().at_least(50.0).resizable(true)) //id
                 .column(Column::auto().at_least(100.0).resizable(true)) //timestamp
+                .column(Column::auto().at_least(100.0).resizable(true)) //class
                 .column(Column::auto().at_least(100.0).resizable(true)) //operation
                 .column(Column::auto().at_least(100.0).resizable(true)) //process
                 .column(Column::auto().at_least(100.0).resizable(true)) //pid
-                .column(Column::remainder()) //path
+                .column(Column::auto().at_least(300.0).resizable(true)) //path
+                .column(Column::auto().at_least(100.0).resizable(true)) //result
+                .column(Column::remainder()) //detail
                 .header(25.0, |mut header| {
-                    for name in ["ID", "TIMESTAMP", "OPERATION", "PROCESS", "PID", "PATH"] {
+                    for name in [
+                        "ID",
+                        "TIMESTAMP",
+                        "CLASS",
+                        "OPERATION",
+                        "PROCESS",
+                        "PID",
+                        "PATH",
+                        "RESULT",
+                        "DETAIL",
+                    ] {
                         header.col(|ui| {
                             ui.label(name);
                         });
                     }
                 })
                 .body(|body| {
-                    body.rows(25.0, self.storage.len(), |mut row| {
-                        let index = row.index();
+                    body.rows(25.0, self.view.len(), |mut row| {
+                        let Some(index) = self.view.get(row.index()) else {
+                            return;
+                        };
+                        row.set_selected(self.selected == Some(index));
+                        let highlight = match self.selected == Some(index) {
+                            true => None,
+                            false => self.highlighted.color(row.index()),
+                        };
 
                         self.storage.read(index, |event| {
                             //id
                             row.col(|ui| {
+                                Self::highlight_cell(ui, highlight);
                                 ui.label(format!("{}", index));
                             });
 
                             //timepstamp
                             row.col(|ui| {
-                                ui.label(format!(
-                                    "{}",
-                                    Self::filetime_to_datetime(event.event.date)
-                                ));
+                                Self::highlight_cell(ui, highlight);
+                                ui.label(
+                                    filetime_to_datetime(event.event.date)
+                                        .map(|time| time.to_string())
+                                        .unwrap_or_default(),
+                                );
+                            });
+
+                            //class
+                            row.col(|ui| {
+                                Self::highlight_cell(ui, highlight);
+                                ui.label(event_class_to_str(&event.event.operation));
                             });
 
                             //operations
                             row.col(|ui| {
-                                ui.label(Self::event_operation_to_str(&event.event.operation));
+                                Self::highlight_cell(ui, highlight);
+                                ui.label(event_operation_to_str(&event.event.operation));
                             });
 
                             //process
                             row.col(|ui| {
+                                Self::highlight_cell(ui, highlight);
                                 let hit = self.runtime.cache().try_get_and(
                                     event.process.unique_id,
                                     |info| match info {
@@ -91,77 +544,38 @@ impl eframe::App for ProcmonApp {
 
                             //pid
                             row.col(|ui| {
+                                Self::highlight_cell(ui, highlight);
                                 ui.label(format!("{}", event.process.pid));
                             });
 
                             //path
                             row.col(|ui| {
-                                ui.label(format!("{}", event.event.path));
+                                Self::highlight_cell(ui, highlight);
+                                ui.label(event_path(event));
+                            });
+
+                            //result
+                            row.col(|ui| {
+                                Self::highlight_cell(ui, highlight);
+                                Self::result_label(ui, NtStatus::from(event.event.result));
+                            });
+
+                            //detail
+                            row.col(|ui| {
+                                Self::highlight_cell(ui, highlight);
+                                ui.label(event_detail(&event.event.operation));
                             });
                         });
+
+                        if row.response().clicked() {
+                            clicked = Some(index);
+                        }
                     });
                 });
         });
-    }
-}
-
-impl ProcmonApp {
-    fn event_operation_to_str(operation: &EventClass) -> &'static str {
-        match operation {
-            EventClass::Process(event_process_operation) => {
-                Self::process_op_to_str(event_process_operation)
-            }
-            EventClass::FileSystem(event_file_system_operation) => {
-                Self::file_op_to_str(event_file_system_operation)
-            }
-            EventClass::Registry(event_registry_operation) => {
-                Self::registry_op_to_str(event_registry_operation)
-            }
-        }
-    }
-
-    fn process_op_to_str(operation: &EventProcessOperation) -> &'static str {
-        match operation {
-            EventProcessOperation::ProcessCreate { .. } => "Process create",
-            EventProcessOperation::ProcessDestroy { .. } => "Process destroy",
-        }
-    }
-
-    fn file_op_to_str(operation: &EventFileSystemOperation) -> &'static str {
-        match operation {
-            EventFileSystemOperation::Create { .. } => "Create",
-            EventFileSystemOperation::Read { .. } => "Read",
-            EventFileSystemOperation::Write { .. } => "Write",
-            EventFileSystemOperation::Close {} => "Close",
-        }
-    }
 
-    fn registry_op_to_str(operation: &EventRegistryOperation) -> &'static str {
-        match operation {
-            EventRegistryOperation::Open() => "RegOpen",
+        if let Some(index) = clicked {
+            self.select(index);
         }
     }
-
-    fn filetime_to_datetime(filetime: u64) -> DateTime<Utc> {
-        // Windows FILETIME is 100-ns intervals since 1601-01-01
-        // Unix timestamp is seconds since 1970-01-01
-        // The difference between these dates is 11644473600 seconds
-
-        const EPOCH_DIFFERENCE: u64 = 11_644_473_600;
-        const HUNDRED_NS_PER_SEC: u64 = 10_000_000;
-
-        // Convert 100-ns intervals to seconds since 1601
-        let total_secs = filetime / HUNDRED_NS_PER_SEC;
-        // Subtract epoch difference to get Unix timestamp
-        let unix_secs = (total_secs - EPOCH_DIFFERENCE) as i64;
-        // The remaining 100-ns intervals after seconds conversion
-        let subsec_nanos = ((filetime % HUNDRED_NS_PER_SEC) * 100) as u32;
-
-        // Create a NaiveDateTime from these values
-        let naive =
-            NaiveDateTime::from_timestamp_opt(unix_secs, subsec_nanos).expect("Invalid timestamp");
-
-        // Convert to UTC DateTime
-        Utc.from_utc_datetime(&naive)
-    }
 }
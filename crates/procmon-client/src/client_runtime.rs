@@ -1,56 +1,129 @@
 use kmum_common::{
-    process::{ProcessInformation, UniqueProcessId},
-    KmMessage, UmSendMessage,
+    event::{EventClass, EventCompoent, EventSessionOperation, EventStack, SimpleProcessDetails},
+    serializable_ntstring::SerializableNtString,
+    ClientConnectMessage, ClientConnectMode, KmMessage,
 };
-use procmon_core::communication::{
-    driver_communication::DriverCommunication, CommunicationInterface, EventProcessor,
+use nt_string::unicode_string::NtUnicodeString;
+use procmon_core::{
+    communication::{
+        batch::BatchStatisticsSnapshot,
+        driver_communication::DriverCommunication,
+        reconnect::{Backoff, ConnectionObserver, Outage, ReconnectingCommunication},
+        recording::RecordingCommunication,
+        replay::ReplayCommunication,
+        socket::SocketCommunication,
+        CommunicationError, CommunicationInterface,
+    },
+    pipeline::Pipeline,
 };
 use std::{
+    path::Path,
     process::{Child, Command},
     sync::Arc,
 };
 use tokio::task::spawn_blocking;
 
 use crate::{
-    events_storage::EventStorage, fake_communication::FakeCommunication,
-    process_cache::ProcessCache, ProcmonArgs,
+    events_storage::EventStorage, fake_communication::FakeCommunication, pipeline::SinkContext,
+    process_cache::ProcessCache, stack::ProcessModules, ProcmonArgs,
 };
 
+/// Connection attempts made at startup before giving up
+const CONNECT_ATTEMPTS: u32 = 5;
+
 pub struct ClientRuntime {
     internal: Box<dyn ClientRuntimeInterface>,
     num_threads: u32,
     child_process: Option<Child>,
     cache: Arc<ProcessCache>,
+    modules: Arc<ProcessModules>,
+    /// Events come from the driver on this machine
+    local: bool,
 }
 
 impl ClientRuntime {
     pub fn from_args(storage: EventStorage, args: &ProcmonArgs) -> Self {
         let mut tester = None;
+        let modules = Arc::new(ProcessModules::default());
+        let pipeline = args.pipeline.build(
+            modules.clone(),
+            &SinkContext {
+                storage: storage.clone(),
+            },
+        );
+        let record = args.record.as_deref();
         let b: Box<dyn ClientRuntimeInterface> = match args.communication {
-            crate::CommunicationType::Driver => {
-                Box::new(InternalRuntime::new(DriverCommunication::new(), storage))
-            }
-            crate::CommunicationType::Fake => {
-                Box::new(InternalRuntime::new(FakeCommunication::new(), storage))
-            }
+            crate::CommunicationType::Driver => runtime(
+                connect_or_exit(DriverCommunication::new, "the driver", &storage),
+                pipeline,
+                record,
+            ),
+            crate::CommunicationType::Fake => runtime(FakeCommunication::new(), pipeline, record),
             crate::CommunicationType::DriverTest => {
                 let child_proc = Command::new("procmon-tester.exe").spawn().unwrap();
                 let id = child_proc.id();
                 tester = Some(child_proc);
 
-                Box::new(InternalRuntime::new(
-                    DriverCommunication::new_test(id as _),
-                    storage,
-                ))
+                runtime(
+                    connect_or_exit(
+                        move || DriverCommunication::new_test(id as _),
+                        "the driver",
+                        &storage,
+                    ),
+                    pipeline,
+                    record,
+                )
+            }
+            crate::CommunicationType::Socket => {
+                let endpoint = args.connect.clone();
+                runtime(
+                    connect_or_exit(
+                        move || {
+                            SocketCommunication::connect(
+                                &endpoint,
+                                ClientConnectMessage::new(ClientConnectMode::Any),
+                            )
+                        },
+                        "the capture agent",
+                        &storage,
+                    ),
+                    pipeline,
+                    record,
+                )
+            }
+            crate::CommunicationType::Replay => {
+                //clap asks for --replay along with this communication
+                let path = args.replay.as_deref().unwrap_or(Path::new(""));
+                runtime(
+                    ReplayCommunication::open(path, args.replay_speed).unwrap_or_else(|e| {
+                        eprintln!("Failed to open the capture {}: {}", path.display(), e);
+                        std::process::exit(2);
+                    }),
+                    pipeline,
+                    record,
+                )
             }
         };
 
-        let cache = b.create_cache();
+        let local = args.import.is_none()
+            && matches!(
+                args.communication,
+                crate::CommunicationType::Driver | crate::CommunicationType::DriverTest
+            );
+
+        //Imported events bring their processes along, the communication
+        //knows nothing about them
+        let cache = match args.import {
+            Some(_) => ProcessCache::offline(),
+            None => b.create_cache(),
+        };
         Self {
             internal: b,
             num_threads: args.num_threads.get(),
             child_process: tester,
             cache: cache,
+            modules,
+            local,
         }
     }
 
@@ -61,9 +134,22 @@ impl ClientRuntime {
         self.internal.stop();
     }
 
-    pub fn cache(&self) -> &ProcessCache {
+    pub fn cache(&self) -> &Arc<ProcessCache> {
         &self.cache
     }
+
+    pub fn modules(&self) -> &Arc<ProcessModules> {
+        &self.modules
+    }
+
+    /// Processes of the events can be looked at on this machine
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+
+    pub fn statistics(&self) -> BatchStatisticsSnapshot {
+        self.internal.statistics()
+    }
 }
 
 impl Drop for ClientRuntime {
@@ -76,53 +162,81 @@ impl Drop for ClientRuntime {
     }
 }
 
+///
+/// Waits for the driver or agent to come up for a few attempts, later
+/// disconnects are retried for as long as the client runs
+///
+fn connect_or_exit<C, F>(
+    connect: F,
+    peer: &str,
+    storage: &EventStorage,
+) -> ReconnectingCommunication<C, F>
+where
+    C: CommunicationInterface,
+    F: Fn() -> Result<C, CommunicationError> + Send + Sync + 'static,
+{
+    ReconnectingCommunication::connect_retrying(
+        connect,
+        Backoff::default(),
+        CONNECT_ATTEMPTS,
+        OutageMarker(storage.clone()),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to connect to {}: {:?}", peer, e);
+        std::process::exit(2);
+    })
+}
+
+///
+/// Wraps `communication` in a recording first if a capture file was asked for
+///
+fn runtime<C: CommunicationInterface>(
+    communication: C,
+    pipeline: Pipeline,
+    record: Option<&Path>,
+) -> Box<dyn ClientRuntimeInterface> {
+    match record {
+        Some(path) => Box::new(InternalRuntime::new(
+            RecordingCommunication::create(communication, path)
+                .expect("Failed to create the capture file"),
+            pipeline,
+        )),
+        None => Box::new(InternalRuntime::new(communication, pipeline)),
+    }
+}
+
 trait ClientRuntimeInterface {
     fn start(&self, num_threads: u32);
     fn stop(&self);
 
     fn create_cache(&self) -> Arc<ProcessCache>;
+
+    fn statistics(&self) -> BatchStatisticsSnapshot;
 }
 
 struct InternalRuntime<C: CommunicationInterface> {
     communication: Arc<C>,
-    storage: EventStorage,
+    pipeline: Pipeline,
 }
 
 impl<C: CommunicationInterface> InternalRuntime<C> {
-    fn new(communication: C, storage: EventStorage) -> Self {
+    fn new(communication: C, pipeline: Pipeline) -> Self {
         Self {
             communication: Arc::new(communication),
-            storage,
+            pipeline,
         }
     }
 }
 
 impl<C: CommunicationInterface> ClientRuntimeInterface for InternalRuntime<C> {
     fn start(&self, num_threads: u32) {
-        struct Processor {
-            storage: EventStorage,
-        }
-        impl EventProcessor for Processor {
-            fn process<I>(
-                &self,
-                iter: &mut I,
-            ) -> anyhow::Result<(), procmon_core::communication::CommunicationError>
-            where
-                I: Iterator<Item = kmum_common::KmMessage>,
-            {
-                self.storage.push_received(iter);
-                Ok(())
-            }
-        }
-
         for _ in 0..num_threads {
             let communication_clone = self.communication.clone();
-            let storage_clone = self.storage.clone();
+            let pipeline = self.pipeline.clone();
             spawn_blocking(move || {
-                let processor = Processor {
-                    storage: storage_clone,
-                };
-                communication_clone.process_blocking(processor);
+                if let Err(e) = communication_clone.process_blocking(pipeline) {
+                    tracing::error!("Stopped receiving events: {:?}", e);
+                }
             });
         }
     }
@@ -131,26 +245,53 @@ impl<C: CommunicationInterface> ClientRuntimeInterface for InternalRuntime<C> {
         self.communication.stop();
     }
 
+    fn statistics(&self) -> BatchStatisticsSnapshot {
+        self.communication.statistics().snapshot()
+    }
+
     fn create_cache(&self) -> Arc<ProcessCache> {
         let communication = self.communication.clone();
-        ProcessCache::new(move |id| {
-            let msg = communication
-                .send_message_blocking(&UmSendMessage::GetExeName(id))
-                .unwrap_or_else(|_| None);
-
-            if let Some(reply) = msg {
-                match reply {
-                    kmum_common::KmReplyMessage::ExeName(name) => Some(name),
-                    _ => {
-                        tracing::error!(
-                            "Received other type of reply instead of process information"
-                        );
-                        None
-                    }
-                }
-            } else {
-                None
-            }
+        ProcessCache::new(move |ids| {
+            communication
+                .query_exe_names(ids)
+                .inspect_err(|e| {
+                    tracing::error!("Failed to query {} exe names: {:?}", ids.len(), e)
+                })
+                .ok()
         })
     }
 }
+
+const STATUS_CONNECTION_DISCONNECTED: u32 = 0xC000_020C;
+
+///
+/// Leaves a marker event in the storage for every interval spent disconnected
+///
+struct OutageMarker(EventStorage);
+
+impl ConnectionObserver for OutageMarker {
+    fn on_reconnected(&self, outage: &Outage) {
+        let reason = NtUnicodeString::try_from(outage.reason.as_str())
+            .unwrap_or_else(|_| NtUnicodeString::new());
+
+        let marker = KmMessage {
+            event: EventCompoent {
+                date: outage.since,
+                thread: 0,
+                operation: EventClass::Session(EventSessionOperation::Disconnected {
+                    attempts: outage.attempts,
+                }),
+                result: STATUS_CONNECTION_DISCONNECTED as _,
+                path: SerializableNtString::new(reason),
+                duration: outage.until.saturating_sub(outage.since),
+            },
+            process: SimpleProcessDetails {
+                pid: 0,
+                unique_id: 0,
+            },
+            stack: EventStack::new(),
+        };
+
+        self.0.push_received(&mut std::iter::once(marker));
+    }
+}
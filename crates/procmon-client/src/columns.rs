@@ -0,0 +1,623 @@
+//!
+//! What the event list shows for every column, shared by the table and the filters
+//!
+
+use chrono::{DateTime, Utc};
+use kmum_common::{
+    event::{
+        EventClass, EventFileSystemOperation, EventNetworkOperation, EventProcessOperation,
+        EventRegistryOperation, EventSessionOperation, FileLockOperation, FileSetInformation,
+        RegistryValueType,
+    },
+    ntstatus::NtStatus,
+    KmMessage,
+};
+use procmon_core::communication::FILETIME_UNIX_EPOCH_SECONDS;
+use serde::{Deserialize, Serialize};
+use windows_sys::Win32::System::Registry::{REG_CREATED_NEW_KEY, REG_OPENED_EXISTING_KEY};
+
+use crate::process_cache::ProcessCache;
+
+/// Event durations are counted in 100ns units
+const TICKS_PER_SECOND: f64 = 10_000_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Column {
+    Time,
+    Class,
+    Operation,
+    ProcessName,
+    ImagePath,
+    Pid,
+    Thread,
+    Path,
+    Result,
+    Duration,
+    Detail,
+}
+
+///
+/// A column of one event, numbers stay numbers so they can be compared as such
+///
+#[derive(Debug, Clone)]
+pub enum ColumnValue {
+    Text(String),
+    Number(u64),
+    Status(NtStatus),
+}
+
+impl Column {
+    pub const ALL: [Column; 11] = [
+        Column::Time,
+        Column::Class,
+        Column::Operation,
+        Column::ProcessName,
+        Column::ImagePath,
+        Column::Pid,
+        Column::Thread,
+        Column::Path,
+        Column::Result,
+        Column::Duration,
+        Column::Detail,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Time => "Time of Day",
+            Column::Class => "Event Class",
+            Column::Operation => "Operation",
+            Column::ProcessName => "Process Name",
+            Column::ImagePath => "Image Path",
+            Column::Pid => "PID",
+            Column::Thread => "TID",
+            Column::Path => "Path",
+            Column::Result => "Result",
+            Column::Duration => "Duration",
+            Column::Detail => "Detail",
+        }
+    }
+
+    ///
+    /// Parses a value typed for a numeric column, durations are in seconds
+    /// like they are shown, everything else is decimal or `0x` hexadecimal
+    ///
+    pub fn parse_number(&self, input: &str) -> Option<u64> {
+        let input = input.trim();
+        match self {
+            Column::Duration => input
+                .parse::<f64>()
+                .ok()
+                .filter(|seconds| *seconds >= 0.0)
+                .map(|seconds| (seconds * TICKS_PER_SECOND).round() as u64),
+            _ => match input
+                .strip_prefix("0x")
+                .or_else(|| input.strip_prefix("0X"))
+            {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => input.parse().ok(),
+            },
+        }
+    }
+
+    ///
+    /// `None` while the process of the event is still being resolved
+    ///
+    pub fn value(&self, event: &KmMessage, cache: &ProcessCache) -> Option<ColumnValue> {
+        let value = match self {
+            Column::Time => ColumnValue::Text(
+                filetime_to_datetime(event.event.date)
+                    .map(|time| time.to_string())
+                    .unwrap_or_default(),
+            ),
+            Column::Class => {
+                ColumnValue::Text(event_class_to_str(&event.event.operation).to_string())
+            }
+            Column::Operation => {
+                ColumnValue::Text(event_operation_to_str(&event.event.operation).to_string())
+            }
+            Column::ProcessName | Column::ImagePath => {
+                let mut path = None;
+                let hit = cache.try_get_and(event.process.unique_id, |info| {
+                    path = Some(
+                        info.as_ref()
+                            .map(|info| info.to_string())
+                            .unwrap_or_default(),
+                    );
+                });
+                if !hit {
+                    return None;
+                }
+
+                let path = path.unwrap_or_default();
+                match self {
+                    Column::ProcessName => {
+                        ColumnValue::Text(path.rsplit('\\').next().unwrap_or_default().to_string())
+                    }
+                    _ => ColumnValue::Text(path),
+                }
+            }
+            Column::Pid => ColumnValue::Number(event.process.pid),
+            Column::Thread => ColumnValue::Number(event.event.thread),
+            Column::Path => ColumnValue::Text(event_path(event)),
+            Column::Result => ColumnValue::Status(NtStatus::from(event.event.result)),
+            Column::Duration => ColumnValue::Number(event.event.duration),
+            Column::Detail => ColumnValue::Text(event_detail(&event.event.operation)),
+        };
+
+        Some(value)
+    }
+
+    ///
+    /// The value the way the event list shows it
+    ///
+    pub fn text(&self, value: &ColumnValue) -> String {
+        match (self, value) {
+            (Column::Duration, ColumnValue::Number(ticks)) => {
+                format!("{:.7}", *ticks as f64 / TICKS_PER_SECOND)
+            }
+            (_, ColumnValue::Text(text)) => text.clone(),
+            (_, ColumnValue::Number(number)) => number.to_string(),
+            (_, ColumnValue::Status(status)) => status.to_string(),
+        }
+    }
+}
+
+pub fn event_operation_to_str(operation: &EventClass) -> &'static str {
+    match operation {
+        EventClass::Process(event_process_operation) => process_op_to_str(event_process_operation),
+        EventClass::FileSystem(event_file_system_operation) => {
+            file_op_to_str(event_file_system_operation)
+        }
+        EventClass::Registry(event_registry_operation) => {
+            registry_op_to_str(event_registry_operation)
+        }
+        EventClass::Network(event_network_operation) => network_op_to_str(event_network_operation),
+        EventClass::Session(EventSessionOperation::Disconnected { .. }) => "Disconnected",
+    }
+}
+
+pub fn event_class_to_str(operation: &EventClass) -> &'static str {
+    match operation {
+        EventClass::Process(_) => "Process",
+        EventClass::FileSystem(_) => "File System",
+        EventClass::Registry(_) => "Registry",
+        EventClass::Network(_) => "Network",
+        EventClass::Session(_) => "Session",
+    }
+}
+
+fn network_op_to_str(operation: &EventNetworkOperation) -> &'static str {
+    match operation {
+        EventNetworkOperation::TcpConnect { .. } => "TCP Connect",
+        EventNetworkOperation::TcpAccept { .. } => "TCP Accept",
+        EventNetworkOperation::TcpSend { .. } => "TCP Send",
+        EventNetworkOperation::TcpReceive { .. } => "TCP Receive",
+        EventNetworkOperation::TcpDisconnect { .. } => "TCP Disconnect",
+        EventNetworkOperation::UdpSend { .. } => "UDP Send",
+        EventNetworkOperation::UdpReceive { .. } => "UDP Receive",
+    }
+}
+
+///
+/// Network events have no path, their endpoints are shown instead
+///
+pub fn event_path(event: &KmMessage) -> String {
+    match &event.event.operation {
+        EventClass::Network(operation) => format!("{}", operation.endpoints()),
+        _ => format!("{}", event.event.path),
+    }
+}
+
+fn process_op_to_str(operation: &EventProcessOperation) -> &'static str {
+    match operation {
+        EventProcessOperation::ProcessCreate { .. } => "Process create",
+        EventProcessOperation::ProcessDestroy { .. } => "Process destroy",
+        EventProcessOperation::ThreadCreate { .. } => "Thread create",
+        EventProcessOperation::ThreadExit { .. } => "Thread exit",
+        EventProcessOperation::ImageLoad { .. } => "Load image",
+    }
+}
+
+fn file_op_to_str(operation: &EventFileSystemOperation) -> &'static str {
+    match operation {
+        EventFileSystemOperation::Create { .. } => "Create",
+        EventFileSystemOperation::Read { .. } => "Read",
+        EventFileSystemOperation::Write { .. } => "Write",
+        EventFileSystemOperation::Close {} => "Close",
+        EventFileSystemOperation::QueryInformation { .. } => "QueryInformation",
+        EventFileSystemOperation::SetInformation { information } => match information {
+            FileSetInformation::Basic { .. } => "SetBasicInformation",
+            FileSetInformation::Rename { .. } => "SetRenameInformation",
+            FileSetInformation::Disposition { .. } => "SetDispositionInformation",
+            FileSetInformation::EndOfFile { .. } => "SetEndOfFileInformation",
+            FileSetInformation::Allocation { .. } => "SetAllocationInformation",
+            FileSetInformation::Other { .. } => "SetInformation",
+        },
+        EventFileSystemOperation::QueryDirectory { .. } => "QueryDirectory",
+        EventFileSystemOperation::FileSystemControl { .. } => "FileSystemControl",
+        EventFileSystemOperation::LockControl { operation, .. } => match operation {
+            FileLockOperation::Lock => "LockFile",
+            FileLockOperation::UnlockSingle => "UnlockFileSingle",
+            FileLockOperation::UnlockAll => "UnlockFileAll",
+            FileLockOperation::UnlockAllByKey => "UnlockFileByKey",
+            FileLockOperation::Unknown(_) => "LockControl",
+        },
+        EventFileSystemOperation::Cleanup {} => "Cleanup",
+        EventFileSystemOperation::FlushBuffers {} => "FlushBuffers",
+    }
+}
+
+fn registry_op_to_str(operation: &EventRegistryOperation) -> &'static str {
+    match operation {
+        EventRegistryOperation::CreateKey { .. } => "RegCreateKey",
+        EventRegistryOperation::OpenKey { .. } => "RegOpenKey",
+        EventRegistryOperation::QueryKey { .. } => "RegQueryKey",
+        EventRegistryOperation::SetValue { .. } => "RegSetValue",
+        EventRegistryOperation::QueryValue { .. } => "RegQueryValue",
+        EventRegistryOperation::DeleteKey {} => "RegDeleteKey",
+        EventRegistryOperation::DeleteValue { .. } => "RegDeleteValue",
+        EventRegistryOperation::EnumerateKey { .. } => "RegEnumKey",
+        EventRegistryOperation::EnumerateValue { .. } => "RegEnumValue",
+        EventRegistryOperation::RenameKey { .. } => "RegRenameKey",
+        EventRegistryOperation::Flush {} => "RegFlushKey",
+    }
+}
+
+pub fn event_detail(operation: &EventClass) -> String {
+    match operation {
+        EventClass::Process(EventProcessOperation::ProcessCreate { pid, cmd }) => match cmd {
+            Some(cmd) => format!("PID: {}, Command line: {}", pid, cmd),
+            None => format!("PID: {}", pid),
+        },
+        EventClass::Process(EventProcessOperation::ProcessDestroy { pid, exit_status }) => {
+            format!(
+                "PID: {}, Exit Status: {}",
+                pid,
+                NtStatus::from(*exit_status)
+            )
+        }
+        EventClass::Process(EventProcessOperation::ThreadCreate { tid, start_address }) => {
+            format!("Thread ID: {}, Start Address: 0x{:x}", tid, start_address)
+        }
+        EventClass::Process(EventProcessOperation::ThreadExit { tid }) => {
+            format!("Thread ID: {}", tid)
+        }
+        EventClass::Process(EventProcessOperation::ImageLoad { base, size }) => {
+            format!("Image Base: 0x{:x}, Image Size: 0x{:x}", base, size)
+        }
+        EventClass::FileSystem(operation) => file_detail(operation),
+        EventClass::Registry(operation) => registry_detail(operation),
+        EventClass::Network(operation) => match operation.length() {
+            0 => String::new(),
+            length => format!("Length: {}", length),
+        },
+        EventClass::Session(EventSessionOperation::Disconnected { attempts }) => {
+            format!("Reconnect attempts: {}", attempts)
+        }
+    }
+}
+
+fn file_detail(operation: &EventFileSystemOperation) -> String {
+    match operation {
+        EventFileSystemOperation::Create {
+            desired_access,
+            share_mode,
+            disposition,
+            options,
+            attribute,
+            open_action,
+        } => format!(
+            "Desired Access: 0x{:x}, Disposition: {}, Options: 0x{:x}, Attributes: 0x{:x}, ShareMode: {}, OpenResult: {}",
+            desired_access,
+            create_disposition_to_str(*disposition),
+            options,
+            attribute,
+            share_mode_to_string(*share_mode),
+            open_action_to_str(*open_action)
+        ),
+        EventFileSystemOperation::Read { length, offset }
+        | EventFileSystemOperation::Write { length, offset } => {
+            format!("Offset: {}, Length: {}", offset, length)
+        }
+        EventFileSystemOperation::QueryInformation {
+            information_class,
+            length,
+        } => format!("Class: {}, Length: {}", information_class, length),
+        EventFileSystemOperation::SetInformation { information } => match information {
+            FileSetInformation::Basic {
+                creation_time,
+                last_access_time,
+                last_write_time,
+                change_time,
+                attributes,
+            } => format!(
+                "CreationTime: {}, LastAccessTime: {}, LastWriteTime: {}, ChangeTime: {}, FileAttributes: 0x{:x}",
+                creation_time, last_access_time, last_write_time, change_time, attributes
+            ),
+            FileSetInformation::Rename {
+                target,
+                replace_if_exists,
+            } => format!(
+                "ReplaceIfExists: {}, FileName: {}",
+                replace_if_exists, target
+            ),
+            FileSetInformation::Disposition { delete } => format!("Delete: {}", delete),
+            FileSetInformation::EndOfFile { end_of_file } => {
+                format!("EndOfFile: {}", end_of_file)
+            }
+            FileSetInformation::Allocation { allocation_size } => {
+                format!("AllocationSize: {}", allocation_size)
+            }
+            FileSetInformation::Other {
+                information_class,
+                length,
+            } => format!("Class: {}, Length: {}", information_class, length),
+        },
+        EventFileSystemOperation::QueryDirectory {
+            pattern,
+            information_class,
+            length,
+        } => format!(
+            "Filter: {}, Class: {}, Length: {}",
+            pattern, information_class, length
+        ),
+        EventFileSystemOperation::FileSystemControl {
+            control_code,
+            input_length,
+            output_length,
+        } => format!(
+            "Control: 0x{:x}, Input Length: {}, Output Length: {}",
+            control_code, input_length, output_length
+        ),
+        EventFileSystemOperation::LockControl {
+            offset,
+            length,
+            exclusive,
+            ..
+        } => format!(
+            "Offset: {}, Length: {}, Exclusive: {}",
+            offset, length, exclusive
+        ),
+        EventFileSystemOperation::Close {}
+        | EventFileSystemOperation::Cleanup {}
+        | EventFileSystemOperation::FlushBuffers {} => String::new(),
+    }
+}
+
+fn create_disposition_to_str(disposition: u32) -> &'static str {
+    match disposition {
+        0 => "Supersede",
+        1 => "Open",
+        2 => "Create",
+        3 => "OpenIf",
+        4 => "Overwrite",
+        5 => "OverwriteIf",
+        _ => "Unknown",
+    }
+}
+
+fn open_action_to_str(open_action: u32) -> &'static str {
+    match open_action {
+        0 => "Superseded",
+        1 => "Opened",
+        2 => "Created",
+        3 => "Overwritten",
+        4 => "Exists",
+        5 => "DoesNotExist",
+        _ => "Unknown",
+    }
+}
+
+fn share_mode_to_string(share_mode: u16) -> String {
+    let names: Vec<&str> = [(0x1, "Read"), (0x2, "Write"), (0x4, "Delete")]
+        .into_iter()
+        .filter(|(flag, _)| share_mode & flag != 0)
+        .map(|(_, name)| name)
+        .collect();
+
+    if names.is_empty() {
+        "None".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+fn registry_detail(operation: &EventRegistryOperation) -> String {
+    match operation {
+        EventRegistryOperation::CreateKey {
+            desired_access,
+            disposition,
+        } => {
+            let disposition = match *disposition {
+                REG_CREATED_NEW_KEY => "REG_CREATED_NEW_KEY",
+                REG_OPENED_EXISTING_KEY => "REG_OPENED_EXISTING_KEY",
+                _ => "Unknown",
+            };
+            format!(
+                "Desired Access: 0x{:x}, Disposition: {}",
+                desired_access, disposition
+            )
+        }
+        EventRegistryOperation::OpenKey { desired_access } => {
+            format!("Desired Access: 0x{:x}", desired_access)
+        }
+        EventRegistryOperation::QueryKey {
+            information_class,
+            length,
+        } => format!("Query: {}, Length: {}", information_class, length),
+        EventRegistryOperation::SetValue {
+            value_name,
+            value_type,
+            data_size,
+            preview,
+        } => format!(
+            "Value: {}, Type: {}, Length: {}, Data: {}",
+            value_name,
+            value_type.name(),
+            data_size,
+            registry_data_to_string(value_type, preview.bytes())
+        ),
+        EventRegistryOperation::QueryValue {
+            value_name,
+            information_class,
+            length,
+        } => format!(
+            "Value: {}, Query: {}, Length: {}",
+            value_name, information_class, length
+        ),
+        EventRegistryOperation::DeleteKey {} | EventRegistryOperation::Flush {} => String::new(),
+        EventRegistryOperation::DeleteValue { value_name } => {
+            format!("Value: {}", value_name)
+        }
+        EventRegistryOperation::EnumerateKey {
+            index,
+            information_class,
+        }
+        | EventRegistryOperation::EnumerateValue {
+            index,
+            information_class,
+        } => format!("Index: {}, Query: {}", index, information_class),
+        EventRegistryOperation::RenameKey { new_name } => format!("New Name: {}", new_name),
+    }
+}
+
+fn registry_data_to_string(value_type: &RegistryValueType, data: &[u8]) -> String {
+    match value_type {
+        RegistryValueType::String
+        | RegistryValueType::ExpandString
+        | RegistryValueType::MultiString
+        | RegistryValueType::Link => {
+            let wide: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .map(|c| if c == 0 { ' ' as u16 } else { c })
+                .collect();
+            String::from_utf16_lossy(&wide).trim_end().to_string()
+        }
+        RegistryValueType::Dword if data.len() >= 4 => {
+            let value = u32::from_le_bytes(data[..4].try_into().unwrap());
+            format!("0x{:x} ({})", value, value)
+        }
+        RegistryValueType::DwordBigEndian if data.len() >= 4 => {
+            let value = u32::from_be_bytes(data[..4].try_into().unwrap());
+            format!("0x{:x} ({})", value, value)
+        }
+        RegistryValueType::Qword if data.len() >= 8 => {
+            let value = u64::from_le_bytes(data[..8].try_into().unwrap());
+            format!("0x{:x} ({})", value, value)
+        }
+        _ => data
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+///
+/// `None` for FILETIMEs before 1970, imported events can carry any of them
+///
+pub fn filetime_to_datetime(filetime: u64) -> Option<DateTime<Utc>> {
+    // Windows FILETIME is 100-ns intervals since 1601-01-01
+    // Unix timestamp is seconds since 1970-01-01
+
+    const HUNDRED_NS_PER_SEC: u64 = 10_000_000;
+
+    // Convert 100-ns intervals to seconds since 1601
+    let total_secs = filetime / HUNDRED_NS_PER_SEC;
+    // Subtract epoch difference to get Unix timestamp
+    let unix_secs = total_secs.checked_sub(FILETIME_UNIX_EPOCH_SECONDS)? as i64;
+    // The remaining 100-ns intervals after seconds conversion
+    let subsec_nanos = ((filetime % HUNDRED_NS_PER_SEC) * 100) as u32;
+
+    DateTime::from_timestamp(unix_secs, subsec_nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cache, event, read, DATE};
+    use procmon_core::json::JsonlReader;
+    use std::io::Cursor;
+
+    fn text(column: Column, event: &KmMessage, cache: &ProcessCache) -> Option<String> {
+        column.value(event, cache).map(|value| column.text(&value))
+    }
+
+    #[test]
+    fn values_come_from_the_event() {
+        let cache = cache(&[]);
+        let mut event = event(7, read(512), "C:\\file.txt");
+        event.event.duration = 15_000;
+
+        assert_eq!(text(Column::Class, &event, &cache).unwrap(), "File System");
+        assert_eq!(text(Column::Operation, &event, &cache).unwrap(), "Read");
+        assert_eq!(text(Column::Path, &event, &cache).unwrap(), "C:\\file.txt");
+        assert_eq!(text(Column::Pid, &event, &cache).unwrap(), "7");
+        assert_eq!(text(Column::Result, &event, &cache).unwrap(), "SUCCESS");
+        assert_eq!(text(Column::Duration, &event, &cache).unwrap(), "0.0015000");
+        assert_eq!(
+            text(Column::Detail, &event, &cache).unwrap(),
+            "Offset: 0, Length: 512"
+        );
+        assert_eq!(
+            text(Column::Time, &event, &cache).unwrap(),
+            "2024-01-01 00:00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn process_columns_wait_for_the_cache() {
+        let cache = cache(&[(7, "C:\\Windows\\System32\\svchost.exe")]);
+
+        let resolved = event(7, read(1), "");
+        assert_eq!(
+            text(Column::ProcessName, &resolved, &cache).unwrap(),
+            "svchost.exe"
+        );
+        assert_eq!(
+            text(Column::ImagePath, &resolved, &cache).unwrap(),
+            "C:\\Windows\\System32\\svchost.exe"
+        );
+
+        let unresolved = event(8, read(1), "");
+        assert!(Column::ProcessName.value(&unresolved, &cache).is_none());
+        assert!(Column::Pid.value(&unresolved, &cache).is_some());
+    }
+
+    #[test]
+    fn numbers_parse_like_they_are_shown() {
+        assert_eq!(Column::Pid.parse_number(" 0x10 "), Some(16));
+        assert_eq!(Column::Thread.parse_number("12"), Some(12));
+        assert_eq!(Column::Duration.parse_number("0.5"), Some(5_000_000));
+        assert_eq!(Column::Duration.parse_number("-1"), None);
+        assert_eq!(Column::Pid.parse_number("pid"), None);
+    }
+
+    #[test]
+    fn filetime_converts_to_utc() {
+        let time = filetime_to_datetime(DATE + 1_234_567).unwrap();
+        assert_eq!(time.timestamp(), 1_704_067_200);
+        assert_eq!(time.timestamp_subsec_nanos(), 123_456_700);
+    }
+
+    #[test]
+    fn imported_times_before_1970_are_empty() {
+        let line = concat!(
+            r#"{"time":"","filetime":0,"pid":1,"process_uid":1,"tid":1,"#,
+            r#""class":"FileSystem","operation":"Close","details":{},"#,
+            r#""path":"","result":0,"duration":0,"stack":[]}"#
+        );
+        let event = JsonlReader::new(Cursor::new(line))
+            .next()
+            .unwrap()
+            .unwrap()
+            .event;
+
+        assert_eq!(filetime_to_datetime(event.event.date), None);
+        assert_eq!(
+            text(Column::Time, &event, &cache(&[(1, "C:\\a.exe")])),
+            Some(String::new())
+        );
+    }
+}
@@ -1,30 +1,926 @@
-use std::sync::Arc;
+//!
+//! Append-only event store shared by the receiver threads and the UI
+//!
+//! Events live in fixed-size chunks that are never moved, so the index of an
+//! event is its id for as long as it is kept. Writers reserve a range of
+//! slots, fill it without holding any lock and then publish it. Readers only
+//! look at the published length and never wait for a writer.
+//!
+//! With a backing directory, chunks that are full are written to segment
+//! files by a background thread. Written chunks stay in memory until the
+//! memory limit is reached, then the least recently read ones are dropped
+//! and read back from their segment when a row in them is asked for.
+//!
+//! Retention drops the oldest full chunks for good, the chunk directory is
+//! reused round robin so a long running capture never runs out of it
+//!
+
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    fs, hint, io,
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, OnceLock, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use egui::mutex::Mutex;
 use kmum_common::KmMessage;
+use procmon_core::segment::{ChunkLocation, SegmentReader, SegmentWriter};
+
+const CHUNK_BITS: u32 = 12;
+/// Events per chunk
+const CHUNK_SIZE: usize = 1 << CHUNK_BITS;
+/// Chunks the directory has room for, about 268 million events kept at once
+const MAX_CHUNKS: usize = 1 << 16;
+const CAPACITY: usize = CHUNK_SIZE * MAX_CHUNKS;
+
+/// Chunks written to a segment file before the next one is started
+const CHUNKS_PER_SEGMENT: u32 = 64;
+
+/// Spins before a writer waiting on an earlier batch starts yielding
+const SPINS_BEFORE_YIELD: u32 = 64;
+
+/// How often retention by age is applied when no chunk fills up
+const RETENTION_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct StorageConfig {
+    /// Directory full chunks of events are written to, like procmon's backing files.
+    /// Without it every event stays in memory
+    #[arg(long, value_name = "DIR")]
+    pub backing_dir: Option<PathBuf>,
+
+    /// Memory for events that are already in the backing files, in MB
+    #[arg(long, value_name = "MB", default_value = "512")]
+    pub memory_limit: NonZeroU64,
+
+    /// Keeps only about this many of the newest events
+    #[arg(long, value_name = "EVENTS")]
+    pub keep_events: Option<NonZeroUsize>,
+
+    /// Drops events received longer ago than this
+    #[arg(long, value_name = "MINUTES")]
+    pub keep_minutes: Option<NonZeroU64>,
+
+    /// Drops the oldest events once all of them take up more than this, in MB
+    #[arg(long, value_name = "MB")]
+    pub keep_mb: Option<NonZeroU64>,
+}
+
+///
+/// Full chunks are dropped oldest first until every limit is met
+///
+#[derive(Debug, Clone, Copy, Default)]
+struct Retention {
+    events: Option<usize>,
+    age: Option<Duration>,
+    bytes: Option<usize>,
+}
+
+impl Retention {
+    fn from_config(config: &StorageConfig) -> Self {
+        Self {
+            events: config.keep_events.map(NonZeroUsize::get),
+            age: config
+                .keep_minutes
+                .map(|minutes| Duration::from_secs(minutes.get() * 60)),
+            bytes: config.keep_mb.map(|mb| megabytes(mb.get())),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.events.is_some() || self.age.is_some() || self.bytes.is_some()
+    }
+}
+
+fn megabytes(mb: u64) -> usize {
+    usize::try_from(mb * 1024 * 1024).unwrap_or(usize::MAX)
+}
+
+struct Chunk {
+    /// Index of the first event divided by `CHUNK_SIZE`
+    number: usize,
+    slots: Box<[OnceLock<KmMessage>]>,
+}
+
+impl Chunk {
+    fn new(number: usize) -> Arc<Self> {
+        Arc::new(Self {
+            number,
+            slots: (0..CHUNK_SIZE).map(|_| OnceLock::new()).collect(),
+        })
+    }
+
+    fn events(&self) -> impl Iterator<Item = &KmMessage> {
+        self.slots.iter().filter_map(OnceLock::get)
+    }
+}
+
+///
+/// Holds chunk `n` and later chunk `n + MAX_CHUNKS` once `n` is dropped,
+/// both fields are checked against the chunk number that is asked for
+///
+#[derive(Default)]
+struct ChunkEntry {
+    /// Set from the first write until the chunk is evicted or dropped
+    resident: Mutex<Option<Arc<Chunk>>>,
+    /// Set once the chunk is in a segment file
+    spilled: Mutex<Option<Spilled>>,
+    /// `Inner::clock` the last time the chunk was read
+    last_used: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Spilled {
+    number: usize,
+    segment: u32,
+    location: ChunkLocation,
+}
+
+struct Inner {
+    chunks: Box<[ChunkEntry]>,
+    /// Slots handed out to writers, can run ahead of `published`
+    reserved: AtomicUsize,
+    /// Every slot below this is filled
+    published: AtomicUsize,
+    /// Index of the oldest event kept, everything below was dropped by retention
+    first: AtomicUsize,
+    full_reported: AtomicBool,
+    clock: AtomicU64,
+    /// Numbers of the chunks that filled up, for the maintenance thread
+    sealed: OnceLock<mpsc::Sender<usize>>,
+    backing: Option<Backing>,
+}
+
+///
+/// Segment files of a storage, removed again when the storage is dropped
+///
+struct Backing {
+    dir: PathBuf,
+    budget: usize,
+    segments: Mutex<Segments>,
+    /// Opened the first time a chunk is read back from them
+    readers: Mutex<HashMap<u32, SegmentReader<fs::File>>>,
+    cache: Mutex<HotCache>,
+}
+
+#[derive(Default)]
+struct Segments {
+    open: Option<OpenSegment>,
+    /// Segments below this were removed by retention
+    oldest: u32,
+}
+
+struct OpenSegment {
+    segment: u32,
+    chunks: u32,
+    writer: SegmentWriter<fs::File>,
+}
+
+///
+/// Chunks that are both resident and spilled, which are the ones that can be evicted
+///
+#[derive(Default)]
+struct HotCache {
+    chunks: Vec<(usize, usize)>,
+    bytes: usize,
+}
+
+impl Inner {
+    fn new(backing: Option<Backing>) -> Self {
+        Self {
+            chunks: (0..MAX_CHUNKS).map(|_| ChunkEntry::default()).collect(),
+            reserved: AtomicUsize::new(0),
+            published: AtomicUsize::new(0),
+            first: AtomicUsize::new(0),
+            full_reported: AtomicBool::new(false),
+            clock: AtomicU64::new(0),
+            sealed: OnceLock::new(),
+            backing,
+        }
+    }
+
+    fn entry(&self, number: usize) -> &ChunkEntry {
+        &self.chunks[number % MAX_CHUNKS]
+    }
+
+    fn is_dropped(&self, number: usize) -> bool {
+        (number + 1) * CHUNK_SIZE <= self.first.load(Ordering::Acquire)
+    }
+
+    /// Chunk `number`, created by the first writer to reach it
+    fn writable(&self, number: usize) -> Arc<Chunk> {
+        let mut resident = self.entry(number).resident.lock();
+        match &*resident {
+            Some(chunk) if chunk.number == number => chunk.clone(),
+            //Empty or still holding a chunk retention is done with
+            _ => resident.insert(Chunk::new(number)).clone(),
+        }
+    }
+
+    /// Only valid for published chunks
+    fn readable(&self, number: usize) -> Option<Arc<Chunk>> {
+        let entry = self.entry(number);
+        entry.last_used.store(
+            self.clock.fetch_add(1, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+
+        if let Some(chunk) = &*entry.resident.lock() {
+            if chunk.number == number {
+                return Some(chunk.clone());
+            }
+        }
+
+        let backing = self.backing.as_ref()?;
+        let spilled = (*entry.spilled.lock()).filter(|spilled| spilled.number == number)?;
+        match backing.page_in(self, &spilled) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to read chunk {} back from segment {}: {}",
+                    number,
+                    spilled.segment,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    ///
+    /// Makes `start..end` visible once everything before it is, batches are
+    /// published in the order their slots were reserved
+    ///
+    fn publish(&self, start: usize, end: usize) {
+        let mut spins = 0;
+        while self
+            .published
+            .compare_exchange_weak(start, end, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            if spins < SPINS_BEFORE_YIELD {
+                spins += 1;
+                hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+
+        if let Some(sealed) = self.sealed.get() {
+            for number in start / CHUNK_SIZE..end / CHUNK_SIZE {
+                let _ = sealed.send(number);
+            }
+        }
+    }
+
+    ///
+    /// Drops the oldest chunk kept, `first` moves past it before the entry is
+    /// cleared so no reader starts using it again
+    ///
+    fn drop_oldest(&self, number: usize) {
+        self.first
+            .fetch_max((number + 1) * CHUNK_SIZE, Ordering::AcqRel);
+
+        let entry = self.entry(number);
+        {
+            let mut resident = entry.resident.lock();
+            if resident
+                .as_ref()
+                .is_some_and(|chunk| chunk.number == number)
+            {
+                *resident = None;
+            }
+        }
+        {
+            let mut spilled = entry.spilled.lock();
+            if spilled.is_some_and(|spilled| spilled.number == number) {
+                *spilled = None;
+            }
+        }
+
+        if let Some(backing) = &self.backing {
+            backing.forget(number);
+        }
+    }
+}
+
+impl Backing {
+    fn new(dir: &Path, memory_limit: NonZeroU64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            budget: megabytes(memory_limit.get()),
+            segments: Mutex::new(Segments::default()),
+            readers: Mutex::new(HashMap::new()),
+            cache: Mutex::new(HotCache::default()),
+        })
+    }
+
+    fn segment_path(&self, segment: u32) -> PathBuf {
+        self.dir.join(format!(
+            "procmon-{}-{:05}.pmseg",
+            std::process::id(),
+            segment
+        ))
+    }
+
+    ///
+    /// Writes a full chunk to the current segment, it stays resident until evicted
+    ///
+    fn spill(&self, inner: &Inner, chunk: &Chunk) -> io::Result<Spilled> {
+        let spilled = {
+            let mut segments = self.segments.lock();
+            let next = match &segments.open {
+                Some(open) if open.chunks < CHUNKS_PER_SEGMENT => None,
+                Some(open) => Some(open.segment + 1),
+                None => Some(0),
+            };
+            if let Some(segment) = next {
+                segments.open = Some(OpenSegment {
+                    segment,
+                    chunks: 0,
+                    writer: SegmentWriter::create(self.segment_path(segment))?,
+                });
+            }
+
+            let open = segments.open.as_mut().unwrap();
+            let location = open.writer.append(chunk.events())?;
+            open.chunks += 1;
+            Spilled {
+                number: chunk.number,
+                segment: open.segment,
+                location,
+            }
+        };
+
+        *inner.entry(chunk.number).spilled.lock() = Some(spilled);
+        self.cache(inner, chunk.number, resident_size(spilled.location.length));
+        Ok(spilled)
+    }
+
+    ///
+    /// `None` if retention dropped the chunk in the meantime
+    ///
+    fn page_in(&self, inner: &Inner, spilled: &Spilled) -> io::Result<Option<Arc<Chunk>>> {
+        let events = {
+            let mut readers = self.readers.lock();
+            if inner.is_dropped(spilled.number) {
+                return Ok(None);
+            }
+            let reader = match readers.entry(spilled.segment) {
+                Entry::Occupied(reader) => reader.into_mut(),
+                Entry::Vacant(vacant) => {
+                    vacant.insert(SegmentReader::open(self.segment_path(spilled.segment))?)
+                }
+            };
+            reader.read_chunk(&spilled.location)?
+        };
+        if events.len() != CHUNK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk has {} events", events.len()),
+            ));
+        }
+
+        let paged = Arc::new(Chunk {
+            number: spilled.number,
+            slots: events.into_iter().map(OnceLock::from).collect(),
+        });
+        {
+            let mut resident = inner.entry(spilled.number).resident.lock();
+            //Retention clears the entry after moving `first`, checking under the lock is enough
+            if inner.is_dropped(spilled.number) {
+                return Ok(None);
+            }
+            //Another reader might have been quicker
+            if let Some(chunk) = &*resident {
+                if chunk.number == spilled.number {
+                    return Ok(Some(chunk.clone()));
+                }
+            }
+            *resident = Some(paged.clone());
+        }
+
+        self.cache(
+            inner,
+            spilled.number,
+            resident_size(spilled.location.length),
+        );
+        Ok(Some(paged))
+    }
+
+    fn cache(&self, inner: &Inner, number: usize, bytes: usize) {
+        let mut cache = self.cache.lock();
+        cache.chunks.push((number, bytes));
+        cache.bytes += bytes;
+
+        while cache.bytes > self.budget && cache.chunks.len() > 1 {
+            let (position, _) = cache
+                .chunks
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (number, _))| {
+                    inner.entry(*number).last_used.load(Ordering::Relaxed)
+                })
+                .unwrap();
+            let (evicted, bytes) = cache.chunks.swap_remove(position);
+            cache.bytes -= bytes;
+
+            let mut resident = inner.entry(evicted).resident.lock();
+            if resident
+                .as_ref()
+                .is_some_and(|chunk| chunk.number == evicted)
+            {
+                *resident = None;
+            }
+        }
+    }
+
+    /// Retention dropped `number`
+    fn forget(&self, number: usize) {
+        let mut cache = self.cache.lock();
+        if let Some(position) = cache
+            .chunks
+            .iter()
+            .position(|(cached, _)| *cached == number)
+        {
+            let (_, bytes) = cache.chunks.swap_remove(position);
+            cache.bytes -= bytes;
+        }
+    }
+
+    ///
+    /// Removes the segments before `segment`, nothing kept is in them anymore
+    ///
+    fn remove_before(&self, segment: u32) {
+        let mut segments = self.segments.lock();
+        let mut readers = self.readers.lock();
+
+        for old in segments.oldest..segment {
+            readers.remove(&old);
+            if let Err(e) = fs::remove_file(self.segment_path(old)) {
+                tracing::warn!("Failed to remove segment {}: {}", old, e);
+            }
+        }
+        segments.oldest = segments.oldest.max(segment);
+    }
+}
 
-#[derive(Default, Clone)]
+impl Drop for Backing {
+    fn drop(&mut self) {
+        self.readers.lock().clear();
+
+        let mut segments = self.segments.lock();
+        if let Some(open) = segments.open.take() {
+            for segment in segments.oldest..=open.segment {
+                let _ = fs::remove_file(self.segment_path(segment));
+            }
+        }
+    }
+}
+
+/// Slots plus what the events point to, which is about what they take up encoded
+fn resident_size(encoded: u32) -> usize {
+    CHUNK_SIZE * std::mem::size_of::<OnceLock<KmMessage>>() + encoded as usize
+}
+
+/// Counts what `postcard` would write
+#[derive(Default)]
+struct EncodedSize(usize);
+
+impl Extend<u8> for EncodedSize {
+    fn extend<T: IntoIterator<Item = u8>>(&mut self, bytes: T) {
+        self.0 += bytes.into_iter().count();
+    }
+}
+
+fn encoded_size(chunk: &Chunk) -> u32 {
+    let size = chunk.events().fold(EncodedSize::default(), |size, event| {
+        postcard::to_extend(event, size).unwrap_or_default()
+    });
+    u32::try_from(size.0).unwrap_or(u32::MAX)
+}
+
+#[derive(Clone)]
 pub struct EventStorage {
-    events: Arc<Mutex<Vec<KmMessage>>>,
+    inner: Arc<Inner>,
+}
+
+impl Default for EventStorage {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner::new(None)),
+        }
+    }
 }
 
 impl EventStorage {
+    ///
+    /// Starts the thread writing full chunks out and applying retention if
+    /// there is a backing directory or a retention limit
+    ///
+    pub fn new(config: &StorageConfig) -> io::Result<Self> {
+        let retention = Retention::from_config(config);
+        let backing = match &config.backing_dir {
+            Some(dir) => Some(Backing::new(dir, config.memory_limit)?),
+            None => None,
+        };
+        if backing.is_none() && !retention.is_active() {
+            return Ok(Self::default());
+        }
+
+        let inner = Arc::new(Inner::new(backing));
+        let (sender, receiver) = mpsc::channel();
+        let _ = inner.sealed.set(sender);
+
+        let weak = Arc::downgrade(&inner);
+        thread::Builder::new()
+            .name("storage-maintenance".to_string())
+            .spawn(move || maintain(weak, receiver, retention))?;
+
+        Ok(Self { inner })
+    }
+
     pub fn push_received(&self, iter: &mut impl Iterator<Item = KmMessage>) {
-        let mut guard = self.events.lock();
+        let batch: Vec<_> = iter.collect();
+        if batch.is_empty() {
+            return;
+        }
+
+        let start = self
+            .inner
+            .reserved
+            .fetch_add(batch.len(), Ordering::Relaxed);
+        let end = start + batch.len();
 
-        for event in iter {
-            guard.push(event);
+        //`first` only grows, an old value can only make this stricter
+        let limit = self.inner.first.load(Ordering::Acquire) + CAPACITY;
+        let mut current: Option<Arc<Chunk>> = None;
+        for (index, event) in (start..end.min(limit)).zip(batch) {
+            let number = index >> CHUNK_BITS;
+            let chunk = match &current {
+                Some(chunk) if chunk.number == number => chunk,
+                _ => current.insert(self.inner.writable(number)),
+            };
+            let _ = chunk.slots[index & (CHUNK_SIZE - 1)].set(event);
         }
+
+        if end > limit && !self.inner.full_reported.swap(true, Ordering::Relaxed) {
+            tracing::warn!("Event storage is full, new events are dropped");
+        }
+
+        self.inner.publish(start, end);
     }
 
     pub fn read<F: FnOnce(&KmMessage)>(&self, index: usize, f: F) {
-        let guard = self.events.lock();
-        if let Some(evnt) = guard.get(index) {
-            f(evnt);
+        if index < self.evicted() || index >= self.len() {
+            return;
+        }
+        let Some(chunk) = self.inner.readable(index >> CHUNK_BITS) else {
+            return;
+        };
+        if let Some(event) = chunk.slots[index & (CHUNK_SIZE - 1)].get() {
+            f(event);
+        }
+    }
+
+    ///
+    /// Calls `f` with every event from `start` on until it returns false.
+    /// Events pushed while this runs are not visited, dropped ones are skipped
+    ///
+    pub fn read_from<F: FnMut(usize, &KmMessage) -> bool>(&self, start: usize, mut f: F) {
+        let end = self.len();
+        let mut index = start.max(self.evicted());
+
+        while index < end {
+            let offset = index & (CHUNK_SIZE - 1);
+            let count = (CHUNK_SIZE - offset).min(end - index);
+
+            let Some(chunk) = self.inner.readable(index >> CHUNK_BITS) else {
+                index += count;
+                continue;
+            };
+            for slot in &chunk.slots[offset..offset + count] {
+                if let Some(event) = slot.get() {
+                    if !f(index, event) {
+                        return;
+                    }
+                }
+                index += 1;
+            }
         }
     }
 
+    /// Index after the newest event, only ever grows
     pub fn len(&self) -> usize {
-        self.events.lock().len()
+        self.inner.published.load(Ordering::Acquire)
+    }
+
+    /// Events dropped by retention, which is also the index of the oldest one kept
+    pub fn evicted(&self) -> usize {
+        self.inner.first.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+impl EventStorage {
+    /// Forgets the chunk holding `index` as if it could not be read back
+    pub fn lose_chunk(&self, index: usize) {
+        *self.inner.entry(index >> CHUNK_BITS).resident.lock() = None;
+    }
+}
+
+///
+/// A full chunk that was not dropped yet
+///
+struct Kept {
+    number: usize,
+    sealed_at: Instant,
+    bytes: usize,
+    segment: Option<u32>,
+}
+
+fn maintain(inner: Weak<Inner>, sealed: mpsc::Receiver<usize>, retention: Retention) {
+    let mut kept = VecDeque::<Kept>::new();
+    let mut kept_bytes = 0;
+
+    loop {
+        let number = match sealed.recv_timeout(RETENTION_INTERVAL) {
+            Ok(number) => Some(number),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+
+        if let Some(number) = number {
+            //Written past capacity, nothing was stored
+            let Some(chunk) = inner
+                .entry(number)
+                .resident
+                .lock()
+                .clone()
+                .filter(|chunk| chunk.number == number)
+            else {
+                continue;
+            };
+
+            let spilled = inner
+                .backing
+                .as_ref()
+                .map(|backing| backing.spill(&inner, &chunk))
+                .transpose()
+                .unwrap_or_else(|e| {
+                    tracing::error!(
+                        "Failed to write chunk {} to the backing files: {}",
+                        number,
+                        e
+                    );
+                    None
+                });
+            let encoded = match &spilled {
+                Some(spilled) => spilled.location.length,
+                None if retention.bytes.is_some() => encoded_size(&chunk),
+                None => 0,
+            };
+
+            kept_bytes += resident_size(encoded);
+            kept.push_back(Kept {
+                number,
+                sealed_at: Instant::now(),
+                bytes: resident_size(encoded),
+                segment: spilled.map(|spilled| spilled.segment),
+            });
+        }
+
+        let published = inner.published.load(Ordering::Acquire);
+        let mut dropped = false;
+        while let Some(oldest) = kept.front() {
+            let over_count = retention
+                .events
+                .is_some_and(|events| published - (oldest.number + 1) * CHUNK_SIZE >= events);
+            let too_old = retention
+                .age
+                .is_some_and(|age| oldest.sealed_at.elapsed() > age);
+            let over_size = retention.bytes.is_some_and(|bytes| kept_bytes > bytes);
+            if !(over_count || too_old || over_size) {
+                break;
+            }
+
+            inner.drop_oldest(oldest.number);
+            kept_bytes -= oldest.bytes;
+            kept.pop_front();
+            dropped = true;
+        }
+
+        if let (true, Some(backing)) = (dropped, &inner.backing) {
+            let in_use = kept.iter().find_map(|kept| kept.segment).or_else(|| {
+                backing
+                    .segments
+                    .lock()
+                    .open
+                    .as_ref()
+                    .map(|open| open.segment)
+            });
+            if let Some(segment) = in_use {
+                backing.remove_before(segment);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{event, read};
+
+    /// Event `index` carries its own index as the date
+    fn numbered(index: usize) -> KmMessage {
+        let mut event = event(1, read(1), "C:\\a.txt");
+        event.event.date = index as u64;
+        event
+    }
+
+    fn push(storage: &EventStorage, indices: std::ops::Range<usize>) {
+        storage.push_received(&mut indices.map(numbered));
+    }
+
+    fn dates_from(storage: &EventStorage, start: usize) -> Vec<(usize, u64)> {
+        let mut dates = Vec::new();
+        storage.read_from(start, |index, event| {
+            dates.push((index, event.event.date));
+            true
+        });
+        dates
+    }
+
+    fn config(backing_dir: Option<PathBuf>) -> StorageConfig {
+        StorageConfig {
+            backing_dir,
+            memory_limit: NonZeroU64::MIN,
+            keep_events: None,
+            keep_minutes: None,
+            keep_mb: None,
+        }
+    }
+
+    /// Directory of its own per test, removed again on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "procmon-storage-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The maintenance thread works in the background
+    fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting until {}",
+                what
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn events_keep_their_index_across_chunks() {
+        const WRITERS: usize = 4;
+        const BATCH: usize = 100;
+        const BATCHES: usize = 30;
+        let total = WRITERS * BATCH * BATCHES;
+        assert!(total > 2 * CHUNK_SIZE);
+
+        //Writers fill chunks concurrently, every event lands in its own slot
+        let storage = EventStorage::default();
+        thread::scope(|scope| {
+            for writer in 0..WRITERS {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for batch in 0..BATCHES {
+                        let start = (writer * BATCHES + batch) * BATCH;
+                        push(storage, start..start + BATCH);
+                    }
+                });
+            }
+        });
+        assert_eq!(storage.len(), total);
+        assert_eq!(storage.evicted(), 0);
+
+        //Batches are never torn apart, only their order depends on the writers
+        let dates = dates_from(&storage, 0);
+        assert!(dates
+            .chunks(BATCH)
+            .all(|batch| batch.windows(2).all(|pair| pair[1].1 == pair[0].1 + 1)));
+        let mut sorted: Vec<_> = dates.iter().map(|(_, date)| *date).collect();
+        sorted.sort_unstable();
+        assert!(sorted.into_iter().eq(0..total as u64));
+
+        //Index and event stay paired across chunk boundaries
+        let storage = EventStorage::default();
+        push(&storage, 0..CHUNK_SIZE - 1);
+        push(&storage, CHUNK_SIZE - 1..2 * CHUNK_SIZE + 10);
+        assert_eq!(storage.len(), 2 * CHUNK_SIZE + 10);
+
+        let dates = dates_from(&storage, CHUNK_SIZE - 5);
+        assert_eq!(dates.len(), CHUNK_SIZE + 15);
+        assert!(dates.iter().all(|(index, date)| *index as u64 == *date));
+
+        let mut date = None;
+        storage.read(2 * CHUNK_SIZE + 3, |event| date = Some(event.event.date));
+        assert_eq!(date, Some(2 * CHUNK_SIZE as u64 + 3));
+
+        let mut past_end = false;
+        storage.read(storage.len(), |_| past_end = true);
+        assert!(!past_end);
+    }
+
+    #[test]
+    fn spilled_chunks_are_read_back_from_segments() {
+        const CHUNKS: usize = 4;
+        //Two resident chunks are over the smallest limit, the hot cache keeps one
+        assert!(2 * resident_size(0) > megabytes(1));
+
+        let dir = TempDir::new("spill");
+        let storage = EventStorage::new(&config(Some(dir.0.clone()))).unwrap();
+        push(&storage, 0..CHUNKS * CHUNK_SIZE + 10);
+
+        let inner = &storage.inner;
+        wait_until("every full chunk is spilled", || {
+            (0..CHUNKS).all(|number| inner.entry(number).spilled.lock().is_some())
+        });
+        assert!(fs::read_dir(&dir.0).unwrap().count() > 0);
+
+        let evicted: Vec<_> = (0..CHUNKS)
+            .filter(|number| inner.entry(*number).resident.lock().is_none())
+            .collect();
+        assert!(evicted.len() >= CHUNKS - 1, "evicted {:?}", evicted);
+
+        //Paging in evicts another chunk, which is read back again in turn
+        for _ in 0..2 {
+            let dates = dates_from(&storage, 0);
+            assert_eq!(dates.len(), storage.len());
+            assert!(dates.iter().all(|(index, date)| *index as u64 == *date));
+        }
+
+        let mut date = None;
+        storage.read(evicted[0] * CHUNK_SIZE + 7, |event| {
+            date = Some(event.event.date)
+        });
+        assert_eq!(date, Some((evicted[0] * CHUNK_SIZE + 7) as u64));
+    }
+
+    #[test]
+    fn retention_drops_whole_chunks_from_the_front() {
+        let storage = EventStorage::new(&StorageConfig {
+            keep_events: NonZeroUsize::new(CHUNK_SIZE),
+            ..config(None)
+        })
+        .unwrap();
+        push(&storage, 0..4 * CHUNK_SIZE + 10);
+
+        //Chunk 3 still holds the newest full `CHUNK_SIZE` events
+        wait_until("three chunks are dropped", || {
+            storage.evicted() == 3 * CHUNK_SIZE
+        });
+        assert_eq!(storage.len(), 4 * CHUNK_SIZE + 10);
+
+        let dates = dates_from(&storage, 0);
+        assert_eq!(dates.len(), storage.len() - storage.evicted());
+        assert_eq!(dates[0], (storage.evicted(), storage.evicted() as u64));
+        assert!(dates.iter().all(|(index, date)| *index as u64 == *date));
+
+        let mut dropped = false;
+        storage.read(storage.evicted() - 1, |_| dropped = true);
+        assert!(!dropped);
+
+        //Ids keep counting up after the drop
+        push(&storage, storage.len()..storage.len() + 5);
+        let mut date = None;
+        storage.read(storage.len() - 1, |event| date = Some(event.event.date));
+        assert_eq!(date, Some(4 * CHUNK_SIZE as u64 + 14));
     }
 }
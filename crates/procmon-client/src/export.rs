@@ -0,0 +1,478 @@
+//!
+//! CSV or JSON Lines export of the events the filter and search query let
+//! through. A background thread reads them straight from the storage and
+//! writes each row as it goes, nothing is collected in memory first
+//!
+
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use kmum_common::{process::UniqueProcessId, KmMessage};
+use procmon_core::{json::JsonlWriter, query::Query};
+
+use crate::{
+    columns::{filetime_to_datetime, Column, ColumnValue},
+    events_storage::EventStorage,
+    filter::CompiledFilter,
+    process_cache::ProcessCache,
+};
+
+/// The columns of a procmon CSV, in its order
+pub const PROCMON_COLUMNS: [Column; 9] = [
+    Column::Time,
+    Column::ProcessName,
+    Column::Pid,
+    Column::Operation,
+    Column::Path,
+    Column::Result,
+    Column::Detail,
+    Column::Duration,
+    Column::Thread,
+];
+
+/// Every column that can be exported, in the order they are written
+pub const EXPORT_COLUMNS: [Column; 11] = [
+    Column::Time,
+    Column::ProcessName,
+    Column::Pid,
+    Column::Operation,
+    Column::Path,
+    Column::Result,
+    Column::Detail,
+    Column::Duration,
+    Column::Thread,
+    Column::Class,
+    Column::ImagePath,
+];
+
+/// Time of Day like procmon writes it, `9:30:12.1234567 AM`. chrono has no
+/// 7 digit fraction so it is added between the seconds and AM/PM
+const TIME_OF_DAY_FORMAT: &str = "%-I:%M:%S";
+const MERIDIEM_FORMAT: &str = "%p";
+const FILETIME_PER_SECOND: u64 = 10_000_000;
+
+/// How long an export waits for a process before its rows are written without it
+const PROCESS_TIMEOUT: Duration = Duration::from_secs(5);
+const PROCESS_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv(Vec<Column>),
+    /// Every field of the events, see `procmon_core::json`
+    JsonLines,
+}
+
+#[derive(Debug, Default)]
+pub struct ExportProgress {
+    /// Events looked at
+    pub scanned: AtomicUsize,
+    /// Rows written
+    pub written: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+///
+/// An export running in the background
+///
+pub struct ExportJob {
+    progress: Arc<ExportProgress>,
+    /// Events to look at, the ones received after the export started are left out
+    pub total: usize,
+    handle: Option<JoinHandle<csv::Result<usize>>>,
+}
+
+impl ExportJob {
+    ///
+    /// Creates the file right away so a bad path is reported before anything runs
+    ///
+    pub fn start(
+        path: &Path,
+        format: ExportFormat,
+        storage: EventStorage,
+        filter: CompiledFilter,
+        query: Option<Query>,
+        cache: Arc<ProcessCache>,
+    ) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let progress = Arc::new(ExportProgress::default());
+        let total = storage.len() - storage.evicted();
+
+        let thread_progress = progress.clone();
+        let handle = thread::Builder::new()
+            .name("export".to_string())
+            .spawn(move || {
+                let rows = Rows::new(filter, query, cache, thread_progress);
+                match format {
+                    ExportFormat::Csv(columns) => {
+                        rows.write_csv(csv::Writer::from_writer(file), &columns, &storage)
+                    }
+                    ExportFormat::JsonLines => rows.write_jsonl(JsonlWriter::new(file), &storage),
+                }
+            })?;
+
+        Ok(Self {
+            progress,
+            total,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn progress(&self) -> &ExportProgress {
+        &self.progress
+    }
+
+    /// Rows that made it to the file so far are kept
+    pub fn cancel(&self) {
+        self.progress.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    ///
+    /// The number of rows written once the export is over
+    ///
+    pub fn finished(&mut self) -> Option<csv::Result<usize>> {
+        if !self.handle.as_ref()?.is_finished() {
+            return None;
+        }
+
+        let result =
+            self.handle.take()?.join().unwrap_or_else(|_| {
+                Err(csv::Error::from(io::Error::other("export thread panicked")))
+            });
+        Some(result)
+    }
+}
+
+struct Rows {
+    filter: CompiledFilter,
+    query: Option<Query>,
+    cache: Arc<ProcessCache>,
+    progress: Arc<ExportProgress>,
+    timeout: Duration,
+    /// Processes that were not resolved in time, their later rows do not wait again
+    timed_out: RefCell<HashSet<UniqueProcessId>>,
+}
+
+impl Rows {
+    fn new(
+        filter: CompiledFilter,
+        query: Option<Query>,
+        cache: Arc<ProcessCache>,
+        progress: Arc<ExportProgress>,
+    ) -> Self {
+        Self {
+            filter,
+            query,
+            cache,
+            progress,
+            timeout: PROCESS_TIMEOUT,
+            timed_out: RefCell::default(),
+        }
+    }
+
+    fn write_csv<W: io::Write>(
+        &self,
+        mut writer: csv::Writer<W>,
+        columns: &[Column],
+        storage: &EventStorage,
+    ) -> csv::Result<usize> {
+        writer.write_record(columns.iter().map(|column| column.name()))?;
+
+        let mut record = Vec::with_capacity(columns.len());
+        let written = self.write(storage, |event| {
+            record.clear();
+            record.extend(columns.iter().map(|column| self.field(*column, event)));
+            writer.write_record(&record)
+        })?;
+
+        writer.flush()?;
+        Ok(written)
+    }
+
+    fn write_jsonl<W: io::Write>(
+        &self,
+        mut writer: JsonlWriter<W>,
+        storage: &EventStorage,
+    ) -> csv::Result<usize> {
+        let written = self.write(storage, |event| {
+            let image = self.resolved(event, || {
+                let mut image = None;
+                self.cache
+                    .try_get_and(event.process.unique_id, |name| image = Some(name.clone()));
+                image
+            });
+            Ok(writer.write(event, image.flatten().as_ref())?)
+        })?;
+
+        writer.flush()?;
+        Ok(written)
+    }
+
+    ///
+    /// Hands `row` the events that match, stops at the first error
+    ///
+    fn write<F>(&self, storage: &EventStorage, mut row: F) -> csv::Result<usize>
+    where
+        F: FnMut(&KmMessage) -> csv::Result<()>,
+    {
+        let end = storage.len();
+        let mut written = 0;
+        let mut result = Ok(());
+
+        storage.read_from(0, |index, event| {
+            if index >= end || self.progress.cancelled.load(Ordering::Relaxed) {
+                return false;
+            }
+            self.progress.scanned.fetch_add(1, Ordering::Relaxed);
+
+            if !self.matches(event) {
+                return true;
+            }
+
+            if let Err(e) = row(event) {
+                result = Err(e);
+                return false;
+            }
+
+            written += 1;
+            self.progress.written.store(written, Ordering::Relaxed);
+            true
+        });
+
+        result.map(|_| written)
+    }
+
+    /// Events whose process never resolves are left out if the filter needs it
+    fn matches(&self, event: &KmMessage) -> bool {
+        let matched = self.resolved(event, || match self.filter.matches(event, &self.cache) {
+            Some(true) => self
+                .query
+                .as_ref()
+                .map_or(Some(true), |query| query.matches(event, &*self.cache)),
+            other => other,
+        });
+
+        matched.unwrap_or(false)
+    }
+
+    fn field(&self, column: Column, event: &KmMessage) -> String {
+        let Some(value) = self.resolved(event, || column.value(event, &self.cache)) else {
+            return String::new();
+        };
+
+        match (column, value) {
+            (Column::Time, _) => {
+                filetime_to_datetime(event.event.date).map_or_else(String::new, |time| {
+                    let time = time.with_timezone(&Local);
+                    format!(
+                        "{}.{:07} {}",
+                        time.format(TIME_OF_DAY_FORMAT),
+                        event.event.date % FILETIME_PER_SECOND,
+                        time.format(MERIDIEM_FORMAT)
+                    )
+                })
+            }
+            (_, ColumnValue::Text(text)) => text,
+            (column, value) => column.text(&value),
+        }
+    }
+
+    ///
+    /// Waits for the process of an event to be resolved, unlike the event list
+    /// an export can not come back to a row later
+    ///
+    fn resolved<T>(&self, event: &KmMessage, mut f: impl FnMut() -> Option<T>) -> Option<T> {
+        let uid = event.process.unique_id;
+        if self.timed_out.borrow().contains(&uid) {
+            return f();
+        }
+
+        let started = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return Some(value);
+            }
+            if started.elapsed() > self.timeout {
+                tracing::warn!("Process {} was not resolved, exporting without it", uid);
+                self.timed_out.borrow_mut().insert(uid);
+                return None;
+            }
+            thread::sleep(PROCESS_POLL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        filter::{FilterAction, FilterRule, FilterSet, Relation},
+        test_support::{cache, event, read, DATE},
+    };
+
+    fn rows(filter: CompiledFilter, query: Option<Query>, cache: Arc<ProcessCache>) -> Rows {
+        Rows::new(filter, query, cache, Arc::default())
+    }
+
+    fn storage(events: impl IntoIterator<Item = KmMessage>) -> EventStorage {
+        let storage = EventStorage::default();
+        storage.push_received(&mut events.into_iter());
+        storage
+    }
+
+    fn csv(rows: &Rows, columns: &[Column], storage: &EventStorage) -> Vec<Vec<String>> {
+        let mut out = Vec::new();
+        rows.write_csv(csv::Writer::from_writer(&mut out), columns, storage)
+            .unwrap();
+
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(out.as_slice())
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_row_per_event() {
+        let cache = cache(&[(7, "C:\\Windows\\System32\\svchost.exe")]);
+        let storage = storage([event(7, read(512), "C:\\file, \"quoted\".txt")]);
+        let rows = rows(CompiledFilter::default(), None, cache);
+
+        let mut out = Vec::new();
+        let written = rows
+            .write_csv(
+                csv::Writer::from_writer(&mut out),
+                &PROCMON_COLUMNS,
+                &storage,
+            )
+            .unwrap();
+        assert_eq!(written, 1);
+
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next(),
+            Some("Time of Day,Process Name,PID,Operation,Path,Result,Detail,Duration,TID")
+        );
+        let row = lines.next().unwrap();
+        assert!(row.ends_with(
+            ",svchost.exe,7,Read,\"C:\\file, \"\"quoted\"\".txt\",SUCCESS,\"Offset: 0, Length: 512\",0.0000000,1"
+        ));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn only_the_selected_columns_are_written() {
+        let storage = storage([event(7, read(1), "C:\\a.txt")]);
+        let rows = rows(CompiledFilter::default(), None, cache(&[]));
+
+        assert_eq!(
+            csv(&rows, &[Column::Path, Column::Class], &storage),
+            [["Path", "Event Class"], ["C:\\a.txt", "File System"]]
+        );
+    }
+
+    #[test]
+    fn filter_and_query_pick_the_rows() {
+        let storage = storage([
+            event(1, read(1), "C:\\a.dll"),
+            event(2, read(1), "C:\\b.dll"),
+            event(1, read(1), "C:\\c.txt"),
+            event(1, read(1), "C:\\d.dll"),
+        ]);
+        let filter = CompiledFilter::compile(&FilterSet {
+            rules: vec![FilterRule {
+                column: Column::Path,
+                relation: Relation::EndsWith,
+                value: ".dll".to_string(),
+                action: FilterAction::Include,
+                enabled: true,
+            }],
+        })
+        .unwrap();
+        let query = Query::parse("pid == 1").unwrap();
+        let rows = rows(filter, Some(query), cache(&[]));
+
+        assert_eq!(
+            csv(&rows, &[Column::Path], &storage),
+            [["Path"], ["C:\\a.dll"], ["C:\\d.dll"]]
+        );
+        assert_eq!(rows.progress.scanned.load(Ordering::Relaxed), 4);
+        assert_eq!(rows.progress.written.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn time_of_day_has_seven_fraction_digits() {
+        let mut event = event(1, read(1), "");
+        event.event.date = DATE + 1_234_567;
+        let rows = rows(CompiledFilter::default(), None, cache(&[]));
+
+        let midnight = Local.from_utc_datetime(
+            &chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        );
+        assert_eq!(
+            rows.field(Column::Time, &event),
+            format!(
+                "{}.1234567 {}",
+                midnight.format("%-I:%M:%S"),
+                midnight.format("%p")
+            )
+        );
+    }
+
+    #[test]
+    fn json_lines_carry_the_image_path() {
+        let storage = storage([event(7, read(1), "C:\\a.txt"), event(8, read(1), "")]);
+        let rows = Rows {
+            timeout: Duration::from_millis(100),
+            ..rows(
+                CompiledFilter::default(),
+                None,
+                cache(&[(7, "C:\\tool.exe")]),
+            )
+        };
+
+        let mut out = Vec::new();
+        rows.write_jsonl(JsonlWriter::new(&mut out), &storage)
+            .unwrap();
+
+        let images = procmon_core::json::JsonlReader::new(out.as_slice())
+            .map(|record| record.unwrap().image.map(|image| image.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(images, [Some("C:\\tool.exe".to_string()), None]);
+    }
+
+    #[test]
+    fn missing_processes_are_waited_for_once() {
+        let storage = storage((0..3).map(|_| event(9, read(1), "C:\\a.txt")));
+        let rows = Rows {
+            timeout: Duration::from_millis(300),
+            ..rows(CompiledFilter::default(), None, cache(&[]))
+        };
+
+        let started = Instant::now();
+        assert_eq!(
+            csv(&rows, &[Column::ProcessName, Column::ImagePath], &storage),
+            [["Process Name", "Image Path"], ["", ""], ["", ""], ["", ""]]
+        );
+        //One timeout for the whole export rather than one per field
+        assert!(started.elapsed() < 2 * rows.timeout);
+    }
+}
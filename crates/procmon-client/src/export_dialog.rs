@@ -0,0 +1,166 @@
+use std::{io, path::Path, sync::atomic::Ordering, time::Duration};
+
+use crate::export::{ExportFormat, ExportJob, EXPORT_COLUMNS, PROCMON_COLUMNS};
+
+const DEFAULT_EXPORT_PATH: &str = "events.csv";
+const CSV_EXTENSION: &str = "csv";
+const JSON_LINES_EXTENSION: &str = "jsonl";
+
+/// Progress is redrawn this often while an export runs
+const PROGRESS_REFRESH: Duration = Duration::from_millis(100);
+
+///
+/// Window picking the file, format and columns of an export and following its progress
+///
+pub struct ExportDialog {
+    pub open: bool,
+    path: String,
+    /// CSV otherwise, columns only apply to it
+    json_lines: bool,
+    /// Same order as `EXPORT_COLUMNS`
+    selected: [bool; EXPORT_COLUMNS.len()],
+    job: Option<ExportJob>,
+    /// How the last export went
+    message: Option<String>,
+}
+
+impl Default for ExportDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            path: DEFAULT_EXPORT_PATH.to_string(),
+            json_lines: false,
+            selected: EXPORT_COLUMNS.map(|column| PROCMON_COLUMNS.contains(&column)),
+            job: None,
+            message: None,
+        }
+    }
+}
+
+impl ExportDialog {
+    ///
+    /// `start` is called with the file and the format when the export is asked for
+    ///
+    pub fn show<S>(&mut self, ctx: &egui::Context, start: S)
+    where
+        S: FnOnce(&Path, ExportFormat) -> io::Result<ExportJob>,
+    {
+        self.poll_job();
+        if !self.open {
+            return;
+        }
+
+        let mut open = self.open;
+        let mut requested = false;
+
+        egui::Window::new("Export")
+            .open(&mut open)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                ui.label("Events shown with the current filter and search are exported");
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.path);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Format:");
+                    let csv = ui.radio_value(&mut self.json_lines, false, "CSV");
+                    let json_lines = ui.radio_value(&mut self.json_lines, true, "JSON Lines");
+                    if csv.changed() || json_lines.changed() {
+                        self.swap_extension();
+                    }
+                });
+
+                ui.separator();
+                ui.add_enabled_ui(!self.json_lines, |ui| {
+                    egui::Grid::new("export_columns")
+                        .num_columns(3)
+                        .show(ui, |ui| {
+                            for (index, column) in EXPORT_COLUMNS.iter().enumerate() {
+                                ui.checkbox(&mut self.selected[index], column.name());
+                                if index % 3 == 2 {
+                                    ui.end_row();
+                                }
+                            }
+                        });
+                });
+                ui.separator();
+
+                match &self.job {
+                    Some(job) => {
+                        let progress = job.progress();
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "Looked at {} of {} events, {} written",
+                                progress.scanned.load(Ordering::Relaxed),
+                                job.total,
+                                progress.written.load(Ordering::Relaxed)
+                            ));
+                            if ui.button("Cancel").clicked() {
+                                job.cancel();
+                            }
+                        });
+                        ctx.request_repaint_after(PROGRESS_REFRESH);
+                    }
+                    None => {
+                        let any = self.json_lines || self.selected.iter().any(|selected| *selected);
+                        requested = ui.add_enabled(any, egui::Button::new("Export")).clicked();
+                    }
+                }
+
+                if let Some(message) = &self.message {
+                    ui.label(message);
+                }
+            });
+        self.open = open;
+
+        if requested {
+            let format = if self.json_lines {
+                ExportFormat::JsonLines
+            } else {
+                ExportFormat::Csv(
+                    EXPORT_COLUMNS
+                        .iter()
+                        .zip(self.selected)
+                        .filter(|(_, selected)| *selected)
+                        .map(|(column, _)| *column)
+                        .collect(),
+                )
+            };
+
+            match start(Path::new(&self.path), format) {
+                Ok(job) => {
+                    self.job = Some(job);
+                    self.message = None;
+                }
+                Err(e) => self.message = Some(format!("Failed to create {}: {}", self.path, e)),
+            }
+        }
+    }
+
+    /// Only a file named after the other format is renamed
+    fn swap_extension(&mut self) {
+        let (from, to) = if self.json_lines {
+            (CSV_EXTENSION, JSON_LINES_EXTENSION)
+        } else {
+            (JSON_LINES_EXTENSION, CSV_EXTENSION)
+        };
+
+        let path = Path::new(&self.path);
+        if path.extension().is_some_and(|extension| extension == from) {
+            self.path = path.with_extension(to).to_string_lossy().into_owned();
+        }
+    }
+
+    fn poll_job(&mut self) {
+        let Some(result) = self.job.as_mut().and_then(ExportJob::finished) else {
+            return;
+        };
+
+        self.job = None;
+        self.message = Some(match result {
+            Ok(rows) => format!("Wrote {} events to {}", rows, self.path),
+            Err(e) => format!("Export to {} failed: {}", self.path, e),
+        });
+    }
+}
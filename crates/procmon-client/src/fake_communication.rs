@@ -1,20 +1,53 @@
 use std::{
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU16, Ordering},
     time::Duration,
 };
 
 use kmum_common::{
-    event::*, process::ProcessInformation, serializable_ntstring::SerializableNtString, *,
+    batch::BatchBuilder,
+    event::*,
+    handshake::{negotiate, BufferSizes, EventCapabilities},
+    ntstatus::{NtStatus, NTSTATUS_TABLE},
+    serializable_ntstring::SerializableNtString,
+    *,
 };
 use nt_string::unicode_string::NtUnicodeString;
-use procmon_core::communication::{CommunicationError, CommunicationInterface};
+use procmon_core::communication::{
+    batch::BatchStatistics, BatchHandler, CommunicationError, CommunicationInterface,
+};
 use rand::Rng;
 use windows_sys::Win32::{
     Foundation::FILETIME, System::SystemInformation::GetSystemTimeAsFileTime,
 };
 
+const KERNEL_BASE: u64 = 0xFFFF_F800_0000_0000;
+/// Fake user frames and image loads share this range so stacks resolve to the fake modules
+const USER_BASE: u64 = 0x0000_7FF6_0000_0000;
+const FAKE_MODULE_SIZE: u64 = 0x0010_0000;
+const FAKE_MODULES: [&str; 16] = [
+    "ntdll.dll",
+    "kernel32.dll",
+    "kernelbase.dll",
+    "user32.dll",
+    "gdi32.dll",
+    "advapi32.dll",
+    "ws2_32.dll",
+    "ole32.dll",
+    "combase.dll",
+    "rpcrt4.dll",
+    "shell32.dll",
+    "msvcrt.dll",
+    "ucrtbase.dll",
+    "bcrypt.dll",
+    "crypt32.dll",
+    "sechost.dll",
+];
+
 pub struct FakeCommunication {
     stop_signal: AtomicBool,
+    connect_message: ClientConnectMessage,
+    next_worker: AtomicU16,
+    statistics: BatchStatistics,
 }
 
 impl CommunicationInterface for FakeCommunication {
@@ -25,28 +58,70 @@ impl CommunicationInterface for FakeCommunication {
     {
         match message {
             UmSendMessage::GetExeName(pid) => {
-                let path = format!("Process{}.exe", pid);
-                Ok(Some(KmReplyMessage::ExeName(SerializableNtString(
-                    NtUnicodeString::try_from(&path).unwrap(),
-                ))))
+                Ok(Some(KmReplyMessage::ExeName(Self::exe_name(*pid))))
             }
+            UmSendMessage::GetExeNameBatch(pids) => Ok(Some(KmReplyMessage::ExeNameBatch(
+                pids.iter().map(|pid| Some(Self::exe_name(*pid))).collect(),
+            ))),
+            UmSendMessage::Handshake => Ok(Some(KmReplyMessage::Handshake(negotiate(
+                &self.connect_message,
+                EventCapabilities::all(),
+                BufferSizes::current(),
+            )))),
             _ => Err(CommunicationError::Port),
         }
     }
 
-    fn process_blocking<P: procmon_core::communication::EventProcessor>(&self, processor: P) {
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> anyhow::Result<(), CommunicationError> {
+        let worker = self.next_worker.fetch_add(1, Ordering::Relaxed);
+        let mut sequence = 0;
+        //Events too large for an empty batch, reported like the driver does
+        let mut dropped = 0;
+        let mut buffer = vec![0u8; MAX_KM_MESSAGE_RECEIVE_SIZE];
+
         loop {
-            if self.stop_signal.load(Ordering::Acquire) {
+            if self.stop_signal.load(Ordering::Acquire) || handler.is_cancelled() {
                 break;
             }
 
             let events = Self::generate_random_events();
             tracing::info!("Generated {} number of new events", events.len());
-            let mut iter = events.into_iter();
-            let _ = processor.process(&mut iter);
+
+            let mut builder = BatchBuilder::new();
+            for event in events {
+                if builder.try_push(&mut buffer, &event).is_ok() {
+                    continue;
+                }
+
+                if !builder.is_empty() {
+                    let batch = builder.finish(&mut buffer, worker, sequence, dropped);
+                    let _ = handler.handle_batch(batch);
+                    sequence += 1;
+                }
+
+                if builder.try_push(&mut buffer, &event).is_err() {
+                    tracing::error!("Generated event does not fit in an empty batch");
+                    dropped += 1;
+                }
+            }
+
+            if !builder.is_empty() {
+                let batch = builder.finish(&mut buffer, worker, sequence, dropped);
+                let _ = handler.handle_batch(batch);
+                sequence += 1;
+            }
 
             std::thread::sleep(Duration::from_secs(1));
         }
+
+        Ok(())
+    }
+
+    fn statistics(&self) -> &BatchStatistics {
+        &self.statistics
     }
 
     fn stop(&self) {
@@ -55,9 +130,17 @@ impl CommunicationInterface for FakeCommunication {
 }
 
 impl FakeCommunication {
+    fn exe_name(pid: u64) -> SerializableNtString {
+        let path = format!("Process{}.exe", pid);
+        SerializableNtString(NtUnicodeString::try_from(&path).unwrap())
+    }
+
     pub fn new() -> Self {
         Self {
             stop_signal: AtomicBool::new(false),
+            connect_message: ClientConnectMessage::new(ClientConnectMode::Any),
+            next_worker: AtomicU16::new(0),
+            statistics: BatchStatistics::default(),
         }
     }
 
@@ -74,19 +157,28 @@ impl FakeCommunication {
 
             let process_details = SimpleProcessDetails { pid, unique_id };
 
+            let operation = Self::generate_random_event_class(&mut rng);
+            let path = match &operation {
+                EventClass::Process(EventProcessOperation::ImageLoad { base, .. }) => {
+                    let module = FAKE_MODULES[((base - USER_BASE) / FAKE_MODULE_SIZE) as usize];
+                    format!("\\Device\\HarddiskVolume3\\Windows\\System32\\{}", module)
+                }
+                _ => "Some path".to_string(),
+            };
+
             let event_component = EventCompoent {
                 date: get_system_time_as_file_time(), // Random date
                 thread: rng.gen_range(1..100),        // Random thread ID
-                operation: Self::generate_random_event_class(&mut rng),
-                result: rng.gen_range(-1..=1), // Random result (-1, 0, or 1)
-                path: SerializableNtString::new(NtUnicodeString::try_from("Some path").unwrap()), // Placeholder for path
+                operation,
+                result: Self::generate_random_result(&mut rng),
+                path: SerializableNtString::new(NtUnicodeString::try_from(path).unwrap()),
                 duration: rng.gen(), // Random duration
             };
 
             let event = KmMessage {
                 event: event_component,
                 process: process_details,
-                stack: EventStack {}, // Placeholder for stack
+                stack: Self::generate_random_stack(&mut rng),
             };
 
             events.push(event);
@@ -95,35 +187,203 @@ impl FakeCommunication {
         events
     }
 
+    /// Mostly successes, failures are picked from the known statuses
+    fn generate_random_result<R: Rng>(rng: &mut R) -> i32 {
+        if rng.gen_bool(0.7) {
+            return NtStatus::SUCCESS.0;
+        }
+
+        NTSTATUS_TABLE[rng.gen_range(0..NTSTATUS_TABLE.len())].code as i32
+    }
+
+    /// Generates a random `EventStack`, kernel frames first and a few repeated frames
+    fn generate_random_stack<R: Rng>(rng: &mut R) -> EventStack {
+        let mut stack = EventStack::new();
+
+        for _ in 0..rng.gen_range(2..=8) {
+            stack.try_push(StackFrame {
+                address: KERNEL_BASE + rng.gen_range(0..0x0100_0000),
+                mode: FrameMode::Kernel,
+            });
+        }
+
+        for _ in 0..rng.gen_range(0..=16) {
+            let frame = match stack.frames().last() {
+                Some(last) if last.mode == FrameMode::User && rng.gen_bool(0.2) => *last,
+                _ => StackFrame {
+                    address: USER_BASE + rng.gen_range(0..0x0100_0000),
+                    mode: FrameMode::User,
+                },
+            };
+            stack.try_push(frame);
+        }
+
+        stack
+    }
+
     /// Generates a random `EventClass`
     fn generate_random_event_class<R: Rng>(rng: &mut R) -> EventClass {
-        match rng.gen_range(0..=2) {
+        match rng.gen_range(0..=3) {
             0 => EventClass::Process(Self::generate_random_process_operation(rng)),
             1 => EventClass::FileSystem(Self::generate_random_filesystem_operation(rng)),
-            2 => EventClass::Registry(EventRegistryOperation::Open()), // Placeholder for registry operations
+            2 => EventClass::Registry(Self::generate_random_registry_operation(rng)),
+            3 => EventClass::Network(Self::generate_random_network_operation(rng)),
             _ => unreachable!(),
         }
     }
 
     /// Generates a random `EventProcessOperation`
     fn generate_random_process_operation<R: Rng>(rng: &mut R) -> EventProcessOperation {
-        match rng.gen_range(0..=1) {
+        match rng.gen_range(0..=4) {
             0 => EventProcessOperation::ProcessCreate {
                 pid: rng.gen_range(1..=30),
                 cmd: None, // Placeholder for command
             },
             1 => EventProcessOperation::ProcessDestroy {
                 pid: rng.gen_range(1..=30),
+                exit_status: rng.gen_range(-1..=1),
+            },
+            2 => EventProcessOperation::ThreadCreate {
+                tid: rng.gen_range(1..100),
+                start_address: USER_BASE + rng.gen_range(0..0x0100_0000),
+            },
+            3 => EventProcessOperation::ThreadExit {
+                tid: rng.gen_range(1..100),
+            },
+            4 => EventProcessOperation::ImageLoad {
+                base: USER_BASE + rng.gen_range(0..FAKE_MODULES.len() as u64) * FAKE_MODULE_SIZE,
+                size: FAKE_MODULE_SIZE,
             },
             _ => unreachable!(),
         }
     }
 
+    /// Generates a random `EventNetworkOperation`
+    fn generate_random_network_operation<R: Rng>(rng: &mut R) -> EventNetworkOperation {
+        let random_ip = |rng: &mut R| {
+            if rng.gen_bool(0.8) {
+                IpAddress::V4(rng.gen())
+            } else {
+                IpAddress::V6(rng.gen())
+            }
+        };
+
+        let local_ip = random_ip(rng);
+        let remote_ip = match local_ip {
+            IpAddress::V4(_) => IpAddress::V4(rng.gen()),
+            IpAddress::V6(_) => IpAddress::V6(rng.gen()),
+        };
+
+        let endpoints = NetworkEndpoints {
+            local: SocketAddress {
+                ip: local_ip,
+                port: rng.gen_range(49152..=65535),
+            },
+            remote: SocketAddress {
+                ip: remote_ip,
+                port: [53, 80, 443, 445, 3389][rng.gen_range(0..5)],
+            },
+        };
+        let length = rng.gen_range(1..=65535);
+
+        match rng.gen_range(0..=6) {
+            0 => EventNetworkOperation::TcpConnect { endpoints },
+            1 => EventNetworkOperation::TcpAccept { endpoints },
+            2 => EventNetworkOperation::TcpSend { endpoints, length },
+            3 => EventNetworkOperation::TcpReceive { endpoints, length },
+            4 => EventNetworkOperation::TcpDisconnect { endpoints },
+            5 => EventNetworkOperation::UdpSend { endpoints, length },
+            6 => EventNetworkOperation::UdpReceive { endpoints, length },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Generates a random `EventRegistryOperation`
+    fn generate_random_registry_operation<R: Rng>(rng: &mut R) -> EventRegistryOperation {
+        const VALUE_NAMES: [&str; 4] = ["(Default)", "InstallPath", "Enabled", "LastUpdate"];
+
+        let value_name = SerializableNtString::new(
+            NtUnicodeString::try_from(VALUE_NAMES[rng.gen_range(0..VALUE_NAMES.len())]).unwrap(),
+        );
+
+        match rng.gen_range(0..=10) {
+            0 => EventRegistryOperation::CreateKey {
+                desired_access: rng.gen(),
+                disposition: rng.gen_range(1..=2),
+            },
+            1 => EventRegistryOperation::OpenKey {
+                desired_access: rng.gen(),
+            },
+            2 => EventRegistryOperation::QueryKey {
+                information_class: rng.gen_range(0..=7),
+                length: rng.gen_range(0..=1024),
+            },
+            3 => {
+                let (value_type, data) = match rng.gen_range(0..=3) {
+                    0 => {
+                        let data: Vec<u8> = "C:\\Program Files\\Some App\0"
+                            .encode_utf16()
+                            .flat_map(u16::to_le_bytes)
+                            .collect();
+                        (RegistryValueType::String, data)
+                    }
+                    1 => (
+                        RegistryValueType::Dword,
+                        rng.gen::<u32>().to_le_bytes().to_vec(),
+                    ),
+                    2 => (
+                        RegistryValueType::Qword,
+                        rng.gen::<u64>().to_le_bytes().to_vec(),
+                    ),
+                    3 => {
+                        let data: Vec<u8> = (0..rng.gen_range(0..=64)).map(|_| rng.gen()).collect();
+                        (RegistryValueType::Binary, data)
+                    }
+                    _ => unreachable!(),
+                };
+
+                EventRegistryOperation::SetValue {
+                    value_name,
+                    value_type,
+                    data_size: data.len() as _,
+                    preview: RegistryDataPreview::new(&data),
+                }
+            }
+            4 => EventRegistryOperation::QueryValue {
+                value_name,
+                information_class: rng.gen_range(0..=2),
+                length: rng.gen_range(0..=1024),
+            },
+            5 => EventRegistryOperation::DeleteKey {},
+            6 => EventRegistryOperation::DeleteValue { value_name },
+            7 => EventRegistryOperation::EnumerateKey {
+                index: rng.gen_range(0..=32),
+                information_class: rng.gen_range(0..=7),
+            },
+            8 => EventRegistryOperation::EnumerateValue {
+                index: rng.gen_range(0..=32),
+                information_class: rng.gen_range(0..=2),
+            },
+            9 => EventRegistryOperation::RenameKey {
+                new_name: SerializableNtString::new(
+                    NtUnicodeString::try_from("Renamed key").unwrap(),
+                ),
+            },
+            10 => EventRegistryOperation::Flush {},
+            _ => unreachable!(),
+        }
+    }
+
     /// Generates a random `EventFileSystemOperation`
     fn generate_random_filesystem_operation<R: Rng>(rng: &mut R) -> EventFileSystemOperation {
-        match rng.gen_range(0..=3) {
+        match rng.gen_range(0..=10) {
             0 => EventFileSystemOperation::Create {
+                desired_access: rng.gen(),
+                share_mode: rng.gen_range(0..=7),
+                disposition: rng.gen_range(0..=5),
+                options: rng.gen_range(0..=0x00FF_FFFF),
                 attribute: rng.gen(),
+                open_action: rng.gen_range(0..=5),
             },
             1 => EventFileSystemOperation::Read {
                 length: rng.gen(),
@@ -134,6 +394,62 @@ impl FakeCommunication {
                 offset: rng.gen(),
             },
             3 => EventFileSystemOperation::Close {},
+            4 => EventFileSystemOperation::QueryInformation {
+                information_class: rng.gen_range(1..=80),
+                length: rng.gen_range(0..=4096),
+            },
+            5 => EventFileSystemOperation::SetInformation {
+                information: Self::generate_random_set_information(rng),
+            },
+            6 => EventFileSystemOperation::QueryDirectory {
+                pattern: SerializableNtString::new(NtUnicodeString::try_from("*.dll").unwrap()),
+                information_class: rng.gen_range(1..=80),
+                length: rng.gen_range(0..=65536),
+            },
+            7 => EventFileSystemOperation::FileSystemControl {
+                control_code: rng.gen(),
+                input_length: rng.gen_range(0..=4096),
+                output_length: rng.gen_range(0..=4096),
+            },
+            8 => EventFileSystemOperation::LockControl {
+                operation: FileLockOperation::from_minor_function(rng.gen_range(1..=4)),
+                offset: rng.gen_range(0..=i64::MAX),
+                length: rng.gen_range(0..=i64::MAX),
+                exclusive: rng.gen(),
+            },
+            9 => EventFileSystemOperation::Cleanup {},
+            10 => EventFileSystemOperation::FlushBuffers {},
+            _ => unreachable!(),
+        }
+    }
+
+    /// Generates a random `FileSetInformation`
+    fn generate_random_set_information<R: Rng>(rng: &mut R) -> FileSetInformation {
+        match rng.gen_range(0..=5) {
+            0 => FileSetInformation::Basic {
+                creation_time: rng.gen_range(0..=i64::MAX),
+                last_access_time: rng.gen_range(0..=i64::MAX),
+                last_write_time: rng.gen_range(0..=i64::MAX),
+                change_time: rng.gen_range(0..=i64::MAX),
+                attributes: rng.gen(),
+            },
+            1 => FileSetInformation::Rename {
+                target: SerializableNtString::new(
+                    NtUnicodeString::try_from("\\??\\C:\\Some renamed path").unwrap(),
+                ),
+                replace_if_exists: rng.gen(),
+            },
+            2 => FileSetInformation::Disposition { delete: rng.gen() },
+            3 => FileSetInformation::EndOfFile {
+                end_of_file: rng.gen_range(0..=i64::MAX),
+            },
+            4 => FileSetInformation::Allocation {
+                allocation_size: rng.gen_range(0..=i64::MAX),
+            },
+            5 => FileSetInformation::Other {
+                information_class: rng.gen_range(1..=80),
+                length: rng.gen_range(0..=4096),
+            },
             _ => unreachable!(),
         }
     }
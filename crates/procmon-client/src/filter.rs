@@ -0,0 +1,576 @@
+//!
+//! Include/exclude rules over the event list columns.
+//!
+//! An event is hidden as soon as one exclude rule matches. If there are include
+//! rules, every column they name needs at least one of its include rules to match,
+//! rules on the same column are alternatives and rules on different columns all apply
+//!
+
+use std::{fs, io, path::Path};
+
+use kmum_common::{ntstatus::NtStatus, KmMessage};
+use procmon_core::query::Query;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    columns::{Column, ColumnValue},
+    events_storage::EventStorage,
+    process_cache::ProcessCache,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Relation {
+    Is,
+    IsNot,
+    LessThan,
+    MoreThan,
+    BeginsWith,
+    EndsWith,
+    Contains,
+    Excludes,
+}
+
+impl Relation {
+    pub const ALL: [Relation; 8] = [
+        Relation::Is,
+        Relation::IsNot,
+        Relation::LessThan,
+        Relation::MoreThan,
+        Relation::BeginsWith,
+        Relation::EndsWith,
+        Relation::Contains,
+        Relation::Excludes,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Relation::Is => "is",
+            Relation::IsNot => "is not",
+            Relation::LessThan => "less than",
+            Relation::MoreThan => "more than",
+            Relation::BeginsWith => "begins with",
+            Relation::EndsWith => "ends with",
+            Relation::Contains => "contains",
+            Relation::Excludes => "excludes",
+        }
+    }
+
+    /// Relations that compare values rather than text
+    fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Relation::Is | Relation::IsNot | Relation::LessThan | Relation::MoreThan
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+impl FilterAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterAction::Include => "Include",
+            FilterAction::Exclude => "Exclude",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterRule {
+    pub column: Column,
+    pub relation: Relation,
+    pub value: String,
+    pub action: FilterAction,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+///
+/// Rules as the user wrote them, saved and loaded as JSON
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FilterSet {
+    pub rules: Vec<FilterRule>,
+}
+
+impl FilterSet {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn active_rules(&self) -> usize {
+        self.rules.iter().filter(|rule| rule.enabled).count()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    /// Index of the offending rule in its set
+    pub rule: usize,
+    pub message: String,
+}
+
+///
+/// The value of a rule parsed for its column
+///
+#[derive(Debug, Clone)]
+enum RuleValue {
+    /// Lowercase, text comparisons ignore case
+    Text(String),
+    Number(u64),
+    Status(NtStatus),
+}
+
+///
+/// One column, relation and value condition, shared by filter and highlight rules
+///
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    column: Column,
+    relation: Relation,
+    value: RuleValue,
+}
+
+impl Matcher {
+    pub fn new(column: Column, relation: Relation, value: &str) -> Result<Self, String> {
+        let text = value.trim();
+
+        let value = match column {
+            _ if !relation.is_comparison() => RuleValue::Text(text.to_lowercase()),
+            Column::Result => RuleValue::Status(
+                NtStatus::parse(text).ok_or_else(|| format!("Unknown status: {}", text))?,
+            ),
+            Column::Pid | Column::Thread | Column::Duration => RuleValue::Number(
+                column
+                    .parse_number(text)
+                    .ok_or_else(|| format!("{} needs a number: {}", column.name(), text))?,
+            ),
+            _ => RuleValue::Text(text.to_lowercase()),
+        };
+
+        Ok(Self {
+            column,
+            relation,
+            value,
+        })
+    }
+
+    /// `None` if the column needs the process of the event and it is not resolved yet
+    pub fn matches_event(&self, event: &KmMessage, cache: &ProcessCache) -> Option<bool> {
+        Some(self.matches(&self.column.value(event, cache)?))
+    }
+
+    fn matches(&self, value: &ColumnValue) -> bool {
+        match (&self.value, value) {
+            (RuleValue::Number(expected), ColumnValue::Number(actual)) => {
+                compare(self.relation, actual.cmp(expected))
+            }
+            (RuleValue::Status(expected), ColumnValue::Status(actual)) => {
+                compare(self.relation, actual.code().cmp(&expected.code()))
+            }
+            (RuleValue::Text(expected), ColumnValue::Status(status)) => {
+                //Either the text shown or the symbolic name may be typed
+                let shown = status.to_string().to_lowercase();
+                let name = status.name().unwrap_or_default().to_lowercase();
+                match self.relation {
+                    Relation::Excludes => {
+                        !text_matches(Relation::Contains, &shown, expected)
+                            && !text_matches(Relation::Contains, &name, expected)
+                    }
+                    relation => {
+                        text_matches(relation, &shown, expected)
+                            || text_matches(relation, &name, expected)
+                    }
+                }
+            }
+            (RuleValue::Text(expected), value) => {
+                let actual = self.column.text(value).to_lowercase();
+                text_matches(self.relation, &actual, expected)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn compare(relation: Relation, ordering: std::cmp::Ordering) -> bool {
+    match relation {
+        Relation::Is => ordering.is_eq(),
+        Relation::IsNot => ordering.is_ne(),
+        Relation::LessThan => ordering.is_lt(),
+        Relation::MoreThan => ordering.is_gt(),
+        _ => false,
+    }
+}
+
+fn text_matches(relation: Relation, actual: &str, expected: &str) -> bool {
+    match relation {
+        Relation::BeginsWith => actual.starts_with(expected),
+        Relation::EndsWith => actual.ends_with(expected),
+        Relation::Contains => actual.contains(expected),
+        Relation::Excludes => !actual.contains(expected),
+        comparison => compare(comparison, actual.cmp(expected)),
+    }
+}
+
+///
+/// A `FilterSet` ready to be run over events, disabled rules are left out
+///
+#[derive(Debug, Clone, Default)]
+pub struct CompiledFilter {
+    /// Include rules grouped by column
+    includes: Vec<(Column, Vec<Matcher>)>,
+    excludes: Vec<Matcher>,
+}
+
+impl CompiledFilter {
+    pub fn compile(set: &FilterSet) -> Result<Self, FilterError> {
+        let mut filter = CompiledFilter::default();
+
+        for (index, rule) in set.rules.iter().enumerate() {
+            if !rule.enabled {
+                continue;
+            }
+
+            let matcher =
+                Matcher::new(rule.column, rule.relation, &rule.value).map_err(|message| {
+                    FilterError {
+                        rule: index,
+                        message,
+                    }
+                })?;
+
+            match rule.action {
+                FilterAction::Exclude => filter.excludes.push(matcher),
+                FilterAction::Include => {
+                    match filter
+                        .includes
+                        .iter_mut()
+                        .find(|(column, _)| *column == rule.column)
+                    {
+                        Some((_, matchers)) => matchers.push(matcher),
+                        None => filter.includes.push((rule.column, vec![matcher])),
+                    }
+                }
+            }
+        }
+
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.includes.is_empty() && self.excludes.is_empty()
+    }
+
+    ///
+    /// `None` if a rule needs the process of the event and it is not resolved yet
+    ///
+    pub fn matches(&self, event: &KmMessage, cache: &ProcessCache) -> Option<bool> {
+        for matcher in &self.excludes {
+            if matcher.matches_event(event, cache)? {
+                return Some(false);
+            }
+        }
+
+        for (column, matchers) in &self.includes {
+            let value = column.value(event, cache)?;
+            if !matchers.iter().any(|matcher| matcher.matches(&value)) {
+                return Some(false);
+            }
+        }
+
+        Some(true)
+    }
+}
+
+/// Events looked at per frame, keeps the window responsive while a large capture is filtered
+const MAX_SCAN_PER_REFRESH: usize = 200_000;
+
+/// Events ahead of a stalled scan whose processes are asked for at once
+const PROCESS_PREFETCH: usize = 256;
+
+///
+/// Storage indices of the events the filter and the search query let through, extended as events arrive
+///
+#[derive(Default)]
+pub struct FilteredView {
+    indices: Vec<usize>,
+    /// Every event below this was looked at
+    scanned: usize,
+    /// Storage index of the first row while unfiltered, events before it were evicted
+    first: usize,
+    /// No rules, every event is shown and `indices` stays empty
+    unfiltered: bool,
+}
+
+impl FilteredView {
+    /// Starts over, after the rules or the query changed
+    pub fn reset(&mut self) {
+        self.indices.clear();
+        self.scanned = 0;
+    }
+
+    ///
+    /// Looks at the events received since the last refresh. An event whose process
+    /// is not resolved yet stops the scan, it is looked at again on the next refresh.
+    ///
+    /// Returns how many rows were dropped from the top because their events were evicted
+    ///
+    pub fn refresh(
+        &mut self,
+        storage: &EventStorage,
+        filter: &CompiledFilter,
+        query: Option<&Query>,
+        cache: &ProcessCache,
+    ) -> usize {
+        self.unfiltered = filter.is_empty() && query.is_none();
+        let dropped = self.drop_evicted(storage.evicted());
+        if self.unfiltered {
+            self.scanned = storage.len();
+            return dropped;
+        }
+
+        let mut stalled = None;
+        let end = self.scanned + MAX_SCAN_PER_REFRESH;
+        storage.read_from(self.scanned, |index, event| {
+            if index >= end {
+                return false;
+            }
+
+            let matched = match filter.matches(event, cache) {
+                Some(true) => query.map_or(Some(true), |query| query.matches(event, cache)),
+                other => other,
+            };
+            match matched {
+                Some(true) => self.indices.push(index),
+                Some(false) => {}
+                None => {
+                    stalled = Some(index);
+                    return false;
+                }
+            }
+            self.scanned = index + 1;
+            true
+        });
+
+        //Resolve the processes of what comes next in one go instead of one per refresh
+        if let Some(stalled) = stalled {
+            storage.read_from(stalled, |index, event| {
+                cache.try_get_and(event.process.unique_id, |_| {});
+                index < stalled + PROCESS_PREFETCH
+            });
+        }
+
+        dropped
+    }
+
+    fn drop_evicted(&mut self, evicted: usize) -> usize {
+        let dropped = match self.unfiltered {
+            true => evicted.saturating_sub(self.first),
+            false => {
+                let dropped = self.indices.partition_point(|index| *index < evicted);
+                self.indices.drain(..dropped);
+                dropped
+            }
+        };
+        self.first = self.first.max(evicted);
+        self.scanned = self.scanned.max(evicted);
+
+        dropped
+    }
+
+    pub fn len(&self) -> usize {
+        match self.unfiltered {
+            true => self.scanned - self.first,
+            false => self.indices.len(),
+        }
+    }
+
+    /// Storage index of the event shown on `row`
+    pub fn get(&self, row: usize) -> Option<usize> {
+        match self.unfiltered {
+            true => Some(self.first + row).filter(|index| *index < self.scanned),
+            false => self.indices.get(row).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cache, event, read};
+
+    fn rule(column: Column, relation: Relation, value: &str, action: FilterAction) -> FilterRule {
+        FilterRule {
+            column,
+            relation,
+            value: value.to_string(),
+            action,
+            enabled: true,
+        }
+    }
+
+    fn compile(rules: Vec<FilterRule>) -> CompiledFilter {
+        CompiledFilter::compile(&FilterSet { rules }).unwrap()
+    }
+
+    #[test]
+    fn relations_match_text_and_numbers() {
+        let cache = cache(&[]);
+        let event = event(42, read(4096), "C:\\Windows\\System32\\ntdll.dll");
+
+        let cases = [
+            (
+                Column::Path,
+                Relation::Is,
+                "c:\\windows\\system32\\NTDLL.dll",
+                true,
+            ),
+            (Column::Path, Relation::BeginsWith, "C:\\Windows", true),
+            (Column::Path, Relation::EndsWith, ".exe", false),
+            (Column::Path, Relation::Contains, "system32", true),
+            (Column::Path, Relation::Excludes, "system32", false),
+            (Column::Operation, Relation::IsNot, "Write", true),
+            (Column::Pid, Relation::Is, "0x2a", true),
+            (Column::Pid, Relation::LessThan, "42", false),
+            (Column::Pid, Relation::MoreThan, "41", true),
+            (Column::Result, Relation::Is, "SUCCESS", true),
+            (Column::Result, Relation::Contains, "success", true),
+        ];
+
+        for (column, relation, value, expected) in cases {
+            let matcher = Matcher::new(column, relation, value).unwrap();
+            assert_eq!(
+                matcher.matches_event(&event, &cache),
+                Some(expected),
+                "{} {} {}",
+                column.name(),
+                relation.name(),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_values_point_at_their_rule() {
+        let set = FilterSet {
+            rules: vec![
+                rule(Column::Path, Relation::Contains, "x", FilterAction::Include),
+                rule(Column::Pid, Relation::Is, "many", FilterAction::Include),
+            ],
+        };
+
+        assert_eq!(CompiledFilter::compile(&set).unwrap_err().rule, 1);
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let cache = cache(&[]);
+        let filter = compile(vec![
+            rule(
+                Column::Path,
+                Relation::EndsWith,
+                ".dll",
+                FilterAction::Include,
+            ),
+            rule(
+                Column::Path,
+                Relation::Contains,
+                "ntdll",
+                FilterAction::Exclude,
+            ),
+        ]);
+
+        assert_eq!(
+            filter.matches(&event(1, read(1), "C:\\kernel32.dll"), &cache),
+            Some(true)
+        );
+        assert_eq!(
+            filter.matches(&event(1, read(1), "C:\\ntdll.dll"), &cache),
+            Some(false)
+        );
+        assert_eq!(
+            filter.matches(&event(1, read(1), "C:\\notepad.exe"), &cache),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn includes_on_one_column_are_alternatives() {
+        let cache = cache(&[]);
+        let filter = compile(vec![
+            rule(Column::Pid, Relation::Is, "1", FilterAction::Include),
+            rule(Column::Pid, Relation::Is, "2", FilterAction::Include),
+            rule(
+                Column::Path,
+                Relation::EndsWith,
+                ".txt",
+                FilterAction::Include,
+            ),
+        ]);
+
+        assert_eq!(
+            filter.matches(&event(1, read(1), "a.txt"), &cache),
+            Some(true)
+        );
+        assert_eq!(
+            filter.matches(&event(2, read(1), "b.txt"), &cache),
+            Some(true)
+        );
+        assert_eq!(
+            filter.matches(&event(3, read(1), "c.txt"), &cache),
+            Some(false)
+        );
+        assert_eq!(
+            filter.matches(&event(1, read(1), "a.log"), &cache),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn disabled_rules_are_left_out() {
+        let mut exclude = rule(Column::Pid, Relation::Is, "1", FilterAction::Exclude);
+        exclude.enabled = false;
+
+        let filter = compile(vec![exclude]);
+        assert!(filter.is_empty());
+        assert_eq!(
+            filter.matches(&event(1, read(1), "a"), &cache(&[])),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn unresolved_process_is_not_decided() {
+        let cache = cache(&[(1, "C:\\Windows\\notepad.exe")]);
+        let filter = compile(vec![rule(
+            Column::ProcessName,
+            Relation::Is,
+            "notepad.exe",
+            FilterAction::Include,
+        )]);
+
+        assert_eq!(filter.matches(&event(1, read(1), "a"), &cache), Some(true));
+        assert_eq!(filter.matches(&event(2, read(1), "a"), &cache), None);
+
+        //Rules that do not need the process still decide right away
+        let by_pid = compile(vec![rule(
+            Column::Pid,
+            Relation::Is,
+            "2",
+            FilterAction::Exclude,
+        )]);
+        assert_eq!(by_pid.matches(&event(2, read(1), "a"), &cache), Some(false));
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    columns::Column,
+    filter::{CompiledFilter, FilterAction, FilterRule, FilterSet, Relation},
+};
+
+const DEFAULT_FILTER_PATH: &str = "filter.json";
+
+///
+/// Window editing the rules of a `FilterSet`, changes apply right away
+///
+pub struct FilterDialog {
+    pub open: bool,
+    /// The rule being put together in the top row
+    draft: FilterRule,
+    path: String,
+    /// Last problem with the rules or with the file
+    message: Option<String>,
+}
+
+impl Default for FilterDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            draft: FilterRule {
+                column: Column::ProcessName,
+                relation: Relation::Is,
+                value: String::new(),
+                action: FilterAction::Include,
+                enabled: true,
+            },
+            path: DEFAULT_FILTER_PATH.to_string(),
+            message: None,
+        }
+    }
+}
+
+impl FilterDialog {
+    ///
+    /// Returns the filter to use from now on if the rules were changed into a valid set
+    ///
+    pub fn show(&mut self, ctx: &egui::Context, set: &mut FilterSet) -> Option<CompiledFilter> {
+        if !self.open {
+            return None;
+        }
+
+        let mut changed = false;
+        let mut open = self.open;
+
+        egui::Window::new("Filter")
+            .open(&mut open)
+            .default_width(700.0)
+            .show(ctx, |ui| {
+                changed |= self.draft_row(ui, set);
+                ui.separator();
+                changed |= Self::rule_list(ui, set);
+                ui.separator();
+                changed |= self.file_row(ui, set);
+
+                if let Some(message) = &self.message {
+                    ui.colored_label(ui.visuals().error_fg_color, message);
+                }
+            });
+        self.open = open;
+
+        if !changed {
+            return None;
+        }
+
+        match CompiledFilter::compile(set) {
+            Ok(filter) => {
+                self.message = None;
+                Some(filter)
+            }
+            Err(e) => {
+                self.message = Some(format!("Rule {}: {}", e.rule + 1, e.message));
+                None
+            }
+        }
+    }
+
+    fn draft_row(&mut self, ui: &mut egui::Ui, set: &mut FilterSet) -> bool {
+        let mut added = false;
+
+        ui.horizontal(|ui| {
+            let submitted = condition_editor(
+                ui,
+                "filter",
+                &mut self.draft.column,
+                &mut self.draft.relation,
+                &mut self.draft.value,
+            );
+            ui.label("then");
+            egui::ComboBox::from_id_salt("filter_action")
+                .selected_text(self.draft.action.name())
+                .show_ui(ui, |ui| {
+                    for action in [FilterAction::Include, FilterAction::Exclude] {
+                        ui.selectable_value(&mut self.draft.action, action, action.name());
+                    }
+                });
+
+            if (ui.button("Add").clicked() || submitted) && !self.draft.value.trim().is_empty() {
+                set.rules.push(self.draft.clone());
+                self.draft.value.clear();
+                added = true;
+            }
+        });
+
+        added
+    }
+
+    fn rule_list(ui: &mut egui::Ui, set: &mut FilterSet) -> bool {
+        let mut changed = false;
+        let mut removed = None;
+
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                egui::Grid::new("filter_rules")
+                    .striped(true)
+                    .num_columns(6)
+                    .show(ui, |ui| {
+                        for (index, rule) in set.rules.iter_mut().enumerate() {
+                            changed |= ui.checkbox(&mut rule.enabled, "").changed();
+                            ui.label(rule.column.name());
+                            ui.label(rule.relation.name());
+                            ui.label(&rule.value);
+                            let action = egui::RichText::new(rule.action.name());
+                            ui.label(match rule.action {
+                                FilterAction::Include => action,
+                                FilterAction::Exclude => action.color(ui.visuals().warn_fg_color),
+                            });
+                            if ui.button("Remove").clicked() {
+                                removed = Some(index);
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        if let Some(index) = removed {
+            set.rules.remove(index);
+            changed = true;
+        }
+
+        changed
+    }
+
+    fn file_row(&mut self, ui: &mut egui::Ui, set: &mut FilterSet) -> bool {
+        let mut changed = false;
+
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut self.path);
+
+            if ui.button("Save").clicked() {
+                if let Err(e) = set.save(&self.path) {
+                    self.message = Some(format!("Failed to save {}: {}", self.path, e));
+                }
+            }
+            if ui.button("Load").clicked() {
+                match FilterSet::load(&self.path) {
+                    Ok(loaded) => {
+                        *set = loaded;
+                        changed = true;
+                    }
+                    Err(e) => self.message = Some(format!("Failed to load {}: {}", self.path, e)),
+                }
+            }
+            if ui.button("Reset").clicked() {
+                set.rules.clear();
+                changed = true;
+            }
+        });
+
+        changed
+    }
+}
+
+///
+/// Column, relation and value of a rule being written, true once enter is pressed in the value
+///
+pub fn condition_editor(
+    ui: &mut egui::Ui,
+    id_salt: &str,
+    column: &mut Column,
+    relation: &mut Relation,
+    value: &mut String,
+) -> bool {
+    egui::ComboBox::from_id_salt((id_salt, "column"))
+        .selected_text(column.name())
+        .show_ui(ui, |ui| {
+            for option in Column::ALL {
+                ui.selectable_value(column, option, option.name());
+            }
+        });
+    egui::ComboBox::from_id_salt((id_salt, "relation"))
+        .selected_text(relation.name())
+        .show_ui(ui, |ui| {
+            for option in Relation::ALL {
+                ui.selectable_value(relation, option, option.name());
+            }
+        });
+
+    let response = ui.text_edit_singleline(value);
+    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))
+}
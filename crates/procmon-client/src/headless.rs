@@ -0,0 +1,186 @@
+//!
+//! Runs without a window, events the filter and query let through are printed as they arrive
+//!
+
+use std::{
+    future::Future,
+    io::{self, Write},
+    time::Duration,
+};
+
+use kmum_common::KmMessage;
+use procmon_core::query::Query;
+
+use crate::{
+    client_runtime::ClientRuntime,
+    columns::Column,
+    events_storage::EventStorage,
+    filter::{CompiledFilter, FilteredView},
+    process_cache::ProcessCache,
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+
+///
+/// Prints tab separated rows to stdout until ctrl+c is pressed
+///
+pub async fn run(
+    runtime: ClientRuntime,
+    storage: EventStorage,
+    filter: CompiledFilter,
+    query: Option<Query>,
+) -> io::Result<()> {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    write_rows(runtime, storage, filter, query, io::stdout(), ctrl_c).await
+}
+
+///
+/// Writes rows to `out` as events arrive until `stop` completes
+///
+pub async fn write_rows<W: Write>(
+    runtime: ClientRuntime,
+    storage: EventStorage,
+    filter: CompiledFilter,
+    query: Option<Query>,
+    mut out: W,
+    stop: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let mut view = FilteredView::default();
+    let mut printed: usize = 0;
+
+    writeln!(
+        out,
+        "{}",
+        Column::ALL
+            .iter()
+            .map(|column| column.name())
+            .collect::<Vec<_>>()
+            .join("\t")
+    )?;
+
+    tokio::pin!(stop);
+    let mut ticker = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = &mut stop => break,
+            _ = ticker.tick() => {}
+        }
+
+        //Rows evicted before they were printed are lost
+        let dropped = view.refresh(&storage, &filter, query.as_ref(), runtime.cache());
+        printed = printed.saturating_sub(dropped);
+
+        while let Some(index) = view.get(printed) {
+            //Stays `None` if the event can not be read, the row is skipped then
+            let mut row = None;
+            storage.read(index, |event| row = Some(row_text(event, runtime.cache())));
+
+            match row {
+                Some(Some(row)) => writeln!(out, "{}", row)?,
+                //Rows stay in order, wait for the process of this one to resolve
+                Some(None) => break,
+                None => {}
+            }
+            printed += 1;
+        }
+        out.flush()?;
+    }
+
+    runtime.stop();
+    Ok(())
+}
+
+fn row_text(event: &KmMessage, cache: &ProcessCache) -> Option<String> {
+    let columns = Column::ALL
+        .iter()
+        .map(|column| column.value(event, cache).map(|value| column.text(&value)))
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(columns.join("\t"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use clap::Parser;
+    use procmon_core::communication::socket::SocketEndpoint;
+    use procmon_simulator::{scenario::Scenario, Simulator, SimulatorOptions};
+
+    use super::*;
+    use crate::{
+        filter::{FilterAction, FilterRule, FilterSet, Relation},
+        ProcmonArgs,
+    };
+
+    /// Events of the simulator's basic scenario reported by tool.exe
+    const TOOL_EVENTS: usize = 257;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prints_rows_from_the_simulator() {
+        let scenario = Scenario::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../procmon-simulator/scenarios/basic.scn"
+        ))
+        .unwrap();
+        let listener = "tcp://127.0.0.1:0"
+            .parse::<SocketEndpoint>()
+            .unwrap()
+            .bind()
+            .unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        thread::spawn(move || {
+            let simulator = Simulator::new(scenario, SimulatorOptions::default());
+            simulator.run_session(listener.accept().unwrap())
+        });
+
+        let args = ProcmonArgs::parse_from([
+            "procmon-client",
+            "--communication",
+            "socket",
+            "--connect",
+            &endpoint.to_string(),
+            "--headless",
+        ]);
+        let storage = EventStorage::new(&args.storage).unwrap();
+        let runtime = ClientRuntime::from_args(storage.clone(), &args);
+        runtime.start();
+
+        //Every row needs its process name, so each one goes through the cache
+        let filter = CompiledFilter::compile(&FilterSet {
+            rules: vec![FilterRule {
+                column: Column::ProcessName,
+                relation: Relation::Is,
+                value: "tool.exe".to_string(),
+                action: FilterAction::Include,
+                enabled: true,
+            }],
+        })
+        .unwrap();
+
+        let mut out = Vec::new();
+        let stop = tokio::time::sleep(Duration::from_secs(3));
+        write_rows(runtime, storage, filter, None, &mut out, stop)
+            .await
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next(),
+            Some(Column::ALL.map(|c| c.name()).join("\t").as_str())
+        );
+
+        let rows: Vec<_> = lines.collect();
+        assert_eq!(rows.len(), TOOL_EVENTS);
+        for row in rows {
+            let columns: Vec<_> = row.split('\t').collect();
+            assert_eq!(columns.len(), Column::ALL.len());
+            assert_eq!(columns[3], "tool.exe");
+        }
+    }
+}
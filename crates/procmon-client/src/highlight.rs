@@ -0,0 +1,313 @@
+//!
+//! Rows matching a highlight rule are drawn in the rule's colour. Rules are
+//! conditions like filter rules, the first enabled one that matches wins
+//!
+
+use std::{fs, io, path::Path};
+
+use egui::Color32;
+use kmum_common::KmMessage;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    columns::Column,
+    events_storage::EventStorage,
+    filter::{FilterError, FilteredView, Matcher, Relation},
+    process_cache::ProcessCache,
+};
+
+/// View rows looked at per frame
+const MAX_SCAN_PER_REFRESH: usize = 200_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HighlightRule {
+    pub column: Column,
+    pub relation: Relation,
+    pub value: String,
+    /// sRGB
+    pub color: [u8; 3],
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HighlightSet {
+    pub rules: Vec<HighlightRule>,
+}
+
+impl HighlightSet {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn active_rules(&self) -> usize {
+        self.rules.iter().filter(|rule| rule.enabled).count()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CompiledHighlights {
+    rules: Vec<(Matcher, Color32)>,
+}
+
+impl CompiledHighlights {
+    pub fn compile(set: &HighlightSet) -> Result<Self, FilterError> {
+        let rules = set
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.enabled)
+            .map(|(index, rule)| {
+                let [r, g, b] = rule.color;
+                Matcher::new(rule.column, rule.relation, &rule.value)
+                    .map(|matcher| (matcher, Color32::from_rgb(r, g, b)))
+                    .map_err(|message| FilterError {
+                        rule: index,
+                        message,
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    ///
+    /// `None` if a rule that comes before the first match needs the process
+    /// of the event and it is not resolved yet
+    ///
+    pub fn color(&self, event: &KmMessage, cache: &ProcessCache) -> Option<Option<Color32>> {
+        for (matcher, color) in &self.rules {
+            if matcher.matches_event(event, cache)? {
+                return Some(Some(*color));
+            }
+        }
+        Some(None)
+    }
+}
+
+///
+/// Colours of the highlighted rows of a `FilteredView`, extended as the view grows
+///
+#[derive(Default)]
+pub struct HighlightedRows {
+    /// View rows in order
+    marks: Vec<(usize, Color32)>,
+    /// Every view row below this was looked at
+    scanned: usize,
+}
+
+impl HighlightedRows {
+    /// Starts over, after the rules or the view changed
+    pub fn reset(&mut self) {
+        self.marks.clear();
+        self.scanned = 0;
+    }
+
+    pub fn refresh(
+        &mut self,
+        view: &FilteredView,
+        storage: &EventStorage,
+        highlights: &CompiledHighlights,
+        cache: &ProcessCache,
+    ) {
+        if highlights.is_empty() {
+            self.scanned = view.len();
+            return;
+        }
+
+        let end = view.len().min(self.scanned + MAX_SCAN_PER_REFRESH);
+        while self.scanned < end {
+            let Some(index) = view.get(self.scanned) else {
+                break;
+            };
+
+            //Stays `None` if the event can not be read, the row is left unmarked then
+            let mut color = None;
+            storage.read(index, |event| color = Some(highlights.color(event, cache)));
+            match color {
+                Some(Some(Some(color))) => self.marks.push((self.scanned, color)),
+                Some(Some(None)) | None => {}
+                //The process is asked for, this row is looked at again next time
+                Some(None) => break,
+            }
+            self.scanned += 1;
+        }
+    }
+
+    /// The view dropped its first `rows` rows
+    pub fn drop_rows(&mut self, rows: usize) {
+        let gone = self.marks.partition_point(|(row, _)| *row < rows);
+        self.marks.drain(..gone);
+        for (row, _) in &mut self.marks {
+            *row -= rows;
+        }
+        self.scanned = self.scanned.saturating_sub(rows);
+    }
+
+    pub fn color(&self, row: usize) -> Option<Color32> {
+        self.marks
+            .binary_search_by_key(&row, |(row, _)| *row)
+            .ok()
+            .map(|index| self.marks[index].1)
+    }
+
+    pub fn marks(&self) -> &[(usize, Color32)] {
+        &self.marks
+    }
+
+    /// The highlighted row closest to `row`
+    pub fn nearest(&self, row: usize) -> Option<usize> {
+        let index = self.marks.partition_point(|(mark, _)| *mark < row);
+        let after = self.marks.get(index).map(|(mark, _)| *mark);
+        let before = index.checked_sub(1).map(|index| self.marks[index].0);
+
+        match (before, after) {
+            (Some(before), Some(after)) if row - before <= after - row => Some(before),
+            (_, Some(after)) => Some(after),
+            (before, None) => before,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        filter::CompiledFilter,
+        test_support::{cache, event, read},
+    };
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    fn rule(column: Column, relation: Relation, value: &str, color: [u8; 3]) -> HighlightRule {
+        HighlightRule {
+            column,
+            relation,
+            value: value.to_string(),
+            color,
+            enabled: true,
+        }
+    }
+
+    fn compile(rules: Vec<HighlightRule>) -> CompiledHighlights {
+        CompiledHighlights::compile(&HighlightSet { rules }).unwrap()
+    }
+
+    #[test]
+    fn first_matching_rule_colours_the_row() {
+        let cache = cache(&[]);
+        let highlights = compile(vec![
+            rule(Column::Path, Relation::EndsWith, ".dll", RED),
+            rule(Column::Path, Relation::Contains, "ntdll", BLUE),
+        ]);
+
+        assert_eq!(
+            highlights.color(&event(1, read(1), "C:\\ntdll.dll"), &cache),
+            Some(Some(Color32::from_rgb(255, 0, 0)))
+        );
+        assert_eq!(
+            highlights.color(&event(1, read(1), "C:\\ntdll.log"), &cache),
+            Some(Some(Color32::from_rgb(0, 0, 255)))
+        );
+    }
+
+    #[test]
+    fn rows_matching_no_rule_are_not_coloured() {
+        let cache = cache(&[]);
+        let mut disabled = rule(Column::Pid, Relation::Is, "1", BLUE);
+        disabled.enabled = false;
+        let highlights = compile(vec![
+            rule(Column::Path, Relation::EndsWith, ".dll", RED),
+            disabled,
+        ]);
+
+        assert_eq!(
+            highlights.color(&event(1, read(1), "C:\\notepad.exe"), &cache),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn unresolved_process_before_a_match_is_not_decided() {
+        let cache = cache(&[(1, "C:\\Windows\\notepad.exe")]);
+        let highlights = compile(vec![
+            rule(Column::ProcessName, Relation::Is, "notepad.exe", RED),
+            rule(Column::Pid, Relation::Is, "2", BLUE),
+        ]);
+
+        assert_eq!(
+            highlights.color(&event(1, read(1), "a"), &cache),
+            Some(Some(Color32::from_rgb(255, 0, 0)))
+        );
+        assert_eq!(highlights.color(&event(2, read(1), "a"), &cache), None);
+    }
+
+    #[test]
+    fn rows_are_marked_and_dropped_with_the_view() {
+        let cache = cache(&[]);
+        let storage = EventStorage::default();
+        storage.push_received(
+            &mut ["a.dll", "b.txt", "c.dll", "d.txt", "e.dll"]
+                .into_iter()
+                .map(|path| event(1, read(1), path)),
+        );
+
+        let mut view = FilteredView::default();
+        view.refresh(&storage, &CompiledFilter::default(), None, &cache);
+
+        let mut rows = HighlightedRows::default();
+        rows.refresh(
+            &view,
+            &storage,
+            &compile(vec![rule(Column::Path, Relation::EndsWith, ".dll", RED)]),
+            &cache,
+        );
+
+        let red = Color32::from_rgb(255, 0, 0);
+        assert_eq!(rows.marks(), [(0, red), (2, red), (4, red)]);
+        assert_eq!(rows.color(1), None);
+        assert_eq!(rows.nearest(1), Some(0));
+        assert_eq!(rows.nearest(3), Some(2));
+
+        rows.drop_rows(1);
+        assert_eq!(rows.marks(), [(1, red), (3, red)]);
+        assert_eq!(rows.color(1), Some(red));
+    }
+
+    #[test]
+    fn unreadable_rows_are_skipped() {
+        const EVENTS: usize = 5_000;
+
+        let cache = cache(&[]);
+        let storage = EventStorage::default();
+        storage.push_received(&mut (0..EVENTS).map(|_| event(1, read(1), "a.dll")));
+        storage.lose_chunk(0);
+
+        let mut view = FilteredView::default();
+        view.refresh(&storage, &CompiledFilter::default(), None, &cache);
+
+        let mut rows = HighlightedRows::default();
+        rows.refresh(
+            &view,
+            &storage,
+            &compile(vec![rule(Column::Path, Relation::EndsWith, ".dll", RED)]),
+            &cache,
+        );
+
+        assert_eq!(rows.color(0), None);
+        assert_eq!(rows.marks().last().map(|(row, _)| *row), Some(EVENTS - 1));
+    }
+}
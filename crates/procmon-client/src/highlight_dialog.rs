@@ -0,0 +1,183 @@
+use crate::{
+    columns::Column,
+    filter::Relation,
+    filter_dialog::condition_editor,
+    highlight::{CompiledHighlights, HighlightRule, HighlightSet},
+};
+
+const DEFAULT_HIGHLIGHT_PATH: &str = "highlight.json";
+
+const DEFAULT_COLOR: [u8; 3] = [255, 230, 150];
+
+///
+/// Window editing the rules of a `HighlightSet`, changes apply right away
+///
+pub struct HighlightDialog {
+    pub open: bool,
+    /// The rule being put together in the top row
+    draft: HighlightRule,
+    path: String,
+    /// Last problem with the rules or with the file
+    message: Option<String>,
+}
+
+impl Default for HighlightDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            draft: HighlightRule {
+                column: Column::Operation,
+                relation: Relation::Is,
+                value: String::new(),
+                color: DEFAULT_COLOR,
+                enabled: true,
+            },
+            path: DEFAULT_HIGHLIGHT_PATH.to_string(),
+            message: None,
+        }
+    }
+}
+
+impl HighlightDialog {
+    ///
+    /// Returns the highlights to use from now on if the rules were changed into a valid set
+    ///
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        set: &mut HighlightSet,
+    ) -> Option<CompiledHighlights> {
+        if !self.open {
+            return None;
+        }
+
+        let mut changed = false;
+        let mut open = self.open;
+
+        egui::Window::new("Highlight")
+            .open(&mut open)
+            .default_width(700.0)
+            .show(ctx, |ui| {
+                changed |= self.draft_row(ui, set);
+                ui.separator();
+                ui.label("The first matching rule picks the colour");
+                changed |= Self::rule_list(ui, set);
+                ui.separator();
+                changed |= self.file_row(ui, set);
+
+                if let Some(message) = &self.message {
+                    ui.colored_label(ui.visuals().error_fg_color, message);
+                }
+            });
+        self.open = open;
+
+        if !changed {
+            return None;
+        }
+
+        match CompiledHighlights::compile(set) {
+            Ok(highlights) => {
+                self.message = None;
+                Some(highlights)
+            }
+            Err(e) => {
+                self.message = Some(format!("Rule {}: {}", e.rule + 1, e.message));
+                None
+            }
+        }
+    }
+
+    fn draft_row(&mut self, ui: &mut egui::Ui, set: &mut HighlightSet) -> bool {
+        let mut added = false;
+
+        ui.horizontal(|ui| {
+            let submitted = condition_editor(
+                ui,
+                "highlight",
+                &mut self.draft.column,
+                &mut self.draft.relation,
+                &mut self.draft.value,
+            );
+            ui.color_edit_button_srgb(&mut self.draft.color);
+
+            if (ui.button("Add").clicked() || submitted) && !self.draft.value.trim().is_empty() {
+                set.rules.push(self.draft.clone());
+                self.draft.value.clear();
+                added = true;
+            }
+        });
+
+        added
+    }
+
+    fn rule_list(ui: &mut egui::Ui, set: &mut HighlightSet) -> bool {
+        let mut changed = false;
+        let mut removed = None;
+        let mut raised = None;
+
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                egui::Grid::new("highlight_rules")
+                    .striped(true)
+                    .num_columns(7)
+                    .show(ui, |ui| {
+                        for (index, rule) in set.rules.iter_mut().enumerate() {
+                            changed |= ui.checkbox(&mut rule.enabled, "").changed();
+                            changed |= ui.color_edit_button_srgb(&mut rule.color).changed();
+                            ui.label(rule.column.name());
+                            ui.label(rule.relation.name());
+                            ui.label(&rule.value);
+                            if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                                raised = Some(index);
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(index);
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        if let Some(index) = raised {
+            set.rules.swap(index - 1, index);
+            changed = true;
+        }
+        if let Some(index) = removed {
+            set.rules.remove(index);
+            changed = true;
+        }
+
+        changed
+    }
+
+    fn file_row(&mut self, ui: &mut egui::Ui, set: &mut HighlightSet) -> bool {
+        let mut changed = false;
+
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut self.path);
+
+            if ui.button("Save").clicked() {
+                if let Err(e) = set.save(&self.path) {
+                    self.message = Some(format!("Failed to save {}: {}", self.path, e));
+                }
+            }
+            if ui.button("Load").clicked() {
+                match HighlightSet::load(&self.path) {
+                    Ok(loaded) => {
+                        *set = loaded;
+                        changed = true;
+                    }
+                    Err(e) => self.message = Some(format!("Failed to load {}: {}", self.path, e)),
+                }
+            }
+            if ui.button("Reset").clicked() {
+                set.rules.clear();
+                changed = true;
+            }
+        });
+
+        changed
+    }
+}
@@ -0,0 +1,133 @@
+//!
+//! Loads events exported as JSON Lines back into the storage, see `procmon_core::json`.
+//! The processes are known from the image paths in the file
+//!
+
+use std::{
+    collections::HashSet,
+    io::{self, BufRead},
+    path::Path,
+    sync::Arc,
+    thread,
+};
+
+use procmon_core::json::JsonlReader;
+
+use crate::{events_storage::EventStorage, process_cache::ProcessCache};
+
+/// Events handed to the storage at once
+const BATCH_SIZE: usize = 4096;
+
+///
+/// Opens the file right away so a bad path is reported before anything runs,
+/// the events show up in the storage as a background thread reads them
+///
+pub fn spawn(path: &Path, storage: EventStorage, cache: Arc<ProcessCache>) -> io::Result<()> {
+    let reader = JsonlReader::open(path)?;
+    let path = path.to_path_buf();
+
+    thread::Builder::new()
+        .name("import".to_string())
+        .spawn(move || load(reader, &path, &storage, &cache))?;
+    Ok(())
+}
+
+///
+/// Lines that are not an event are skipped, only the first one is reported.
+///
+/// Image paths go to `cache` before their events reach the storage, nothing
+/// asks for a process of the file before it is known
+///
+fn load<R: BufRead>(
+    reader: JsonlReader<R>,
+    path: &Path,
+    storage: &EventStorage,
+    cache: &ProcessCache,
+) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut known = HashSet::new();
+    let mut loaded = 0;
+    let mut skipped = 0;
+
+    for record in reader {
+        match record {
+            Ok(record) => {
+                let uid = record.event.process.unique_id;
+                if let Some(image) = record.image {
+                    if known.insert(uid) {
+                        cache.insert(uid, Some(image));
+                    }
+                }
+                batch.push(record.event);
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                if skipped == 0 {
+                    tracing::warn!("Skipping events of {}: {}", path.display(), e);
+                }
+                skipped += 1;
+            }
+            Err(e) => {
+                tracing::error!("Failed to read {}: {}", path.display(), e);
+                break;
+            }
+        }
+
+        if batch.len() == BATCH_SIZE {
+            loaded += batch.len();
+            storage.push_received(&mut batch.drain(..));
+        }
+    }
+
+    loaded += batch.len();
+    storage.push_received(&mut batch.drain(..));
+
+    tracing::info!(
+        "Imported {} events from {}, {} lines skipped",
+        loaded,
+        path.display(),
+        skipped
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use procmon_core::{json::JsonlWriter, query::ProcessLookup};
+
+    use super::*;
+    use crate::test_support::{event, nt_string, read};
+
+    #[tokio::test]
+    async fn processes_are_known_from_the_file() {
+        let mut writer = JsonlWriter::new(Vec::new());
+        writer
+            .write(
+                &event(7, read(1), "C:\\a.txt"),
+                Some(&nt_string("C:\\tool.exe")),
+            )
+            .unwrap();
+        writer.write(&event(8, read(1), "C:\\b.txt"), None).unwrap();
+        let bytes = writer.into_inner();
+
+        let storage = EventStorage::default();
+        let cache = ProcessCache::offline();
+        load(
+            JsonlReader::new(bytes.as_slice()),
+            Path::new("events.jsonl"),
+            &storage,
+            &cache,
+        );
+
+        assert_eq!(storage.len(), 2);
+        assert_eq!(cache.image_path(7), Some(Some("C:\\tool.exe".to_string())));
+
+        //Processes without an image path are unknown rather than looked up
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while cache.image_path(8).is_none() {
+            assert!(Instant::now() < deadline, "uid 8 was never answered");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(cache.image_path(8), Some(None));
+    }
+}
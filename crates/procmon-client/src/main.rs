@@ -3,10 +3,23 @@
 
 mod app;
 mod client_runtime;
+mod columns;
 mod event_reader;
 mod events_storage;
+mod export;
+mod export_dialog;
 mod fake_communication;
+mod filter;
+mod filter_dialog;
+mod headless;
+mod highlight;
+mod highlight_dialog;
+mod import;
+mod pipeline;
 mod process_cache;
+mod stack;
+#[cfg(test)]
+mod test_support;
 
 use app::ProcmonApp;
 use clap::Parser;
@@ -15,18 +28,30 @@ use client_runtime::ClientRuntime;
 use eframe::NativeOptions;
 use egui::Vec2;
 use egui::ViewportBuilder;
-use events_storage::EventStorage;
+use events_storage::{EventStorage, StorageConfig};
+use filter::{CompiledFilter, FilterSet};
+use highlight::HighlightSet;
 use kmum_common::KmMessage;
+use pipeline::PipelineConfig;
+use procmon_core::communication::replay::ReplaySpeed;
+use procmon_core::communication::socket::SocketEndpoint;
+use procmon_core::query::Query;
 use std::num::NonZeroU32;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 #[derive(Debug, Clone, ValueEnum)]
 enum CommunicationType {
     Driver,
     Fake,
     DriverTest,
+    /// Events streamed by a capture agent, see `--connect`
+    Socket,
+    /// A capture recorded with `--record`, see `--replay`
+    Replay,
 }
 
 #[derive(Parser, Debug)]
@@ -37,19 +62,76 @@ struct ProcmonArgs {
 
     #[arg(short, long, default_value = "4")]
     num_threads: NonZeroU32,
+
+    /// Capture agent to connect to, tcp://host:port or unix:///path
+    #[arg(long, default_value = "tcp://127.0.0.1:9700")]
+    connect: SocketEndpoint,
+
+    /// Records every received batch and process query to this capture file
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Capture file played back by the replay communication
+    #[arg(long, required_if_eq("communication", "replay"))]
+    replay: Option<PathBuf>,
+
+    /// original, max or a factor like 4x
+    #[arg(long, default_value = "original")]
+    replay_speed: ReplaySpeed,
+
+    /// Filter rules saved from the filter dialog, applied at startup
+    #[arg(long)]
+    filter: Option<PathBuf>,
+
+    /// Highlight rules saved from the highlight dialog, applied at startup
+    #[arg(long)]
+    highlight: Option<PathBuf>,
+
+    /// Search query applied at startup, see `procmon_core::query` for the syntax
+    #[arg(long)]
+    query: Option<String>,
+
+    /// Events exported as JSON Lines to look at instead of receiving any,
+    /// their processes are known from the file
+    #[arg(long)]
+    import: Option<PathBuf>,
+
+    /// Prints the events that match the filter and query instead of opening a window
+    #[arg(long)]
+    headless: bool,
+
+    #[command(flatten)]
+    pipeline: PipelineConfig,
+
+    #[command(flatten)]
+    storage: StorageConfig,
 }
 
 fn main() {
     let args = ProcmonArgs::parse();
-    println!("Args: {:#?}", args);
 
+    //Headless rows go to stdout, everything else stays out of their way
+    let writer = if args.headless {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        println!("Args: {:#?}", args);
+        BoxMakeWriter::new(std::io::stdout)
+    };
     let sub = tracing_subscriber::fmt()
         .with_ansi(false) // Disable ANSI color codes
+        .with_writer(writer)
         .finish();
     tracing::subscriber::set_global_default(sub).expect("Failed to sent global tracing subscriber");
 
     info!("Starting client");
 
+    let query = args.query.as_deref().map(|text| {
+        Query::parse(text).unwrap_or_else(|e| {
+            eprintln!("Invalid query:\n{}", e.annotate(text));
+            std::process::exit(2);
+        })
+    });
+
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(4)
@@ -58,10 +140,49 @@ fn main() {
 
     let _guard = rt.enter();
 
-    let storage = EventStorage::default();
+    let storage = EventStorage::new(&args.storage).unwrap_or_else(|e| {
+        eprintln!("Failed to set up the event storage: {}", e);
+        std::process::exit(2);
+    });
+
+    let filter = match &args.filter {
+        Some(path) => FilterSet::load(path).unwrap_or_else(|e| {
+            tracing::error!("Failed to load filter {}: {}", path.display(), e);
+            FilterSet::default()
+        }),
+        None => FilterSet::default(),
+    };
+
+    let highlights = match &args.highlight {
+        Some(path) => HighlightSet::load(path).unwrap_or_else(|e| {
+            tracing::error!("Failed to load highlights {}: {}", path.display(), e);
+            HighlightSet::default()
+        }),
+        None => HighlightSet::default(),
+    };
 
     let runtime = ClientRuntime::from_args(storage.clone(), &args);
-    runtime.start();
+    match &args.import {
+        Some(path) => {
+            import::spawn(path, storage.clone(), runtime.cache().clone()).unwrap_or_else(|e| {
+                eprintln!("Failed to import {}: {}", path.display(), e);
+                std::process::exit(2);
+            })
+        }
+        None => runtime.start(),
+    }
+
+    if args.headless {
+        let filter = CompiledFilter::compile(&filter).unwrap_or_else(|e| {
+            eprintln!("Invalid filter rule {}: {}", e.rule + 1, e.message);
+            std::process::exit(2);
+        });
+
+        if let Err(e) = rt.block_on(headless::run(runtime, storage, filter, query)) {
+            tracing::error!("Failed to write events: {}", e);
+        }
+        return;
+    }
 
     eframe::run_native(
         "Procmon in Rust",
@@ -72,7 +193,33 @@ fn main() {
             },
             ..NativeOptions::default()
         },
-        Box::new(|_cc| Ok(Box::new(ProcmonApp::new(runtime, storage)))),
+        Box::new(|_cc| {
+            Ok(Box::new(ProcmonApp::new(
+                runtime, storage, filter, highlights, query,
+            )))
+        }),
     )
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_needs_a_capture() {
+        assert!(
+            ProcmonArgs::try_parse_from(["procmon-client", "--communication", "replay"]).is_err()
+        );
+
+        let args = ProcmonArgs::try_parse_from([
+            "procmon-client",
+            "--communication",
+            "replay",
+            "--replay",
+            "capture.pmcap",
+        ])
+        .unwrap();
+        assert_eq!(args.replay, Some(PathBuf::from("capture.pmcap")));
+    }
+}
@@ -0,0 +1,138 @@
+//!
+//! Builds the processor received events go through from the command line.
+//!
+//! New sinks are a `SinkKind` variant plus an arm in `SinkKind::create`,
+//! new stages the same for `StageConfig`
+//!
+
+use std::{num::NonZeroU32, str::FromStr, sync::Arc};
+
+use clap::ValueEnum;
+use kmum_common::KmMessage;
+use procmon_core::{
+    communication::{CommunicationError, EventProcessor},
+    pipeline::{BoxedProcessor, Pipeline, PipelineBuilder},
+};
+
+use crate::{events_storage::EventStorage, stack::ProcessModules};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PipelineConfig {
+    /// Stage received events go through, in the order given:
+    /// sample=N, rate-limit=EVENTS_PER_SECOND or exclude-pid=PID
+    #[arg(long = "stage", value_name = "STAGE")]
+    pub stages: Vec<StageConfig>,
+
+    /// Where events that made it through the stages end up, can be given more than once
+    #[arg(long = "sink", value_name = "SINK", default_value = "storage")]
+    pub sinks: Vec<SinkKind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageConfig {
+    Sample(NonZeroU32),
+    RateLimit(NonZeroU32),
+    ExcludePid(u32),
+}
+
+impl FromStr for StageConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected name=value, got {}", s))?;
+
+        let number = |what: &str| -> Result<u32, String> {
+            value
+                .parse()
+                .map_err(|_| format!("{} of stage {} is not a number: {}", what, name, value))
+        };
+        let non_zero = |what: &str| {
+            NonZeroU32::new(number(what)?).ok_or_else(|| format!("{} can not be 0", what))
+        };
+
+        match name {
+            "sample" => non_zero("Sample rate").map(StageConfig::Sample),
+            "rate-limit" => non_zero("Rate limit").map(StageConfig::RateLimit),
+            "exclude-pid" => number("Pid").map(StageConfig::ExcludePid),
+            _ => Err(format!("Unknown stage {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SinkKind {
+    /// The event list shown in the window
+    Storage,
+    /// Every event written to the log at trace level
+    Log,
+}
+
+///
+/// What sinks may write to
+///
+pub struct SinkContext {
+    pub storage: EventStorage,
+}
+
+impl SinkKind {
+    fn create(self, context: &SinkContext) -> BoxedProcessor {
+        match self {
+            SinkKind::Storage => Box::new(StorageSink(context.storage.clone())),
+            SinkKind::Log => Box::new(LogSink),
+        }
+    }
+}
+
+impl PipelineConfig {
+    ///
+    /// Modules are observed ahead of every configured stage, stacks of kept
+    /// events have to resolve even if the image load was sampled away
+    ///
+    pub fn build(&self, modules: Arc<ProcessModules>, context: &SinkContext) -> Pipeline {
+        let builder = PipelineBuilder::new().map(move |event| {
+            modules.observe(&event);
+            event
+        });
+
+        let builder = self
+            .stages
+            .iter()
+            .fold(builder, |builder, stage| match *stage {
+                StageConfig::Sample(every) => builder.sample(every),
+                StageConfig::RateLimit(per_second) => builder.rate_limit(per_second),
+                StageConfig::ExcludePid(pid) => {
+                    builder.filter(move |event| event.process.pid != u64::from(pid))
+                }
+            });
+
+        builder.build_tee(self.sinks.iter().map(|sink| sink.create(context)).collect())
+    }
+}
+
+struct StorageSink(EventStorage);
+
+impl EventProcessor for StorageSink {
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        self.0.push_received(iter);
+        Ok(())
+    }
+}
+
+struct LogSink;
+
+impl EventProcessor for LogSink {
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        for event in iter {
+            tracing::trace!("{:?}", event);
+        }
+        Ok(())
+    }
+}
@@ -1,24 +1,35 @@
 use std::{
     collections::HashMap,
     sync::{Arc, Weak},
+    time::Duration,
 };
 
 use egui::mutex::RwLock;
-use kmum_common::{process::UniqueProcessId, serializable_ntstring::SerializableNtString};
+use kmum_common::{
+    process::UniqueProcessId, serializable_ntstring::SerializableNtString, MAX_BATCH_QUERY,
+};
+use procmon_core::query::ProcessLookup;
 use tokio::{
     sync::mpsc::{channel, Receiver, Sender},
     task::spawn_blocking,
 };
 
+/// Pause after a failed query, the uids are asked for again right away
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
 pub struct ProcessCache {
     cache: RwLock<HashMap<UniqueProcessId, Option<SerializableNtString>>>,
     sender: Sender<UniqueProcessId>,
 }
 
 impl ProcessCache {
+    ///
+    /// `query_cb` returns `None` if the query failed, nothing is remembered
+    /// for its uids then
+    ///
     pub fn new<Q>(query_cb: Q) -> Arc<Self>
     where
-        Q: Fn(UniqueProcessId) -> Option<SerializableNtString> + 'static + Send,
+        Q: Fn(&[UniqueProcessId]) -> Option<Vec<Option<SerializableNtString>>> + 'static + Send,
     {
         let (sender, recv) = channel(1024);
 
@@ -33,6 +44,19 @@ impl ProcessCache {
         cache
     }
 
+    ///
+    /// Only knows the processes passed to `insert`, anything else is answered
+    /// as unknown. For events that were not received through a communication
+    ///
+    pub fn offline() -> Arc<Self> {
+        Self::new(|ids| Some(vec![None; ids.len()]))
+    }
+
+    /// Remembers the image path of `uid`, it is not asked for anymore
+    pub fn insert(&self, uid: UniqueProcessId, name: Option<SerializableNtString>) {
+        self.cache.write().insert(uid, name);
+    }
+
     pub fn try_get_and<F>(&self, uid: UniqueProcessId, cb: F) -> bool
     where
         F: FnOnce(&Option<SerializableNtString>),
@@ -42,23 +66,27 @@ impl ProcessCache {
             cb(hit);
             true
         } else {
-            let _ = self.sender.blocking_send(uid);
+            //Never blocks, the caller may run on the runtime. A uid that does not
+            //fit is asked for again the next time its row is looked at
+            let _ = self.sender.try_send(uid);
             false
         }
     }
 
+    ///
+    /// Collects the uids that were asked for in the meantime and resolves
+    /// the ones still missing with a single batched query
+    ///
     fn internal_worker<Q>(weak_self: Weak<Self>, query: Q, mut receiver: Receiver<UniqueProcessId>)
     where
-        Q: Fn(UniqueProcessId) -> Option<SerializableNtString> + 'static + Send,
+        Q: Fn(&[UniqueProcessId]) -> Option<Vec<Option<SerializableNtString>>> + 'static + Send,
     {
-        let mut data = Vec::with_capacity(16);
-        let mut cached_names: Vec<(u64, Option<SerializableNtString>)> = Vec::new();
+        let mut data = Vec::with_capacity(MAX_BATCH_QUERY);
 
         loop {
-            cached_names.clear();
             data.clear();
 
-            let size = receiver.blocking_recv_many(&mut data, 16);
+            let size = receiver.blocking_recv_many(&mut data, MAX_BATCH_QUERY);
             if size == 0 {
                 break;
             }
@@ -70,20 +98,90 @@ impl ProcessCache {
 
             let cache = cache.unwrap();
 
+            //The same uid is asked for on every frame until it is resolved
+            data.sort_unstable();
+            data.dedup();
             {
                 let read_guard = cache.cache.read();
-                cached_names.extend(data.iter().filter(|id| !read_guard.contains_key(id)).map(
-                    |id| {
-                        let process_info = query(*id);
-                        (*id, process_info)
-                    },
-                ));
+                data.retain(|id| !read_guard.contains_key(id));
             }
+            if data.is_empty() {
+                continue;
+            }
+
+            //A failed query, for example while reconnecting, is not an answer
+            let Some(names) = query(&data) else {
+                drop(cache);
+                std::thread::sleep(RETRY_DELAY);
+                continue;
+            };
 
             {
                 let mut guard = cache.cache.write();
-                guard.extend(cached_names.drain(..));
+                guard.extend(data.iter().copied().zip(names));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl ProcessCache {
+    ///
+    /// Knows `entries` up front and never resolves anything else
+    ///
+    pub fn resolved<I>(entries: I) -> Arc<Self>
+    where
+        I: IntoIterator<Item = (UniqueProcessId, Option<SerializableNtString>)>,
+    {
+        let (sender, _) = channel(1);
+
+        Arc::new(Self {
+            cache: RwLock::new(entries.into_iter().collect()),
+            sender,
+        })
+    }
+}
+
+impl ProcessLookup for ProcessCache {
+    fn image_path(&self, uid: UniqueProcessId) -> Option<Option<String>> {
+        let mut path = None;
+        self.try_get_and(uid, |name| {
+            path = Some(name.as_ref().map(|name| name.to_string()));
+        });
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::test_support::nt_string;
+
+    #[tokio::test]
+    async fn failed_queries_are_asked_again() {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counted = queries.clone();
+        let cache = ProcessCache::new(move |ids| {
+            //The first query fails like it does while reconnecting
+            match counted.fetch_add(1, Ordering::Relaxed) {
+                0 => None,
+                _ => Some(ids.iter().map(|_| Some(nt_string("tool.exe"))).collect()),
             }
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut name = None;
+        while !cache.try_get_and(7, |hit| name = Some(hit.as_ref().map(|n| n.to_string()))) {
+            assert!(Instant::now() < deadline, "uid 7 was never resolved");
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+
+        assert_eq!(name, Some(Some("tool.exe".to_string())));
+        assert!(queries.load(Ordering::Relaxed) >= 2);
     }
 }
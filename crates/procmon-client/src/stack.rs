@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, OnceLock},
+};
+
+use egui::mutex::{Mutex, RwLock};
+use kmum_common::{
+    event::{
+        EventClass, EventProcessOperation, EventStack, FrameMode, SimpleProcessDetails, StackFrame,
+    },
+    process::UniqueProcessId,
+    KmMessage,
+};
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, INVALID_HANDLE_VALUE},
+    System::{
+        Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, MODULEENTRY32W,
+            TH32CS_SNAPMODULE, TH32CS_SNAPMODULE32,
+        },
+        ProcessStatus::{EnumDeviceDrivers, GetDeviceDriverBaseNameW},
+    },
+};
+
+#[derive(Clone)]
+pub struct ModuleRange {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+}
+
+///
+/// Modules loaded in an address space, sorted by base address
+///
+#[derive(Default)]
+pub struct ModuleMap {
+    modules: Vec<ModuleRange>,
+}
+
+impl ModuleMap {
+    pub fn new(mut modules: Vec<ModuleRange>) -> Self {
+        modules.sort_by_key(|module| module.base);
+        Self { modules }
+    }
+
+    pub fn insert(&mut self, module: ModuleRange) {
+        let index = self
+            .modules
+            .partition_point(|existing| existing.base <= module.base);
+
+        //A new image mapped at the same base replaces the unloaded one
+        if index > 0 && self.modules[index - 1].base == module.base {
+            self.modules[index - 1] = module;
+        } else {
+            self.modules.insert(index, module);
+        }
+    }
+
+    pub fn modules(&self) -> &[ModuleRange] {
+        &self.modules
+    }
+
+    pub fn find(&self, address: u64) -> Option<&ModuleRange> {
+        let index = self
+            .modules
+            .partition_point(|module| module.base <= address)
+            .checked_sub(1)?;
+
+        let module = &self.modules[index];
+        (address - module.base < module.size).then_some(module)
+    }
+
+    fn from_process(pid: u32) -> Self {
+        let mut modules = Vec::new();
+
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, pid);
+            if snapshot == INVALID_HANDLE_VALUE {
+                tracing::warn!("Failed to snapshot the modules of process {pid}");
+                return Self::default();
+            }
+
+            let mut entry: MODULEENTRY32W = std::mem::zeroed();
+            entry.dwSize = std::mem::size_of::<MODULEENTRY32W>() as _;
+
+            let mut found = Module32FirstW(snapshot, &mut entry);
+            while found != 0 {
+                modules.push(ModuleRange {
+                    name: wide_to_string(&entry.szModule),
+                    base: entry.modBaseAddr as u64,
+                    size: entry.modBaseSize as u64,
+                });
+                found = Module32NextW(snapshot, &mut entry);
+            }
+
+            CloseHandle(snapshot);
+        }
+
+        Self::new(modules)
+    }
+
+    ///
+    /// Driver sizes are not reported, each one is assumed to end where the next begins
+    ///
+    fn from_drivers() -> Self {
+        let mut bases: Vec<*mut core::ffi::c_void> = vec![std::ptr::null_mut(); 1024];
+
+        unsafe {
+            let mut needed = 0u32;
+            loop {
+                let size = (bases.len() * std::mem::size_of::<*mut core::ffi::c_void>()) as u32;
+                if EnumDeviceDrivers(bases.as_mut_ptr(), size, &mut needed) == 0 {
+                    tracing::warn!("Failed to enumerate the loaded drivers");
+                    return Self::default();
+                }
+
+                if needed <= size {
+                    break;
+                }
+                bases.resize(
+                    needed as usize / std::mem::size_of::<*mut core::ffi::c_void>(),
+                    std::ptr::null_mut(),
+                );
+            }
+            bases.truncate(needed as usize / std::mem::size_of::<*mut core::ffi::c_void>());
+        }
+
+        let mut bases: Vec<u64> = bases.into_iter().map(|base| base as u64).collect();
+        bases.sort_unstable();
+
+        let modules = bases
+            .iter()
+            .enumerate()
+            .map(|(index, &base)| {
+                let mut name = [0u16; 260];
+                let len = unsafe {
+                    GetDeviceDriverBaseNameW(base as _, name.as_mut_ptr(), name.len() as _)
+                };
+
+                ModuleRange {
+                    name: String::from_utf16_lossy(&name[..len as usize]),
+                    base,
+                    size: bases.get(index + 1).copied().unwrap_or(u64::MAX) - base,
+                }
+            })
+            .collect();
+
+        Self::new(modules)
+    }
+}
+
+fn wide_to_string(buffer: &[u16]) -> String {
+    let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    String::from_utf16_lossy(&buffer[..len])
+}
+
+pub struct ResolvedFrame {
+    pub frame: StackFrame,
+    pub module: Option<String>,
+    /// Offset from the module base, or the raw address when no module was found
+    pub offset: u64,
+}
+
+impl Display for ResolvedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.module {
+            Some(module) => write!(f, "{}+0x{:x}", module, self.offset),
+            None => write!(f, "0x{:x}", self.offset),
+        }
+    }
+}
+
+///
+/// Modules of every process built from the image load events received so far
+///
+#[derive(Default)]
+pub struct ProcessModules {
+    processes: RwLock<HashMap<UniqueProcessId, ModuleMap>>,
+}
+
+impl ProcessModules {
+    pub fn observe(&self, event: &KmMessage) {
+        if let EventClass::Process(EventProcessOperation::ImageLoad { base, size }) =
+            &event.event.operation
+        {
+            let path = event.event.path.to_string();
+            let name = path.rsplit('\\').next().unwrap_or(&path).to_string();
+
+            self.processes
+                .write()
+                .entry(event.process.unique_id)
+                .or_default()
+                .insert(ModuleRange {
+                    name,
+                    base: *base,
+                    size: *size,
+                });
+        }
+    }
+
+    pub fn read<R, F: FnOnce(Option<&ModuleMap>) -> R>(&self, uid: UniqueProcessId, f: F) -> R {
+        f(self.processes.read().get(&uid))
+    }
+}
+
+///
+/// Turns raw return addresses into module+offset.
+///
+/// Image load events are used first, frames they do not cover fall back to
+/// a module snapshot taken the first time a process is resolved. Snapshots
+/// describe this machine, they are only taken when `local` events are shown
+///
+pub struct StackResolver {
+    modules: Arc<ProcessModules>,
+    local: bool,
+    kernel: OnceLock<ModuleMap>,
+    snapshots: Mutex<HashMap<UniqueProcessId, Arc<ModuleMap>>>,
+}
+
+impl StackResolver {
+    pub fn new(modules: Arc<ProcessModules>, local: bool) -> Self {
+        Self {
+            modules,
+            local,
+            kernel: OnceLock::new(),
+            snapshots: Mutex::default(),
+        }
+    }
+
+    pub fn resolve(
+        &self,
+        process: &SimpleProcessDetails,
+        stack: &EventStack,
+    ) -> Vec<ResolvedFrame> {
+        let kernel = self
+            .local
+            .then(|| self.kernel.get_or_init(ModuleMap::from_drivers));
+        //A pid can be reused by another process, the uid is not
+        let snapshot = || {
+            self.local.then(|| {
+                self.snapshots
+                    .lock()
+                    .entry(process.unique_id)
+                    .or_insert_with(|| Arc::new(ModuleMap::from_process(process.pid as _)))
+                    .clone()
+            })
+        };
+
+        self.modules.read(process.unique_id, |loaded| {
+            stack
+                .frames()
+                .iter()
+                .map(|frame| {
+                    let module = match frame.mode {
+                        FrameMode::Kernel => kernel
+                            .and_then(|kernel| kernel.find(frame.address))
+                            .map(|m| m.to_owned()),
+                        FrameMode::User => loaded
+                            .and_then(|loaded| loaded.find(frame.address))
+                            .map(|m| m.to_owned())
+                            .or_else(|| snapshot()?.find(frame.address).map(|m| m.to_owned())),
+                    };
+
+                    Self::resolve_frame(frame, module)
+                })
+                .collect()
+        })
+    }
+
+    fn resolve_frame(frame: &StackFrame, module: Option<ModuleRange>) -> ResolvedFrame {
+        match module {
+            Some(module) => ResolvedFrame {
+                frame: *frame,
+                module: Some(module.name),
+                offset: frame.address - module.base,
+            },
+            None => ResolvedFrame {
+                frame: *frame,
+                module: None,
+                offset: frame.address,
+            },
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//!
+//! Events and process caches shared by the unit tests
+//!
+
+use std::sync::Arc;
+
+use kmum_common::{
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    process::UniqueProcessId,
+    serializable_ntstring::SerializableNtString,
+    KmMessage,
+};
+use nt_string::unicode_string::NtUnicodeString;
+
+use crate::process_cache::ProcessCache;
+
+/// 2024-01-01 00:00:00 UTC as a FILETIME
+pub const DATE: u64 = 133_485_408_000_000_000;
+
+pub fn nt_string(value: &str) -> SerializableNtString {
+    SerializableNtString::new(NtUnicodeString::try_from(value).unwrap())
+}
+
+pub fn read(length: u64) -> EventClass {
+    EventClass::FileSystem(EventFileSystemOperation::Read { length, offset: 0 })
+}
+
+///
+/// `operation` on `path` by process `uid`, its pid is the uid as well
+///
+pub fn event(uid: UniqueProcessId, operation: EventClass, path: &str) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date: DATE,
+            thread: 1,
+            operation,
+            result: 0,
+            path: nt_string(path),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: uid,
+            unique_id: uid,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+///
+/// Knows the image path of every process in `processes`, anything else stays unresolved
+///
+pub fn cache(processes: &[(UniqueProcessId, &str)]) -> Arc<ProcessCache> {
+    ProcessCache::resolved(
+        processes
+            .iter()
+            .map(|(uid, path)| (*uid, Some(nt_string(path)))),
+    )
+}
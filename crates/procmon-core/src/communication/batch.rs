@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use kmum_common::{batch::BatchHeader, KmMessage, KmMessageRef};
+
+use super::{BatchHandler, BorrowedEventProcessor, CommunicationError, EventProcessor};
+
+/// Gaps remembered per worker, a batch from an older gap counts as a duplicate
+const MAX_MISSING_RANGES: usize = 64;
+
+///
+/// Gap and drop accounting over every batch received by a communication
+///
+#[derive(Default)]
+pub struct BatchStatistics {
+    batches: AtomicU64,
+    events: AtomicU64,
+    lost_batches: AtomicU64,
+    reordered_batches: AtomicU64,
+    duplicate_batches: AtomicU64,
+    truncated_batches: AtomicU64,
+    malformed_batches: AtomicU64,
+    /// Drops reported by the workers of earlier connections
+    previous_dropped: AtomicU64,
+    workers: Mutex<HashMap<u16, WorkerState>>,
+}
+
+///
+/// Workers outlive client connections, so the first batch seen from
+/// a worker is used as the baseline for its sequence and drop counter
+///
+#[derive(Clone)]
+struct WorkerState {
+    next_sequence: u64,
+    baseline_dropped: u64,
+    dropped: u64,
+    /// Sequences counted as lost that may still arrive late, oldest first
+    missing: Vec<Range<u64>>,
+}
+
+impl WorkerState {
+    fn new(header: &BatchHeader) -> Self {
+        Self {
+            next_sequence: header.sequence,
+            baseline_dropped: header.dropped,
+            dropped: header.dropped,
+            missing: Vec::new(),
+        }
+    }
+
+    fn add_missing(&mut self, sequences: Range<u64>) {
+        if self.missing.len() == MAX_MISSING_RANGES {
+            self.missing.remove(0);
+        }
+        self.missing.push(sequences);
+    }
+
+    /// Whether `sequence` was counted as lost, it is not anymore afterwards
+    fn take_missing(&mut self, sequence: u64) -> bool {
+        let Some(index) = self
+            .missing
+            .iter()
+            .position(|range| range.contains(&sequence))
+        else {
+            return false;
+        };
+
+        let range = self.missing[index].clone();
+        match (range.start == sequence, range.end == sequence + 1) {
+            (true, true) => {
+                self.missing.remove(index);
+            }
+            (true, false) => self.missing[index].start += 1,
+            (false, true) => self.missing[index].end -= 1,
+            (false, false) => {
+                self.missing[index].end = sequence;
+                self.missing.insert(index + 1, sequence + 1..range.end);
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchStatisticsSnapshot {
+    pub batches: u64,
+    pub events: u64,
+    /// Batches missing from the sequence of a worker
+    pub lost_batches: u64,
+    /// Batches received after a newer batch of the same worker
+    pub reordered_batches: u64,
+    /// Batches of a worker that were not missing when they arrived
+    pub duplicate_batches: u64,
+    /// Batches that decoded fewer events than their header announced
+    pub truncated_batches: u64,
+    /// Batches whose header could not be read
+    pub malformed_batches: u64,
+    /// Events the driver reported as dropped before they made it into a batch
+    pub dropped_events: u64,
+}
+
+impl BatchStatistics {
+    pub fn snapshot(&self) -> BatchStatisticsSnapshot {
+        let dropped_events = self.previous_dropped.load(Ordering::Relaxed)
+            + Self::workers_dropped(&self.workers.lock().unwrap());
+
+        BatchStatisticsSnapshot {
+            batches: self.batches.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+            lost_batches: self.lost_batches.load(Ordering::Relaxed),
+            reordered_batches: self.reordered_batches.load(Ordering::Relaxed),
+            duplicate_batches: self.duplicate_batches.load(Ordering::Relaxed),
+            truncated_batches: self.truncated_batches.load(Ordering::Relaxed),
+            malformed_batches: self.malformed_batches.load(Ordering::Relaxed),
+            dropped_events,
+        }
+    }
+
+    ///
+    /// Starts over with the workers of a new connection, the driver may have been
+    /// restarted in between and its sequences with it
+    ///
+    pub(crate) fn new_session(&self) {
+        let mut workers = self.workers.lock().unwrap();
+        self.previous_dropped
+            .fetch_add(Self::workers_dropped(&workers), Ordering::Relaxed);
+        workers.clear();
+    }
+
+    fn workers_dropped(workers: &HashMap<u16, WorkerState>) -> u64 {
+        workers
+            .values()
+            .map(|worker| worker.dropped - worker.baseline_dropped)
+            .sum()
+    }
+
+    fn record_header(&self, header: &BatchHeader) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+
+        let mut workers = self.workers.lock().unwrap();
+        let worker = workers
+            .entry(header.worker)
+            .or_insert_with(|| WorkerState::new(header));
+
+        if header.sequence >= worker.next_sequence {
+            let lost = header.sequence - worker.next_sequence;
+            if lost != 0 {
+                tracing::warn!(
+                    "Worker {} lost {lost} batches before sequence {}",
+                    header.worker,
+                    header.sequence
+                );
+                self.lost_batches.fetch_add(lost, Ordering::Relaxed);
+                worker.add_missing(worker.next_sequence..header.sequence);
+            }
+            worker.next_sequence = header.sequence + 1;
+        } else if worker.take_missing(header.sequence) {
+            //Was counted as lost when the newer batch arrived
+            self.reordered_batches.fetch_add(1, Ordering::Relaxed);
+            self.lost_batches
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |lost| {
+                    Some(lost.saturating_sub(1))
+                })
+                .unwrap();
+        } else {
+            tracing::warn!(
+                "Worker {} sent batch {} more than once",
+                header.worker,
+                header.sequence
+            );
+            self.duplicate_batches.fetch_add(1, Ordering::Relaxed);
+        }
+
+        worker.dropped = worker.dropped.max(header.dropped);
+    }
+
+    pub(crate) fn record_events(&self, header: &BatchHeader, decoded: u32) {
+        self.events.fetch_add(decoded as _, Ordering::Relaxed);
+
+        if decoded != header.event_count {
+            tracing::warn!(
+                "Batch {} of worker {} decoded {decoded} out of {} events",
+                header.sequence,
+                header.worker,
+                header.event_count
+            );
+            self.truncated_batches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn record_malformed(&self) {
+        self.malformed_batches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn split_batch<'b>(
+        &self,
+        batch: &'b [u8],
+    ) -> Result<(BatchHeader, &'b [u8]), CommunicationError> {
+        let (header, payload) = BatchHeader::split(batch).map_err(|e| {
+            tracing::error!("Received malformed batch: {:?}", e);
+            self.record_malformed();
+            CommunicationError::Parsing
+        })?;
+
+        self.record_header(&header);
+        Ok((header, payload))
+    }
+}
+
+///
+/// Checks the envelope of every received batch and hands its events to `processor`
+///
+pub struct BatchDecoder<'a, P: EventProcessor> {
+    processor: P,
+    statistics: &'a BatchStatistics,
+}
+
+impl<'a, P: EventProcessor> BatchDecoder<'a, P> {
+    pub fn new(processor: P, statistics: &'a BatchStatistics) -> Self {
+        Self {
+            processor,
+            statistics,
+        }
+    }
+}
+
+impl<'a, P: EventProcessor> BatchHandler for BatchDecoder<'a, P> {
+    fn handle_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError> {
+        let (header, payload) = self.statistics.split_batch(batch)?;
+
+        let mut iter = KmMessageIterator::new(payload, header.event_count);
+        let result = self.processor.process(&mut iter);
+
+        //Account for whatever the processor did not consume
+        iter.by_ref().for_each(drop);
+        self.statistics.record_events(&header, iter.decoded());
+
+        result
+    }
+}
+
+///
+/// Same as `BatchDecoder` for processors that work on borrowed views
+///
+pub struct BorrowedBatchDecoder<'a, P: BorrowedEventProcessor> {
+    processor: P,
+    statistics: &'a BatchStatistics,
+}
+
+impl<'a, P: BorrowedEventProcessor> BorrowedBatchDecoder<'a, P> {
+    pub fn new(processor: P, statistics: &'a BatchStatistics) -> Self {
+        Self {
+            processor,
+            statistics,
+        }
+    }
+}
+
+impl<'a, P: BorrowedEventProcessor> BatchHandler for BorrowedBatchDecoder<'a, P> {
+    fn handle_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError> {
+        let (header, payload) = self.statistics.split_batch(batch)?;
+
+        let mut iter = KmMessageRefIterator::new(payload, header.event_count);
+        let result = self.processor.process_borrowed(&mut iter);
+
+        iter.by_ref().for_each(drop);
+        self.statistics.record_events(&header, iter.decoded());
+
+        result
+    }
+}
+
+pub struct KmMessageIterator<'a> {
+    buffer: &'a [u8],
+    remaining: u32,
+    decoded: u32,
+}
+
+impl<'a> KmMessageIterator<'a> {
+    pub fn new(payload: &'a [u8], event_count: u32) -> Self {
+        Self {
+            buffer: payload,
+            remaining: event_count,
+            decoded: 0,
+        }
+    }
+
+    pub fn decoded(&self) -> u32 {
+        self.decoded
+    }
+}
+
+impl<'a> Iterator for KmMessageIterator<'a> {
+    type Item = KmMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        if let Ok((message, remaining)) = postcard::take_from_bytes::<KmMessage>(self.buffer) {
+            self.buffer = remaining;
+            self.remaining -= 1;
+            self.decoded += 1;
+            Some(message)
+        } else {
+            self.remaining = 0;
+            None
+        }
+    }
+}
+
+pub struct KmMessageRefIterator<'a> {
+    buffer: &'a [u8],
+    remaining: u32,
+    decoded: u32,
+}
+
+impl<'a> KmMessageRefIterator<'a> {
+    pub fn new(payload: &'a [u8], event_count: u32) -> Self {
+        Self {
+            buffer: payload,
+            remaining: event_count,
+            decoded: 0,
+        }
+    }
+
+    pub fn decoded(&self) -> u32 {
+        self.decoded
+    }
+}
+
+impl<'a> Iterator for KmMessageRefIterator<'a> {
+    type Item = KmMessageRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        if let Ok((message, remaining)) = KmMessageRef::take_from_bytes(self.buffer) {
+            self.buffer = remaining;
+            self.remaining -= 1;
+            self.decoded += 1;
+            Some(message)
+        } else {
+            self.remaining = 0;
+            None
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//!
+//! Capture files, raw batches as they were received plus the process
+//! information that was asked for while recording.
+//!
+//! Everything is little endian:
+//!
+//! ```text
+//! header  magic "PMCAPTUR" | format version u16 | protocol version u32 | started FILETIME u64
+//! record  kind u8 | micros since started u64 | payload length u32 | payload
+//! ```
+//!
+//! A batch record holds the batch exactly as `BatchHandler::handle_batch` got it, process
+//! and exe name records hold a postcard `(UniqueProcessId, Option<_>)`. A record cut
+//! short by a crash ends the capture
+//!
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use kmum_common::{
+    handshake::PROTOCOL_VERSION,
+    process::{ProcessInformation, UniqueProcessId},
+    serializable_ntstring::SerializableNtString,
+    MAX_KM_MESSAGE_RECEIVE_SIZE, MAX_UM_REPLY_MESSAGE_SIZE,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::filetime_now;
+
+pub const CAPTURE_MAGIC: [u8; 8] = *b"PMCAPTUR";
+pub const CAPTURE_FORMAT_VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 8 + 2 + 4 + 8;
+const RECORD_HEADER_SIZE: usize = 1 + 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum RecordKind {
+    Batch = 0,
+    Process = 1,
+    ExeName = 2,
+}
+
+impl RecordKind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Batch),
+            1 => Some(Self::Process),
+            2 => Some(Self::ExeName),
+            _ => None,
+        }
+    }
+
+    /// The largest payload a record of this kind can hold when it was written
+    fn max_length(&self) -> usize {
+        match self {
+            Self::Batch => MAX_KM_MESSAGE_RECEIVE_SIZE,
+            Self::Process | Self::ExeName => MAX_UM_REPLY_MESSAGE_SIZE,
+        }
+    }
+}
+
+///
+/// Appends records to a capture, the header is written on creation
+///
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    started: Instant,
+    scratch: Vec<u8>,
+}
+
+impl CaptureWriter<io::BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(io::BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..8].copy_from_slice(&CAPTURE_MAGIC);
+        header[8..10].copy_from_slice(&CAPTURE_FORMAT_VERSION.to_le_bytes());
+        header[10..14].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        header[14..22].copy_from_slice(&filetime_now().to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+            scratch: vec![0u8; MAX_UM_REPLY_MESSAGE_SIZE],
+        })
+    }
+
+    pub fn write_batch(&mut self, batch: &[u8]) -> io::Result<()> {
+        self.write_record(RecordKind::Batch, batch)
+    }
+
+    pub fn write_process(
+        &mut self,
+        uid: UniqueProcessId,
+        info: Option<&ProcessInformation>,
+    ) -> io::Result<()> {
+        self.write_serialized(RecordKind::Process, &(uid, info))
+    }
+
+    pub fn write_exe_name(
+        &mut self,
+        uid: UniqueProcessId,
+        name: Option<&SerializableNtString>,
+    ) -> io::Result<()> {
+        self.write_serialized(RecordKind::ExeName, &(uid, name))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_serialized<T: Serialize>(&mut self, kind: RecordKind, value: &T) -> io::Result<()> {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let result = match postcard::to_slice(value, &mut scratch) {
+            Ok(payload) => self.write_record(kind, payload),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?}", e),
+            )),
+        };
+        self.scratch = scratch;
+
+        result
+    }
+
+    fn write_record(&mut self, kind: RecordKind, payload: &[u8]) -> io::Result<()> {
+        let length = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        header[0] = kind as u8;
+        header[1..9].copy_from_slice(&(self.started.elapsed().as_micros() as u64).to_le_bytes());
+        header[9..13].copy_from_slice(&length.to_le_bytes());
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(payload)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturedBatch {
+    /// Time since the capture started
+    pub elapsed: Duration,
+    pub data: Vec<u8>,
+}
+
+///
+/// A whole capture read back into memory
+///
+#[derive(Debug, Default)]
+pub struct Capture {
+    /// FILETIME the capture started at
+    pub started: u64,
+    pub batches: Vec<CapturedBatch>,
+    pub processes: HashMap<UniqueProcessId, Option<ProcessInformation>>,
+    pub exe_names: HashMap<UniqueProcessId, Option<SerializableNtString>>,
+}
+
+impl Capture {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        if header[..8] != CAPTURE_MAGIC {
+            return Err(invalid_data("not a capture file"));
+        }
+        let format = u16::from_le_bytes(header[8..10].try_into().unwrap());
+        if format != CAPTURE_FORMAT_VERSION {
+            return Err(invalid_data(&format!("unknown capture format {}", format)));
+        }
+        let protocol = u32::from_le_bytes(header[10..14].try_into().unwrap());
+        if protocol != PROTOCOL_VERSION {
+            return Err(invalid_data(&format!(
+                "captured with protocol {}, this build speaks {}",
+                protocol, PROTOCOL_VERSION
+            )));
+        }
+
+        let mut capture = Capture {
+            started: u64::from_le_bytes(header[14..22].try_into().unwrap()),
+            ..Capture::default()
+        };
+
+        loop {
+            let mut record = [0u8; RECORD_HEADER_SIZE];
+            match read_full(&mut reader, &mut record)? {
+                0 => break,
+                RECORD_HEADER_SIZE => {}
+                _ => {
+                    tracing::warn!("Capture ends in the middle of a record header");
+                    break;
+                }
+            }
+
+            let kind = RecordKind::from_u8(record[0])
+                .ok_or_else(|| invalid_data(&format!("unknown record kind {}", record[0])))?;
+            let elapsed =
+                Duration::from_micros(u64::from_le_bytes(record[1..9].try_into().unwrap()));
+            let length = u32::from_le_bytes(record[9..13].try_into().unwrap()) as usize;
+            //The length comes from the file, nothing is allocated for a corrupt one
+            if length > kind.max_length() {
+                return Err(invalid_data(&format!(
+                    "{:?} record of {} bytes",
+                    kind, length
+                )));
+            }
+
+            let mut payload = vec![0u8; length];
+            if read_full(&mut reader, &mut payload)? != length {
+                tracing::warn!("Capture ends in the middle of a record");
+                break;
+            }
+
+            match kind {
+                RecordKind::Batch => capture.batches.push(CapturedBatch {
+                    elapsed,
+                    data: payload,
+                }),
+                RecordKind::Process => {
+                    let (uid, info) = deserialize(&payload)?;
+                    capture.processes.insert(uid, info);
+                }
+                RecordKind::ExeName => {
+                    let (uid, name) = deserialize(&payload)?;
+                    capture.exe_names.insert(uid, name);
+                }
+            }
+        }
+
+        Ok(capture)
+    }
+}
+
+/// Like `read_exact`, but a short read at the end of the stream returns how much was read
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
+
+fn deserialize<T: DeserializeOwned>(payload: &[u8]) -> io::Result<T> {
+    postcard::from_bytes(payload).map_err(|e| invalid_data(&format!("{:?}", e)))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
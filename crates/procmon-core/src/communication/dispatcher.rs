@@ -3,13 +3,11 @@ use std::{
     thread::{spawn, JoinHandle},
 };
 
-use kmum_common::{
-    get_communication_port_name, MAX_KM_MESSAGE_RECEIVE_SIZE, MAX_UM_REPLY_MESSAGE_SIZE,
-};
+use kmum_common::{get_communication_port_name, handshake::BufferSizes};
 use windows_sys::Win32::{
-    Foundation::{GetLastError, STATUS_SUCCESS, STATUS_UNSUCCESSFUL, WAIT_OBJECT_0},
+    Foundation::{GetLastError, STATUS_SUCCESS, STATUS_UNSUCCESSFUL, WAIT_OBJECT_0, WAIT_TIMEOUT},
     System::{
-        Threading::{WaitForMultipleObjects, INFINITE},
+        Threading::WaitForMultipleObjects,
         IO::{CancelIoEx, GetOverlappedResult},
     },
 };
@@ -28,8 +26,15 @@ pub trait FilterBufferHandler {
         receive_buffer: &[u8],
         reply_buffer: &mut [u8],
     ) -> anyhow::Result<(), CommunicationError>;
+
+    fn is_cancelled(&self) -> bool {
+        false
+    }
 }
 
+/// How often a pending receive checks whether its handler was cancelled
+const CANCEL_POLL_INTERVAL_MS: u32 = 100;
+
 #[allow(dead_code)]
 pub struct Dispatcher {
     raw_communication: RawCommunication,
@@ -37,15 +42,16 @@ pub struct Dispatcher {
 }
 
 impl Dispatcher {
-    pub fn new(options: kmum_common::ClientConnectMessage) -> Self {
-        Self {
+    pub fn new(
+        options: kmum_common::ClientConnectMessage,
+    ) -> anyhow::Result<Self, CommunicationError> {
+        Ok(Self {
             raw_communication: RawCommunication::new(
                 get_communication_port_name().as_slice(),
                 Some(options),
-            )
-            .unwrap(),
-            stop_event: Event::new().unwrap(),
-        }
+            )?,
+            stop_event: Event::new().ok_or(CommunicationError::NoMemory)?,
+        })
     }
 
     pub fn send_message(
@@ -60,38 +66,48 @@ impl Dispatcher {
         self.stop_event.signal();
     }
 
-    pub fn process_blocking<Handler: FilterBufferHandler>(&self, handler: Handler) {
-        let mut overlapped = Box::pin(Overlapped::new().unwrap());
+    ///
+    /// Receives and answers messages until stopped or the handler is cancelled,
+    /// a failing port is returned as an error
+    ///
+    pub fn process_blocking<Handler: FilterBufferHandler>(
+        &self,
+        handler: Handler,
+        buffers: &BufferSizes,
+    ) -> anyhow::Result<(), CommunicationError> {
+        let mut overlapped = Box::pin(Overlapped::new().ok_or(CommunicationError::NoMemory)?);
         let handles = [self.stop_event.handle(), overlapped.ov().hEvent];
 
-        let mut send_buffer = FilterMessageBuffer::new(MAX_KM_MESSAGE_RECEIVE_SIZE);
-        let mut reply_buffer = FilterReplyBuffer::new(MAX_UM_REPLY_MESSAGE_SIZE);
+        let mut send_buffer = FilterMessageBuffer::new(buffers.km_message as _);
+        let mut reply_buffer = FilterReplyBuffer::new(buffers.um_reply as _);
 
-        loop {
-            let status = unsafe {
+        while !handler.is_cancelled() {
+            unsafe {
                 self.raw_communication
                     .get_message_overlapped_raw(send_buffer.mut_buffer(), overlapped.mut_ov())
-            };
-
-            if status.is_err() {
-                panic!(
-                    "Failed to receive km message status: {:#?}",
-                    status.unwrap_err()
-                );
             }
+            .inspect_err(|e| tracing::error!("Failed to receive km message: {:?}", e))?;
 
-            let status =
-                unsafe { WaitForMultipleObjects(2, handles.as_ptr(), false as _, INFINITE) };
-            match status {
-                WAIT_OBJECT_0 => unsafe {
-                    CancelIoEx(self.raw_communication.handle(), overlapped.ov());
-                    return;
-                },
-                WAIT_OBJECT_1 => {}
-                _ => panic!(
-                    "Unknown waiting result from WaitForMultipleObjects: {:x}",
-                    status
-                ),
+            loop {
+                let status = unsafe {
+                    WaitForMultipleObjects(2, handles.as_ptr(), false as _, CANCEL_POLL_INTERVAL_MS)
+                };
+                match status {
+                    WAIT_OBJECT_1 => break,
+                    WAIT_TIMEOUT if !handler.is_cancelled() => {}
+                    WAIT_OBJECT_0 | WAIT_TIMEOUT => {
+                        self.cancel_receive(&overlapped);
+                        return Ok(());
+                    }
+                    _ => {
+                        tracing::error!(
+                            "Unknown waiting result from WaitForMultipleObjects: {:x}",
+                            status
+                        );
+                        self.cancel_receive(&overlapped);
+                        return Err(CommunicationError::Wait(status));
+                    }
+                }
             }
 
             let message_size = unsafe {
@@ -103,7 +119,8 @@ impl Dispatcher {
                     false as _,
                 ) {
                     let last_error = GetLastError();
-                    panic!("GetOverlappedResult returned false, last error {last_error}");
+                    tracing::error!("GetOverlappedResult returned false, last error {last_error}");
+                    return Err(CommunicationError::Completion(last_error));
                 }
 
                 transfered
@@ -121,15 +138,35 @@ impl Dispatcher {
                     Err(_) => reply_parsed.construct_reply(&send_parsed, STATUS_UNSUCCESSFUL),
                 };
 
-                let _ = unsafe {
+                match unsafe {
                     self.raw_communication
                         .reply_message_raw(reply_buffer.as_buffer())
+                } {
+                    Ok(()) | Err(CommunicationError::NoWaiterPresent) => {}
+                    Err(e) => {
+                        tracing::error!("Failed to send raw message: {:?}", e);
+                        return Err(e);
+                    }
                 }
-                .inspect_err(|e| match e {
-                    CommunicationError::NoWaiterPresent => {}
-                    _ => panic!("Failed to send raw message: {:#?}", e),
-                });
             }
         }
+
+        Ok(())
+    }
+
+    ///
+    /// Takes back a pending receive, the buffers it writes to must outlive it
+    ///
+    fn cancel_receive(&self, overlapped: &Overlapped) {
+        unsafe {
+            CancelIoEx(self.raw_communication.handle(), overlapped.ov());
+            let mut transfered: u32 = 0;
+            GetOverlappedResult(
+                self.raw_communication.handle(),
+                overlapped.ov(),
+                &mut transfered,
+                true as _,
+            );
+        }
     }
 }
@@ -1,36 +1,58 @@
 use std::marker::PhantomData;
 
-use kmum_common::{KmMessage, KmReplyMessage, UmSendMessage, MAX_UM_SEND_MESSAGE_BUFFER_SIZE};
+use kmum_common::{
+    handshake::DriverHandshake, ClientConnectMessage, ClientConnectMode, KmReply, KmReplyMessage,
+    UmSendMessage, MAX_UM_SEND_MESSAGE_BUFFER_SIZE,
+};
 use windows_sys::Wdk::Foundation::NonPagedPoolExecute;
 
 use super::{
+    batch::BatchStatistics,
     dispatcher::{Dispatcher, FilterBufferHandler},
-    CommunicationError, CommunicationInterface, EventProcessor,
+    handshake::perform_handshake,
+    request::{reply_result, RequestContext},
+    BatchHandler, CommunicationError, CommunicationInterface,
 };
 
 pub struct DriverCommunication {
     dispatcher: Dispatcher,
+    handshake: Option<DriverHandshake>,
+    requests: RequestContext,
+    statistics: BatchStatistics,
 }
 
 impl DriverCommunication {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new() -> anyhow::Result<Self, CommunicationError> {
+        Self::connect(ClientConnectMessage::new(ClientConnectMode::Any))
     }
 
-    pub fn new_test(test_pid: u64) -> Self {
-        Self {
-            dispatcher: Dispatcher::new(kmum_common::ClientConnectMessage::Testing {
-                filter_pid: test_pid,
-            }),
-        }
+    pub fn new_test(test_pid: u64) -> anyhow::Result<Self, CommunicationError> {
+        Self::connect(ClientConnectMessage::new(ClientConnectMode::Testing {
+            filter_pid: test_pid,
+        }))
     }
-}
 
-impl Default for DriverCommunication {
-    fn default() -> Self {
-        Self {
-            dispatcher: Dispatcher::new(kmum_common::ClientConnectMessage::Any),
-        }
+    pub fn connect(options: ClientConnectMessage) -> anyhow::Result<Self, CommunicationError> {
+        let mut communication = Self {
+            dispatcher: Dispatcher::new(options)?,
+            handshake: None,
+            requests: RequestContext::new(MAX_UM_SEND_MESSAGE_BUFFER_SIZE),
+            statistics: BatchStatistics::default(),
+        };
+
+        let handshake = perform_handshake(&communication, &options)?;
+        communication
+            .requests
+            .set_buffer_size(handshake.buffers.um_send as usize);
+        communication.handshake = Some(handshake);
+
+        Ok(communication)
+    }
+
+    pub fn handshake(&self) -> &DriverHandshake {
+        self.handshake
+            .as_ref()
+            .expect("DriverCommunication is only handed out after a successful handshake")
     }
 }
 
@@ -39,28 +61,46 @@ impl CommunicationInterface for DriverCommunication {
         &self,
         message: &UmSendMessage,
     ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
-        let mut send_buffer = vec![0u8; MAX_UM_SEND_MESSAGE_BUFFER_SIZE];
-        let mut reply_buffer = vec![0u8; MAX_UM_SEND_MESSAGE_BUFFER_SIZE];
-
-        let send_slice = postcard::to_slice(message, &mut send_buffer)
-            .map_err(|_| CommunicationError::Parsing)?;
-
-        let reply_size = self
-            .dispatcher
-            .send_message(&send_slice, Some(&mut reply_buffer))? as usize;
-
-        if reply_size > 0 {
-            postcard::from_bytes(&reply_buffer[..reply_size])
-                .map_err(|_| CommunicationError::Parsing)
-                .map(|reply| Some(reply))
-        } else {
-            Ok(None)
-        }
+        let mut send_buffer = self.requests.take_buffer();
+        let mut reply_buffer = self.requests.take_buffer();
+
+        let reply = self
+            .requests
+            .encode(message, &mut send_buffer)
+            .and_then(|(id, send_slice)| {
+                let reply_size = self
+                    .dispatcher
+                    .send_message(send_slice, Some(&mut reply_buffer))?
+                    as usize;
+
+                let reply: KmReply = postcard::from_bytes(&reply_buffer[..reply_size])
+                    .map_err(|_| CommunicationError::Parsing)?;
+                if reply.id != id {
+                    tracing::error!("Received the reply to request {} for {}", reply.id, id);
+                    return Err(CommunicationError::Parsing);
+                }
+
+                reply_result(reply)
+            });
+
+        self.requests.give_buffer(send_buffer);
+        self.requests.give_buffer(reply_buffer);
+
+        reply
+    }
+
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> anyhow::Result<(), CommunicationError> {
+        self.dispatcher.process_blocking(
+            CommunicationBatchCallback { handler },
+            &self.handshake().buffers,
+        )
     }
 
-    fn process_blocking<P: EventProcessor>(&self, processor: P) {
-        self.dispatcher
-            .process_blocking(CommunicationProcessorCallback { processor });
+    fn statistics(&self) -> &BatchStatistics {
+        &self.statistics
     }
 
     fn stop(&self) {
@@ -68,42 +108,23 @@ impl CommunicationInterface for DriverCommunication {
     }
 }
 
-struct CommunicationProcessorCallback<P: EventProcessor> {
-    processor: P,
+struct CommunicationBatchCallback<H: BatchHandler> {
+    handler: H,
 }
 
-impl<P> FilterBufferHandler for CommunicationProcessorCallback<P>
+impl<H> FilterBufferHandler for CommunicationBatchCallback<H>
 where
-    P: EventProcessor,
+    H: BatchHandler,
 {
     fn handle_buffer(
         &self,
         receive_buffer: &[u8],
         _reply_buffer: &mut [u8],
     ) -> anyhow::Result<(), CommunicationError> {
-        let mut km_iter = KmMessageIterator {
-            buffer: receive_buffer,
-        };
-
-        let _ = self.processor.process(&mut km_iter);
-
-        Ok(())
+        self.handler.handle_batch(receive_buffer)
     }
-}
-
-pub struct KmMessageIterator<'a> {
-    buffer: &'a [u8],
-}
-
-impl<'a> Iterator for KmMessageIterator<'a> {
-    type Item = KmMessage;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Ok((message, remaining)) = postcard::take_from_bytes::<KmMessage>(&self.buffer) {
-            self.buffer = remaining;
-            Some(message)
-        } else {
-            None
-        }
+    fn is_cancelled(&self) -> bool {
+        self.handler.is_cancelled()
     }
 }
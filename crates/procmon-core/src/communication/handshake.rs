@@ -0,0 +1,40 @@
+use kmum_common::{
+    handshake::{DriverHandshake, HandshakeError},
+    ClientConnectMessage, KmReplyMessage, UmSendMessage,
+};
+
+use super::{CommunicationError, CommunicationInterface};
+
+///
+/// Asks the other side of `communication` for the result of the negotiation
+/// started by `request` and validates the answer
+///
+pub fn perform_handshake<C: CommunicationInterface>(
+    communication: &C,
+    request: &ClientConnectMessage,
+) -> anyhow::Result<DriverHandshake, CommunicationError> {
+    let reply = communication
+        .send_message_blocking(&UmSendMessage::Handshake)
+        .map_err(|e| match e {
+            CommunicationError::Port => CommunicationError::Handshake(HandshakeError::Unanswered),
+            e => e,
+        })?;
+
+    match reply {
+        Some(KmReplyMessage::Handshake(result)) => {
+            let handshake = result.map_err(CommunicationError::Handshake)?;
+            handshake
+                .validate(request)
+                .map_err(CommunicationError::Handshake)?;
+
+            tracing::info!(
+                "Negotiated protocol v{} with capabilities {:?}",
+                handshake.preamble.version,
+                handshake.capabilities
+            );
+            Ok(handshake)
+        }
+        Some(_) => Err(CommunicationError::Parsing),
+        None => Err(CommunicationError::Handshake(HandshakeError::Unanswered)),
+    }
+}
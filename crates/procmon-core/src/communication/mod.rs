@@ -1,11 +1,35 @@
-use kmum_common::{KmMessage, KmReplyMessage, UmSendMessage};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use batch::{BatchDecoder, BatchStatistics, BorrowedBatchDecoder};
+use kmum_common::{
+    handshake::HandshakeError,
+    process::{ProcessInformation, UniqueProcessId},
+    serializable_ntstring::SerializableNtString,
+    KmMessage, KmMessageRef, KmReplyMessage, UmSendMessage,
+};
+
+/// Seconds between 1601-01-01 and 1970-01-01
+pub const FILETIME_UNIX_EPOCH_SECONDS: u64 = 11_644_473_600;
+
+#[cfg(windows)]
 mod dispatcher;
 mod message_handler;
+#[cfg(windows)]
 mod parsed;
+#[cfg(windows)]
 mod raw_communication;
 
+pub mod batch;
+pub mod capture;
+#[cfg(windows)]
 pub mod driver_communication;
+pub mod handshake;
+pub mod reconnect;
+pub mod recording;
+pub mod replay;
+mod request;
+pub mod socket;
+pub mod stream;
 
 #[derive(Debug)]
 pub enum CommunicationError {
@@ -14,6 +38,21 @@ pub enum CommunicationError {
     Port,
     NoWaiterPresent,
     TokioSender,
+    Handshake(HandshakeError),
+    /// Whoever asked for the result is gone
+    Cancelled,
+    /// The other side closed the connection
+    Disconnected,
+    /// Connecting to the driver port failed with this HRESULT
+    Connect(i32),
+    /// `FilterGetMessage` failed with this HRESULT
+    Receive(i32),
+    /// A pending receive completed with this Win32 error
+    Completion(u32),
+    /// `FilterReplyMessage` failed with this HRESULT
+    Reply(i32),
+    /// Waiting for a message returned this unexpected status
+    Wait(u32),
 }
 
 pub trait EventProcessor {
@@ -22,13 +61,116 @@ pub trait EventProcessor {
         I: Iterator<Item = KmMessage>;
 }
 
+///
+/// Receives events as views into the batch buffer, nothing is copied
+/// unless the processor asks for an owned message
+///
+pub trait BorrowedEventProcessor {
+    fn process_borrowed<'a, I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessageRef<'a>>;
+}
+
+///
+/// Receives every raw batch (header included) exactly as it came from the other side
+///
+pub trait BatchHandler {
+    fn handle_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError>;
+
+    ///
+    /// Checked every time the receive loop wakes up, `process_batches_blocking`
+    /// returns once it is set even if the communication itself keeps running
+    ///
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+///
+/// Lets a handler outlive the receive loops it is handed to, see `ReconnectingCommunication`
+///
+impl<H: BatchHandler + ?Sized> BatchHandler for &H {
+    fn handle_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError> {
+        (**self).handle_batch(batch)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        (**self).is_cancelled()
+    }
+}
+
 pub trait CommunicationInterface: Sync + Send + 'static {
     fn send_message_blocking(
         &self,
         message: &UmSendMessage,
     ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError>;
 
-    fn process_blocking<P: EventProcessor>(&self, processor: P);
+    ///
+    /// Process information for every uid in order, asked for in as few round trips as possible
+    ///
+    fn query_process_infos(
+        &self,
+        uids: &[UniqueProcessId],
+    ) -> anyhow::Result<Vec<Option<ProcessInformation>>, CommunicationError> {
+        request::query_batched(
+            self,
+            uids,
+            UmSendMessage::GetProcessInfoBatch,
+            |reply| match reply {
+                KmReplyMessage::ProcessInfoBatch(infos) => Some(infos),
+                _ => None,
+            },
+        )
+    }
+
+    fn query_exe_names(
+        &self,
+        uids: &[UniqueProcessId],
+    ) -> anyhow::Result<Vec<Option<SerializableNtString>>, CommunicationError> {
+        request::query_batched(
+            self,
+            uids,
+            UmSendMessage::GetExeNameBatch,
+            |reply| match reply {
+                KmReplyMessage::ExeNameBatch(names) => Some(names),
+                _ => None,
+            },
+        )
+    }
+
+    ///
+    /// Hands every received batch to `handler` until the communication is stopped or
+    /// the handler cancelled. Returns an error once the connection can not go on
+    ///
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> anyhow::Result<(), CommunicationError>;
+
+    fn process_blocking<P: EventProcessor>(
+        &self,
+        processor: P,
+    ) -> anyhow::Result<(), CommunicationError> {
+        self.process_batches_blocking(BatchDecoder::new(processor, self.statistics()))
+    }
+
+    fn process_borrowed_blocking<P: BorrowedEventProcessor>(
+        &self,
+        processor: P,
+    ) -> anyhow::Result<(), CommunicationError> {
+        self.process_batches_blocking(BorrowedBatchDecoder::new(processor, self.statistics()))
+    }
+
+    fn statistics(&self) -> &BatchStatistics;
 
     fn stop(&self);
 }
+
+/// The current time as a FILETIME, like the driver stamps events
+pub fn filetime_now() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    (since_epoch.as_nanos() / 100) as u64 + FILETIME_UNIX_EPOCH_SECONDS * 10_000_000
+}
@@ -1,7 +1,5 @@
 use windows_sys::Win32::{
-    Foundation::{
-        CloseHandle, ERROR_FLT_NO_WAITER_FOR_REPLY, ERROR_IO_PENDING, HANDLE, NO_ERROR, S_OK,
-    },
+    Foundation::{CloseHandle, ERROR_FLT_NO_WAITER_FOR_REPLY, ERROR_IO_PENDING, HANDLE, S_OK},
     Storage::InstallableFileSystems::{
         FilterConnectCommunicationPort, FilterGetMessage, FilterReplyMessage, FilterSendMessage,
     },
@@ -27,28 +25,31 @@ impl PortHandle {
     pub fn new(
         name: &[u16],
         connect_msg: Option<kmum_common::ClientConnectMessage>,
-    ) -> anyhow::Result<Self> {
+    ) -> anyhow::Result<Self, CommunicationError> {
         let mut handle = 0;
 
         let mut connect_buffer = [0u8; 1024];
-        let msg = connect_msg.unwrap_or_else(|| kmum_common::ClientConnectMessage::Any);
+        let msg = connect_msg.unwrap_or_else(|| {
+            kmum_common::ClientConnectMessage::new(kmum_common::ClientConnectMode::Any)
+        });
 
-        let context = postcard::to_slice(&msg, &mut connect_buffer).unwrap();
+        let context = postcard::to_slice(&msg, &mut connect_buffer)
+            .map_err(|_| CommunicationError::Parsing)?;
 
         let status = unsafe {
             FilterConnectCommunicationPort(
                 name.as_ptr(),
                 0,
                 context.as_ptr() as _,
-                connect_buffer.len() as _,
+                context.len() as _,
                 core::ptr::null(),
                 &mut handle,
             )
         };
-        anyhow::ensure!(
-            status == NO_ERROR as _,
-            "FilterConnectCommunicationPort failed"
-        );
+        if status != S_OK {
+            return Err(CommunicationError::Connect(status));
+        }
+
         Ok(Self { handle })
     }
 }
@@ -65,7 +66,7 @@ impl RawCommunication {
     pub fn new(
         name: &[u16],
         options: Option<kmum_common::ClientConnectMessage>,
-    ) -> anyhow::Result<Self> {
+    ) -> anyhow::Result<Self, CommunicationError> {
         let port = PortHandle::new(name, options)?;
 
         Ok(Self { port })
@@ -98,7 +99,7 @@ impl RawCommunication {
                 &mut bytes_written,
             )
         };
-        if status == S_OK as _ {
+        if status == S_OK {
             Ok(bytes_written)
         } else {
             Err(CommunicationError::Port)
@@ -121,7 +122,7 @@ impl RawCommunication {
             buffer.len() as _,
             core::ptr::null_mut(),
         );
-        if status == S_OK as _ {
+        if status == S_OK {
             Ok(())
         } else {
             Err(CommunicationError::Port)
@@ -147,7 +148,7 @@ impl RawCommunication {
         if status == hresult_from_win32(ERROR_IO_PENDING as _) {
             Ok(())
         } else {
-            Err(CommunicationError::Port)
+            Err(CommunicationError::Receive(status))
         }
     }
 
@@ -162,13 +163,12 @@ impl RawCommunication {
     ) -> anyhow::Result<(), CommunicationError> {
         let status =
             FilterReplyMessage(self.port.handle, buffer.as_ptr().cast(), buffer.len() as _);
-        if status == S_OK as _ {
+        if status == S_OK {
             Ok(())
         } else if status == ERROR_FLT_NO_WAITER_FOR_REPLY {
             Err(CommunicationError::NoWaiterPresent)
         } else {
-            println!("FilterReplyMessage with status: {:x}", status);
-            Err(CommunicationError::Port)
+            Err(CommunicationError::Reply(status))
         }
     }
 }
@@ -0,0 +1,282 @@
+//!
+//! Keeps a communication usable across restarts of the driver or the capture agent.
+//!
+//! Receive loops that fail hand the error to a single reconnecting thread, which
+//! connects again with the original connect options while the others wait for it
+//!
+use std::{
+    sync::{Arc, Condvar, Mutex, RwLock},
+    time::Duration,
+};
+
+use kmum_common::{KmReplyMessage, UmSendMessage};
+
+use super::{
+    batch::BatchStatistics, filetime_now, BatchHandler, CommunicationError, CommunicationInterface,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// Wait before the first attempt, doubled after every failed one
+    pub initial: Duration,
+    pub max: Duration,
+    /// Failed attempts in a row before giving up, `None` retries forever
+    pub attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(10),
+            attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max)
+    }
+}
+
+///
+/// A connection that was lost and came back
+///
+#[derive(Debug, Clone)]
+pub struct Outage {
+    /// FILETIME the connection was lost at
+    pub since: u64,
+    /// FILETIME the new connection was made at
+    pub until: u64,
+    /// Connection attempts it took to get back
+    pub attempts: u32,
+    /// The error that ended the previous connection
+    pub reason: String,
+}
+
+///
+/// Told about the connection going away and coming back
+///
+pub trait ConnectionObserver: Send + Sync + 'static {
+    fn on_disconnected(&self, _error: &CommunicationError) {}
+
+    fn on_reconnected(&self, _outage: &Outage) {}
+}
+
+impl ConnectionObserver for () {}
+
+struct Current<C> {
+    communication: Arc<C>,
+    /// Bumped on every reconnect so waiting loops can tell it already happened
+    generation: u64,
+}
+
+///
+/// `CommunicationInterface` that connects again with `connect` whenever a receive loop fails
+///
+pub struct ReconnectingCommunication<C, F> {
+    connect: F,
+    backoff: Backoff,
+    observer: Box<dyn ConnectionObserver>,
+    current: RwLock<Current<C>>,
+    /// Held while reconnecting, set once the backoff gave up
+    reconnecting: Mutex<bool>,
+    stopped: Mutex<bool>,
+    stop_signal: Condvar,
+    statistics: BatchStatistics,
+}
+
+impl<C, F> ReconnectingCommunication<C, F>
+where
+    C: CommunicationInterface,
+    F: Fn() -> Result<C, CommunicationError> + Send + Sync + 'static,
+{
+    ///
+    /// The first connection has to succeed, only later ones are retried
+    ///
+    pub fn connect<O: ConnectionObserver>(
+        connect: F,
+        backoff: Backoff,
+        observer: O,
+    ) -> anyhow::Result<Self, CommunicationError> {
+        Self::connect_retrying(connect, backoff, 1, observer)
+    }
+
+    ///
+    /// Same as `connect` but the first connection is retried with `backoff` too,
+    /// at most `attempts` times. Returns the last error once they run out
+    ///
+    pub fn connect_retrying<O: ConnectionObserver>(
+        connect: F,
+        backoff: Backoff,
+        attempts: u32,
+        observer: O,
+    ) -> anyhow::Result<Self, CommunicationError> {
+        let mut attempt = 0;
+        let communication = loop {
+            match connect() {
+                Ok(communication) => break communication,
+                Err(e) if attempt + 1 >= attempts => return Err(e),
+                Err(e) => {
+                    tracing::warn!("Connect attempt {} failed: {:?}", attempt + 1, e);
+                    std::thread::sleep(backoff.delay(attempt));
+                    attempt += 1;
+                }
+            }
+        };
+
+        Ok(Self {
+            connect,
+            backoff,
+            observer: Box::new(observer),
+            current: RwLock::new(Current {
+                communication: Arc::new(communication),
+                generation: 0,
+            }),
+            reconnecting: Mutex::new(false),
+            stopped: Mutex::new(false),
+            stop_signal: Condvar::new(),
+            statistics: BatchStatistics::default(),
+        })
+    }
+
+    /// The connection in use right now
+    pub fn communication(&self) -> Arc<C> {
+        self.current.read().unwrap().communication.clone()
+    }
+
+    /// Times a lost connection was made again
+    pub fn reconnects(&self) -> u64 {
+        self.current.read().unwrap().generation
+    }
+
+    fn current(&self) -> (Arc<C>, u64) {
+        let current = self.current.read().unwrap();
+        (current.communication.clone(), current.generation)
+    }
+
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap()
+    }
+
+    /// Returns true if stopped before `timeout` elapsed
+    fn wait_stopped(&self, timeout: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        let (stopped, _) = self
+            .stop_signal
+            .wait_timeout_while(stopped, timeout, |stopped| !*stopped)
+            .unwrap();
+
+        *stopped
+    }
+
+    ///
+    /// Replaces the connection of `generation` that failed with `error`,
+    /// returns right away if another loop already did
+    ///
+    fn reconnect(
+        &self,
+        generation: u64,
+        error: CommunicationError,
+        cancelled: impl Fn() -> bool,
+    ) -> anyhow::Result<(), CommunicationError> {
+        let mut gave_up = self.reconnecting.lock().unwrap();
+        if *gave_up {
+            return Err(error);
+        }
+        if self.current.read().unwrap().generation != generation {
+            return Ok(());
+        }
+
+        tracing::warn!("Connection lost: {:?}", error);
+        self.observer.on_disconnected(&error);
+        let since = filetime_now();
+
+        let mut attempt = 0;
+        loop {
+            if self.wait_stopped(self.backoff.delay(attempt)) || cancelled() {
+                return Err(error);
+            }
+            attempt += 1;
+
+            match (self.connect)() {
+                Ok(communication) => {
+                    self.statistics.new_session();
+
+                    let previous = {
+                        let mut current = self.current.write().unwrap();
+                        current.generation += 1;
+                        std::mem::replace(&mut current.communication, Arc::new(communication))
+                    };
+                    //Loops still waiting on the old connection move over
+                    previous.stop();
+                    if self.is_stopped() {
+                        self.communication().stop();
+                    }
+
+                    let outage = Outage {
+                        since,
+                        until: filetime_now(),
+                        attempts: attempt,
+                        reason: format!("{:?}", error),
+                    };
+                    tracing::info!("Connection is back: {:?}", outage);
+                    self.observer.on_reconnected(&outage);
+
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::warn!("Reconnect attempt {} failed: {:?}", attempt, e);
+                    if self.backoff.attempts.is_some_and(|max| attempt >= max) {
+                        *gave_up = true;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<C, F> CommunicationInterface for ReconnectingCommunication<C, F>
+where
+    C: CommunicationInterface,
+    F: Fn() -> Result<C, CommunicationError> + Send + Sync + 'static,
+{
+    fn send_message_blocking(
+        &self,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        self.communication().send_message_blocking(message)
+    }
+
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> anyhow::Result<(), CommunicationError> {
+        loop {
+            let (communication, generation) = self.current();
+            let result = communication.process_batches_blocking(&handler);
+
+            match result {
+                _ if self.is_stopped() || handler.is_cancelled() => return Ok(()),
+                //The connection was replaced under this loop
+                Ok(()) => continue,
+                Err(e) => self.reconnect(generation, e, || handler.is_cancelled())?,
+            }
+        }
+    }
+
+    fn statistics(&self) -> &BatchStatistics {
+        &self.statistics
+    }
+
+    fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.stop_signal.notify_all();
+
+        self.communication().stop();
+    }
+}
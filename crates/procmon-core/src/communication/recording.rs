@@ -0,0 +1,135 @@
+//!
+//! Writes everything a communication receives to a capture file, see `capture`
+//!
+
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    sync::Mutex,
+};
+
+use kmum_common::{KmReplyMessage, UmSendMessage};
+
+use super::{
+    batch::BatchStatistics, capture::CaptureWriter, BatchHandler, CommunicationError,
+    CommunicationInterface,
+};
+
+///
+/// `CommunicationInterface` that records every batch and process query reply of `inner`.
+///
+/// Recording is best effort, once writing fails the capture is closed and
+/// `inner` keeps running as if it was never wrapped
+///
+pub struct RecordingCommunication<C> {
+    inner: C,
+    writer: Mutex<Option<CaptureWriter<BufWriter<File>>>>,
+}
+
+impl<C: CommunicationInterface> RecordingCommunication<C> {
+    pub fn create<P: AsRef<Path>>(inner: C, path: P) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            writer: Mutex::new(Some(CaptureWriter::create(path)?)),
+        })
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// False once writing to the capture failed
+    pub fn is_recording(&self) -> bool {
+        self.writer.lock().unwrap().is_some()
+    }
+
+    pub fn flush(&self) {
+        self.record(|writer| writer.flush());
+    }
+
+    fn record<F>(&self, write: F)
+    where
+        F: FnOnce(&mut CaptureWriter<BufWriter<File>>) -> io::Result<()>,
+    {
+        let mut guard = self.writer.lock().unwrap();
+        let Some(writer) = guard.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = write(writer) {
+            tracing::error!("Failed to write to the capture, recording stopped: {}", e);
+            *guard = None;
+        }
+    }
+
+    fn record_reply(&self, message: &UmSendMessage, reply: &KmReplyMessage) {
+        self.record(|writer| match (message, reply) {
+            (UmSendMessage::GetProcessInfo(uid), KmReplyMessage::ProcessInfo(info)) => {
+                writer.write_process(*uid, Some(info))
+            }
+            (UmSendMessage::GetExeName(uid), KmReplyMessage::ExeName(name)) => {
+                writer.write_exe_name(*uid, Some(name))
+            }
+            (UmSendMessage::GetProcessInfoBatch(uids), KmReplyMessage::ProcessInfoBatch(infos)) => {
+                uids.iter()
+                    .zip(infos)
+                    .try_for_each(|(uid, info)| writer.write_process(*uid, info.as_ref()))
+            }
+            (UmSendMessage::GetExeNameBatch(uids), KmReplyMessage::ExeNameBatch(names)) => uids
+                .iter()
+                .zip(names)
+                .try_for_each(|(uid, name)| writer.write_exe_name(*uid, name.as_ref())),
+            _ => Ok(()),
+        });
+    }
+}
+
+struct RecordingHandler<'a, C, H> {
+    recording: &'a RecordingCommunication<C>,
+    handler: H,
+}
+
+impl<C: CommunicationInterface, H: BatchHandler> BatchHandler for RecordingHandler<'_, C, H> {
+    fn handle_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError> {
+        self.recording.record(|writer| writer.write_batch(batch));
+        self.handler.handle_batch(batch)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.handler.is_cancelled()
+    }
+}
+
+impl<C: CommunicationInterface> CommunicationInterface for RecordingCommunication<C> {
+    fn send_message_blocking(
+        &self,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        let reply = self.inner.send_message_blocking(message)?;
+        if let Some(reply) = &reply {
+            self.record_reply(message, reply);
+        }
+
+        Ok(reply)
+    }
+
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> anyhow::Result<(), CommunicationError> {
+        self.inner.process_batches_blocking(RecordingHandler {
+            recording: self,
+            handler,
+        })
+    }
+
+    fn statistics(&self) -> &BatchStatistics {
+        self.inner.statistics()
+    }
+
+    fn stop(&self) {
+        self.inner.stop();
+        self.flush();
+    }
+}
@@ -0,0 +1,223 @@
+//!
+//! Plays a capture back as if it was received live, see `capture`
+//!
+
+use std::{
+    fmt::Display,
+    io,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use kmum_common::{
+    process::UniqueProcessId, serializable_ntstring::SerializableNtString, KmReplyMessage,
+    UmSendMessage,
+};
+
+use super::{
+    batch::BatchStatistics, capture::Capture, BatchHandler, CommunicationError,
+    CommunicationInterface,
+};
+
+/// Longest a receive loop sleeps before looking at its handler again
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Batches are handed out with the gaps they were recorded with
+    Original,
+    /// Gaps are divided by the factor
+    Scaled(f64),
+    /// No waiting at all
+    Unthrottled,
+}
+
+impl ReplaySpeed {
+    fn scale(&self, elapsed: Duration) -> Option<Duration> {
+        match *self {
+            ReplaySpeed::Original => Some(elapsed),
+            ReplaySpeed::Scaled(factor) => Some(elapsed.div_f64(factor)),
+            ReplaySpeed::Unthrottled => None,
+        }
+    }
+}
+
+///
+/// `original`, `max` or a factor like `4` or `4x`
+///
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(ReplaySpeed::Original),
+            "max" => Ok(ReplaySpeed::Unthrottled),
+            _ => {
+                let factor: f64 = s
+                    .strip_suffix('x')
+                    .unwrap_or(s)
+                    .parse()
+                    .map_err(|_| format!("Invalid replay speed: {}", s))?;
+
+                if factor.is_finite() && factor > 0.0 {
+                    Ok(ReplaySpeed::Scaled(factor))
+                } else {
+                    Err(format!("Replay speed has to be above 0: {}", s))
+                }
+            }
+        }
+    }
+}
+
+impl Display for ReplaySpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplaySpeed::Original => write!(f, "original"),
+            ReplaySpeed::Scaled(factor) => write!(f, "{}x", factor),
+            ReplaySpeed::Unthrottled => write!(f, "max"),
+        }
+    }
+}
+
+///
+/// `CommunicationInterface` over a recorded capture.
+///
+/// Every batch is handed out once across all receive loops, which return once the
+/// capture is played through. Process queries are answered from what was recorded
+///
+pub struct ReplayCommunication {
+    capture: Capture,
+    speed: ReplaySpeed,
+    next_batch: AtomicUsize,
+    /// Set by the first receive loop
+    started: OnceLock<Instant>,
+    stopped: Mutex<bool>,
+    stop_signal: Condvar,
+    statistics: BatchStatistics,
+}
+
+impl ReplayCommunication {
+    pub fn open<P: AsRef<Path>>(path: P, speed: ReplaySpeed) -> io::Result<Self> {
+        Ok(Self::new(Capture::open(path)?, speed))
+    }
+
+    pub fn new(capture: Capture, speed: ReplaySpeed) -> Self {
+        Self {
+            capture,
+            speed,
+            next_batch: AtomicUsize::new(0),
+            started: OnceLock::new(),
+            stopped: Mutex::new(false),
+            stop_signal: Condvar::new(),
+            statistics: BatchStatistics::default(),
+        }
+    }
+
+    pub fn capture(&self) -> &Capture {
+        &self.capture
+    }
+
+    /// True once every batch was handed to a receive loop
+    pub fn is_finished(&self) -> bool {
+        self.next_batch.load(Ordering::Relaxed) >= self.capture.batches.len()
+    }
+
+    ///
+    /// Sleeps until `due`, returns false if stopped or cancelled first
+    ///
+    fn wait_until<H: BatchHandler>(&self, due: Instant, handler: &H) -> bool {
+        let mut stopped = self.stopped.lock().unwrap();
+        loop {
+            if *stopped || handler.is_cancelled() {
+                return false;
+            }
+
+            let now = Instant::now();
+            if now >= due {
+                return true;
+            }
+
+            let timeout = (due - now).min(CANCEL_POLL_INTERVAL);
+            stopped = self.stop_signal.wait_timeout(stopped, timeout).unwrap().0;
+        }
+    }
+
+    fn exe_name(&self, uid: UniqueProcessId) -> Option<SerializableNtString> {
+        match self.capture.exe_names.get(&uid) {
+            Some(name) => name.clone(),
+            None => self
+                .capture
+                .processes
+                .get(&uid)
+                .and_then(|info| info.as_ref().map(|info| info.path.clone())),
+        }
+    }
+}
+
+impl CommunicationInterface for ReplayCommunication {
+    fn send_message_blocking(
+        &self,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        //Like the driver, a process that is not known gets no reply
+        match message {
+            UmSendMessage::GetProcessInfo(uid) => Ok(self
+                .capture
+                .processes
+                .get(uid)
+                .cloned()
+                .flatten()
+                .map(KmReplyMessage::ProcessInfo)),
+            UmSendMessage::GetExeName(uid) => Ok(self.exe_name(*uid).map(KmReplyMessage::ExeName)),
+            UmSendMessage::GetProcessInfoBatch(uids) => Ok(Some(KmReplyMessage::ProcessInfoBatch(
+                uids.iter()
+                    .map(|uid| self.capture.processes.get(uid).cloned().flatten())
+                    .collect(),
+            ))),
+            UmSendMessage::GetExeNameBatch(uids) => Ok(Some(KmReplyMessage::ExeNameBatch(
+                uids.iter().map(|uid| self.exe_name(*uid)).collect(),
+            ))),
+            UmSendMessage::Handshake => Err(CommunicationError::Port),
+        }
+    }
+
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> anyhow::Result<(), CommunicationError> {
+        let started = *self.started.get_or_init(Instant::now);
+
+        loop {
+            let index = self.next_batch.fetch_add(1, Ordering::Relaxed);
+            let Some(batch) = self.capture.batches.get(index) else {
+                return Ok(());
+            };
+
+            let due = match self.speed.scale(batch.elapsed) {
+                Some(offset) => started + offset,
+                None => Instant::now(),
+            };
+            if !self.wait_until(due, &handler) {
+                return Ok(());
+            }
+
+            if let Err(e) = handler.handle_batch(&batch.data) {
+                tracing::error!("Failed to handle replayed batch {}: {:?}", index, e);
+            }
+        }
+    }
+
+    fn statistics(&self) -> &BatchStatistics {
+        &self.statistics
+    }
+
+    fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.stop_signal.notify_all();
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+};
+
+use kmum_common::{
+    process::UniqueProcessId, KmReply, KmReplyMessage, RequestError, RequestId, UmRequest,
+    UmSendMessage, MAX_BATCH_QUERY,
+};
+
+use super::{CommunicationError, CommunicationInterface};
+
+///
+/// Hands out request ids and keeps request buffers around between calls,
+/// shared by every thread sending on the same communication
+///
+pub(crate) struct RequestContext {
+    next_id: AtomicU32,
+    buffers: Mutex<Vec<Vec<u8>>>,
+    buffer_size: usize,
+}
+
+impl RequestContext {
+    pub(crate) fn new(buffer_size: usize) -> Self {
+        Self {
+            next_id: AtomicU32::new(0),
+            buffers: Mutex::new(Vec::new()),
+            buffer_size,
+        }
+    }
+
+    pub(crate) fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.buffer_size = buffer_size;
+        self.buffers.get_mut().unwrap().clear();
+    }
+
+    pub(crate) fn next_id(&self) -> RequestId {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// A buffer of the negotiated size, reused if one was given back
+    pub(crate) fn take_buffer(&self) -> Vec<u8> {
+        self.buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![0u8; self.buffer_size])
+    }
+
+    pub(crate) fn give_buffer(&self, buffer: Vec<u8>) {
+        if buffer.len() == self.buffer_size {
+            self.buffers.lock().unwrap().push(buffer);
+        }
+    }
+
+    ///
+    /// Serializes `message` with a fresh id into `buffer`
+    ///
+    pub(crate) fn encode<'b>(
+        &self,
+        message: &UmSendMessage,
+        buffer: &'b mut [u8],
+    ) -> anyhow::Result<(RequestId, &'b mut [u8]), CommunicationError> {
+        let id = self.next_id();
+        let request = UmRequest { id, message };
+
+        postcard::to_slice(&request, buffer)
+            .map(|serialized| (id, serialized))
+            .map_err(|_| CommunicationError::Parsing)
+    }
+}
+
+///
+/// Turns a decoded reply into what `send_message_blocking` returns
+///
+pub(crate) fn reply_result(
+    reply: KmReply,
+) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+    reply.result.map_err(|e| match e {
+        RequestError::Failed => CommunicationError::Port,
+        RequestError::Malformed => CommunicationError::Parsing,
+    })
+}
+
+///
+/// Resolves `uids` with as few batch requests as possible, short replies
+/// are continued with the uids they left out
+///
+pub(crate) fn query_batched<C, T, R, U>(
+    communication: &C,
+    uids: &[UniqueProcessId],
+    request: R,
+    unpack: U,
+) -> anyhow::Result<Vec<Option<T>>, CommunicationError>
+where
+    C: CommunicationInterface + ?Sized,
+    R: Fn(Vec<UniqueProcessId>) -> UmSendMessage,
+    U: Fn(KmReplyMessage) -> Option<Vec<Option<T>>>,
+{
+    let mut resolved = Vec::with_capacity(uids.len());
+
+    while resolved.len() < uids.len() {
+        let remaining = &uids[resolved.len()..];
+        let chunk = &remaining[..remaining.len().min(MAX_BATCH_QUERY)];
+
+        let entries = match communication.send_message_blocking(&request(chunk.to_vec()))? {
+            Some(reply) => unpack(reply).ok_or_else(|| {
+                tracing::error!("Received another type of reply to a batch query");
+                CommunicationError::Parsing
+            })?,
+            None => return Err(CommunicationError::Parsing),
+        };
+
+        //Not even one entry fit, asking again would not help
+        if entries.is_empty() || entries.len() > chunk.len() {
+            return Err(CommunicationError::Parsing);
+        }
+        resolved.extend(entries);
+    }
+
+    Ok(resolved)
+}
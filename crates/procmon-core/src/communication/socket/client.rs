@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use kmum_common::{
+    handshake::DriverHandshake, ClientConnectMessage, KmReply, KmReplyMessage, RequestId,
+    UmSendMessage, MAX_UM_SEND_MESSAGE_BUFFER_SIZE,
+};
+
+use super::{io_error, read_frame, write_frame, FrameKind, SocketEndpoint, SocketStream};
+use crate::communication::{
+    batch::BatchStatistics,
+    handshake::perform_handshake,
+    request::{reply_result, RequestContext},
+    BatchHandler, CommunicationError, CommunicationInterface,
+};
+
+/// Batches waiting for a processor, the reader drops new batches past this
+const PENDING_BATCHES: usize = 64;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type ReplySender = Sender<Result<Option<KmReplyMessage>, CommunicationError>>;
+
+///
+/// `CommunicationInterface` connected to a `SocketPublisher` instead of the driver port.
+///
+/// A reader thread owns the receiving half of the stream, it routes replies to the
+/// waiting requests and queues batches for `process_batches_blocking`.
+///
+/// Replies and batches share the stream, so the reader never waits for a processor:
+/// once the queue is full new batches are dropped like the driver drops events it can
+/// not queue. The gap shows up in `lost_batches` and requests keep being answered
+/// even when nobody processes batches
+///
+pub struct SocketCommunication {
+    stream: SocketStream,
+    writer: Mutex<SocketStream>,
+    pending: Arc<Mutex<HashMap<RequestId, ReplySender>>>,
+    batches: Mutex<Receiver<Vec<u8>>>,
+    requests: RequestContext,
+    stop_signal: AtomicBool,
+    handshake: Option<DriverHandshake>,
+    statistics: BatchStatistics,
+}
+
+impl SocketCommunication {
+    pub fn connect(
+        endpoint: &SocketEndpoint,
+        options: ClientConnectMessage,
+    ) -> anyhow::Result<Self, CommunicationError> {
+        let stream = endpoint
+            .connect()
+            .map_err(|e| io_error(&format!("Failed to connect to {}", endpoint), e))?;
+
+        Self::from_stream(stream, options)
+    }
+
+    pub fn from_stream(
+        stream: SocketStream,
+        options: ClientConnectMessage,
+    ) -> anyhow::Result<Self, CommunicationError> {
+        let mut writer = stream
+            .try_clone()
+            .map_err(|e| io_error("Failed to clone the socket", e))?;
+        let reader = stream
+            .try_clone()
+            .map_err(|e| io_error("Failed to clone the socket", e))?;
+
+        let mut connect = [0u8; 64];
+        let connect =
+            postcard::to_slice(&options, &mut connect).map_err(|_| CommunicationError::Parsing)?;
+        write_frame(&mut writer, FrameKind::Connect, &[connect])
+            .map_err(|e| io_error("Failed to send the connect message", e))?;
+
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let (batch_sender, batches) = mpsc::sync_channel(PENDING_BATCHES);
+
+        let reader_pending = pending.clone();
+        std::thread::spawn(move || read_loop(reader, reader_pending, batch_sender));
+
+        let mut communication = Self {
+            stream,
+            writer: Mutex::new(writer),
+            pending,
+            batches: Mutex::new(batches),
+            requests: RequestContext::new(MAX_UM_SEND_MESSAGE_BUFFER_SIZE),
+            stop_signal: AtomicBool::new(false),
+            handshake: None,
+            statistics: BatchStatistics::default(),
+        };
+
+        let handshake = perform_handshake(&communication, &options)?;
+        communication
+            .requests
+            .set_buffer_size(handshake.buffers.um_send as usize);
+        communication.handshake = Some(handshake);
+
+        Ok(communication)
+    }
+
+    pub fn handshake(&self) -> &DriverHandshake {
+        self.handshake
+            .as_ref()
+            .expect("SocketCommunication is only handed out after a successful handshake")
+    }
+}
+
+impl CommunicationInterface for SocketCommunication {
+    fn send_message_blocking(
+        &self,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        let mut send_buffer = self.requests.take_buffer();
+        let (id, send_slice) = match self.requests.encode(message, &mut send_buffer) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                self.requests.give_buffer(send_buffer);
+                return Err(e);
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+
+        let sent = write_frame(
+            &mut *self.writer.lock().unwrap(),
+            FrameKind::Request,
+            &[send_slice],
+        );
+        self.requests.give_buffer(send_buffer);
+        if let Err(e) = sent {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(io_error("Failed to send a request", e));
+        }
+
+        match receiver.recv_timeout(REQUEST_TIMEOUT) {
+            Ok(reply) => reply,
+            Err(RecvTimeoutError::Timeout) => {
+                self.pending.lock().unwrap().remove(&id);
+                tracing::error!("Request {} was not answered in time", id);
+                Err(CommunicationError::Port)
+            }
+            //The reader exited and dropped every waiter
+            Err(RecvTimeoutError::Disconnected) => Err(CommunicationError::Disconnected),
+        }
+    }
+
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> anyhow::Result<(), CommunicationError> {
+        while !self.stop_signal.load(Ordering::Acquire) && !handler.is_cancelled() {
+            let batch = self
+                .batches
+                .lock()
+                .unwrap()
+                .recv_timeout(STOP_POLL_INTERVAL);
+
+            match batch {
+                Ok(batch) => {
+                    if let Err(e) = handler.handle_batch(&batch) {
+                        tracing::error!("Failed to handle batch: {:?}", e);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                //The reader only exits once the stream failed
+                Err(RecvTimeoutError::Disconnected) => {
+                    if self.stop_signal.load(Ordering::Acquire) {
+                        break;
+                    }
+                    return Err(CommunicationError::Disconnected);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statistics(&self) -> &BatchStatistics {
+        &self.statistics
+    }
+
+    fn stop(&self) {
+        self.stop_signal.store(true, Ordering::Release);
+        self.stream.shutdown();
+    }
+}
+
+///
+/// The reader is not joined, it exits once the shutdown stream fails
+/// or the batch queue is dropped along with `self`
+///
+impl Drop for SocketCommunication {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn read_loop(
+    mut stream: SocketStream,
+    pending: Arc<Mutex<HashMap<RequestId, ReplySender>>>,
+    batches: SyncSender<Vec<u8>>,
+) {
+    let mut body = Vec::new();
+
+    loop {
+        let kind = match read_frame(&mut stream, &mut body) {
+            Ok(kind) => kind,
+            Err(e) => {
+                tracing::info!("Socket reader stopped: {}", e);
+                break;
+            }
+        };
+
+        match kind {
+            FrameKind::Batch => match batches.try_send(std::mem::take(&mut body)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!("Batch queue is full, dropping a batch");
+                }
+                Err(TrySendError::Disconnected(_)) => break,
+            },
+            FrameKind::Reply => {
+                let Ok(reply) = postcard::from_bytes::<KmReply>(&body) else {
+                    tracing::error!("Received a malformed reply");
+                    break;
+                };
+
+                match pending.lock().unwrap().remove(&reply.id) {
+                    Some(waiter) => {
+                        let id = reply.id;
+                        if waiter.send(reply_result(reply)).is_err() {
+                            tracing::warn!("Request {} was given up on", id);
+                        }
+                    }
+                    None => tracing::warn!("Dropping the reply to request {}", reply.id),
+                }
+            }
+            kind => {
+                tracing::error!("Unexpected {:?} frame from the server", kind);
+                break;
+            }
+        }
+    }
+
+    pending.lock().unwrap().clear();
+}
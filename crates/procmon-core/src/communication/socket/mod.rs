@@ -0,0 +1,298 @@
+//!
+//! Carries the driver protocol over a byte stream so a client can run on another
+//! machine than the capture agent, or against a stand-in server in tests.
+//!
+//! Every frame is a little endian `u32` length followed by a kind byte and its body.
+//! Batches are forwarded exactly as the driver produced them, requests and replies
+//! are the `UmRequest` and `KmReply` envelopes of the driver port, their ids let
+//! several threads wait for their answers on the same stream
+//!
+
+use std::{
+    fmt::{self, Display},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    str::FromStr,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+use super::CommunicationError;
+
+mod client;
+mod server;
+
+pub use client::SocketCommunication;
+pub use server::{RequestResponder, SocketPublisher};
+
+/// Frames past this size are treated as a corrupted stream
+pub const MAX_SOCKET_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum FrameKind {
+    /// Client -> server, first frame of a connection, a `ClientConnectMessage`
+    Connect = 1,
+    /// Client -> server, an `UmRequest`
+    Request = 2,
+    /// Server -> client, the `KmReply` to a request
+    Reply = 3,
+    /// Server -> client, a batch with its header
+    Batch = 4,
+}
+
+impl FrameKind {
+    fn from_raw(kind: u8) -> Option<Self> {
+        match kind {
+            1 => Some(Self::Connect),
+            2 => Some(Self::Request),
+            3 => Some(Self::Reply),
+            4 => Some(Self::Batch),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Where a capture agent listens, `tcp://host:port` or `unix:///path/to/socket`.
+/// An address without a scheme is taken as TCP
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketEndpoint {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl SocketEndpoint {
+    pub fn connect(&self) -> io::Result<SocketStream> {
+        match self {
+            SocketEndpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address.as_str())?;
+                stream.set_nodelay(true)?;
+                Ok(SocketStream::Tcp(stream))
+            }
+            #[cfg(unix)]
+            SocketEndpoint::Unix(path) => UnixStream::connect(path).map(SocketStream::Unix),
+        }
+    }
+
+    ///
+    /// Listens on the endpoint, a Unix socket path must not exist yet
+    ///
+    pub fn bind(&self) -> io::Result<SocketListener> {
+        match self {
+            SocketEndpoint::Tcp(address) => {
+                TcpListener::bind(address.as_str()).map(SocketListener::Tcp)
+            }
+            #[cfg(unix)]
+            SocketEndpoint::Unix(path) => UnixListener::bind(path).map(SocketListener::Unix),
+        }
+    }
+}
+
+impl FromStr for SocketEndpoint {
+    type Err = String;
+
+    fn from_str(endpoint: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = endpoint.strip_prefix("tcp://") {
+            return Ok(Self::Tcp(address.to_string()));
+        }
+
+        if let Some(path) = endpoint.strip_prefix("unix://") {
+            #[cfg(unix)]
+            return Ok(Self::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(format!("Unix sockets are not supported here: {}", path));
+        }
+
+        if endpoint.contains("://") {
+            return Err(format!("Unknown endpoint scheme: {}", endpoint));
+        }
+
+        Ok(Self::Tcp(endpoint.to_string()))
+    }
+}
+
+impl Display for SocketEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketEndpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            #[cfg(unix)]
+            SocketEndpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+///
+/// Listening side of either transport
+///
+pub enum SocketListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl SocketListener {
+    pub fn accept(&self) -> io::Result<SocketStream> {
+        match self {
+            SocketListener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(SocketStream::Tcp(stream))
+            }
+            #[cfg(unix)]
+            SocketListener::Unix(listener) => listener
+                .accept()
+                .map(|(stream, _)| SocketStream::Unix(stream)),
+        }
+    }
+
+    ///
+    /// Where clients can reach the listener, resolves the port of a `:0` bind
+    ///
+    pub fn local_endpoint(&self) -> io::Result<SocketEndpoint> {
+        match self {
+            SocketListener::Tcp(listener) => listener
+                .local_addr()
+                .map(|address| SocketEndpoint::Tcp(address.to_string())),
+            #[cfg(unix)]
+            SocketListener::Unix(listener) => {
+                let address = listener.local_addr()?;
+                let path = address.as_pathname().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "unnamed unix socket")
+                })?;
+                Ok(SocketEndpoint::Unix(path.to_path_buf()))
+            }
+        }
+    }
+}
+
+///
+/// Connected stream of either transport
+///
+pub enum SocketStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl SocketStream {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            SocketStream::Tcp(stream) => stream.try_clone().map(SocketStream::Tcp),
+            #[cfg(unix)]
+            SocketStream::Unix(stream) => stream.try_clone().map(SocketStream::Unix),
+        }
+    }
+
+    /// Unblocks every thread reading from a clone of this stream
+    pub fn shutdown(&self) {
+        let _ = match self {
+            SocketStream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            SocketStream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl From<TcpStream> for SocketStream {
+    fn from(stream: TcpStream) -> Self {
+        SocketStream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for SocketStream {
+    fn from(stream: UnixStream) -> Self {
+        SocketStream::Unix(stream)
+    }
+}
+
+impl Read for SocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SocketStream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            SocketStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for SocketStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SocketStream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            SocketStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SocketStream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            SocketStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+///
+/// Writes a whole frame, `parts` are concatenated into its body
+///
+fn write_frame<W: Write>(writer: &mut W, kind: FrameKind, parts: &[&[u8]]) -> io::Result<()> {
+    let length = 1 + parts.iter().map(|part| part.len()).sum::<usize>();
+    if length > MAX_SOCKET_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame exceeds the maximum size",
+        ));
+    }
+
+    writer.write_all(&(length as u32).to_le_bytes())?;
+    writer.write_all(&[kind as u8])?;
+    for part in parts {
+        writer.write_all(part)?;
+    }
+    writer.flush()
+}
+
+///
+/// Reads the next frame into `body`, replacing its content
+///
+fn read_frame<R: Read>(reader: &mut R, body: &mut Vec<u8>) -> io::Result<FrameKind> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_le_bytes(length) as usize;
+
+    if length == 0 || length > MAX_SOCKET_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid frame length {}", length),
+        ));
+    }
+
+    let mut kind = [0u8; 1];
+    reader.read_exact(&mut kind)?;
+    let kind = FrameKind::from_raw(kind[0]).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown frame kind {}", kind[0]),
+        )
+    })?;
+
+    body.clear();
+    body.resize(length - 1, 0);
+    reader.read_exact(body)?;
+
+    Ok(kind)
+}
+
+fn io_error(context: &str, error: io::Error) -> CommunicationError {
+    tracing::error!("{}: {}", context, error);
+    CommunicationError::Port
+}
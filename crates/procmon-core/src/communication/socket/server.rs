@@ -0,0 +1,188 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use kmum_common::{
+    ClientConnectMessage, KmReply, KmReplyMessage, RequestError, UmRequest, UmSendMessage,
+    MAX_UM_REPLY_MESSAGE_SIZE,
+};
+
+use super::{io_error, read_frame, write_frame, FrameKind, SocketStream};
+use crate::communication::{BatchHandler, CommunicationError, CommunicationInterface};
+
+///
+/// Answers the requests a remote client sends to a `SocketPublisher`
+///
+pub trait RequestResponder: Send + Sync + 'static {
+    fn respond(
+        &self,
+        connect: &ClientConnectMessage,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError>;
+}
+
+///
+/// Forwards requests as they are, a capture agent answers with the driver itself
+///
+impl<C: CommunicationInterface> RequestResponder for Arc<C> {
+    fn respond(
+        &self,
+        _connect: &ClientConnectMessage,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        self.send_message_blocking(message)
+    }
+}
+
+///
+/// Server side of one socket connection, the capture agent or a stand-in for it.
+///
+/// Batches handed to it are written to the client as they are,
+/// requests from the client are answered by a `RequestResponder` on its own thread
+///
+pub struct SocketPublisher {
+    stream: SocketStream,
+    writer: Arc<Mutex<SocketStream>>,
+    connect: ClientConnectMessage,
+    closed: Arc<AtomicBool>,
+}
+
+impl SocketPublisher {
+    ///
+    /// Waits for the connect message of a freshly accepted client
+    ///
+    pub fn accept<R: RequestResponder>(
+        stream: SocketStream,
+        responder: R,
+    ) -> anyhow::Result<Self, CommunicationError> {
+        let mut reader = stream
+            .try_clone()
+            .map_err(|e| io_error("Failed to clone the socket", e))?;
+        let writer = stream
+            .try_clone()
+            .map_err(|e| io_error("Failed to clone the socket", e))?;
+
+        let mut body = Vec::new();
+        let kind = read_frame(&mut reader, &mut body)
+            .map_err(|e| io_error("Failed to read the connect message", e))?;
+        if kind != FrameKind::Connect {
+            tracing::error!("Expected a connect message, received {:?}", kind);
+            return Err(CommunicationError::Parsing);
+        }
+
+        let connect: ClientConnectMessage =
+            postcard::from_bytes(&body).map_err(|_| CommunicationError::Parsing)?;
+
+        let publisher = Self {
+            stream,
+            writer: Arc::new(Mutex::new(writer)),
+            connect,
+            closed: Arc::new(AtomicBool::new(false)),
+        };
+
+        let writer = publisher.writer.clone();
+        let closed = publisher.closed.clone();
+        std::thread::spawn(move || {
+            serve_requests(reader, writer, connect, responder);
+            closed.store(true, Ordering::Release);
+        });
+
+        Ok(publisher)
+    }
+
+    pub fn connect_message(&self) -> &ClientConnectMessage {
+        &self.connect
+    }
+
+    /// Set once the client went away
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub fn send_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError> {
+        write_frame(
+            &mut *self.writer.lock().unwrap(),
+            FrameKind::Batch,
+            &[batch],
+        )
+        .map_err(|e| {
+            self.closed.store(true, Ordering::Release);
+            io_error("Failed to send a batch", e)
+        })
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.stream.shutdown();
+    }
+}
+
+impl Drop for SocketPublisher {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl BatchHandler for SocketPublisher {
+    fn handle_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError> {
+        self.send_batch(batch)
+    }
+}
+
+fn serve_requests<R: RequestResponder>(
+    mut reader: SocketStream,
+    writer: Arc<Mutex<SocketStream>>,
+    connect: ClientConnectMessage,
+    responder: R,
+) {
+    let mut body = Vec::new();
+    let mut reply = vec![0u8; MAX_UM_REPLY_MESSAGE_SIZE];
+
+    loop {
+        let kind = match read_frame(&mut reader, &mut body) {
+            Ok(kind) => kind,
+            Err(e) => {
+                tracing::info!("Client disconnected: {}", e);
+                return;
+            }
+        };
+
+        if kind != FrameKind::Request {
+            tracing::error!("Unexpected {:?} frame from the client", kind);
+            return;
+        }
+
+        let Some(id) = UmRequest::peek_id(&body) else {
+            tracing::error!("Received a request without an id");
+            return;
+        };
+
+        let result = match postcard::from_bytes::<UmRequest>(&body) {
+            Ok(request) => responder.respond(&connect, &request.message).map_err(|e| {
+                tracing::warn!("Failed to answer request {}: {:?}", id, e);
+                RequestError::Failed
+            }),
+            Err(_) => {
+                tracing::warn!("Failed to decode request {}", id);
+                Err(RequestError::Malformed)
+            }
+        };
+
+        //Replies are held to the size the driver port allows
+        let Ok(length) = KmReply::new(id, result).serialize_fitting(&mut reply) else {
+            tracing::error!("Failed to serialize the reply to request {}", id);
+            return;
+        };
+
+        let sent = write_frame(
+            &mut *writer.lock().unwrap(),
+            FrameKind::Reply,
+            &[&reply[..length]],
+        );
+        if let Err(e) = sent {
+            tracing::info!("Client disconnected: {}", e);
+            return;
+        }
+    }
+}
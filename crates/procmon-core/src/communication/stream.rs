@@ -0,0 +1,161 @@
+//!
+//! Async access to a `CommunicationInterface` for clients running on tokio.
+//!
+//! The blocking receive loop still runs on a blocking pool thread, events are
+//! handed to the stream through a bounded channel. Dropping the stream cancels
+//! its loop, the communication itself keeps running for everyone else
+//!
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use kmum_common::{KmMessage, KmReplyMessage, UmSendMessage};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use super::{
+    batch::{BatchStatistics, KmMessageIterator},
+    BatchHandler, CommunicationError, CommunicationInterface,
+};
+
+/// Events buffered between the receive loop and the consumer of a stream
+pub const DEFAULT_STREAM_CAPACITY: usize = 4096;
+
+///
+/// Cheap to clone handle over a shared communication
+///
+pub struct AsyncCommunication<C: CommunicationInterface> {
+    communication: Arc<C>,
+}
+
+impl<C: CommunicationInterface> Clone for AsyncCommunication<C> {
+    fn clone(&self) -> Self {
+        Self {
+            communication: self.communication.clone(),
+        }
+    }
+}
+
+impl<C: CommunicationInterface> AsyncCommunication<C> {
+    pub fn new(communication: C) -> Self {
+        Self::from_arc(Arc::new(communication))
+    }
+
+    pub fn from_arc(communication: Arc<C>) -> Self {
+        Self { communication }
+    }
+
+    pub fn communication(&self) -> &Arc<C> {
+        &self.communication
+    }
+
+    ///
+    /// Starts a receive loop feeding a new stream, must be called from a tokio runtime.
+    ///
+    /// Several streams can be open at once, each batch goes to only one of them
+    ///
+    pub fn events(&self) -> EventStream {
+        self.events_with_capacity(DEFAULT_STREAM_CAPACITY)
+    }
+
+    pub fn events_with_capacity(&self, capacity: usize) -> EventStream {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+
+        let communication = self.communication.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = communication.process_batches_blocking(StreamForwarder {
+                sender: sender.clone(),
+                statistics: communication.statistics(),
+            });
+
+            //The error that ended the loop is the last item
+            if let Err(e) = result {
+                let _ = sender.blocking_send(Err(e));
+            }
+        });
+
+        EventStream { receiver }
+    }
+
+    ///
+    /// Sends `message` from a blocking pool thread, dropping the future does not
+    /// take back a request that was already sent
+    ///
+    pub async fn send_message(
+        &self,
+        message: UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        let communication = self.communication.clone();
+
+        tokio::task::spawn_blocking(move || communication.send_message_blocking(&message))
+            .await
+            .map_err(|_| CommunicationError::Cancelled)?
+    }
+
+    /// Ends every stream, see `CommunicationInterface::stop`
+    pub fn stop(&self) {
+        self.communication.stop();
+    }
+}
+
+///
+/// Events in the order their batches were received, malformed batches are
+/// reported in place. Ends once the communication stops, after its error if it failed
+///
+pub struct EventStream {
+    receiver: Receiver<Result<KmMessage, CommunicationError>>,
+}
+
+impl Stream for EventStream {
+    type Item = Result<KmMessage, CommunicationError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+struct StreamForwarder<'a> {
+    sender: Sender<Result<KmMessage, CommunicationError>>,
+    statistics: &'a BatchStatistics,
+}
+
+impl StreamForwarder<'_> {
+    fn forward(
+        &self,
+        item: Result<KmMessage, CommunicationError>,
+    ) -> Result<(), CommunicationError> {
+        self.sender
+            .blocking_send(item)
+            .map_err(|_| CommunicationError::Cancelled)
+    }
+}
+
+impl BatchHandler for StreamForwarder<'_> {
+    fn handle_batch(&self, batch: &[u8]) -> anyhow::Result<(), CommunicationError> {
+        let (header, payload) = match self.statistics.split_batch(batch) {
+            Ok(split) => split,
+            Err(e) => return self.forward(Err(e)),
+        };
+
+        //Decoded up front so the statistics already hold the batch once a consumer sees it
+        let mut iter = KmMessageIterator::new(payload, header.event_count);
+        let messages: Vec<KmMessage> = iter.by_ref().collect();
+        self.statistics.record_events(&header, iter.decoded());
+
+        messages
+            .into_iter()
+            .try_for_each(|message| self.forward(Ok(message)))?;
+
+        if iter.decoded() != header.event_count {
+            return self.forward(Err(CommunicationError::Parsing));
+        }
+        Ok(())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
@@ -0,0 +1,1233 @@
+//!
+//! JSON representation of events, written as JSON Lines with one event per line.
+//!
+//! The layout is defined here rather than taken from the serde derives of
+//! `kmum_common`, those are the wire format and change along with it:
+//!
+//! ```text
+//! {"time":"2024-05-01T09:30:12.1234567Z","filetime":133590294121234567,
+//!  "pid":1234,"process_uid":77,"image":"C:\\Windows\\notepad.exe","tid":5678,
+//!  "class":"FileSystem","operation":"Write","details":{"length":512,"offset":0},
+//!  "path":"C:\\Temp\\a.txt","result":0,"duration":120,
+//!  "stack":[{"address":"0xfffff8031a2b3c4d","mode":"Kernel"}]}
+//! ```
+//!
+//! - Strings are UTF-8, unpaired surrogates are replaced with U+FFFD
+//! - `time` is UTC with the full 100ns precision, it is derived from the raw
+//!   `filetime` which is the one read back
+//! - Addresses are hex strings, kernel ones do not fit the numbers most JSON readers use
+//! - Registry previews are hex strings, value types and lock operations are
+//!   named with the raw number kept for unknown ones
+//! - Endpoints are `ip:port`, `[ip]:port` for IPv6
+//! - `image` is the image path of the process, it is left out if it was not
+//!   known when the event was written
+//!
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+use chrono::DateTime;
+use kmum_common::{
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventNetworkOperation,
+        EventProcessOperation, EventRegistryOperation, EventSessionOperation, EventStack,
+        FileLockOperation, FileSetInformation, FrameMode, IpAddress, NetworkEndpoints,
+        RegistryDataPreview, RegistryValueType, SimpleProcessDetails, SocketAddress, StackFrame,
+    },
+    serializable_ntstring::SerializableNtString,
+    KmMessage,
+};
+use nt_string::unicode_string::NtUnicodeString;
+use serde::{Deserialize, Serialize};
+
+use crate::communication::FILETIME_UNIX_EPOCH_SECONDS;
+
+/// FILETIME counts 100ns intervals since 1601-01-01
+const FILETIME_UNIX_EPOCH: i128 = FILETIME_UNIX_EPOCH_SECONDS as i128 * FILETIME_PER_SECOND;
+const FILETIME_PER_SECOND: i128 = 10_000_000;
+
+/// Up to the seconds, the fraction is added separately
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonEvent {
+    /// ISO-8601, only written for readers
+    pub time: String,
+    pub filetime: u64,
+    pub pid: u64,
+    pub process_uid: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    pub tid: u64,
+    /// `class`, `operation` and `details`
+    #[serde(flatten)]
+    pub operation: JsonOperation,
+    pub path: String,
+    pub result: i32,
+    /// In 100ns intervals
+    pub duration: u64,
+    /// Innermost frame first
+    pub stack: Vec<JsonFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "class")]
+pub enum JsonOperation {
+    Process(JsonProcessOperation),
+    FileSystem(JsonFileSystemOperation),
+    Registry(JsonRegistryOperation),
+    Network(JsonNetworkOperation),
+    Session(JsonSessionOperation),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", content = "details")]
+pub enum JsonProcessOperation {
+    ProcessCreate {
+        pid: u64,
+        cmd: Option<String>,
+    },
+    ProcessDestroy {
+        pid: u64,
+        exit_status: i32,
+    },
+    ThreadCreate {
+        tid: u64,
+        #[serde(with = "hex_address")]
+        start_address: u64,
+    },
+    ThreadExit {
+        tid: u64,
+    },
+    ImageLoad {
+        #[serde(with = "hex_address")]
+        base: u64,
+        size: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", content = "details")]
+pub enum JsonFileSystemOperation {
+    Create {
+        desired_access: u32,
+        share_mode: u16,
+        disposition: u32,
+        options: u32,
+        attribute: u16,
+        open_action: u32,
+    },
+    Read {
+        length: u64,
+        offset: i64,
+    },
+    Write {
+        length: u64,
+        offset: i64,
+    },
+    Close {},
+    QueryInformation {
+        information_class: u32,
+        length: u32,
+    },
+    SetInformation {
+        information: JsonSetInformation,
+    },
+    QueryDirectory {
+        pattern: String,
+        information_class: u32,
+        length: u32,
+    },
+    FileSystemControl {
+        control_code: u32,
+        input_length: u32,
+        output_length: u32,
+    },
+    LockControl {
+        #[serde(with = "lock_operation")]
+        operation: FileLockOperation,
+        offset: i64,
+        length: i64,
+        exclusive: bool,
+    },
+    Cleanup {},
+    FlushBuffers {},
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum JsonSetInformation {
+    Basic {
+        creation_time: i64,
+        last_access_time: i64,
+        last_write_time: i64,
+        change_time: i64,
+        attributes: u32,
+    },
+    Rename {
+        target: String,
+        replace_if_exists: bool,
+    },
+    Disposition {
+        delete: bool,
+    },
+    EndOfFile {
+        end_of_file: i64,
+    },
+    Allocation {
+        allocation_size: i64,
+    },
+    Other {
+        information_class: u32,
+        length: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", content = "details")]
+pub enum JsonRegistryOperation {
+    CreateKey {
+        desired_access: u32,
+        disposition: u32,
+    },
+    OpenKey {
+        desired_access: u32,
+    },
+    QueryKey {
+        information_class: u32,
+        length: u32,
+    },
+    SetValue {
+        value_name: String,
+        #[serde(with = "value_type")]
+        value_type: RegistryValueType,
+        data_size: u32,
+        #[serde(with = "preview")]
+        preview: RegistryDataPreview,
+    },
+    QueryValue {
+        value_name: String,
+        information_class: u32,
+        length: u32,
+    },
+    DeleteKey {},
+    DeleteValue {
+        value_name: String,
+    },
+    EnumerateKey {
+        index: u32,
+        information_class: u32,
+    },
+    EnumerateValue {
+        index: u32,
+        information_class: u32,
+    },
+    RenameKey {
+        new_name: String,
+    },
+    Flush {},
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", content = "details")]
+pub enum JsonNetworkOperation {
+    TcpConnect {
+        #[serde(flatten)]
+        endpoints: JsonEndpoints,
+    },
+    TcpAccept {
+        #[serde(flatten)]
+        endpoints: JsonEndpoints,
+    },
+    TcpSend {
+        #[serde(flatten)]
+        endpoints: JsonEndpoints,
+        length: u32,
+    },
+    TcpReceive {
+        #[serde(flatten)]
+        endpoints: JsonEndpoints,
+        length: u32,
+    },
+    TcpDisconnect {
+        #[serde(flatten)]
+        endpoints: JsonEndpoints,
+    },
+    UdpSend {
+        #[serde(flatten)]
+        endpoints: JsonEndpoints,
+        length: u32,
+    },
+    UdpReceive {
+        #[serde(flatten)]
+        endpoints: JsonEndpoints,
+        length: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonEndpoints {
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", content = "details")]
+pub enum JsonSessionOperation {
+    Disconnected { attempts: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonFrame {
+    #[serde(with = "hex_address")]
+    pub address: u64,
+    pub mode: JsonFrameMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JsonFrameMode {
+    User,
+    Kernel,
+}
+
+impl From<&KmMessage> for JsonEvent {
+    fn from(message: &KmMessage) -> Self {
+        let event = &message.event;
+
+        Self {
+            time: iso_time(event.date),
+            filetime: event.date,
+            pid: message.process.pid,
+            process_uid: message.process.unique_id,
+            image: None,
+            tid: event.thread,
+            operation: JsonOperation::from(&event.operation),
+            path: utf8(&event.path),
+            result: event.result,
+            duration: event.duration,
+            stack: message
+                .stack
+                .frames()
+                .iter()
+                .map(|frame| JsonFrame {
+                    address: frame.address,
+                    mode: match frame.mode {
+                        FrameMode::User => JsonFrameMode::User,
+                        FrameMode::Kernel => JsonFrameMode::Kernel,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+impl JsonEvent {
+    /// `message` along with the image path of its process
+    pub fn new(message: &KmMessage, image: Option<&SerializableNtString>) -> Self {
+        Self {
+            image: image.map(utf8),
+            ..Self::from(message)
+        }
+    }
+}
+
+///
+/// Fails for strings longer than a `UNICODE_STRING` holds and stacks deeper
+/// than `MAX_STACK_FRAMES`
+///
+impl TryFrom<JsonEvent> for KmMessage {
+    type Error = io::Error;
+
+    fn try_from(event: JsonEvent) -> io::Result<Self> {
+        let mut stack = EventStack::new();
+        for frame in &event.stack {
+            let frame = StackFrame {
+                address: frame.address,
+                mode: match frame.mode {
+                    JsonFrameMode::User => FrameMode::User,
+                    JsonFrameMode::Kernel => FrameMode::Kernel,
+                },
+            };
+            if !stack.try_push(frame) {
+                return Err(invalid_data(&format!(
+                    "stack has more than {} frames",
+                    stack.len()
+                )));
+            }
+        }
+
+        Ok(KmMessage {
+            event: EventCompoent {
+                date: event.filetime,
+                thread: event.tid,
+                operation: event.operation.try_into()?,
+                result: event.result,
+                path: nt_string(&event.path)?,
+                duration: event.duration,
+            },
+            process: SimpleProcessDetails {
+                pid: event.pid,
+                unique_id: event.process_uid,
+            },
+            stack,
+        })
+    }
+}
+
+///
+/// An event read back, along with the image path of its process if it was written
+///
+#[derive(Debug, Clone)]
+pub struct JsonRecord {
+    pub event: KmMessage,
+    pub image: Option<SerializableNtString>,
+}
+
+impl TryFrom<JsonEvent> for JsonRecord {
+    type Error = io::Error;
+
+    fn try_from(event: JsonEvent) -> io::Result<Self> {
+        let image = event.image.as_deref().map(nt_string).transpose()?;
+
+        Ok(Self {
+            event: event.try_into()?,
+            image,
+        })
+    }
+}
+
+impl From<&EventClass> for JsonOperation {
+    fn from(operation: &EventClass) -> Self {
+        match operation {
+            EventClass::Process(operation) => JsonOperation::Process(match operation {
+                EventProcessOperation::ProcessCreate { pid, cmd } => {
+                    JsonProcessOperation::ProcessCreate {
+                        pid: *pid,
+                        cmd: cmd.as_ref().map(utf8),
+                    }
+                }
+                EventProcessOperation::ProcessDestroy { pid, exit_status } => {
+                    JsonProcessOperation::ProcessDestroy {
+                        pid: *pid,
+                        exit_status: *exit_status,
+                    }
+                }
+                EventProcessOperation::ThreadCreate { tid, start_address } => {
+                    JsonProcessOperation::ThreadCreate {
+                        tid: *tid,
+                        start_address: *start_address,
+                    }
+                }
+                EventProcessOperation::ThreadExit { tid } => {
+                    JsonProcessOperation::ThreadExit { tid: *tid }
+                }
+                EventProcessOperation::ImageLoad { base, size } => {
+                    JsonProcessOperation::ImageLoad {
+                        base: *base,
+                        size: *size,
+                    }
+                }
+            }),
+            EventClass::FileSystem(operation) => JsonOperation::FileSystem(match operation {
+                EventFileSystemOperation::Create {
+                    desired_access,
+                    share_mode,
+                    disposition,
+                    options,
+                    attribute,
+                    open_action,
+                } => JsonFileSystemOperation::Create {
+                    desired_access: *desired_access,
+                    share_mode: *share_mode,
+                    disposition: *disposition,
+                    options: *options,
+                    attribute: *attribute,
+                    open_action: *open_action,
+                },
+                EventFileSystemOperation::Read { length, offset } => {
+                    JsonFileSystemOperation::Read {
+                        length: *length,
+                        offset: *offset,
+                    }
+                }
+                EventFileSystemOperation::Write { length, offset } => {
+                    JsonFileSystemOperation::Write {
+                        length: *length,
+                        offset: *offset,
+                    }
+                }
+                EventFileSystemOperation::Close {} => JsonFileSystemOperation::Close {},
+                EventFileSystemOperation::QueryInformation {
+                    information_class,
+                    length,
+                } => JsonFileSystemOperation::QueryInformation {
+                    information_class: *information_class,
+                    length: *length,
+                },
+                EventFileSystemOperation::SetInformation { information } => {
+                    JsonFileSystemOperation::SetInformation {
+                        information: JsonSetInformation::from(information),
+                    }
+                }
+                EventFileSystemOperation::QueryDirectory {
+                    pattern,
+                    information_class,
+                    length,
+                } => JsonFileSystemOperation::QueryDirectory {
+                    pattern: utf8(pattern),
+                    information_class: *information_class,
+                    length: *length,
+                },
+                EventFileSystemOperation::FileSystemControl {
+                    control_code,
+                    input_length,
+                    output_length,
+                } => JsonFileSystemOperation::FileSystemControl {
+                    control_code: *control_code,
+                    input_length: *input_length,
+                    output_length: *output_length,
+                },
+                EventFileSystemOperation::LockControl {
+                    operation,
+                    offset,
+                    length,
+                    exclusive,
+                } => JsonFileSystemOperation::LockControl {
+                    operation: *operation,
+                    offset: *offset,
+                    length: *length,
+                    exclusive: *exclusive,
+                },
+                EventFileSystemOperation::Cleanup {} => JsonFileSystemOperation::Cleanup {},
+                EventFileSystemOperation::FlushBuffers {} => {
+                    JsonFileSystemOperation::FlushBuffers {}
+                }
+            }),
+            EventClass::Registry(operation) => JsonOperation::Registry(match operation {
+                EventRegistryOperation::CreateKey {
+                    desired_access,
+                    disposition,
+                } => JsonRegistryOperation::CreateKey {
+                    desired_access: *desired_access,
+                    disposition: *disposition,
+                },
+                EventRegistryOperation::OpenKey { desired_access } => {
+                    JsonRegistryOperation::OpenKey {
+                        desired_access: *desired_access,
+                    }
+                }
+                EventRegistryOperation::QueryKey {
+                    information_class,
+                    length,
+                } => JsonRegistryOperation::QueryKey {
+                    information_class: *information_class,
+                    length: *length,
+                },
+                EventRegistryOperation::SetValue {
+                    value_name,
+                    value_type,
+                    data_size,
+                    preview,
+                } => JsonRegistryOperation::SetValue {
+                    value_name: utf8(value_name),
+                    value_type: *value_type,
+                    data_size: *data_size,
+                    preview: *preview,
+                },
+                EventRegistryOperation::QueryValue {
+                    value_name,
+                    information_class,
+                    length,
+                } => JsonRegistryOperation::QueryValue {
+                    value_name: utf8(value_name),
+                    information_class: *information_class,
+                    length: *length,
+                },
+                EventRegistryOperation::DeleteKey {} => JsonRegistryOperation::DeleteKey {},
+                EventRegistryOperation::DeleteValue { value_name } => {
+                    JsonRegistryOperation::DeleteValue {
+                        value_name: utf8(value_name),
+                    }
+                }
+                EventRegistryOperation::EnumerateKey {
+                    index,
+                    information_class,
+                } => JsonRegistryOperation::EnumerateKey {
+                    index: *index,
+                    information_class: *information_class,
+                },
+                EventRegistryOperation::EnumerateValue {
+                    index,
+                    information_class,
+                } => JsonRegistryOperation::EnumerateValue {
+                    index: *index,
+                    information_class: *information_class,
+                },
+                EventRegistryOperation::RenameKey { new_name } => {
+                    JsonRegistryOperation::RenameKey {
+                        new_name: utf8(new_name),
+                    }
+                }
+                EventRegistryOperation::Flush {} => JsonRegistryOperation::Flush {},
+            }),
+            EventClass::Network(operation) => {
+                let endpoints = JsonEndpoints::from(operation.endpoints());
+                let length = operation.length();
+
+                JsonOperation::Network(match operation {
+                    EventNetworkOperation::TcpConnect { .. } => {
+                        JsonNetworkOperation::TcpConnect { endpoints }
+                    }
+                    EventNetworkOperation::TcpAccept { .. } => {
+                        JsonNetworkOperation::TcpAccept { endpoints }
+                    }
+                    EventNetworkOperation::TcpSend { .. } => {
+                        JsonNetworkOperation::TcpSend { endpoints, length }
+                    }
+                    EventNetworkOperation::TcpReceive { .. } => {
+                        JsonNetworkOperation::TcpReceive { endpoints, length }
+                    }
+                    EventNetworkOperation::TcpDisconnect { .. } => {
+                        JsonNetworkOperation::TcpDisconnect { endpoints }
+                    }
+                    EventNetworkOperation::UdpSend { .. } => {
+                        JsonNetworkOperation::UdpSend { endpoints, length }
+                    }
+                    EventNetworkOperation::UdpReceive { .. } => {
+                        JsonNetworkOperation::UdpReceive { endpoints, length }
+                    }
+                })
+            }
+            EventClass::Session(operation) => JsonOperation::Session(match operation {
+                EventSessionOperation::Disconnected { attempts } => {
+                    JsonSessionOperation::Disconnected {
+                        attempts: *attempts,
+                    }
+                }
+            }),
+        }
+    }
+}
+
+impl TryFrom<JsonOperation> for EventClass {
+    type Error = io::Error;
+
+    fn try_from(operation: JsonOperation) -> io::Result<Self> {
+        Ok(match operation {
+            JsonOperation::Process(operation) => EventClass::Process(match operation {
+                JsonProcessOperation::ProcessCreate { pid, cmd } => {
+                    EventProcessOperation::ProcessCreate {
+                        pid,
+                        cmd: cmd.as_deref().map(nt_string).transpose()?,
+                    }
+                }
+                JsonProcessOperation::ProcessDestroy { pid, exit_status } => {
+                    EventProcessOperation::ProcessDestroy { pid, exit_status }
+                }
+                JsonProcessOperation::ThreadCreate { tid, start_address } => {
+                    EventProcessOperation::ThreadCreate { tid, start_address }
+                }
+                JsonProcessOperation::ThreadExit { tid } => {
+                    EventProcessOperation::ThreadExit { tid }
+                }
+                JsonProcessOperation::ImageLoad { base, size } => {
+                    EventProcessOperation::ImageLoad { base, size }
+                }
+            }),
+            JsonOperation::FileSystem(operation) => EventClass::FileSystem(match operation {
+                JsonFileSystemOperation::Create {
+                    desired_access,
+                    share_mode,
+                    disposition,
+                    options,
+                    attribute,
+                    open_action,
+                } => EventFileSystemOperation::Create {
+                    desired_access,
+                    share_mode,
+                    disposition,
+                    options,
+                    attribute,
+                    open_action,
+                },
+                JsonFileSystemOperation::Read { length, offset } => {
+                    EventFileSystemOperation::Read { length, offset }
+                }
+                JsonFileSystemOperation::Write { length, offset } => {
+                    EventFileSystemOperation::Write { length, offset }
+                }
+                JsonFileSystemOperation::Close {} => EventFileSystemOperation::Close {},
+                JsonFileSystemOperation::QueryInformation {
+                    information_class,
+                    length,
+                } => EventFileSystemOperation::QueryInformation {
+                    information_class,
+                    length,
+                },
+                JsonFileSystemOperation::SetInformation { information } => {
+                    EventFileSystemOperation::SetInformation {
+                        information: information.try_into()?,
+                    }
+                }
+                JsonFileSystemOperation::QueryDirectory {
+                    pattern,
+                    information_class,
+                    length,
+                } => EventFileSystemOperation::QueryDirectory {
+                    pattern: nt_string(&pattern)?,
+                    information_class,
+                    length,
+                },
+                JsonFileSystemOperation::FileSystemControl {
+                    control_code,
+                    input_length,
+                    output_length,
+                } => EventFileSystemOperation::FileSystemControl {
+                    control_code,
+                    input_length,
+                    output_length,
+                },
+                JsonFileSystemOperation::LockControl {
+                    operation,
+                    offset,
+                    length,
+                    exclusive,
+                } => EventFileSystemOperation::LockControl {
+                    operation,
+                    offset,
+                    length,
+                    exclusive,
+                },
+                JsonFileSystemOperation::Cleanup {} => EventFileSystemOperation::Cleanup {},
+                JsonFileSystemOperation::FlushBuffers {} => {
+                    EventFileSystemOperation::FlushBuffers {}
+                }
+            }),
+            JsonOperation::Registry(operation) => EventClass::Registry(match operation {
+                JsonRegistryOperation::CreateKey {
+                    desired_access,
+                    disposition,
+                } => EventRegistryOperation::CreateKey {
+                    desired_access,
+                    disposition,
+                },
+                JsonRegistryOperation::OpenKey { desired_access } => {
+                    EventRegistryOperation::OpenKey { desired_access }
+                }
+                JsonRegistryOperation::QueryKey {
+                    information_class,
+                    length,
+                } => EventRegistryOperation::QueryKey {
+                    information_class,
+                    length,
+                },
+                JsonRegistryOperation::SetValue {
+                    value_name,
+                    value_type,
+                    data_size,
+                    preview,
+                } => EventRegistryOperation::SetValue {
+                    value_name: nt_string(&value_name)?,
+                    value_type,
+                    data_size,
+                    preview,
+                },
+                JsonRegistryOperation::QueryValue {
+                    value_name,
+                    information_class,
+                    length,
+                } => EventRegistryOperation::QueryValue {
+                    value_name: nt_string(&value_name)?,
+                    information_class,
+                    length,
+                },
+                JsonRegistryOperation::DeleteKey {} => EventRegistryOperation::DeleteKey {},
+                JsonRegistryOperation::DeleteValue { value_name } => {
+                    EventRegistryOperation::DeleteValue {
+                        value_name: nt_string(&value_name)?,
+                    }
+                }
+                JsonRegistryOperation::EnumerateKey {
+                    index,
+                    information_class,
+                } => EventRegistryOperation::EnumerateKey {
+                    index,
+                    information_class,
+                },
+                JsonRegistryOperation::EnumerateValue {
+                    index,
+                    information_class,
+                } => EventRegistryOperation::EnumerateValue {
+                    index,
+                    information_class,
+                },
+                JsonRegistryOperation::RenameKey { new_name } => {
+                    EventRegistryOperation::RenameKey {
+                        new_name: nt_string(&new_name)?,
+                    }
+                }
+                JsonRegistryOperation::Flush {} => EventRegistryOperation::Flush {},
+            }),
+            JsonOperation::Network(operation) => EventClass::Network(match operation {
+                JsonNetworkOperation::TcpConnect { endpoints } => {
+                    EventNetworkOperation::TcpConnect {
+                        endpoints: endpoints.into(),
+                    }
+                }
+                JsonNetworkOperation::TcpAccept { endpoints } => EventNetworkOperation::TcpAccept {
+                    endpoints: endpoints.into(),
+                },
+                JsonNetworkOperation::TcpSend { endpoints, length } => {
+                    EventNetworkOperation::TcpSend {
+                        endpoints: endpoints.into(),
+                        length,
+                    }
+                }
+                JsonNetworkOperation::TcpReceive { endpoints, length } => {
+                    EventNetworkOperation::TcpReceive {
+                        endpoints: endpoints.into(),
+                        length,
+                    }
+                }
+                JsonNetworkOperation::TcpDisconnect { endpoints } => {
+                    EventNetworkOperation::TcpDisconnect {
+                        endpoints: endpoints.into(),
+                    }
+                }
+                JsonNetworkOperation::UdpSend { endpoints, length } => {
+                    EventNetworkOperation::UdpSend {
+                        endpoints: endpoints.into(),
+                        length,
+                    }
+                }
+                JsonNetworkOperation::UdpReceive { endpoints, length } => {
+                    EventNetworkOperation::UdpReceive {
+                        endpoints: endpoints.into(),
+                        length,
+                    }
+                }
+            }),
+            JsonOperation::Session(operation) => EventClass::Session(match operation {
+                JsonSessionOperation::Disconnected { attempts } => {
+                    EventSessionOperation::Disconnected { attempts }
+                }
+            }),
+        })
+    }
+}
+
+impl From<&FileSetInformation> for JsonSetInformation {
+    fn from(information: &FileSetInformation) -> Self {
+        match information {
+            FileSetInformation::Basic {
+                creation_time,
+                last_access_time,
+                last_write_time,
+                change_time,
+                attributes,
+            } => JsonSetInformation::Basic {
+                creation_time: *creation_time,
+                last_access_time: *last_access_time,
+                last_write_time: *last_write_time,
+                change_time: *change_time,
+                attributes: *attributes,
+            },
+            FileSetInformation::Rename {
+                target,
+                replace_if_exists,
+            } => JsonSetInformation::Rename {
+                target: utf8(target),
+                replace_if_exists: *replace_if_exists,
+            },
+            FileSetInformation::Disposition { delete } => {
+                JsonSetInformation::Disposition { delete: *delete }
+            }
+            FileSetInformation::EndOfFile { end_of_file } => JsonSetInformation::EndOfFile {
+                end_of_file: *end_of_file,
+            },
+            FileSetInformation::Allocation { allocation_size } => JsonSetInformation::Allocation {
+                allocation_size: *allocation_size,
+            },
+            FileSetInformation::Other {
+                information_class,
+                length,
+            } => JsonSetInformation::Other {
+                information_class: *information_class,
+                length: *length,
+            },
+        }
+    }
+}
+
+impl TryFrom<JsonSetInformation> for FileSetInformation {
+    type Error = io::Error;
+
+    fn try_from(information: JsonSetInformation) -> io::Result<Self> {
+        Ok(match information {
+            JsonSetInformation::Basic {
+                creation_time,
+                last_access_time,
+                last_write_time,
+                change_time,
+                attributes,
+            } => FileSetInformation::Basic {
+                creation_time,
+                last_access_time,
+                last_write_time,
+                change_time,
+                attributes,
+            },
+            JsonSetInformation::Rename {
+                target,
+                replace_if_exists,
+            } => FileSetInformation::Rename {
+                target: nt_string(&target)?,
+                replace_if_exists,
+            },
+            JsonSetInformation::Disposition { delete } => {
+                FileSetInformation::Disposition { delete }
+            }
+            JsonSetInformation::EndOfFile { end_of_file } => {
+                FileSetInformation::EndOfFile { end_of_file }
+            }
+            JsonSetInformation::Allocation { allocation_size } => {
+                FileSetInformation::Allocation { allocation_size }
+            }
+            JsonSetInformation::Other {
+                information_class,
+                length,
+            } => FileSetInformation::Other {
+                information_class,
+                length,
+            },
+        })
+    }
+}
+
+impl From<&NetworkEndpoints> for JsonEndpoints {
+    fn from(endpoints: &NetworkEndpoints) -> Self {
+        Self {
+            local: socket_addr(&endpoints.local),
+            remote: socket_addr(&endpoints.remote),
+        }
+    }
+}
+
+impl From<JsonEndpoints> for NetworkEndpoints {
+    fn from(endpoints: JsonEndpoints) -> Self {
+        Self {
+            local: socket_address(endpoints.local),
+            remote: socket_address(endpoints.remote),
+        }
+    }
+}
+
+fn socket_addr(address: &SocketAddress) -> SocketAddr {
+    let ip = match address.ip {
+        IpAddress::V4(octets) => IpAddr::V4(Ipv4Addr::from(octets)),
+        IpAddress::V6(octets) => IpAddr::V6(Ipv6Addr::from(octets)),
+    };
+
+    SocketAddr::new(ip, address.port)
+}
+
+fn socket_address(address: SocketAddr) -> SocketAddress {
+    let ip = match address.ip() {
+        IpAddr::V4(ip) => IpAddress::V4(ip.octets()),
+        IpAddr::V6(ip) => IpAddress::V6(ip.octets()),
+    };
+
+    SocketAddress {
+        ip,
+        port: address.port(),
+    }
+}
+
+///
+/// Writes events as JSON Lines, each one is complete once `write` returns
+///
+pub struct JsonlWriter<W: Write> {
+    writer: W,
+    written: u64,
+}
+
+impl JsonlWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, written: 0 }
+    }
+
+    /// Events written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// `image` is the image path of the process of `event` if it is known
+    pub fn write(
+        &mut self,
+        event: &KmMessage,
+        image: Option<&SerializableNtString>,
+    ) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &JsonEvent::new(event, image))?;
+        self.writer.write_all(b"\n")?;
+        self.written += 1;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+///
+/// Reads events back from JSON Lines, blank lines are skipped.
+///
+/// Errors carry the line they were found on, reading can go on with the next line
+///
+pub struct JsonlReader<R: BufRead> {
+    reader: R,
+    line: String,
+    line_number: usize,
+}
+
+impl JsonlReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            line_number: 0,
+        }
+    }
+
+    /// Of the last line read, starting at 1
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlReader<R> {
+    type Item = io::Result<JsonRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => self.line_number += 1,
+                Err(e) => return Some(Err(e)),
+            }
+
+            let line = self.line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let event = serde_json::from_str::<JsonEvent>(line)
+                .map_err(io::Error::from)
+                .and_then(JsonRecord::try_from)
+                .map_err(|e| io::Error::new(e.kind(), format!("line {}: {}", self.line_number, e)));
+            return Some(event);
+        }
+    }
+}
+
+///
+/// Empty if the FILETIME is out of the range chrono can represent
+///
+fn iso_time(filetime: u64) -> String {
+    let unix = filetime as i128 - FILETIME_UNIX_EPOCH;
+    let seconds = unix.div_euclid(FILETIME_PER_SECOND);
+    let fraction = unix.rem_euclid(FILETIME_PER_SECOND);
+
+    //chrono has no 7 digit fraction, FILETIME has exactly that precision
+    i64::try_from(seconds)
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map_or_else(String::new, |time| {
+            format!("{}.{:07}Z", time.format(TIME_FORMAT), fraction)
+        })
+}
+
+fn utf8(string: &SerializableNtString) -> String {
+    if string.is_empty() {
+        String::new()
+    } else {
+        String::from_utf16_lossy(string.as_slice())
+    }
+}
+
+fn nt_string(string: &str) -> io::Result<SerializableNtString> {
+    if string.is_empty() {
+        return Ok(SerializableNtString::empty());
+    }
+
+    NtUnicodeString::try_from(string)
+        .map(SerializableNtString::new)
+        .map_err(|_| invalid_data(&format!("string of {} bytes is too long", string.len())))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// `0x` prefixed lowercase hex
+mod hex_address {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(address: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:#x}", address))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.strip_prefix("0x")
+            .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| de::Error::custom(format!("invalid address {:?}", text)))
+    }
+}
+
+/// Lowercase hex of the bytes
+mod preview {
+    use std::fmt::Write;
+
+    use kmum_common::event::{RegistryDataPreview, MAX_REGISTRY_PREVIEW_SIZE};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        preview: &RegistryDataPreview,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut text = String::with_capacity(preview.bytes().len() * 2);
+        for byte in preview.bytes() {
+            let _ = write!(text, "{:02x}", byte);
+        }
+        serializer.serialize_str(&text)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<RegistryDataPreview, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if !text.len().is_multiple_of(2) || text.len() / 2 > MAX_REGISTRY_PREVIEW_SIZE {
+            return Err(de::Error::custom(format!(
+                "preview has to be at most {} hex encoded bytes",
+                MAX_REGISTRY_PREVIEW_SIZE
+            )));
+        }
+
+        let digit = |byte: u8| (byte as char).to_digit(16);
+        let bytes = text
+            .as_bytes()
+            .chunks_exact(2)
+            .map(|pair| Some((digit(pair[0])? << 4 | digit(pair[1])?) as u8))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| de::Error::custom(format!("invalid preview {:?}", text)))?;
+        Ok(RegistryDataPreview::new(&bytes))
+    }
+}
+
+///
+/// A name for the known variants and the raw number for the rest, either is accepted back
+///
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NameOrRaw<T> {
+    Name(String),
+    Raw(T),
+}
+
+/// `REG_SZ`, `REG_DWORD`...
+mod value_type {
+    use kmum_common::event::RegistryValueType;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::NameOrRaw;
+
+    /// Types past this one are all unknown
+    const LAST_KNOWN: u32 = 11;
+
+    pub fn serialize<S: Serializer>(
+        value_type: &RegistryValueType,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value_type {
+            RegistryValueType::Unknown(raw) => serializer.serialize_u32(*raw),
+            known => serializer.serialize_str(known.name()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<RegistryValueType, D::Error> {
+        match NameOrRaw::<u32>::deserialize(deserializer)? {
+            NameOrRaw::Raw(raw) => Ok(RegistryValueType::from_raw(raw)),
+            NameOrRaw::Name(name) => (0..=LAST_KNOWN)
+                .map(RegistryValueType::from_raw)
+                .find(|value_type| value_type.name() == name)
+                .ok_or_else(|| de::Error::custom(format!("unknown value type {:?}", name))),
+        }
+    }
+}
+
+/// `Lock`, `UnlockSingle`, `UnlockAll` and `UnlockAllByKey`
+mod lock_operation {
+    use kmum_common::event::FileLockOperation;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::NameOrRaw;
+
+    /// Minor functions past this one are all unknown
+    const LAST_KNOWN: u8 = 4;
+
+    pub fn serialize<S: Serializer>(
+        operation: &FileLockOperation,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match operation {
+            FileLockOperation::Unknown(minor) => serializer.serialize_u8(*minor),
+            known => serializer.serialize_str(name(known)),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FileLockOperation, D::Error> {
+        match NameOrRaw::<u8>::deserialize(deserializer)? {
+            NameOrRaw::Raw(minor) => Ok(FileLockOperation::from_minor_function(minor)),
+            NameOrRaw::Name(text) => (1..=LAST_KNOWN)
+                .map(FileLockOperation::from_minor_function)
+                .find(|operation| name(operation) == text)
+                .ok_or_else(|| de::Error::custom(format!("unknown lock operation {:?}", text))),
+        }
+    }
+
+    fn name(operation: &FileLockOperation) -> &'static str {
+        match operation {
+            FileLockOperation::Lock => "Lock",
+            FileLockOperation::UnlockSingle => "UnlockSingle",
+            FileLockOperation::UnlockAll => "UnlockAll",
+            FileLockOperation::UnlockAllByKey => "UnlockAllByKey",
+            FileLockOperation::Unknown(_) => "Unknown",
+        }
+    }
+}
@@ -1,6 +1,11 @@
 #![allow(internal_features)]
 #![feature(core_intrinsics)]
 
+#[cfg(windows)]
 mod win;
 
 pub mod communication;
+pub mod json;
+pub mod pipeline;
+pub mod query;
+pub mod segment;
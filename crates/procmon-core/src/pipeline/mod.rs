@@ -0,0 +1,161 @@
+//!
+//! Composable `EventProcessor`s.
+//!
+//! Every stage wraps the processor events go to next, so a pipeline reads from
+//! the receive loop inwards: `Filter<Sample<Tee<...>>>` filters, then samples,
+//! then hands what is left to every sink. Stages can be nested directly when the
+//! shape is known at compile time, `PipelineBuilder` puts them together at runtime
+//!
+
+use std::{num::NonZeroU32, sync::Arc};
+
+use kmum_common::KmMessage;
+
+use crate::communication::{CommunicationError, EventProcessor};
+
+mod stages;
+mod throttle;
+
+pub use stages::*;
+pub use throttle::*;
+
+///
+/// Object safe `EventProcessor`, implemented for every processor that can be shared between threads
+///
+pub trait DynEventProcessor: Send + Sync {
+    fn process_dyn(
+        &self,
+        iter: &mut dyn Iterator<Item = KmMessage>,
+    ) -> anyhow::Result<(), CommunicationError>;
+}
+
+impl<P: EventProcessor + Send + Sync> DynEventProcessor for P {
+    fn process_dyn(
+        &self,
+        iter: &mut dyn Iterator<Item = KmMessage>,
+    ) -> anyhow::Result<(), CommunicationError> {
+        self.process(&mut &mut *iter)
+    }
+}
+
+pub type BoxedProcessor = Box<dyn DynEventProcessor>;
+
+impl EventProcessor for BoxedProcessor {
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        //The box itself is a `DynEventProcessor` too, go through to what it holds
+        (**self).process_dyn(iter)
+    }
+}
+
+///
+/// A built pipeline, cheap to clone so every receive loop can get its own handle
+///
+#[derive(Clone)]
+pub struct Pipeline {
+    head: Arc<dyn DynEventProcessor>,
+}
+
+impl Pipeline {
+    pub fn new<P: DynEventProcessor + 'static>(processor: P) -> Self {
+        Self {
+            head: Arc::new(processor),
+        }
+    }
+}
+
+impl EventProcessor for Pipeline {
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        self.head.process_dyn(iter)
+    }
+}
+
+type Predicate = Box<dyn Fn(&KmMessage) -> bool + Send + Sync>;
+type Transform = Box<dyn Fn(KmMessage) -> KmMessage + Send + Sync>;
+
+enum Stage {
+    Filter(Predicate),
+    Map(Transform),
+    Sample(NonZeroU32),
+    RateLimit(NonZeroU32),
+}
+
+///
+/// Stages in the order events go through them, finished off with the sinks
+///
+#[derive(Default)]
+pub struct PipelineBuilder {
+    stages: Vec<Stage>,
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops every event `predicate` returns false for
+    pub fn filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&KmMessage) -> bool + Send + Sync + 'static,
+    {
+        self.stages.push(Stage::Filter(Box::new(predicate)));
+        self
+    }
+
+    /// Rewrites or enriches every event
+    pub fn map<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(KmMessage) -> KmMessage + Send + Sync + 'static,
+    {
+        self.stages.push(Stage::Map(Box::new(transform)));
+        self
+    }
+
+    /// Keeps one of every `every` events
+    pub fn sample(mut self, every: NonZeroU32) -> Self {
+        self.stages.push(Stage::Sample(every));
+        self
+    }
+
+    /// Lets at most `per_second` events through every second
+    pub fn rate_limit(mut self, per_second: NonZeroU32) -> Self {
+        self.stages.push(Stage::RateLimit(per_second));
+        self
+    }
+
+    pub fn build<P: DynEventProcessor + 'static>(self, sink: P) -> Pipeline {
+        self.build_boxed(Box::new(sink))
+    }
+
+    ///
+    /// Every event that made it through the stages goes to all of `sinks`
+    ///
+    pub fn build_tee(self, mut sinks: Vec<BoxedProcessor>) -> Pipeline {
+        match sinks.len() {
+            1 => self.build_boxed(sinks.pop().unwrap()),
+            _ => self.build(Tee::new(sinks)),
+        }
+    }
+
+    fn build_boxed(self, sink: BoxedProcessor) -> Pipeline {
+        let head = self
+            .stages
+            .into_iter()
+            .rev()
+            .fold(sink, |next, stage| -> BoxedProcessor {
+                match stage {
+                    Stage::Filter(predicate) => Box::new(Filter::new(predicate, next)),
+                    Stage::Map(transform) => Box::new(Map::new(transform, next)),
+                    Stage::Sample(every) => Box::new(Sample::new(every, next)),
+                    Stage::RateLimit(per_second) => Box::new(RateLimit::new(per_second, next)),
+                }
+            });
+
+        Pipeline { head: head.into() }
+    }
+}
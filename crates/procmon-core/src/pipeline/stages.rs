@@ -0,0 +1,100 @@
+use kmum_common::KmMessage;
+
+use crate::communication::{CommunicationError, EventProcessor};
+
+///
+/// Passes on only the events `predicate` returns true for
+///
+pub struct Filter<F, P> {
+    predicate: F,
+    next: P,
+}
+
+impl<F, P> Filter<F, P> {
+    pub fn new(predicate: F, next: P) -> Self {
+        Self { predicate, next }
+    }
+}
+
+impl<F, P> EventProcessor for Filter<F, P>
+where
+    F: Fn(&KmMessage) -> bool,
+    P: EventProcessor,
+{
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        self.next
+            .process(&mut iter.filter(|event| (self.predicate)(event)))
+    }
+}
+
+///
+/// Passes on every event after running it through `transform`
+///
+pub struct Map<F, P> {
+    transform: F,
+    next: P,
+}
+
+impl<F, P> Map<F, P> {
+    pub fn new(transform: F, next: P) -> Self {
+        Self { transform, next }
+    }
+}
+
+impl<F, P> EventProcessor for Map<F, P>
+where
+    F: Fn(KmMessage) -> KmMessage,
+    P: EventProcessor,
+{
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        self.next.process(&mut iter.map(&self.transform))
+    }
+}
+
+///
+/// Hands every event to each of its sinks.
+///
+/// The events of a batch are collected once and cloned for all but the last
+/// sink. A failing sink does not keep the others from seeing the batch, the
+/// first error is returned after all of them ran
+///
+pub struct Tee<P> {
+    sinks: Vec<P>,
+}
+
+impl<P> Tee<P> {
+    pub fn new(sinks: Vec<P>) -> Self {
+        Self { sinks }
+    }
+}
+
+impl<P: EventProcessor> EventProcessor for Tee<P> {
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        let Some((last, others)) = self.sinks.split_last() else {
+            iter.for_each(drop);
+            return Ok(());
+        };
+        if others.is_empty() {
+            return last.process(iter);
+        }
+
+        let events: Vec<KmMessage> = iter.collect();
+        let mut result = Ok(());
+        for sink in others {
+            let sink_result = sink.process(&mut events.iter().cloned());
+            result = result.and(sink_result);
+        }
+        let sink_result = last.process(&mut events.into_iter());
+
+        result.and(sink_result)
+    }
+}
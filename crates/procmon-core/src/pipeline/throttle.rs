@@ -0,0 +1,117 @@
+use std::{
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use kmum_common::KmMessage;
+
+use crate::communication::{CommunicationError, EventProcessor};
+
+///
+/// Passes on one of every `every` events, counted across all receive loops
+///
+pub struct Sample<P> {
+    every: u64,
+    seen: AtomicU64,
+    next: P,
+}
+
+impl<P> Sample<P> {
+    pub fn new(every: NonZeroU32, next: P) -> Self {
+        Self {
+            every: every.get() as _,
+            seen: AtomicU64::new(0),
+            next,
+        }
+    }
+
+    /// Events left out so far
+    pub fn dropped(&self) -> u64 {
+        let seen = self.seen.load(Ordering::Relaxed);
+        seen - seen.div_ceil(self.every)
+    }
+}
+
+impl<P: EventProcessor> EventProcessor for Sample<P> {
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        self.next.process(&mut iter.filter(|_| {
+            self.seen
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.every)
+        }))
+    }
+}
+
+///
+/// Token bucket holding up to a second worth of events
+///
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+///
+/// Passes on at most `per_second` events a second on average, bursts of up to
+/// a second worth of events go through at once. Events over the limit are dropped
+///
+pub struct RateLimit<P> {
+    per_second: f64,
+    bucket: Mutex<Bucket>,
+    dropped: AtomicU64,
+    next: P,
+}
+
+impl<P> RateLimit<P> {
+    pub fn new(per_second: NonZeroU32, next: P) -> Self {
+        let per_second = per_second.get() as f64;
+
+        Self {
+            per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: per_second,
+                refilled: Instant::now(),
+            }),
+            dropped: AtomicU64::new(0),
+            next,
+        }
+    }
+
+    /// Events over the limit so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn take_token(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.per_second);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            drop(bucket);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+impl<P: EventProcessor> EventProcessor for RateLimit<P> {
+    fn process<I>(&self, iter: &mut I) -> anyhow::Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        self.next.process(&mut iter.filter(|_| self.take_token()))
+    }
+}
@@ -0,0 +1,546 @@
+use std::borrow::Cow;
+
+use kmum_common::{
+    event::{
+        EventClass, EventFileSystemOperation as File, EventNetworkOperation as Network,
+        EventProcessOperation as Process, EventRegistryOperation as Registry,
+        EventSessionOperation as Session, FileLockOperation, FileSetInformation as SetInformation,
+    },
+    ntstatus::NtStatus,
+    KmMessage,
+};
+
+use super::ProcessLookup;
+
+///
+/// How values written for a field are read and which operators it takes
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Kind {
+    Text,
+    Number,
+    /// Seconds unless a unit is given, compared in 100ns ticks
+    Duration,
+    /// Numeric or symbolic `NTSTATUS`
+    Status,
+    /// true or false
+    Flag,
+    /// One of `OPERATIONS`
+    Operation,
+    /// One of `CLASSES`
+    Class,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Field {
+    Time,
+    Thread,
+    Class,
+    Operation,
+    Result,
+    Path,
+    Duration,
+    Pid,
+    UniqueId,
+    Process,
+    Image,
+
+    TargetPid,
+    CommandLine,
+    ExitStatus,
+    Tid,
+    StartAddress,
+    Base,
+    Size,
+
+    DesiredAccess,
+    ShareMode,
+    Disposition,
+    Options,
+    Attributes,
+    OpenAction,
+    Length,
+    Offset,
+    InformationClass,
+    Pattern,
+    ControlCode,
+    InputLength,
+    OutputLength,
+    LockOperation,
+    Exclusive,
+    CreationTime,
+    LastAccessTime,
+    LastWriteTime,
+    ChangeTime,
+    Target,
+    ReplaceIfExists,
+    Delete,
+    EndOfFile,
+    AllocationSize,
+
+    ValueName,
+    ValueType,
+    DataSize,
+    Index,
+    NewName,
+
+    Local,
+    Remote,
+    LocalAddress,
+    RemoteAddress,
+    LocalPort,
+    RemotePort,
+
+    Attempts,
+}
+
+/// Names fields can be written as, not case sensitive
+const FIELDS: &[(&str, Field)] = &[
+    ("time", Field::Time),
+    ("date", Field::Time),
+    ("thread", Field::Thread),
+    ("class", Field::Class),
+    ("op", Field::Operation),
+    ("operation", Field::Operation),
+    ("result", Field::Result),
+    ("status", Field::Result),
+    ("path", Field::Path),
+    ("duration", Field::Duration),
+    ("pid", Field::Pid),
+    ("uid", Field::UniqueId),
+    ("unique_id", Field::UniqueId),
+    ("process", Field::Process),
+    ("process_name", Field::Process),
+    ("image", Field::Image),
+    ("image_path", Field::Image),
+    ("target_pid", Field::TargetPid),
+    ("cmd", Field::CommandLine),
+    ("command_line", Field::CommandLine),
+    ("exit_status", Field::ExitStatus),
+    ("tid", Field::Tid),
+    ("start_address", Field::StartAddress),
+    ("base", Field::Base),
+    ("size", Field::Size),
+    ("desired_access", Field::DesiredAccess),
+    ("access", Field::DesiredAccess),
+    ("share_mode", Field::ShareMode),
+    ("disposition", Field::Disposition),
+    ("options", Field::Options),
+    ("attributes", Field::Attributes),
+    ("open_action", Field::OpenAction),
+    ("length", Field::Length),
+    ("offset", Field::Offset),
+    ("information_class", Field::InformationClass),
+    ("pattern", Field::Pattern),
+    ("control_code", Field::ControlCode),
+    ("input_length", Field::InputLength),
+    ("output_length", Field::OutputLength),
+    ("lock_operation", Field::LockOperation),
+    ("exclusive", Field::Exclusive),
+    ("creation_time", Field::CreationTime),
+    ("last_access_time", Field::LastAccessTime),
+    ("last_write_time", Field::LastWriteTime),
+    ("change_time", Field::ChangeTime),
+    ("target", Field::Target),
+    ("replace_if_exists", Field::ReplaceIfExists),
+    ("delete", Field::Delete),
+    ("end_of_file", Field::EndOfFile),
+    ("allocation_size", Field::AllocationSize),
+    ("value_name", Field::ValueName),
+    ("value_type", Field::ValueType),
+    ("data_size", Field::DataSize),
+    ("index", Field::Index),
+    ("new_name", Field::NewName),
+    ("local", Field::Local),
+    ("remote", Field::Remote),
+    ("local_address", Field::LocalAddress),
+    ("remote_address", Field::RemoteAddress),
+    ("local_port", Field::LocalPort),
+    ("remote_port", Field::RemotePort),
+    ("attempts", Field::Attempts),
+];
+
+pub(super) const CLASSES: &[&str] = &["Process", "FileSystem", "Registry", "Network", "Session"];
+
+pub(super) const OPERATIONS: &[&str] = &[
+    "ProcessCreate",
+    "ProcessDestroy",
+    "ThreadCreate",
+    "ThreadExit",
+    "ImageLoad",
+    "Create",
+    "Read",
+    "Write",
+    "Close",
+    "QueryInformation",
+    "SetInformation",
+    "QueryDirectory",
+    "FileSystemControl",
+    "LockControl",
+    "Cleanup",
+    "FlushBuffers",
+    "CreateKey",
+    "OpenKey",
+    "QueryKey",
+    "SetValue",
+    "QueryValue",
+    "DeleteKey",
+    "DeleteValue",
+    "EnumerateKey",
+    "EnumerateValue",
+    "RenameKey",
+    "Flush",
+    "TcpConnect",
+    "TcpAccept",
+    "TcpSend",
+    "TcpReceive",
+    "TcpDisconnect",
+    "UdpSend",
+    "UdpReceive",
+    "Disconnected",
+];
+
+///
+/// Operation and class names are matched without case, spaces or underscores,
+/// so `SetValue`, `set_value` and `Set Value` are the same
+///
+pub(super) fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+pub(super) fn class_name(operation: &EventClass) -> &'static str {
+    match operation {
+        EventClass::Process(_) => "Process",
+        EventClass::FileSystem(_) => "FileSystem",
+        EventClass::Registry(_) => "Registry",
+        EventClass::Network(_) => "Network",
+        EventClass::Session(_) => "Session",
+    }
+}
+
+pub(super) fn operation_name(operation: &EventClass) -> &'static str {
+    match operation {
+        EventClass::Process(operation) => match operation {
+            Process::ProcessCreate { .. } => "ProcessCreate",
+            Process::ProcessDestroy { .. } => "ProcessDestroy",
+            Process::ThreadCreate { .. } => "ThreadCreate",
+            Process::ThreadExit { .. } => "ThreadExit",
+            Process::ImageLoad { .. } => "ImageLoad",
+        },
+        EventClass::FileSystem(operation) => match operation {
+            File::Create { .. } => "Create",
+            File::Read { .. } => "Read",
+            File::Write { .. } => "Write",
+            File::Close {} => "Close",
+            File::QueryInformation { .. } => "QueryInformation",
+            File::SetInformation { .. } => "SetInformation",
+            File::QueryDirectory { .. } => "QueryDirectory",
+            File::FileSystemControl { .. } => "FileSystemControl",
+            File::LockControl { .. } => "LockControl",
+            File::Cleanup {} => "Cleanup",
+            File::FlushBuffers {} => "FlushBuffers",
+        },
+        EventClass::Registry(operation) => match operation {
+            Registry::CreateKey { .. } => "CreateKey",
+            Registry::OpenKey { .. } => "OpenKey",
+            Registry::QueryKey { .. } => "QueryKey",
+            Registry::SetValue { .. } => "SetValue",
+            Registry::QueryValue { .. } => "QueryValue",
+            Registry::DeleteKey {} => "DeleteKey",
+            Registry::DeleteValue { .. } => "DeleteValue",
+            Registry::EnumerateKey { .. } => "EnumerateKey",
+            Registry::EnumerateValue { .. } => "EnumerateValue",
+            Registry::RenameKey { .. } => "RenameKey",
+            Registry::Flush {} => "Flush",
+        },
+        EventClass::Network(operation) => match operation {
+            Network::TcpConnect { .. } => "TcpConnect",
+            Network::TcpAccept { .. } => "TcpAccept",
+            Network::TcpSend { .. } => "TcpSend",
+            Network::TcpReceive { .. } => "TcpReceive",
+            Network::TcpDisconnect { .. } => "TcpDisconnect",
+            Network::UdpSend { .. } => "UdpSend",
+            Network::UdpReceive { .. } => "UdpReceive",
+        },
+        EventClass::Session(Session::Disconnected { .. }) => "Disconnected",
+    }
+}
+
+fn lock_operation_name(operation: &FileLockOperation) -> &'static str {
+    match operation {
+        FileLockOperation::Lock => "Lock",
+        FileLockOperation::UnlockSingle => "UnlockSingle",
+        FileLockOperation::UnlockAll => "UnlockAll",
+        FileLockOperation::UnlockAllByKey => "UnlockAllByKey",
+        FileLockOperation::Unknown(_) => "Unknown",
+    }
+}
+
+pub(super) enum Value {
+    Text(Cow<'static, str>),
+    Number(i128),
+    /// The event has no such field
+    Missing,
+    /// The process of the event is not resolved yet
+    Pending,
+}
+
+fn number<N: Into<i128>>(value: N) -> Value {
+    Value::Number(value.into())
+}
+
+fn text<S: Into<Cow<'static, str>>>(value: S) -> Value {
+    Value::Text(value.into())
+}
+
+impl Field {
+    pub fn lookup(name: &str) -> Option<Field> {
+        FIELDS
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, field)| *field)
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Field::Class => Kind::Class,
+            Field::Operation => Kind::Operation,
+            Field::Result | Field::ExitStatus => Kind::Status,
+            Field::Duration => Kind::Duration,
+            Field::Exclusive | Field::ReplaceIfExists | Field::Delete => Kind::Flag,
+            Field::Path
+            | Field::Process
+            | Field::Image
+            | Field::CommandLine
+            | Field::Pattern
+            | Field::LockOperation
+            | Field::Target
+            | Field::ValueName
+            | Field::ValueType
+            | Field::NewName
+            | Field::Local
+            | Field::Remote
+            | Field::LocalAddress
+            | Field::RemoteAddress => Kind::Text,
+            _ => Kind::Number,
+        }
+    }
+
+    pub fn value<P: ProcessLookup + ?Sized>(&self, event: &KmMessage, processes: &P) -> Value {
+        let operation = &event.event.operation;
+
+        match self {
+            Field::Time => number(event.event.date),
+            Field::Thread => number(event.event.thread),
+            Field::Class => text(class_name(operation)),
+            Field::Operation => text(operation_name(operation)),
+            Field::Result => number(NtStatus::from(event.event.result).code()),
+            Field::Path => text(event.event.path.to_string()),
+            Field::Duration => number(event.event.duration),
+            Field::Pid => number(event.process.pid),
+            Field::UniqueId => number(event.process.unique_id),
+            Field::Process | Field::Image => match processes.image_path(event.process.unique_id) {
+                None => Value::Pending,
+                Some(None) => Value::Missing,
+                Some(Some(path)) if *self == Field::Process => {
+                    text(path.rsplit('\\').next().unwrap_or_default().to_string())
+                }
+                Some(Some(path)) => text(path),
+            },
+            _ => self.payload_value(operation),
+        }
+    }
+
+    fn payload_value(&self, operation: &EventClass) -> Value {
+        use EventClass::{FileSystem as F, Network as N, Process as P, Registry as R};
+
+        match (self, operation) {
+            (
+                Field::TargetPid,
+                P(Process::ProcessCreate { pid, .. } | Process::ProcessDestroy { pid, .. }),
+            ) => number(*pid),
+            (Field::CommandLine, P(Process::ProcessCreate { cmd: Some(cmd), .. })) => {
+                text(cmd.to_string())
+            }
+            (Field::ExitStatus, P(Process::ProcessDestroy { exit_status, .. })) => {
+                number(NtStatus::from(*exit_status).code())
+            }
+            (Field::Tid, P(Process::ThreadCreate { tid, .. } | Process::ThreadExit { tid })) => {
+                number(*tid)
+            }
+            (Field::StartAddress, P(Process::ThreadCreate { start_address, .. })) => {
+                number(*start_address)
+            }
+            (Field::Base, P(Process::ImageLoad { base, .. })) => number(*base),
+            (Field::Size, P(Process::ImageLoad { size, .. })) => number(*size),
+
+            (
+                Field::DesiredAccess,
+                F(File::Create { desired_access, .. })
+                | R(
+                    Registry::CreateKey { desired_access, .. }
+                    | Registry::OpenKey { desired_access },
+                ),
+            ) => number(*desired_access),
+            (Field::ShareMode, F(File::Create { share_mode, .. })) => number(*share_mode),
+            (
+                Field::Disposition,
+                F(File::Create { disposition, .. }) | R(Registry::CreateKey { disposition, .. }),
+            ) => number(*disposition),
+            (Field::Options, F(File::Create { options, .. })) => number(*options),
+            (Field::Attributes, F(File::Create { attribute, .. })) => number(*attribute),
+            (
+                Field::Attributes,
+                F(File::SetInformation {
+                    information: SetInformation::Basic { attributes, .. },
+                }),
+            ) => number(*attributes),
+            (Field::OpenAction, F(File::Create { open_action, .. })) => number(*open_action),
+
+            (Field::Length, F(File::Read { length, .. } | File::Write { length, .. })) => {
+                number(*length)
+            }
+            (
+                Field::Length,
+                F(File::QueryInformation { length, .. } | File::QueryDirectory { length, .. })
+                | R(Registry::QueryKey { length, .. } | Registry::QueryValue { length, .. }),
+            ) => number(*length),
+            (Field::Length, F(File::LockControl { length, .. })) => number(*length),
+            (Field::Length, N(operation)) => number(operation.length()),
+            (
+                Field::Offset,
+                F(
+                    File::Read { offset, .. }
+                    | File::Write { offset, .. }
+                    | File::LockControl { offset, .. },
+                ),
+            ) => number(*offset),
+            (
+                Field::InformationClass,
+                F(
+                    File::QueryInformation {
+                        information_class, ..
+                    }
+                    | File::QueryDirectory {
+                        information_class, ..
+                    }
+                    | File::SetInformation {
+                        information:
+                            SetInformation::Other {
+                                information_class, ..
+                            },
+                    },
+                )
+                | R(
+                    Registry::QueryKey {
+                        information_class, ..
+                    }
+                    | Registry::QueryValue {
+                        information_class, ..
+                    }
+                    | Registry::EnumerateKey {
+                        information_class, ..
+                    }
+                    | Registry::EnumerateValue {
+                        information_class, ..
+                    },
+                ),
+            ) => number(*information_class),
+            (Field::Pattern, F(File::QueryDirectory { pattern, .. })) => text(pattern.to_string()),
+            (Field::ControlCode, F(File::FileSystemControl { control_code, .. })) => {
+                number(*control_code)
+            }
+            (Field::InputLength, F(File::FileSystemControl { input_length, .. })) => {
+                number(*input_length)
+            }
+            (Field::OutputLength, F(File::FileSystemControl { output_length, .. })) => {
+                number(*output_length)
+            }
+            (Field::LockOperation, F(File::LockControl { operation, .. })) => {
+                text(lock_operation_name(operation))
+            }
+            (Field::Exclusive, F(File::LockControl { exclusive, .. })) => number(*exclusive),
+
+            (
+                Field::CreationTime
+                | Field::LastAccessTime
+                | Field::LastWriteTime
+                | Field::ChangeTime
+                | Field::Target
+                | Field::ReplaceIfExists
+                | Field::Delete
+                | Field::EndOfFile
+                | Field::AllocationSize,
+                F(File::SetInformation { information }),
+            ) => set_information_value(self, information),
+
+            (
+                Field::ValueName,
+                R(
+                    Registry::SetValue { value_name, .. }
+                    | Registry::QueryValue { value_name, .. }
+                    | Registry::DeleteValue { value_name },
+                ),
+            ) => text(value_name.to_string()),
+            (Field::ValueType, R(Registry::SetValue { value_type, .. })) => text(value_type.name()),
+            (Field::DataSize, R(Registry::SetValue { data_size, .. })) => number(*data_size),
+            (
+                Field::Index,
+                R(Registry::EnumerateKey { index, .. } | Registry::EnumerateValue { index, .. }),
+            ) => number(*index),
+            (Field::NewName, R(Registry::RenameKey { new_name })) => text(new_name.to_string()),
+
+            (Field::Local, N(operation)) => text(operation.endpoints().local.to_string()),
+            (Field::Remote, N(operation)) => text(operation.endpoints().remote.to_string()),
+            (Field::LocalAddress, N(operation)) => text(operation.endpoints().local.ip.to_string()),
+            (Field::RemoteAddress, N(operation)) => {
+                text(operation.endpoints().remote.ip.to_string())
+            }
+            (Field::LocalPort, N(operation)) => number(operation.endpoints().local.port),
+            (Field::RemotePort, N(operation)) => number(operation.endpoints().remote.port),
+
+            (Field::Attempts, EventClass::Session(Session::Disconnected { attempts })) => {
+                number(*attempts)
+            }
+
+            _ => Value::Missing,
+        }
+    }
+}
+
+fn set_information_value(field: &Field, information: &SetInformation) -> Value {
+    match (field, information) {
+        (Field::CreationTime, SetInformation::Basic { creation_time, .. }) => {
+            number(*creation_time)
+        }
+        (
+            Field::LastAccessTime,
+            SetInformation::Basic {
+                last_access_time, ..
+            },
+        ) => number(*last_access_time),
+        (
+            Field::LastWriteTime,
+            SetInformation::Basic {
+                last_write_time, ..
+            },
+        ) => number(*last_write_time),
+        (Field::ChangeTime, SetInformation::Basic { change_time, .. }) => number(*change_time),
+        (Field::Target, SetInformation::Rename { target, .. }) => text(target.to_string()),
+        (
+            Field::ReplaceIfExists,
+            SetInformation::Rename {
+                replace_if_exists, ..
+            },
+        ) => number(*replace_if_exists),
+        (Field::Delete, SetInformation::Disposition { delete }) => number(*delete),
+        (Field::EndOfFile, SetInformation::EndOfFile { end_of_file }) => number(*end_of_file),
+        (Field::AllocationSize, SetInformation::Allocation { allocation_size }) => {
+            number(*allocation_size)
+        }
+        _ => Value::Missing,
+    }
+}
@@ -0,0 +1,220 @@
+use std::ops::Range;
+
+use super::QueryError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum TokenKind {
+    /// Field names, keywords and bare values like `Write` or `STATUS_ACCESS_DENIED`
+    Word(String),
+    /// Quoted, escapes already resolved
+    Text(String),
+    /// Digits as written, a unit may follow directly like in `10ms`
+    Number {
+        digits: String,
+        unit: Option<String>,
+    },
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Glob,
+    NotGlob,
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+    Comma,
+    End,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Token {
+    pub kind: TokenKind,
+    pub span: Range<usize>,
+}
+
+pub(super) fn tokenize(source: &str) -> Result<Vec<Token>, QueryError> {
+    let mut lexer = Lexer {
+        source,
+        position: 0,
+        tokens: Vec::new(),
+    };
+    lexer.run()?;
+    Ok(lexer.tokens)
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    position: usize,
+    tokens: Vec<Token>,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.source[self.position..].chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, accept: F) -> &str {
+        let start = self.position;
+        while self.peek().is_some_and(&accept) {
+            self.bump();
+        }
+        &self.source[start..self.position]
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize) {
+        self.tokens.push(Token {
+            kind,
+            span: start..self.position,
+        });
+    }
+
+    fn run(&mut self) -> Result<(), QueryError> {
+        loop {
+            self.take_while(char::is_whitespace);
+
+            let start = self.position;
+            let Some(c) = self.bump() else {
+                self.push(TokenKind::End, start);
+                return Ok(());
+            };
+
+            let kind = match c {
+                '(' => TokenKind::OpenParen,
+                ')' => TokenKind::CloseParen,
+                ',' => TokenKind::Comma,
+                '~' => TokenKind::Glob,
+                '=' => {
+                    //`=` alone is accepted as well
+                    if self.peek() == Some('=') {
+                        self.bump();
+                    }
+                    TokenKind::Equal
+                }
+                '!' => match self.peek() {
+                    Some('=') => {
+                        self.bump();
+                        TokenKind::NotEqual
+                    }
+                    Some('~') => {
+                        self.bump();
+                        TokenKind::NotGlob
+                    }
+                    _ => TokenKind::Not,
+                },
+                '<' | '>' => {
+                    let or_equal = self.peek() == Some('=');
+                    if or_equal {
+                        self.bump();
+                    }
+                    match (c, or_equal) {
+                        ('<', false) => TokenKind::Less,
+                        ('<', true) => TokenKind::LessEqual,
+                        (_, false) => TokenKind::Greater,
+                        (_, true) => TokenKind::GreaterEqual,
+                    }
+                }
+                '&' | '|' => {
+                    if self.bump() != Some(c) {
+                        return Err(QueryError::new(
+                            start..self.position,
+                            format!("Expected {}{}", c, c),
+                        ));
+                    }
+                    match c {
+                        '&' => TokenKind::And,
+                        _ => TokenKind::Or,
+                    }
+                }
+                '"' => self.text(start)?,
+                '-' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.number(start),
+                c if c.is_ascii_digit() => self.number(start),
+                c if is_word_start(c) => {
+                    self.take_while(is_word_part);
+                    let word = &self.source[start..self.position];
+                    match word.to_ascii_lowercase().as_str() {
+                        "and" => TokenKind::And,
+                        "or" => TokenKind::Or,
+                        "not" => TokenKind::Not,
+                        _ => TokenKind::Word(word.to_string()),
+                    }
+                }
+                other => {
+                    return Err(QueryError::new(
+                        start..self.position,
+                        format!("Unexpected character '{}'", other),
+                    ))
+                }
+            };
+
+            self.push(kind, start);
+        }
+    }
+
+    ///
+    /// Only `\"` and `\\` are escapes, any other backslash is kept so paths can be typed as is
+    ///
+    fn text(&mut self, start: usize) -> Result<TokenKind, QueryError> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(TokenKind::Text(text)),
+                Some('\\') if matches!(self.peek(), Some('"' | '\\')) => {
+                    text.push(self.bump().unwrap());
+                }
+                Some(c) => text.push(c),
+                None => {
+                    return Err(QueryError::new(
+                        start..self.position,
+                        "Unterminated string".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn number(&mut self, start: usize) -> TokenKind {
+        if self.source[start..].starts_with("0x") || self.source[start..].starts_with("-0x") {
+            while self.peek() != Some('x') {
+                self.bump();
+            }
+            self.bump();
+            self.take_while(|c| c.is_ascii_hexdigit());
+        } else {
+            self.take_while(|c| c.is_ascii_digit());
+            if self.peek() == Some('.') && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+                self.bump();
+                self.take_while(|c| c.is_ascii_digit());
+            }
+        }
+
+        let digits = self.source[start..self.position].to_string();
+        let unit = self.take_while(|c| c.is_ascii_alphabetic());
+
+        TokenKind::Number {
+            digits,
+            unit: (!unit.is_empty()).then(|| unit.to_string()),
+        }
+    }
+}
+
+fn is_word_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_word_part(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
@@ -0,0 +1,476 @@
+//!
+//! Text queries over events, for example
+//!
+//! ```text
+//! process == "svchost.exe" && op in (Write, SetInformation) && path ~ "*\\Run\\*" && duration > 1ms
+//! ```
+//!
+//! Conditions compare a field to a value and are combined with `&&`, `||`, `!`
+//! and parentheses (`and`, `or` and `not` work too). Text is compared without
+//! case, `~` and `!~` match `*` and `?` wildcards over the whole value. Numbers
+//! are decimal or `0x` hexadecimal, durations are seconds unless a unit from
+//! `ns`, `us`, `ms`, `s`, `min` or `h` follows. Results take a status name like
+//! `STATUS_ACCESS_DENIED` or `ACCESS_DENIED`, its text or its code.
+//!
+//! Operation payload fields like `length` or `value_name` only exist on the
+//! operations that carry them, a condition on a field the event does not have
+//! is false whatever the operator
+//!
+
+use std::{collections::HashMap, fmt::Display, ops::Range};
+
+use kmum_common::{
+    ntstatus::NtStatus,
+    process::{ProcessInformation, UniqueProcessId},
+    KmMessage,
+};
+
+mod fields;
+mod lexer;
+mod parser;
+
+use fields::{normalize_name, Field, Kind, Value, CLASSES, OPERATIONS};
+use parser::{Expression, Literal, Operator, Spanned};
+
+///
+/// Where the image path of an event's process comes from, needed by `process` and `image`
+///
+pub trait ProcessLookup {
+    /// `None` while the process is still being resolved, `Some(None)` if it is not known
+    fn image_path(&self, uid: UniqueProcessId) -> Option<Option<String>>;
+}
+
+/// Nothing is known about any process
+impl ProcessLookup for () {
+    fn image_path(&self, _uid: UniqueProcessId) -> Option<Option<String>> {
+        Some(None)
+    }
+}
+
+/// The processes of a capture, see `Capture::processes`
+impl ProcessLookup for HashMap<UniqueProcessId, Option<ProcessInformation>> {
+    fn image_path(&self, uid: UniqueProcessId) -> Option<Option<String>> {
+        Some(
+            self.get(&uid)
+                .and_then(|info| info.as_ref())
+                .map(|info| info.path.to_string()),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    /// Byte range of the query the error is about
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl QueryError {
+    fn new(span: Range<usize>, message: String) -> Self {
+        Self { span, message }
+    }
+
+    ///
+    /// The query with the offending part underlined and the message below it
+    ///
+    pub fn annotate(&self, source: &str) -> String {
+        let start = source[..self.span.start.min(source.len())].chars().count();
+        let width = source
+            .get(self.span.clone())
+            .map_or(0, |part| part.chars().count())
+            .max(1);
+
+        format!(
+            "{}\n{}{}\n{}",
+            source,
+            " ".repeat(start),
+            "^".repeat(width),
+            self.message
+        )
+    }
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.span.start + 1)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+///
+/// A parsed query, ready to be run over events
+///
+#[derive(Debug, Clone)]
+pub struct Query {
+    source: String,
+    root: Node,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Self, QueryError> {
+        let expression = parser::parse(lexer::tokenize(source)?)?;
+
+        Ok(Self {
+            source: source.to_string(),
+            root: compile(expression)?,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    ///
+    /// `None` if the query needs the process of the event and it is not resolved yet
+    ///
+    pub fn matches<P: ProcessLookup + ?Sized>(
+        &self,
+        event: &KmMessage,
+        processes: &P,
+    ) -> Option<bool> {
+        self.root.evaluate(event, processes)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Test { field: Field, check: Check },
+}
+
+#[derive(Debug, Clone)]
+enum Check {
+    /// Matches if any pattern does, `negate` for `!=` and `!~`
+    Text {
+        patterns: Vec<Pattern>,
+        negate: bool,
+    },
+    Compare {
+        operator: Operator,
+        value: i128,
+    },
+    In(Vec<i128>),
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Exact(String),
+    Glob(Vec<char>),
+}
+
+impl Node {
+    fn evaluate<P: ProcessLookup + ?Sized>(
+        &self,
+        event: &KmMessage,
+        processes: &P,
+    ) -> Option<bool> {
+        match self {
+            Node::And(left, right) => {
+                let left = left.evaluate(event, processes);
+                if left == Some(false) {
+                    return Some(false);
+                }
+                match (left, right.evaluate(event, processes)) {
+                    (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            Node::Or(left, right) => {
+                let left = left.evaluate(event, processes);
+                if left == Some(true) {
+                    return Some(true);
+                }
+                match (left, right.evaluate(event, processes)) {
+                    (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+            Node::Not(inner) => inner.evaluate(event, processes).map(|matched| !matched),
+            Node::Test { field, check } => match field.value(event, processes) {
+                Value::Pending => None,
+                Value::Missing => Some(false),
+                value => Some(check.matches(field.kind(), value)),
+            },
+        }
+    }
+}
+
+impl Check {
+    fn matches(&self, kind: Kind, value: Value) -> bool {
+        match (self, value) {
+            (Check::Text { patterns, negate }, Value::Text(text)) => {
+                let text = match kind {
+                    Kind::Operation | Kind::Class => normalize_name(&text),
+                    _ => text.to_lowercase(),
+                };
+                patterns.iter().any(|pattern| pattern.matches(&text)) != *negate
+            }
+            (Check::Compare { operator, value }, Value::Number(actual)) => match operator {
+                Operator::Equal => actual == *value,
+                Operator::NotEqual => actual != *value,
+                Operator::Less => actual < *value,
+                Operator::LessEqual => actual <= *value,
+                Operator::Greater => actual > *value,
+                Operator::GreaterEqual => actual >= *value,
+                _ => false,
+            },
+            (Check::In(values), Value::Number(actual)) => values.contains(&actual),
+            _ => false,
+        }
+    }
+}
+
+impl Pattern {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Pattern::Exact(expected) => text == expected,
+            Pattern::Glob(pattern) => glob_matches(pattern, text),
+        }
+    }
+}
+
+///
+/// `*` matches any run of characters and `?` a single one, backtracking only to the last `*`
+///
+fn glob_matches(pattern: &[char], text: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn compile(expression: Expression) -> Result<Node, QueryError> {
+    Ok(match expression {
+        Expression::And(left, right) => {
+            Node::And(Box::new(compile(*left)?), Box::new(compile(*right)?))
+        }
+        Expression::Or(left, right) => {
+            Node::Or(Box::new(compile(*left)?), Box::new(compile(*right)?))
+        }
+        Expression::Not(inner) => Node::Not(Box::new(compile(*inner)?)),
+        Expression::Flag(name) => {
+            let field = lookup(&name)?;
+            if field.kind() != Kind::Flag {
+                return Err(QueryError::new(
+                    name.span,
+                    format!("{} has to be compared to a value", name.value),
+                ));
+            }
+            Node::Test {
+                field,
+                check: Check::Compare {
+                    operator: Operator::Equal,
+                    value: 1,
+                },
+            }
+        }
+        Expression::Compare {
+            field: name,
+            operator,
+            values,
+        } => {
+            let field = lookup(&name)?;
+            let kind = field.kind();
+
+            let allowed = match kind {
+                Kind::Text | Kind::Operation | Kind::Class => matches!(
+                    operator.value,
+                    Operator::Equal
+                        | Operator::NotEqual
+                        | Operator::Glob
+                        | Operator::NotGlob
+                        | Operator::In
+                ),
+                Kind::Number | Kind::Duration => {
+                    !matches!(operator.value, Operator::Glob | Operator::NotGlob)
+                }
+                Kind::Status | Kind::Flag => matches!(
+                    operator.value,
+                    Operator::Equal | Operator::NotEqual | Operator::In
+                ),
+            };
+            if !allowed {
+                return Err(QueryError::new(
+                    operator.span,
+                    format!("This operator can not be used on {}", name.value),
+                ));
+            }
+
+            let check = match kind {
+                Kind::Text | Kind::Operation | Kind::Class => {
+                    let glob = matches!(operator.value, Operator::Glob | Operator::NotGlob);
+                    let patterns = values
+                        .iter()
+                        .map(|value| text_pattern(kind, value, glob))
+                        .collect::<Result<_, _>>()?;
+
+                    Check::Text {
+                        patterns,
+                        negate: matches!(operator.value, Operator::NotEqual | Operator::NotGlob),
+                    }
+                }
+                _ => {
+                    let mut numbers = values
+                        .iter()
+                        .map(|value| number_value(kind, &name.value, value))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    match operator.value {
+                        Operator::In => Check::In(numbers),
+                        operator => Check::Compare {
+                            operator,
+                            value: numbers.remove(0),
+                        },
+                    }
+                }
+            };
+
+            Node::Test { field, check }
+        }
+    })
+}
+
+fn lookup(name: &Spanned<String>) -> Result<Field, QueryError> {
+    Field::lookup(&name.value)
+        .ok_or_else(|| QueryError::new(name.span.clone(), format!("Unknown field {}", name.value)))
+}
+
+fn literal_text(literal: &Literal) -> String {
+    match literal {
+        Literal::Word(text) | Literal::Text(text) => text.clone(),
+        Literal::Number { digits, unit } => format!("{}{}", digits, unit.as_deref().unwrap_or("")),
+    }
+}
+
+fn text_pattern(kind: Kind, value: &Spanned<Literal>, glob: bool) -> Result<Pattern, QueryError> {
+    let text = literal_text(&value.value);
+
+    let known = match kind {
+        Kind::Operation => OPERATIONS,
+        Kind::Class => CLASSES,
+        _ => {
+            let text = text.to_lowercase();
+            return Ok(match glob {
+                true => Pattern::Glob(text.chars().collect()),
+                false => Pattern::Exact(text),
+            });
+        }
+    };
+
+    let text = normalize_name(&text);
+    if glob {
+        return Ok(Pattern::Glob(text.chars().collect()));
+    }
+
+    if !known.iter().any(|name| normalize_name(name) == text) {
+        return Err(QueryError::new(
+            value.span.clone(),
+            format!(
+                "Unknown {} {}, expected one of {}",
+                match kind {
+                    Kind::Operation => "operation",
+                    _ => "class",
+                },
+                literal_text(&value.value),
+                known.join(", ")
+            ),
+        ));
+    }
+
+    Ok(Pattern::Exact(text))
+}
+
+fn number_value(kind: Kind, field: &str, value: &Spanned<Literal>) -> Result<i128, QueryError> {
+    let error = |message: String| QueryError::new(value.span.clone(), message);
+
+    match (kind, &value.value) {
+        (Kind::Status, literal) => {
+            let text = literal_text(literal);
+            NtStatus::parse(&text)
+                .map(|status| status.code().into())
+                .ok_or_else(|| error(format!("Unknown status {}", text)))
+        }
+        (Kind::Flag, Literal::Word(word)) if word.eq_ignore_ascii_case("true") => Ok(1),
+        (Kind::Flag, Literal::Word(word)) if word.eq_ignore_ascii_case("false") => Ok(0),
+        (Kind::Flag, _) => Err(error(format!("{} is either true or false", field))),
+        (Kind::Duration, Literal::Number { digits, unit }) => {
+            duration_ticks(digits, unit.as_deref()).map_err(error)
+        }
+        (_, Literal::Number { digits, unit: None }) => {
+            parse_integer(digits).ok_or_else(|| error(format!("Invalid number {}", digits)))
+        }
+        (
+            _,
+            Literal::Number {
+                unit: Some(unit), ..
+            },
+        ) => Err(error(format!(
+            "{} does not take a unit like {}",
+            field, unit
+        ))),
+        _ => Err(error(format!("{} needs a number", field))),
+    }
+}
+
+fn parse_integer(digits: &str) -> Option<i128> {
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits),
+    };
+
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// Durations are kept in 100ns ticks like the driver reports them
+fn duration_ticks(digits: &str, unit: Option<&str>) -> Result<i128, String> {
+    let ticks_per_unit = match unit.map(str::to_ascii_lowercase).as_deref() {
+        Some("ns") => 0.01,
+        Some("us") => 10.0,
+        Some("ms") => 10_000.0,
+        None | Some("s") => 10_000_000.0,
+        Some("min") => 600_000_000.0,
+        Some("h") => 36_000_000_000.0,
+        Some(unit) => return Err(format!("Unknown duration unit {}", unit)),
+    };
+
+    let value: f64 = match parse_integer(digits) {
+        Some(value) => value as f64,
+        None => digits
+            .parse()
+            .map_err(|_| format!("Invalid duration {}", digits))?,
+    };
+
+    Ok((value * ticks_per_unit).round() as i128)
+}
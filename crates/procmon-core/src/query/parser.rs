@@ -0,0 +1,215 @@
+//!
+//! expression := or
+//! or         := and (("||" | "or") and)*
+//! and        := unary (("&&" | "and") unary)*
+//! unary      := ("!" | "not") unary | primary
+//! primary    := "(" expression ")" | field operator value | field "in" "(" value ("," value)* ")" | field
+//!
+
+use std::ops::Range;
+
+use super::{
+    lexer::{Token, TokenKind},
+    QueryError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Glob,
+    NotGlob,
+    In,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Literal {
+    Word(String),
+    Text(String),
+    Number {
+        digits: String,
+        unit: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Spanned<T> {
+    pub value: T,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug)]
+pub(super) enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Compare {
+        field: Spanned<String>,
+        operator: Spanned<Operator>,
+        values: Vec<Spanned<Literal>>,
+    },
+    /// A true/false field on its own, like `delete`
+    Flag(Spanned<String>),
+}
+
+pub(super) fn parse(tokens: Vec<Token>) -> Result<Expression, QueryError> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+
+    if parser.peek().kind == TokenKind::End {
+        return Err(QueryError::new(0..0, "Empty query".to_string()));
+    }
+
+    let expression = parser.or()?;
+    let token = parser.peek();
+    match token.kind {
+        TokenKind::End => Ok(expression),
+        TokenKind::CloseParen => Err(QueryError::new(
+            token.span.clone(),
+            "Unmatched )".to_string(),
+        )),
+        _ => Err(QueryError::new(
+            token.span.clone(),
+            "Expected && or || between conditions".to_string(),
+        )),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        //The lexer always ends with `End`
+        &self.tokens[self.position.min(self.tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Token, QueryError> {
+        let token = self.next();
+        if token.kind == kind {
+            Ok(token)
+        } else {
+            Err(QueryError::new(token.span, format!("Expected {}", what)))
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression, QueryError> {
+        let mut left = self.and()?;
+        while self.peek().kind == TokenKind::Or {
+            self.next();
+            left = Expression::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, QueryError> {
+        let mut left = self.unary()?;
+        while self.peek().kind == TokenKind::And {
+            self.next();
+            left = Expression::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, QueryError> {
+        if self.peek().kind == TokenKind::Not {
+            self.next();
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, QueryError> {
+        let token = self.next();
+        let field = match token.kind {
+            TokenKind::OpenParen => {
+                let expression = self.or()?;
+                self.expect(TokenKind::CloseParen, "a closing )")?;
+                return Ok(expression);
+            }
+            TokenKind::Word(name) => Spanned {
+                value: name,
+                span: token.span,
+            },
+            _ => {
+                return Err(QueryError::new(
+                    token.span,
+                    "Expected a field name".to_string(),
+                ))
+            }
+        };
+
+        let operator = match &self.peek().kind {
+            TokenKind::Equal => Operator::Equal,
+            TokenKind::NotEqual => Operator::NotEqual,
+            TokenKind::Less => Operator::Less,
+            TokenKind::LessEqual => Operator::LessEqual,
+            TokenKind::Greater => Operator::Greater,
+            TokenKind::GreaterEqual => Operator::GreaterEqual,
+            TokenKind::Glob => Operator::Glob,
+            TokenKind::NotGlob => Operator::NotGlob,
+            TokenKind::Word(word) if word.eq_ignore_ascii_case("in") => Operator::In,
+            _ => return Ok(Expression::Flag(field)),
+        };
+        let operator = Spanned {
+            value: operator,
+            span: self.next().span,
+        };
+
+        let values = match operator.value {
+            Operator::In => self.list()?,
+            _ => vec![self.literal()?],
+        };
+
+        Ok(Expression::Compare {
+            field,
+            operator,
+            values,
+        })
+    }
+
+    fn list(&mut self) -> Result<Vec<Spanned<Literal>>, QueryError> {
+        self.expect(TokenKind::OpenParen, "( after in")?;
+
+        let mut values = vec![self.literal()?];
+        loop {
+            let token = self.next();
+            match token.kind {
+                TokenKind::Comma => values.push(self.literal()?),
+                TokenKind::CloseParen => return Ok(values),
+                _ => return Err(QueryError::new(token.span, "Expected , or )".to_string())),
+            }
+        }
+    }
+
+    fn literal(&mut self) -> Result<Spanned<Literal>, QueryError> {
+        let token = self.next();
+        let value = match token.kind {
+            TokenKind::Word(word) => Literal::Word(word),
+            TokenKind::Text(text) => Literal::Text(text),
+            TokenKind::Number { digits, unit } => Literal::Number { digits, unit },
+            _ => return Err(QueryError::new(token.span, "Expected a value".to_string())),
+        };
+
+        Ok(Spanned {
+            value,
+            span: token.span,
+        })
+    }
+}
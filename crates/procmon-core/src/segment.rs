@@ -0,0 +1,184 @@
+//!
+//! Segment files, chunks of events the client moved out of memory.
+//!
+//! Everything is little endian:
+//!
+//! ```text
+//! header  magic "PMSEGMNT" | format version u16
+//! chunk   event count u32 | payload length u32 | payload
+//! ```
+//!
+//! The payload is the postcard encoded events back to back. Chunks are only
+//! ever appended and are found again through the `ChunkLocation` returned
+//! when they were written
+//!
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use kmum_common::KmMessage;
+
+pub const SEGMENT_MAGIC: [u8; 8] = *b"PMSEGMNT";
+pub const SEGMENT_FORMAT_VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 8 + 2;
+const CHUNK_HEADER_SIZE: usize = 4 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLocation {
+    /// Of the chunk header from the start of the file
+    pub offset: u64,
+    pub count: u32,
+    /// Of the payload
+    pub length: u32,
+}
+
+///
+/// Appends chunks to a segment, the header is written on creation
+///
+pub struct SegmentWriter<W: Write> {
+    writer: W,
+    position: u64,
+    scratch: Vec<u8>,
+}
+
+impl SegmentWriter<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(File::create(path)?)
+    }
+}
+
+impl<W: Write> SegmentWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..8].copy_from_slice(&SEGMENT_MAGIC);
+        header[8..10].copy_from_slice(&SEGMENT_FORMAT_VERSION.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer,
+            position: HEADER_SIZE as u64,
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Size of the segment so far
+    pub fn len(&self) -> u64 {
+        self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == HEADER_SIZE as u64
+    }
+
+    ///
+    /// Writes `events` as one chunk, it is readable once this returns
+    ///
+    pub fn append<'a, I>(&mut self, events: I) -> io::Result<ChunkLocation>
+    where
+        I: IntoIterator<Item = &'a KmMessage>,
+    {
+        let mut record = std::mem::take(&mut self.scratch);
+        record.clear();
+        record.extend_from_slice(&[0u8; CHUNK_HEADER_SIZE]);
+
+        let mut count = 0u32;
+        for event in events {
+            record = postcard::to_extend(event, record)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+            count += 1;
+        }
+
+        let length = u32::try_from(record.len() - CHUNK_HEADER_SIZE)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "chunk too large"))?;
+        record[..4].copy_from_slice(&count.to_le_bytes());
+        record[4..8].copy_from_slice(&length.to_le_bytes());
+
+        let location = ChunkLocation {
+            offset: self.position,
+            count,
+            length,
+        };
+
+        self.writer.write_all(&record)?;
+        self.writer.flush()?;
+        self.position += record.len() as u64;
+        self.scratch = record;
+
+        Ok(location)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+///
+/// Reads chunks back from a segment
+///
+pub struct SegmentReader<R: Read + Seek> {
+    reader: R,
+}
+
+impl SegmentReader<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> SegmentReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+
+        if header[..8] != SEGMENT_MAGIC {
+            return Err(invalid_data("not a segment file"));
+        }
+        let format = u16::from_le_bytes(header[8..10].try_into().unwrap());
+        if format != SEGMENT_FORMAT_VERSION {
+            return Err(invalid_data(&format!("unknown segment format {}", format)));
+        }
+
+        Ok(Self { reader })
+    }
+
+    pub fn read_chunk(&mut self, location: &ChunkLocation) -> io::Result<Vec<KmMessage>> {
+        let mut header = [0u8; CHUNK_HEADER_SIZE];
+        self.reader.seek(SeekFrom::Start(location.offset))?;
+        self.reader.read_exact(&mut header)?;
+
+        let count = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let length = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if count != location.count || length != location.length {
+            return Err(invalid_data(&format!(
+                "no chunk at offset {}",
+                location.offset
+            )));
+        }
+
+        let mut payload = vec![0u8; length as usize];
+        self.reader.read_exact(&mut payload)?;
+
+        let mut events = Vec::with_capacity(count as usize);
+        let mut remaining = payload.as_slice();
+        for _ in 0..count {
+            let (event, rest) = postcard::take_from_bytes::<KmMessage>(remaining)
+                .map_err(|e| invalid_data(&format!("{:?}", e)))?;
+            events.push(event);
+            remaining = rest;
+        }
+        if !remaining.is_empty() {
+            return Err(invalid_data("chunk is longer than its events"));
+        }
+
+        Ok(events)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,217 @@
+mod common;
+
+use std::{
+    net::TcpListener,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use common::nt_string;
+use futures_util::StreamExt;
+use kmum_common::{
+    batch::{BatchBuilder, BatchHeader},
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    handshake::{negotiate, BufferSizes, EventCapabilities},
+    ClientConnectMessage, ClientConnectMode, KmMessage, KmReplyMessage, UmSendMessage,
+};
+use procmon_core::communication::{
+    socket::{RequestResponder, SocketCommunication, SocketEndpoint, SocketPublisher},
+    stream::{AsyncCommunication, EventStream},
+    CommunicationError, CommunicationInterface,
+};
+use tokio::time::timeout;
+
+const WAIT: Duration = Duration::from_secs(10);
+
+struct StandIn;
+
+impl RequestResponder for StandIn {
+    fn respond(
+        &self,
+        connect: &ClientConnectMessage,
+        message: &UmSendMessage,
+    ) -> Result<Option<KmReplyMessage>, CommunicationError> {
+        match message {
+            UmSendMessage::Handshake => Ok(Some(KmReplyMessage::Handshake(negotiate(
+                connect,
+                EventCapabilities::all(),
+                BufferSizes::current(),
+            )))),
+            UmSendMessage::GetExeName(uid) => Ok(Some(KmReplyMessage::ExeName(nt_string(
+                &format!("Process{}.exe", uid),
+            )))),
+            _ => Err(CommunicationError::Port),
+        }
+    }
+}
+
+fn event(index: u64) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date: index,
+            thread: 1,
+            operation: EventClass::FileSystem(EventFileSystemOperation::Read {
+                length: 4096,
+                offset: index as _,
+            }),
+            result: 0,
+            path: nt_string(&format!("\\Device\\HarddiskVolume3\\file{}.txt", index)),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: 4,
+            unique_id: index,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+fn batch(sequence: u64, dates: std::ops::Range<u64>) -> Vec<u8> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut builder = BatchBuilder::new();
+    for date in dates {
+        builder.try_push(&mut buffer, &event(date)).unwrap();
+    }
+
+    builder.finish(&mut buffer, 0, sequence, 0).to_vec()
+}
+
+///
+/// Accepts one client and publishes whatever batches are handed over,
+/// the connection closes once `batches` is dropped
+///
+fn serve() -> (SocketEndpoint, mpsc::Sender<Vec<u8>>, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = SocketEndpoint::Tcp(listener.local_addr().unwrap().to_string());
+
+    let (batches, pending) = mpsc::channel::<Vec<u8>>();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let publisher = SocketPublisher::accept(stream.into(), StandIn).unwrap();
+        for batch in pending {
+            publisher.send_batch(&batch).unwrap();
+        }
+    });
+
+    (endpoint, batches, server)
+}
+
+fn connect(endpoint: &SocketEndpoint) -> AsyncCommunication<SocketCommunication> {
+    AsyncCommunication::new(
+        SocketCommunication::connect(endpoint, ClientConnectMessage::new(ClientConnectMode::Any))
+            .unwrap(),
+    )
+}
+
+async fn next_date(events: &mut EventStream) -> u64 {
+    timeout(WAIT, events.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+        .event
+        .date
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stream_yields_events_and_answers_queries() {
+    let (endpoint, batches, server) = serve();
+    let communication = connect(&endpoint);
+
+    match communication
+        .send_message(UmSendMessage::GetExeName(42))
+        .await
+    {
+        Ok(Some(KmReplyMessage::ExeName(name))) => assert_eq!(name.to_string(), "Process42.exe"),
+        other => panic!("unexpected reply {:?}", other),
+    }
+
+    let mut events = communication.events();
+    for sequence in 0..4 {
+        batches
+            .send(batch(sequence, sequence * 25..(sequence + 1) * 25))
+            .unwrap();
+    }
+
+    //A single stream sees batches in the order they arrived
+    for date in 0..100 {
+        assert_eq!(next_date(&mut events).await, date);
+    }
+
+    communication.stop();
+    assert!(timeout(WAIT, events.next()).await.unwrap().is_none());
+
+    let statistics = communication.communication().statistics().snapshot();
+    assert_eq!(statistics.batches, 4);
+    assert_eq!(statistics.events, 100);
+
+    drop(batches);
+    server.join().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn malformed_batch_is_reported_in_place() {
+    let (endpoint, batches, server) = serve();
+    let communication = connect(&endpoint);
+    let mut events = communication.events();
+
+    //The header promises an event the payload does not hold
+    let mut short = batch(0, 0..1);
+    let (mut header, _) = BatchHeader::split(&short).unwrap();
+    header.event_count += 1;
+    header.write((&mut short[..BatchHeader::SIZE]).try_into().unwrap());
+    batches.send(short).unwrap();
+    batches.send(batch(1, 2..4)).unwrap();
+
+    assert_eq!(next_date(&mut events).await, 0);
+    assert!(matches!(
+        timeout(WAIT, events.next()).await.unwrap(),
+        Some(Err(CommunicationError::Parsing))
+    ));
+    assert_eq!(next_date(&mut events).await, 2);
+    assert_eq!(next_date(&mut events).await, 3);
+
+    let statistics = communication.communication().statistics().snapshot();
+    assert_eq!(statistics.events, 3);
+
+    communication.stop();
+    drop(batches);
+    server.join().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropped_stream_releases_its_loop() {
+    let (endpoint, batches, server) = serve();
+    let communication = connect(&endpoint);
+
+    drop(communication.events());
+    //Give the abandoned loop a chance to notice
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    //Had the first loop kept running it would swallow some of these
+    let mut events = communication.events();
+    for sequence in 0..8 {
+        batches
+            .send(batch(sequence, sequence * 10..(sequence + 1) * 10))
+            .unwrap();
+    }
+    for date in 0..80 {
+        assert_eq!(next_date(&mut events).await, date);
+    }
+
+    //Dropping the last stream leaves the connection usable
+    drop(events);
+    assert!(matches!(
+        communication
+            .send_message(UmSendMessage::GetExeName(7))
+            .await,
+        Ok(Some(KmReplyMessage::ExeName(_)))
+    ));
+
+    communication.stop();
+    drop(batches);
+    server.join().unwrap();
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use common::nt_string;
+use kmum_common::{
+    handshake::{negotiate, BufferSizes, EventCapabilities},
+    process::ProcessInformation,
+    serializable_ntstring::SerializableNtString,
+    ClientConnectMessage, ClientConnectMode, KmReplyMessage, UmSendMessage,
+};
+use procmon_core::communication::{
+    socket::{RequestResponder, SocketCommunication, SocketEndpoint, SocketPublisher},
+    CommunicationError, CommunicationInterface,
+};
+
+/// Uids above this are unknown to the stand-in
+const KNOWN_PROCESSES: u64 = 5000;
+
+#[derive(Default)]
+struct StandIn {
+    batch_requests: Arc<AtomicUsize>,
+}
+
+impl StandIn {
+    //Long enough that a full batch does not fit a single reply
+    fn exe_name(uid: u64) -> Option<SerializableNtString> {
+        (uid < KNOWN_PROCESSES)
+            .then(|| nt_string(&format!("{}Process{}.exe", "x".repeat(120), uid)))
+    }
+
+    fn process_info(uid: u64) -> Option<ProcessInformation> {
+        Self::exe_name(uid).map(|path| ProcessInformation {
+            path,
+            cmd: None,
+            pid: uid * 4,
+            parent_pid: 4,
+            start_time: uid,
+            end_time: None,
+            unique_id: uid,
+        })
+    }
+}
+
+impl RequestResponder for StandIn {
+    fn respond(
+        &self,
+        connect: &ClientConnectMessage,
+        message: &UmSendMessage,
+    ) -> Result<Option<KmReplyMessage>, CommunicationError> {
+        match message {
+            UmSendMessage::Handshake => Ok(Some(KmReplyMessage::Handshake(negotiate(
+                connect,
+                EventCapabilities::all(),
+                BufferSizes::current(),
+            )))),
+            UmSendMessage::GetExeName(uid) => Ok(Self::exe_name(*uid).map(KmReplyMessage::ExeName)),
+            UmSendMessage::GetExeNameBatch(uids) => {
+                self.batch_requests.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(KmReplyMessage::ExeNameBatch(
+                    uids.iter().map(|uid| Self::exe_name(*uid)).collect(),
+                )))
+            }
+            UmSendMessage::GetProcessInfoBatch(uids) => {
+                self.batch_requests.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(KmReplyMessage::ProcessInfoBatch(
+                    uids.iter().map(|uid| Self::process_info(*uid)).collect(),
+                )))
+            }
+            _ => Err(CommunicationError::Port),
+        }
+    }
+}
+
+///
+/// Connects to a stand-in that stays up until `done` fires
+///
+fn connect() -> (Arc<SocketCommunication>, Arc<AtomicUsize>, mpsc::Sender<()>) {
+    let listener = "tcp://127.0.0.1:0"
+        .parse::<SocketEndpoint>()
+        .unwrap()
+        .bind()
+        .unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+
+    let batch_requests = Arc::new(AtomicUsize::new(0));
+    let responder = StandIn {
+        batch_requests: batch_requests.clone(),
+    };
+
+    let (done, wait) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        let _publisher = SocketPublisher::accept(listener.accept().unwrap(), responder).unwrap();
+        let _ = wait.recv_timeout(Duration::from_secs(10));
+    });
+
+    let communication =
+        SocketCommunication::connect(&endpoint, ClientConnectMessage::new(ClientConnectMode::Any))
+            .unwrap();
+
+    (Arc::new(communication), batch_requests, done)
+}
+
+#[test]
+fn batch_queries_resolve_every_uid_in_order() {
+    let (communication, batch_requests, done) = connect();
+
+    //Includes uids the stand-in does not know
+    let uids: Vec<u64> = (KNOWN_PROCESSES - 900..KNOWN_PROCESSES + 100).collect();
+
+    let names = communication.query_exe_names(&uids).unwrap();
+    assert_eq!(names.len(), uids.len());
+    for (uid, name) in uids.iter().zip(&names) {
+        assert_eq!(
+            name.as_ref().map(|name| name.to_string()),
+            StandIn::exe_name(*uid).map(|name| name.to_string())
+        );
+    }
+
+    //Short replies were continued, still far fewer round trips than uids
+    let requests = batch_requests.swap(0, Ordering::Relaxed);
+    assert!(requests > uids.len() / 256, "{} requests", requests);
+    assert!(requests < uids.len() / 16, "{} requests", requests);
+
+    let infos = communication.query_process_infos(&uids[..10]).unwrap();
+    assert_eq!(infos[3].as_ref().unwrap().pid, uids[3] * 4);
+    assert_eq!(batch_requests.load(Ordering::Relaxed), 1);
+
+    assert!(communication.query_exe_names(&[]).unwrap().is_empty());
+
+    done.send(()).unwrap();
+}
+
+#[test]
+fn concurrent_requests_get_their_own_replies() {
+    let (communication, _, done) = connect();
+
+    let threads: Vec<_> = (0..8u64)
+        .map(|thread| {
+            let communication = communication.clone();
+            thread::spawn(move || {
+                for uid in (thread..400).step_by(8) {
+                    match communication.send_message_blocking(&UmSendMessage::GetExeName(uid)) {
+                        Ok(Some(KmReplyMessage::ExeName(name))) => {
+                            assert!(name.to_string().ends_with(&format!("Process{}.exe", uid)))
+                        }
+                        other => panic!("unexpected reply {:?}", other),
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    done.send(()).unwrap();
+}
@@ -0,0 +1,63 @@
+use kmum_common::{batch::BatchBuilder, KmMessage};
+use procmon_core::communication::{
+    batch::{BatchDecoder, BatchStatistics, BatchStatisticsSnapshot},
+    BatchHandler, CommunicationError, EventProcessor,
+};
+
+struct Discard;
+
+impl EventProcessor for Discard {
+    fn process<I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        iter.for_each(drop);
+        Ok(())
+    }
+}
+
+///
+/// Feeds empty batches of `(worker, sequence)` through a decoder
+///
+fn receive(batches: &[(u16, u64)]) -> BatchStatisticsSnapshot {
+    let statistics = BatchStatistics::default();
+    let decoder = BatchDecoder::new(Discard, &statistics);
+
+    let mut buffer = vec![0u8; 1024];
+    let mut builder = BatchBuilder::new();
+    for (worker, sequence) in batches {
+        let batch = builder.finish(&mut buffer, *worker, *sequence, 0);
+        decoder.handle_batch(batch).unwrap();
+    }
+
+    statistics.snapshot()
+}
+
+#[test]
+fn late_batches_only_make_up_for_their_own_gap() {
+    let statistics = receive(&[
+        (1, 0),
+        (2, 0),
+        //Worker 1 misses 1 and 2, worker 2 misses 1
+        (1, 3),
+        (2, 2),
+        (1, 2),
+        //Worker 2 sending 2 again does not cover for the loss of worker 1
+        (2, 2),
+        (1, 0),
+    ]);
+
+    assert_eq!(statistics.batches, 7);
+    assert_eq!(statistics.lost_batches, 2);
+    assert_eq!(statistics.reordered_batches, 1);
+    assert_eq!(statistics.duplicate_batches, 2);
+}
+
+#[test]
+fn a_gap_is_made_up_once_per_sequence() {
+    let statistics = receive(&[(0, 10), (0, 15), (0, 12), (0, 12), (0, 11), (0, 14)]);
+
+    assert_eq!(statistics.lost_batches, 1);
+    assert_eq!(statistics.reordered_batches, 3);
+    assert_eq!(statistics.duplicate_batches, 1);
+}
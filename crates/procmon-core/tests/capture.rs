@@ -0,0 +1,326 @@
+mod common;
+
+use std::{
+    fs::OpenOptions,
+    io,
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::nt_string;
+use kmum_common::{
+    batch::BatchBuilder,
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    process::{ProcessInformation, UniqueProcessId},
+    serializable_ntstring::SerializableNtString,
+    KmMessage, KmReplyMessage, UmSendMessage,
+};
+use procmon_core::communication::{
+    batch::BatchStatistics,
+    capture::{Capture, CaptureWriter, CapturedBatch},
+    recording::RecordingCommunication,
+    replay::{ReplayCommunication, ReplaySpeed},
+    BatchHandler, CommunicationError, CommunicationInterface, EventProcessor,
+};
+
+const BATCHES: u64 = 5;
+const EVENTS_PER_BATCH: u64 = 10;
+const KNOWN_PROCESSES: u64 = 3;
+
+fn event(date: u64) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date,
+            thread: 1,
+            operation: EventClass::FileSystem(EventFileSystemOperation::Read {
+                length: 4096,
+                offset: 0,
+            }),
+            result: 0,
+            path: nt_string("\\Device\\HarddiskVolume3\\file.txt"),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: 4,
+            unique_id: date % KNOWN_PROCESSES,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+fn batch(sequence: u64) -> Vec<u8> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut builder = BatchBuilder::new();
+    for index in 0..EVENTS_PER_BATCH {
+        builder
+            .try_push(&mut buffer, &event(sequence * EVENTS_PER_BATCH + index))
+            .unwrap();
+    }
+
+    builder.finish(&mut buffer, 0, sequence, 0).to_vec()
+}
+
+fn process_info(uid: UniqueProcessId) -> Option<ProcessInformation> {
+    (uid < KNOWN_PROCESSES).then(|| ProcessInformation {
+        path: nt_string(&format!("C:\\Process{}.exe", uid)),
+        cmd: None,
+        pid: uid * 4,
+        parent_pid: 4,
+        start_time: uid,
+        end_time: None,
+        unique_id: uid,
+    })
+}
+
+///
+/// Hands out every batch once and answers process queries, like a driver
+/// that only ever saw a handful of events
+///
+#[derive(Default)]
+struct Scripted {
+    statistics: BatchStatistics,
+}
+
+impl CommunicationInterface for Scripted {
+    fn send_message_blocking(
+        &self,
+        message: &UmSendMessage,
+    ) -> Result<Option<KmReplyMessage>, CommunicationError> {
+        match message {
+            UmSendMessage::GetProcessInfoBatch(uids) => Ok(Some(KmReplyMessage::ProcessInfoBatch(
+                uids.iter().map(|uid| process_info(*uid)).collect(),
+            ))),
+            UmSendMessage::GetExeNameBatch(uids) => Ok(Some(KmReplyMessage::ExeNameBatch(
+                uids.iter()
+                    .map(|uid| process_info(*uid).map(|info| info.path))
+                    .collect(),
+            ))),
+            _ => Err(CommunicationError::Port),
+        }
+    }
+
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        handler: H,
+    ) -> Result<(), CommunicationError> {
+        for sequence in 0..BATCHES {
+            handler.handle_batch(&batch(sequence))?;
+        }
+        Ok(())
+    }
+
+    fn statistics(&self) -> &BatchStatistics {
+        &self.statistics
+    }
+
+    fn stop(&self) {}
+}
+
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<u64>>>);
+
+impl Collect {
+    fn dates(&self) -> Vec<u64> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl EventProcessor for Collect {
+    fn process<I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        self.0
+            .lock()
+            .unwrap()
+            .extend(iter.map(|event| event.event.date));
+        Ok(())
+    }
+}
+
+fn capture_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "procmon-core-{}-{}.pmcap",
+        name,
+        std::process::id()
+    ))
+}
+
+fn names(names: Vec<Option<SerializableNtString>>) -> Vec<Option<String>> {
+    names
+        .into_iter()
+        .map(|name| name.map(|name| name.to_string()))
+        .collect()
+}
+
+#[test]
+fn recorded_capture_replays_the_same_events_and_processes() {
+    let path = capture_path("roundtrip");
+    let uids = [0, 1, 2, 7];
+
+    let recording = RecordingCommunication::create(Scripted::default(), &path).unwrap();
+    let live = Collect::default();
+    recording.process_blocking(live.clone()).unwrap();
+    let live_names = names(recording.query_exe_names(&uids).unwrap());
+    let live_infos = recording.query_process_infos(&uids).unwrap();
+    recording.stop();
+    assert!(recording.is_recording());
+    drop(recording);
+
+    let capture = Capture::open(&path).unwrap();
+    assert_eq!(capture.batches.len(), BATCHES as usize);
+    assert!(capture
+        .batches
+        .windows(2)
+        .all(|pair| pair[0].elapsed <= pair[1].elapsed));
+
+    let replay = ReplayCommunication::new(capture, ReplaySpeed::Unthrottled);
+    let replayed = Collect::default();
+    replay.process_blocking(replayed.clone()).unwrap();
+    assert!(replay.is_finished());
+
+    assert_eq!(replayed.dates(), live.dates());
+    assert_eq!(
+        replayed.dates().len(),
+        (BATCHES * EVENTS_PER_BATCH) as usize
+    );
+    assert_eq!(replay.statistics().snapshot().lost_batches, 0);
+
+    assert_eq!(names(replay.query_exe_names(&uids).unwrap()), live_names);
+    let replayed_infos = replay.query_process_infos(&uids).unwrap();
+    assert_eq!(
+        replayed_infos
+            .iter()
+            .map(|info| info.as_ref().map(|info| info.pid))
+            .collect::<Vec<_>>(),
+        live_infos
+            .iter()
+            .map(|info| info.as_ref().map(|info| info.pid))
+            .collect::<Vec<_>>()
+    );
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn capture_cut_short_keeps_complete_records() {
+    let path = capture_path("truncated");
+
+    let recording = RecordingCommunication::create(Scripted::default(), &path).unwrap();
+    recording.process_blocking(Collect::default()).unwrap();
+    recording.stop();
+    drop(recording);
+
+    let length = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(length - 3)
+        .unwrap();
+
+    let capture = Capture::open(&path).unwrap();
+    assert_eq!(capture.batches.len(), BATCHES as usize - 1);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn oversized_records_are_rejected() {
+    let path = capture_path("oversized");
+
+    let mut recording = CaptureWriter::create(&path).unwrap();
+    recording.write_batch(&batch(0)).unwrap();
+    recording.flush().unwrap();
+    drop(recording);
+
+    //A batch record claiming 4 GiB, with no payload behind it
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.push(0);
+    bytes.extend(0u64.to_le_bytes());
+    bytes.extend(u32::MAX.to_le_bytes());
+
+    let error = Capture::read(bytes.as_slice()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+fn timed_capture(gaps: &[u64]) -> Capture {
+    Capture {
+        batches: gaps
+            .iter()
+            .enumerate()
+            .map(|(sequence, millis)| CapturedBatch {
+                elapsed: Duration::from_millis(*millis),
+                data: batch(sequence as _),
+            })
+            .collect(),
+        ..Capture::default()
+    }
+}
+
+#[test]
+fn scaled_replay_keeps_recorded_gaps() {
+    let replay = ReplayCommunication::new(timed_capture(&[0, 200]), ReplaySpeed::Scaled(4.0));
+
+    let start = Instant::now();
+    replay.process_blocking(Collect::default()).unwrap();
+
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn stop_interrupts_a_waiting_replay() {
+    let replay = Arc::new(ReplayCommunication::new(
+        timed_capture(&[0, 60_000]),
+        ReplaySpeed::Original,
+    ));
+
+    let replayed = Collect::default();
+    let (done, finished) = mpsc::channel();
+    let processing = {
+        let replay = replay.clone();
+        let replayed = replayed.clone();
+        thread::spawn(move || {
+            let result = replay.process_blocking(replayed);
+            done.send(()).unwrap();
+            result
+        })
+    };
+
+    thread::sleep(Duration::from_millis(50));
+    replay.stop();
+    finished.recv_timeout(Duration::from_secs(5)).unwrap();
+    processing.join().unwrap().unwrap();
+
+    assert_eq!(replayed.dates().len(), EVENTS_PER_BATCH as usize);
+}
+
+#[test]
+fn unknown_processes_are_not_an_error() {
+    let replay = ReplayCommunication::new(Capture::default(), ReplaySpeed::Unthrottled);
+
+    assert!(matches!(
+        replay.send_message_blocking(&UmSendMessage::GetExeName(99)),
+        Ok(None)
+    ));
+    assert!(matches!(
+        replay.send_message_blocking(&UmSendMessage::GetProcessInfo(99)),
+        Ok(None)
+    ));
+}
+
+#[test]
+fn replay_speeds_parse() {
+    assert_eq!("original".parse(), Ok(ReplaySpeed::Original));
+    assert_eq!("max".parse(), Ok(ReplaySpeed::Unthrottled));
+    assert_eq!("4x".parse(), Ok(ReplaySpeed::Scaled(4.0)));
+    assert_eq!("0.5".parse(), Ok(ReplaySpeed::Scaled(0.5)));
+    assert!("0x".parse::<ReplaySpeed>().is_err());
+    assert!("fast".parse::<ReplaySpeed>().is_err());
+}
@@ -0,0 +1,6 @@
+use kmum_common::serializable_ntstring::SerializableNtString;
+use nt_string::unicode_string::NtUnicodeString;
+
+pub fn nt_string(value: &str) -> SerializableNtString {
+    SerializableNtString::new(NtUnicodeString::try_from(value).unwrap())
+}
@@ -0,0 +1,193 @@
+use kmum_common::{
+    handshake::{
+        negotiate, BufferSizes, DriverHandshake, EventCapabilities, HandshakeError,
+        ProtocolPreamble, PROTOCOL_MAGIC, PROTOCOL_VERSION,
+    },
+    ClientConnectMessage, ClientConnectMode, KmReplyMessage, UmSendMessage,
+};
+use procmon_core::communication::{
+    batch::BatchStatistics, handshake::perform_handshake, BatchHandler, CommunicationError,
+    CommunicationInterface,
+};
+
+///
+/// Driver stand-in that answers the handshake with a fixed result
+///
+struct StandIn {
+    reply: Result<DriverHandshake, HandshakeError>,
+    statistics: BatchStatistics,
+}
+
+impl StandIn {
+    fn new(reply: Result<DriverHandshake, HandshakeError>) -> Self {
+        Self {
+            reply,
+            statistics: BatchStatistics::default(),
+        }
+    }
+}
+
+impl CommunicationInterface for StandIn {
+    fn send_message_blocking(
+        &self,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        match message {
+            UmSendMessage::Handshake => Ok(Some(KmReplyMessage::Handshake(self.reply))),
+            _ => Err(CommunicationError::Port),
+        }
+    }
+
+    fn process_batches_blocking<H: BatchHandler>(
+        &self,
+        _handler: H,
+    ) -> anyhow::Result<(), CommunicationError> {
+        Ok(())
+    }
+
+    fn statistics(&self) -> &BatchStatistics {
+        &self.statistics
+    }
+
+    fn stop(&self) {}
+}
+
+fn request() -> ClientConnectMessage {
+    ClientConnectMessage::new(ClientConnectMode::Any)
+}
+
+fn accepted(request: &ClientConnectMessage) -> DriverHandshake {
+    negotiate(request, EventCapabilities::all(), BufferSizes::current()).unwrap()
+}
+
+fn handshake_error(reply: Result<DriverHandshake, HandshakeError>) -> HandshakeError {
+    match perform_handshake(&StandIn::new(reply), &request()) {
+        Err(CommunicationError::Handshake(e)) => e,
+        other => panic!("expected a handshake error, got {:?}", other),
+    }
+}
+
+#[test]
+fn driver_accepts_a_matching_client() {
+    let request = request().with_capabilities(EventCapabilities::FILE_SYSTEM);
+    let handshake = negotiate(
+        &request,
+        EventCapabilities::FILE_SYSTEM | EventCapabilities::PROCESS,
+        BufferSizes::current(),
+    )
+    .unwrap();
+
+    assert_eq!(handshake.capabilities, EventCapabilities::FILE_SYSTEM);
+    assert_eq!(handshake.validate(&request), Ok(()));
+}
+
+#[test]
+fn driver_rejects_an_invalid_magic() {
+    let mut request = request();
+    request.preamble.magic = u32::from_le_bytes(*b"NOPE");
+
+    assert_eq!(
+        negotiate(&request, EventCapabilities::all(), BufferSizes::current()),
+        Err(HandshakeError::InvalidMagic(request.preamble.magic))
+    );
+}
+
+#[test]
+fn driver_rejects_another_version() {
+    let mut request = request();
+    request.preamble.version = PROTOCOL_VERSION + 1;
+
+    assert_eq!(
+        negotiate(&request, EventCapabilities::all(), BufferSizes::current()),
+        Err(HandshakeError::VersionMismatch {
+            client: PROTOCOL_VERSION + 1,
+            driver: PROTOCOL_VERSION,
+        })
+    );
+}
+
+#[test]
+fn driver_rejects_disjoint_capabilities() {
+    let request = request().with_capabilities(EventCapabilities::REGISTRY);
+
+    assert_eq!(
+        negotiate(
+            &request,
+            EventCapabilities::FILE_SYSTEM,
+            BufferSizes::current()
+        ),
+        Err(HandshakeError::NoCommonCapabilities)
+    );
+}
+
+#[test]
+fn client_rejects_an_invalid_magic() {
+    let mut reply = accepted(&request());
+    reply.preamble.magic = 0;
+
+    assert_eq!(handshake_error(Ok(reply)), HandshakeError::InvalidMagic(0));
+}
+
+#[test]
+fn client_rejects_another_version() {
+    let mut reply = accepted(&request());
+    reply.preamble = ProtocolPreamble {
+        magic: PROTOCOL_MAGIC,
+        version: PROTOCOL_VERSION - 1,
+    };
+
+    assert_eq!(
+        handshake_error(Ok(reply)),
+        HandshakeError::VersionMismatch {
+            client: PROTOCOL_VERSION,
+            driver: PROTOCOL_VERSION - 1,
+        }
+    );
+}
+
+#[test]
+fn client_rejects_capabilities_it_did_not_ask_for() {
+    let request = request().with_capabilities(EventCapabilities::PROCESS);
+    let mut reply = accepted(&request);
+
+    reply.capabilities = EventCapabilities::empty();
+    assert_eq!(
+        reply.validate(&request),
+        Err(HandshakeError::NoCommonCapabilities)
+    );
+
+    reply.capabilities = EventCapabilities::PROCESS | EventCapabilities::NETWORK;
+    assert_eq!(
+        reply.validate(&request),
+        Err(HandshakeError::NoCommonCapabilities)
+    );
+}
+
+#[test]
+fn client_rejects_empty_buffers() {
+    let mut reply = accepted(&request());
+    reply.buffers.um_send = 0;
+
+    assert_eq!(
+        handshake_error(Ok(reply)),
+        HandshakeError::InvalidBufferSizes
+    );
+}
+
+#[test]
+fn driver_rejection_is_carried_by_the_communication_error() {
+    assert_eq!(
+        handshake_error(Err(HandshakeError::NoCommonCapabilities)),
+        HandshakeError::NoCommonCapabilities
+    );
+    assert_eq!(
+        handshake_error(Err(HandshakeError::VersionMismatch {
+            client: PROTOCOL_VERSION + 1,
+            driver: PROTOCOL_VERSION,
+        })),
+        HandshakeError::VersionMismatch {
+            client: PROTOCOL_VERSION + 1,
+            driver: PROTOCOL_VERSION,
+        }
+    );
+}
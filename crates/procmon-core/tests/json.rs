@@ -0,0 +1,408 @@
+mod common;
+
+use std::io::{Cursor, ErrorKind};
+
+use common::nt_string;
+use kmum_common::{
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventNetworkOperation,
+        EventProcessOperation, EventRegistryOperation, EventSessionOperation, EventStack,
+        FileLockOperation, FileSetInformation, FrameMode, IpAddress, NetworkEndpoints,
+        RegistryDataPreview, RegistryValueType, SimpleProcessDetails, SocketAddress, StackFrame,
+    },
+    KmMessage,
+};
+use procmon_core::json::{JsonEvent, JsonlReader, JsonlWriter};
+
+/// 2024-05-01T09:30:12.1234567Z
+const FILETIME: u64 = 133_590_294_121_234_567;
+
+fn event(operation: EventClass, path: &str) -> KmMessage {
+    let mut stack = EventStack::new();
+    stack.try_push(StackFrame {
+        address: 0xffff_f803_1a2b_3c4d,
+        mode: FrameMode::Kernel,
+    });
+    stack.try_push(StackFrame {
+        address: 0x7ff6_1234_0000,
+        mode: FrameMode::User,
+    });
+
+    KmMessage {
+        event: EventCompoent {
+            date: FILETIME,
+            thread: 5678,
+            operation,
+            result: 0xC000_0022_u32 as i32,
+            path: nt_string(path),
+            duration: 120,
+        },
+        process: SimpleProcessDetails {
+            pid: 1234,
+            unique_id: 77,
+        },
+        stack,
+    }
+}
+
+fn endpoints() -> NetworkEndpoints {
+    NetworkEndpoints {
+        local: SocketAddress {
+            ip: IpAddress::V4([10, 0, 0, 2]),
+            port: 50123,
+        },
+        remote: SocketAddress {
+            ip: IpAddress::V6([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+            port: 443,
+        },
+    }
+}
+
+/// At least one event of every operation
+fn every_operation() -> Vec<KmMessage> {
+    let operations = vec![
+        EventClass::Process(EventProcessOperation::ProcessCreate {
+            pid: 99,
+            cmd: Some(nt_string("cmd.exe /c \"echo ünïcode\"")),
+        }),
+        EventClass::Process(EventProcessOperation::ProcessCreate { pid: 98, cmd: None }),
+        EventClass::Process(EventProcessOperation::ProcessDestroy {
+            pid: 99,
+            exit_status: -1,
+        }),
+        EventClass::Process(EventProcessOperation::ThreadCreate {
+            tid: 4,
+            start_address: u64::MAX,
+        }),
+        EventClass::Process(EventProcessOperation::ThreadExit { tid: 4 }),
+        EventClass::Process(EventProcessOperation::ImageLoad {
+            base: 0x7ff6_1234_0000,
+            size: 0x1000,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::Create {
+            desired_access: 0x12_0089,
+            share_mode: 7,
+            disposition: 1,
+            options: 0x60,
+            attribute: 0x80,
+            open_action: 1,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::Read {
+            length: 4096,
+            offset: -1,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::Write {
+            length: 512,
+            offset: 0,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::Close {}),
+        EventClass::FileSystem(EventFileSystemOperation::QueryInformation {
+            information_class: 5,
+            length: 24,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+            information: FileSetInformation::Basic {
+                creation_time: 1,
+                last_access_time: 2,
+                last_write_time: 3,
+                change_time: 4,
+                attributes: 0x20,
+            },
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+            information: FileSetInformation::Rename {
+                target: nt_string("\\??\\C:\\Temp\\b.txt"),
+                replace_if_exists: true,
+            },
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+            information: FileSetInformation::Disposition { delete: true },
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+            information: FileSetInformation::EndOfFile { end_of_file: 100 },
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+            information: FileSetInformation::Allocation {
+                allocation_size: 4096,
+            },
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+            information: FileSetInformation::Other {
+                information_class: 64,
+                length: 8,
+            },
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::QueryDirectory {
+            pattern: nt_string("*.dll"),
+            information_class: 37,
+            length: 65536,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::FileSystemControl {
+            control_code: 0x9_00a8,
+            input_length: 0,
+            output_length: 16,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::LockControl {
+            operation: FileLockOperation::Lock,
+            offset: 0,
+            length: 10,
+            exclusive: true,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::LockControl {
+            operation: FileLockOperation::Unknown(9),
+            offset: 0,
+            length: 10,
+            exclusive: false,
+        }),
+        EventClass::FileSystem(EventFileSystemOperation::Cleanup {}),
+        EventClass::FileSystem(EventFileSystemOperation::FlushBuffers {}),
+        EventClass::Registry(EventRegistryOperation::CreateKey {
+            desired_access: 0xf003f,
+            disposition: 2,
+        }),
+        EventClass::Registry(EventRegistryOperation::OpenKey {
+            desired_access: 0x20019,
+        }),
+        EventClass::Registry(EventRegistryOperation::QueryKey {
+            information_class: 2,
+            length: 256,
+        }),
+        EventClass::Registry(EventRegistryOperation::SetValue {
+            value_name: nt_string("Run"),
+            value_type: RegistryValueType::String,
+            data_size: 40,
+            preview: RegistryDataPreview::new(&[0x43, 0x00, 0x3a, 0x00, 0xff]),
+        }),
+        EventClass::Registry(EventRegistryOperation::SetValue {
+            value_name: nt_string(""),
+            value_type: RegistryValueType::Unknown(1234),
+            data_size: 0,
+            preview: RegistryDataPreview::new(&[]),
+        }),
+        EventClass::Registry(EventRegistryOperation::QueryValue {
+            value_name: nt_string("Version"),
+            information_class: 2,
+            length: 144,
+        }),
+        EventClass::Registry(EventRegistryOperation::DeleteKey {}),
+        EventClass::Registry(EventRegistryOperation::DeleteValue {
+            value_name: nt_string("Old"),
+        }),
+        EventClass::Registry(EventRegistryOperation::EnumerateKey {
+            index: 3,
+            information_class: 0,
+        }),
+        EventClass::Registry(EventRegistryOperation::EnumerateValue {
+            index: 4,
+            information_class: 1,
+        }),
+        EventClass::Registry(EventRegistryOperation::RenameKey {
+            new_name: nt_string("Renamed"),
+        }),
+        EventClass::Registry(EventRegistryOperation::Flush {}),
+        EventClass::Network(EventNetworkOperation::TcpConnect {
+            endpoints: endpoints(),
+        }),
+        EventClass::Network(EventNetworkOperation::TcpAccept {
+            endpoints: endpoints(),
+        }),
+        EventClass::Network(EventNetworkOperation::TcpSend {
+            endpoints: endpoints(),
+            length: 100,
+        }),
+        EventClass::Network(EventNetworkOperation::TcpReceive {
+            endpoints: endpoints(),
+            length: 200,
+        }),
+        EventClass::Network(EventNetworkOperation::TcpDisconnect {
+            endpoints: endpoints(),
+        }),
+        EventClass::Network(EventNetworkOperation::UdpSend {
+            endpoints: endpoints(),
+            length: 300,
+        }),
+        EventClass::Network(EventNetworkOperation::UdpReceive {
+            endpoints: endpoints(),
+            length: 400,
+        }),
+        EventClass::Session(EventSessionOperation::Disconnected { attempts: 3 }),
+    ];
+
+    operations
+        .into_iter()
+        .enumerate()
+        .map(|(index, operation)| event(operation, &format!("C:\\Temp\\{}.txt", index)))
+        .collect()
+}
+
+#[test]
+fn every_operation_round_trips() {
+    let events = every_operation();
+
+    let mut writer = JsonlWriter::new(Vec::new());
+    for event in &events {
+        writer.write(event, None).unwrap();
+    }
+    assert_eq!(writer.written(), events.len() as u64);
+    let bytes = writer.into_inner();
+    assert_eq!(
+        bytes.iter().filter(|byte| **byte == b'\n').count(),
+        events.len()
+    );
+
+    let read = JsonlReader::new(Cursor::new(bytes))
+        .map(|record| record.map(|record| record.event))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(read.len(), events.len());
+
+    //The wire encoding compares every field, strings and stack included
+    for (original, read) in events.iter().zip(&read) {
+        assert_eq!(
+            postcard::to_extend(original, Vec::new()).unwrap(),
+            postcard::to_extend(read, Vec::new()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn layout_is_stable() {
+    let write = event(
+        EventClass::FileSystem(EventFileSystemOperation::Write {
+            length: 512,
+            offset: 0,
+        }),
+        "C:\\Temp\\a.txt",
+    );
+    let expected = concat!(
+        r#"{"time":"2024-05-01T09:30:12.1234567Z","filetime":133590294121234567,"#,
+        r#""pid":1234,"process_uid":77,"tid":5678,"#,
+        r#""class":"FileSystem","operation":"Write","details":{"length":512,"offset":0},"#,
+        r#""path":"C:\\Temp\\a.txt","result":-1073741790,"duration":120,"#,
+        r#""stack":[{"address":"0xfffff8031a2b3c4d","mode":"Kernel"},"#,
+        r#"{"address":"0x7ff612340000","mode":"User"}]}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&JsonEvent::from(&write)).unwrap(),
+        expected
+    );
+
+    let set_value = event(
+        EventClass::Registry(EventRegistryOperation::SetValue {
+            value_name: nt_string("Run"),
+            value_type: RegistryValueType::String,
+            data_size: 40,
+            preview: RegistryDataPreview::new(&[0x43, 0x00, 0x3a, 0x00]),
+        }),
+        "\\REGISTRY\\MACHINE\\SOFTWARE",
+    );
+    let json = serde_json::to_value(JsonEvent::from(&set_value)).unwrap();
+    assert_eq!(json["class"], "Registry");
+    assert_eq!(json["operation"], "SetValue");
+    assert_eq!(
+        json["details"],
+        serde_json::json!({
+            "value_name": "Run",
+            "value_type": "REG_SZ",
+            "data_size": 40,
+            "preview": "43003a00",
+        })
+    );
+
+    let connect = event(
+        EventClass::Network(EventNetworkOperation::TcpConnect {
+            endpoints: endpoints(),
+        }),
+        "",
+    );
+    let json = serde_json::to_value(JsonEvent::from(&connect)).unwrap();
+    assert_eq!(
+        json["details"],
+        serde_json::json!({ "local": "10.0.0.2:50123", "remote": "[2001:db8::1]:443" })
+    );
+}
+
+#[test]
+fn raw_numbers_are_accepted_for_named_values() {
+    let line = concat!(
+        r#"{"time":"","filetime":0,"pid":1,"process_uid":2,"tid":3,"#,
+        r#""class":"Registry","operation":"SetValue","#,
+        r#""details":{"value_name":"A","value_type":4,"data_size":4,"preview":"01000000"},"#,
+        r#""path":"","result":0,"duration":0,"stack":[]}"#
+    );
+
+    let record = JsonlReader::new(Cursor::new(line)).next().unwrap().unwrap();
+    match record.event.event.operation {
+        EventClass::Registry(EventRegistryOperation::SetValue {
+            value_type,
+            preview,
+            ..
+        }) => {
+            assert_eq!(value_type, RegistryValueType::Dword);
+            assert_eq!(preview.bytes(), &[1, 0, 0, 0]);
+        }
+        other => panic!("unexpected operation {:?}", other),
+    }
+}
+
+#[test]
+fn bad_lines_report_their_line_number() {
+    let good = serde_json::to_string(&JsonEvent::from(&every_operation()[0])).unwrap();
+    let input = format!(
+        "{}\n\n{{\"class\":\"Process\"}}\n{}\n",
+        good,
+        good.replace("\"Process\"", "\"Unknown\"")
+    );
+
+    let mut reader = JsonlReader::new(Cursor::new(input));
+    assert!(reader.next().unwrap().is_ok());
+
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("line 3:"), "{}", error);
+
+    //Reading goes on after a bad line
+    let error = reader.next().unwrap().unwrap_err();
+    assert!(error.to_string().starts_with("line 4:"), "{}", error);
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn oversized_strings_are_rejected() {
+    let mut json = JsonEvent::from(&every_operation()[0]);
+    json.path = "a".repeat(u16::MAX as usize);
+
+    let error = KmMessage::try_from(json).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn images_are_written_when_known() {
+    let events = every_operation();
+    let image = nt_string("C:\\Windows\\notepad.exe");
+
+    let mut writer = JsonlWriter::new(Vec::new());
+    writer.write(&events[0], Some(&image)).unwrap();
+    writer.write(&events[1], None).unwrap();
+    let bytes = writer.into_inner();
+
+    let lines = std::str::from_utf8(&bytes)
+        .unwrap()
+        .lines()
+        .collect::<Vec<_>>();
+    assert!(
+        lines[0].contains(r#""process_uid":77,"image":"C:\\Windows\\notepad.exe","#),
+        "{}",
+        lines[0]
+    );
+    assert!(!lines[1].contains("image"), "{}", lines[1]);
+
+    let read = JsonlReader::new(Cursor::new(bytes))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        read[0].image.as_ref().map(|image| image.to_string()),
+        Some(image.to_string())
+    );
+    assert!(read[1].image.is_none());
+}
@@ -0,0 +1,145 @@
+use std::{
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+};
+
+use kmum_common::{
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    serializable_ntstring::SerializableNtString,
+    KmMessage,
+};
+use procmon_core::{
+    communication::{CommunicationError, EventProcessor},
+    pipeline::{BoxedProcessor, PipelineBuilder, RateLimit, Sample, Tee},
+};
+
+fn event(date: u64, pid: u32) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date,
+            thread: 1,
+            operation: EventClass::FileSystem(EventFileSystemOperation::Read {
+                length: 4096,
+                offset: 0,
+            }),
+            result: 0,
+            path: SerializableNtString::empty(),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: pid as _,
+            unique_id: pid as _,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<(u64, u64)>>>);
+
+impl Collect {
+    fn dates(&self) -> Vec<u64> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(date, _)| *date)
+            .collect()
+    }
+}
+
+impl EventProcessor for Collect {
+    fn process<I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        let mut events = self.0.lock().unwrap();
+        events.extend(iter.map(|event| (event.event.date, event.event.duration)));
+        Ok(())
+    }
+}
+
+struct Fail;
+
+impl EventProcessor for Fail {
+    fn process<I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        iter.for_each(drop);
+        Err(CommunicationError::Parsing)
+    }
+}
+
+#[test]
+fn stages_run_in_order_and_every_sink_sees_the_result() {
+    let first = Collect::default();
+    let second = Collect::default();
+
+    let pipeline = PipelineBuilder::new()
+        .filter(|event| event.process.pid != 8)
+        .map(|mut event| {
+            event.event.duration = event.event.date * 10;
+            event
+        })
+        .sample(NonZeroU32::new(2).unwrap())
+        .build_tee(vec![
+            Box::new(first.clone()) as BoxedProcessor,
+            Box::new(second.clone()),
+        ]);
+
+    //Every third event is filtered out before sampling sees it
+    let events = (0..12).map(|date| event(date, if date % 3 == 0 { 8 } else { 4 }));
+    pipeline.process(&mut events.into_iter()).unwrap();
+
+    assert_eq!(first.dates(), vec![1, 4, 7, 10]);
+    assert_eq!(second.dates(), first.dates());
+    assert!(first
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|(date, duration)| *duration == date * 10));
+}
+
+#[test]
+fn sample_counts_across_batches() {
+    let sink = Collect::default();
+    let sample = Sample::new(NonZeroU32::new(3).unwrap(), sink.clone());
+
+    sample
+        .process(&mut (0..4).map(|date| event(date, 4)))
+        .unwrap();
+    sample
+        .process(&mut (4..8).map(|date| event(date, 4)))
+        .unwrap();
+
+    assert_eq!(sink.dates(), vec![0, 3, 6]);
+    assert_eq!(sample.dropped(), 5);
+}
+
+#[test]
+fn rate_limit_drops_events_over_the_limit() {
+    let sink = Collect::default();
+    let limit = RateLimit::new(NonZeroU32::new(5).unwrap(), sink.clone());
+
+    limit
+        .process(&mut (0..20).map(|date| event(date, 4)))
+        .unwrap();
+
+    assert_eq!(sink.dates(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(limit.dropped(), 15);
+}
+
+#[test]
+fn failing_sink_does_not_starve_the_others() {
+    let sink = Collect::default();
+    let tee: Tee<BoxedProcessor> = Tee::new(vec![Box::new(Fail), Box::new(sink.clone())]);
+
+    let result = tee.process(&mut (0..3).map(|date| event(date, 4)));
+
+    assert!(matches!(result, Err(CommunicationError::Parsing)));
+    assert_eq!(sink.dates(), vec![0, 1, 2]);
+}
@@ -0,0 +1,213 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::nt_string;
+use kmum_common::{
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventRegistryOperation, EventStack,
+        FileSetInformation, RegistryDataPreview, RegistryValueType, SimpleProcessDetails,
+    },
+    process::{ProcessInformation, UniqueProcessId},
+    KmMessage,
+};
+use procmon_core::query::{ProcessLookup, Query};
+
+const STATUS_ACCESS_DENIED: i32 = 0xC000_0022_u32 as i32;
+
+fn event(operation: EventClass, path: &str, result: i32, duration: u64) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date: 0,
+            thread: 8,
+            operation,
+            result,
+            path: nt_string(path),
+            duration,
+        },
+        process: SimpleProcessDetails {
+            pid: 1234,
+            unique_id: 1,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+fn run_key_write() -> KmMessage {
+    event(
+        EventClass::FileSystem(EventFileSystemOperation::Write {
+            length: 512,
+            offset: 0,
+        }),
+        "\\REGISTRY\\MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run\\Updater",
+        0,
+        20_000,
+    )
+}
+
+fn set_value() -> KmMessage {
+    event(
+        EventClass::Registry(EventRegistryOperation::SetValue {
+            value_name: nt_string("Updater"),
+            value_type: RegistryValueType::String,
+            data_size: 42,
+            preview: RegistryDataPreview::new(b"C:\\updater.exe"),
+        }),
+        "\\REGISTRY\\MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run",
+        STATUS_ACCESS_DENIED,
+        100,
+    )
+}
+
+fn delete_on_close() -> KmMessage {
+    event(
+        EventClass::FileSystem(EventFileSystemOperation::SetInformation {
+            information: FileSetInformation::Disposition { delete: true },
+        }),
+        "C:\\Temp\\dropped.dll",
+        0,
+        0,
+    )
+}
+
+fn processes() -> HashMap<UniqueProcessId, Option<ProcessInformation>> {
+    HashMap::from([(
+        1,
+        Some(ProcessInformation {
+            path: nt_string("C:\\Windows\\System32\\svchost.exe"),
+            cmd: None,
+            pid: 1234,
+            parent_pid: 4,
+            start_time: 0,
+            end_time: None,
+            unique_id: 1,
+        }),
+    )])
+}
+
+fn matches(query: &str, event: &KmMessage) -> bool {
+    Query::parse(query)
+        .unwrap_or_else(|e| panic!("{}", e.annotate(query)))
+        .matches(event, &processes())
+        .unwrap()
+}
+
+#[test]
+fn example_query_matches() {
+    let query = r#"process == "svchost.exe" && op in (Write, SetInformation) && path ~ "*\\Run\\*" && duration > 1ms"#;
+
+    assert!(matches(query, &run_key_write()));
+    assert!(!matches(query, &set_value()));
+    //Too short
+    assert!(!matches(
+        query,
+        &event(
+            EventClass::FileSystem(EventFileSystemOperation::Write {
+                length: 1,
+                offset: 0,
+            }),
+            "C:\\Run\\x",
+            0,
+            5_000,
+        )
+    ));
+}
+
+#[test]
+fn payload_fields_exist_only_on_their_operations() {
+    assert!(matches(
+        "value_name == updater && value_type == REG_SZ && data_size >= 0x20",
+        &set_value()
+    ));
+    assert!(matches("length == 512 and offset == 0", &run_key_write()));
+    assert!(matches("delete", &delete_on_close()));
+    assert!(matches("not delete == false", &delete_on_close()));
+
+    //A missing field never matches, whatever the operator
+    assert!(!matches("length != 512", &set_value()));
+    assert!(!matches("delete", &run_key_write()));
+    assert!(matches("!(length == 512)", &set_value()));
+}
+
+#[test]
+fn results_compare_by_name_text_or_code() {
+    for query in [
+        "result == STATUS_ACCESS_DENIED",
+        "result == access_denied",
+        r#"result == "ACCESS DENIED""#,
+        "result == 0xC0000022",
+        "status in (SUCCESS, ACCESS_DENIED)",
+    ] {
+        assert!(matches(query, &set_value()), "{}", query);
+    }
+    assert!(matches("result != ACCESS_DENIED", &run_key_write()));
+}
+
+#[test]
+fn precedence_and_grouping() {
+    let event = run_key_write();
+
+    //&& binds tighter than ||
+    assert!(matches(
+        "class == Registry && pid == 1 || thread == 8",
+        &event
+    ));
+    assert!(!matches(
+        "class == Registry && (pid == 1 || thread == 8)",
+        &event
+    ));
+    assert!(matches(
+        "class == \"File System\" and (op == read or op == write)",
+        &event
+    ));
+}
+
+#[test]
+fn unresolved_processes_are_reported() {
+    struct Loading;
+    impl ProcessLookup for Loading {
+        fn image_path(&self, _uid: UniqueProcessId) -> Option<Option<String>> {
+            None
+        }
+    }
+
+    let event = run_key_write();
+    let query = Query::parse("process == svchost.exe || pid == 1234").unwrap();
+    assert_eq!(query.matches(&event, &Loading), Some(true));
+
+    let query = Query::parse("process == svchost.exe && pid == 1234").unwrap();
+    assert_eq!(query.matches(&event, &Loading), None);
+    //Settled without the process
+    let query = Query::parse("process == svchost.exe && pid == 1").unwrap();
+    assert_eq!(query.matches(&event, &Loading), Some(false));
+    //Unknown processes are a missing field
+    assert_eq!(query.matches(&event, &()), Some(false));
+}
+
+#[test]
+fn errors_point_at_the_problem() {
+    let cases = [
+        ("proces == x", 0..6, "Unknown field"),
+        ("op == Wirte", 6..11, "Unknown operation"),
+        ("duration > 5 parsecs", 13..20, "Expected &&"),
+        ("length ~ \"1*\"", 7..8, "operator"),
+        ("length > 10ms", 9..13, "unit"),
+        ("path == \"C:\\", 8..12, "Unterminated"),
+        ("(pid == 1", 9..9, "closing"),
+        ("pid == 1)", 8..9, "Unmatched"),
+        ("result == STATUS_NOT_A_STATUS", 10..29, "Unknown status"),
+        ("pid", 0..3, "compared"),
+        ("pid == 1 & tid == 2", 9..11, "&&"),
+    ];
+
+    for (query, span, message) in cases {
+        let error = Query::parse(query).unwrap_err();
+        assert_eq!(error.span, span, "{}", error.annotate(query));
+        assert!(error.message.contains(message), "{}", error.annotate(query));
+    }
+
+    let error = Query::parse("op == Wirte").unwrap_err();
+    assert!(error
+        .annotate("op == Wirte")
+        .starts_with("op == Wirte\n      ^^^^^\n"));
+}
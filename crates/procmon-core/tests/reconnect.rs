@@ -0,0 +1,266 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use kmum_common::{
+    batch::BatchBuilder,
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    handshake::{negotiate, BufferSizes, EventCapabilities},
+    serializable_ntstring::SerializableNtString,
+    ClientConnectMessage, ClientConnectMode, KmMessage, KmReplyMessage, UmSendMessage,
+};
+use nt_string::unicode_string::NtUnicodeString;
+use procmon_core::communication::{
+    reconnect::{Backoff, ConnectionObserver, Outage, ReconnectingCommunication},
+    socket::{RequestResponder, SocketCommunication, SocketEndpoint, SocketPublisher},
+    CommunicationError, CommunicationInterface, EventProcessor,
+};
+
+const EVENTS_PER_SESSION: u64 = 20;
+
+struct StandIn;
+
+impl RequestResponder for StandIn {
+    fn respond(
+        &self,
+        connect: &ClientConnectMessage,
+        message: &UmSendMessage,
+    ) -> Result<Option<KmReplyMessage>, CommunicationError> {
+        match message {
+            UmSendMessage::Handshake => Ok(Some(KmReplyMessage::Handshake(negotiate(
+                connect,
+                EventCapabilities::FILE_SYSTEM,
+                BufferSizes::current(),
+            )))),
+            _ => Err(CommunicationError::Port),
+        }
+    }
+}
+
+fn event(date: u64) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date,
+            thread: 1,
+            operation: EventClass::FileSystem(EventFileSystemOperation::Read {
+                length: 4096,
+                offset: 0,
+            }),
+            result: 0,
+            path: SerializableNtString::new(
+                NtUnicodeString::try_from("\\Device\\HarddiskVolume3\\file.txt").unwrap(),
+            ),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: 4,
+            unique_id: 1,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+fn publish(publisher: &SocketPublisher, first: u64) {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut builder = BatchBuilder::new();
+    for date in first..first + EVENTS_PER_SESSION {
+        builder.try_push(&mut buffer, &event(date)).unwrap();
+    }
+
+    let batch = builder.finish(&mut buffer, 0, 0, 0);
+    publisher.send_batch(batch).unwrap();
+}
+
+struct Counter(mpsc::Sender<u64>);
+
+impl EventProcessor for Counter {
+    fn process<I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        for event in iter {
+            let _ = self.0.send(event.event.date);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Clone)]
+struct Recorder {
+    disconnects: Arc<Mutex<u32>>,
+    outages: Arc<Mutex<Vec<Outage>>>,
+}
+
+impl ConnectionObserver for Recorder {
+    fn on_disconnected(&self, _error: &CommunicationError) {
+        *self.disconnects.lock().unwrap() += 1;
+    }
+
+    fn on_reconnected(&self, outage: &Outage) {
+        self.outages.lock().unwrap().push(outage.clone());
+    }
+}
+
+fn quick_backoff(attempts: Option<u32>) -> Backoff {
+    Backoff {
+        initial: Duration::from_millis(10),
+        max: Duration::from_millis(50),
+        attempts,
+    }
+}
+
+fn connect(endpoint: &SocketEndpoint) -> Result<SocketCommunication, CommunicationError> {
+    SocketCommunication::connect(endpoint, ClientConnectMessage::new(ClientConnectMode::Any))
+}
+
+fn receive(received: &mpsc::Receiver<u64>, dates: std::ops::Range<u64>) {
+    for date in dates {
+        assert_eq!(
+            received.recv_timeout(Duration::from_secs(10)).unwrap(),
+            date
+        );
+    }
+}
+
+#[test]
+fn lost_connection_is_made_again() {
+    let listener = "127.0.0.1:0"
+        .parse::<SocketEndpoint>()
+        .unwrap()
+        .bind()
+        .unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+
+    let (first_seen, wait_first) = mpsc::channel::<()>();
+    let (done, wait_done) = mpsc::channel::<()>();
+    let server = thread::spawn(move || {
+        let publisher = SocketPublisher::accept(listener.accept().unwrap(), StandIn).unwrap();
+        publish(&publisher, 0);
+        let _ = wait_first.recv_timeout(Duration::from_secs(10));
+        //Looks like the capture agent restarting
+        drop(publisher);
+
+        let publisher = SocketPublisher::accept(listener.accept().unwrap(), StandIn).unwrap();
+        publish(&publisher, EVENTS_PER_SESSION);
+        let _ = wait_done.recv_timeout(Duration::from_secs(10));
+    });
+
+    let observer = Recorder::default();
+    let communication = Arc::new(
+        ReconnectingCommunication::connect(
+            move || connect(&endpoint),
+            quick_backoff(None),
+            observer.clone(),
+        )
+        .unwrap(),
+    );
+
+    let (sender, received) = mpsc::channel();
+    let processing = {
+        let communication = communication.clone();
+        thread::spawn(move || communication.process_blocking(Counter(sender)))
+    };
+
+    receive(&received, 0..EVENTS_PER_SESSION);
+    first_seen.send(()).unwrap();
+    receive(&received, EVENTS_PER_SESSION..EVENTS_PER_SESSION * 2);
+
+    assert_eq!(communication.reconnects(), 1);
+    assert_eq!(*observer.disconnects.lock().unwrap(), 1);
+    {
+        let outages = observer.outages.lock().unwrap();
+        assert_eq!(outages.len(), 1);
+        assert!(outages[0].attempts >= 1);
+        assert!(outages[0].until >= outages[0].since);
+    }
+
+    communication.stop();
+    processing.join().unwrap().unwrap();
+    done.send(()).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn backoff_gives_up_once_attempts_run_out() {
+    let listener = "127.0.0.1:0"
+        .parse::<SocketEndpoint>()
+        .unwrap()
+        .bind()
+        .unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+
+    let server = thread::spawn(move || {
+        let publisher = SocketPublisher::accept(listener.accept().unwrap(), StandIn).unwrap();
+        //Nobody listens anymore once this returns
+        drop(listener);
+        publisher
+    });
+
+    let observer = Recorder::default();
+    let communication = ReconnectingCommunication::connect(
+        move || connect(&endpoint),
+        quick_backoff(Some(3)),
+        observer.clone(),
+    )
+    .unwrap();
+    drop(server.join().unwrap());
+
+    let (sender, _received) = mpsc::channel();
+    assert!(communication.process_blocking(Counter(sender)).is_err());
+    assert_eq!(*observer.disconnects.lock().unwrap(), 1);
+    assert!(observer.outages.lock().unwrap().is_empty());
+    assert_eq!(communication.reconnects(), 0);
+}
+
+#[test]
+fn first_connection_is_retried() {
+    let attempts = Arc::new(AtomicU32::new(0));
+    let flaky = {
+        let attempts = attempts.clone();
+        move || -> Result<SocketCommunication, CommunicationError> {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Err(CommunicationError::Port)
+        }
+    };
+
+    assert!(matches!(
+        ReconnectingCommunication::connect_retrying(flaky, quick_backoff(None), 3, ()),
+        Err(CommunicationError::Port)
+    ));
+    assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+    //Comes up while the client is still retrying
+    let listener = "127.0.0.1:0"
+        .parse::<SocketEndpoint>()
+        .unwrap()
+        .bind()
+        .unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+    let server = thread::spawn(move || {
+        SocketPublisher::accept(listener.accept().unwrap(), StandIn).unwrap()
+    });
+
+    let attempts = Arc::new(AtomicU32::new(0));
+    let flaky = {
+        let attempts = attempts.clone();
+        move || match attempts.fetch_add(1, Ordering::Relaxed) {
+            0 | 1 => Err(CommunicationError::Port),
+            _ => connect(&endpoint),
+        }
+    };
+
+    let communication =
+        ReconnectingCommunication::connect_retrying(flaky, quick_backoff(None), 5, ()).unwrap();
+    assert_eq!(attempts.load(Ordering::Relaxed), 3);
+    assert_eq!(communication.reconnects(), 0);
+
+    communication.stop();
+    drop(server.join().unwrap());
+}
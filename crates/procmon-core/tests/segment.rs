@@ -0,0 +1,99 @@
+use std::io::{Cursor, ErrorKind};
+
+use kmum_common::{
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    serializable_ntstring::SerializableNtString,
+    KmMessage,
+};
+use nt_string::unicode_string::NtUnicodeString;
+use procmon_core::segment::{ChunkLocation, SegmentReader, SegmentWriter};
+
+const CHUNKS: u64 = 4;
+const EVENTS_PER_CHUNK: u64 = 100;
+
+fn event(date: u64) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date,
+            thread: 1,
+            operation: EventClass::FileSystem(EventFileSystemOperation::Write {
+                length: date,
+                offset: 0,
+            }),
+            result: 0,
+            path: SerializableNtString::new(
+                NtUnicodeString::try_from(format!("C:\\Temp\\{}.txt", date).as_str()).unwrap(),
+            ),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: 4,
+            unique_id: 1,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+fn segment() -> (Vec<u8>, Vec<ChunkLocation>) {
+    let mut writer = SegmentWriter::new(Vec::new()).unwrap();
+    assert!(writer.is_empty());
+
+    let locations = (0..CHUNKS)
+        .map(|chunk| {
+            let events: Vec<_> = (0..EVENTS_PER_CHUNK)
+                .map(|index| event(chunk * EVENTS_PER_CHUNK + index))
+                .collect();
+            writer.append(&events).unwrap()
+        })
+        .collect();
+    assert!(!writer.is_empty());
+
+    let length = writer.len();
+    let bytes = writer.into_inner();
+    assert_eq!(bytes.len() as u64, length);
+
+    (bytes, locations)
+}
+
+#[test]
+fn chunks_are_read_back_in_any_order() {
+    let (bytes, locations) = segment();
+    let mut reader = SegmentReader::new(Cursor::new(bytes)).unwrap();
+
+    for chunk in [2, 0, 3, 1, 2] {
+        let events = reader.read_chunk(&locations[chunk as usize]).unwrap();
+        assert_eq!(events.len() as u64, EVENTS_PER_CHUNK);
+
+        for (index, event) in events.iter().enumerate() {
+            let date = chunk * EVENTS_PER_CHUNK + index as u64;
+            assert_eq!(event.event.date, date);
+            assert_eq!(
+                event.event.path.to_string(),
+                format!("C:\\Temp\\{}.txt", date)
+            );
+        }
+    }
+}
+
+#[test]
+fn mismatched_locations_are_rejected() {
+    let (mut bytes, locations) = segment();
+
+    let mut reader = SegmentReader::new(Cursor::new(bytes.clone())).unwrap();
+    let wrong = ChunkLocation {
+        offset: locations[1].offset + 1,
+        ..locations[1]
+    };
+    assert_eq!(
+        reader.read_chunk(&wrong).unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+
+    bytes[0] = b'X';
+    assert_eq!(
+        SegmentReader::new(Cursor::new(bytes)).err().unwrap().kind(),
+        ErrorKind::InvalidData
+    );
+}
@@ -0,0 +1,322 @@
+mod common;
+
+use std::{
+    net::TcpListener,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use common::nt_string;
+use kmum_common::{
+    batch::BatchBuilder,
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    handshake::{negotiate, BufferSizes, EventCapabilities},
+    ClientConnectMessage, ClientConnectMode, KmMessage, KmMessageRef, KmReplyMessage,
+    UmSendMessage,
+};
+use procmon_core::communication::{
+    socket::{
+        RequestResponder, SocketCommunication, SocketEndpoint, SocketPublisher, SocketStream,
+    },
+    BorrowedEventProcessor, CommunicationError, CommunicationInterface, EventProcessor,
+};
+
+const BATCHES: u64 = 8;
+const EVENTS_PER_BATCH: u64 = 50;
+
+struct StandIn;
+
+impl RequestResponder for StandIn {
+    fn respond(
+        &self,
+        connect: &ClientConnectMessage,
+        message: &UmSendMessage,
+    ) -> Result<Option<KmReplyMessage>, CommunicationError> {
+        match message {
+            UmSendMessage::Handshake => Ok(Some(KmReplyMessage::Handshake(negotiate(
+                connect,
+                EventCapabilities::FILE_SYSTEM | EventCapabilities::PROCESS,
+                BufferSizes::current(),
+            )))),
+            UmSendMessage::GetExeName(uid) => Ok(Some(KmReplyMessage::ExeName(nt_string(
+                &format!("Process{}.exe", uid),
+            )))),
+            _ => Err(CommunicationError::Port),
+        }
+    }
+}
+
+fn event(index: u64) -> KmMessage {
+    KmMessage {
+        event: EventCompoent {
+            date: index,
+            thread: 1,
+            operation: EventClass::FileSystem(EventFileSystemOperation::Read {
+                length: 4096,
+                offset: index as _,
+            }),
+            result: 0,
+            path: nt_string(&format!("\\Device\\HarddiskVolume3\\file{}.txt", index)),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: 4,
+            unique_id: index,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+///
+/// Accepts one client, publishes every batch and keeps the connection
+/// open until `done` fires
+///
+fn serve(stream: SocketStream, done: mpsc::Receiver<()>) {
+    let publisher = SocketPublisher::accept(stream, StandIn).unwrap();
+    assert!(matches!(
+        publisher.connect_message().mode,
+        ClientConnectMode::Any
+    ));
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut builder = BatchBuilder::new();
+    for sequence in 0..BATCHES {
+        for index in 0..EVENTS_PER_BATCH {
+            builder
+                .try_push(&mut buffer, &event(sequence * EVENTS_PER_BATCH + index))
+                .unwrap();
+        }
+
+        let batch = builder.finish(&mut buffer, 0, sequence, 0);
+        publisher.send_batch(batch).unwrap();
+    }
+
+    let _ = done.recv_timeout(Duration::from_secs(10));
+}
+
+struct Counter(mpsc::Sender<u64>);
+
+impl EventProcessor for Counter {
+    fn process<I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        for event in iter {
+            self.0.send(event.event.date).unwrap();
+        }
+        Ok(())
+    }
+}
+
+impl BorrowedEventProcessor for Counter {
+    fn process_borrowed<'a, I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessageRef<'a>>,
+    {
+        for event in iter {
+            assert!(event.event.path.to_string().ends_with(".txt"));
+            self.0.send(event.event.date).unwrap();
+        }
+        Ok(())
+    }
+}
+
+fn run_client(endpoint: SocketEndpoint, borrowed: bool) {
+    let communication = Arc::new(
+        SocketCommunication::connect(&endpoint, ClientConnectMessage::new(ClientConnectMode::Any))
+            .unwrap(),
+    );
+
+    assert_eq!(
+        communication.handshake().capabilities,
+        EventCapabilities::FILE_SYSTEM | EventCapabilities::PROCESS
+    );
+
+    match communication.send_message_blocking(&UmSendMessage::GetExeName(42)) {
+        Ok(Some(KmReplyMessage::ExeName(name))) => assert_eq!(name.to_string(), "Process42.exe"),
+        other => panic!("unexpected reply {:?}", other),
+    }
+
+    //Unknown requests fail without tearing the connection down
+    assert!(communication
+        .send_message_blocking(&UmSendMessage::GetProcessInfo(1))
+        .is_err());
+
+    let (sender, received) = mpsc::channel();
+    let processing = {
+        let communication = communication.clone();
+        thread::spawn(move || {
+            if borrowed {
+                communication.process_borrowed_blocking(Counter(sender))
+            } else {
+                communication.process_blocking(Counter(sender))
+            }
+        })
+    };
+
+    let mut dates: Vec<u64> = (0..BATCHES * EVENTS_PER_BATCH)
+        .map(|_| received.recv_timeout(Duration::from_secs(10)).unwrap())
+        .collect();
+    dates.sort_unstable();
+    assert!(dates.iter().copied().eq(0..BATCHES * EVENTS_PER_BATCH));
+
+    communication.stop();
+    processing.join().unwrap().unwrap();
+
+    let statistics = communication.statistics().snapshot();
+    assert_eq!(statistics.batches, BATCHES);
+    assert_eq!(statistics.events, BATCHES * EVENTS_PER_BATCH);
+    assert_eq!(statistics.lost_batches, 0);
+}
+
+#[test]
+fn tcp_round_trip() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint: SocketEndpoint = format!("tcp://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+
+    let (done, wait) = mpsc::channel();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream.into(), wait);
+    });
+
+    run_client(endpoint, false);
+    done.send(()).unwrap();
+    server.join().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_round_trip_borrowed() {
+    use std::os::unix::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("procmon-core-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let endpoint: SocketEndpoint = format!("unix://{}", path.display()).parse().unwrap();
+
+    let (done, wait) = mpsc::channel();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream.into(), wait);
+    });
+
+    run_client(endpoint, true);
+    done.send(()).unwrap();
+    server.join().unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn server_disconnect_ends_processing() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = SocketEndpoint::Tcp(listener.local_addr().unwrap().to_string());
+
+    let (done, wait) = mpsc::channel::<()>();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let publisher = SocketPublisher::accept(stream.into(), StandIn).unwrap();
+        let _ = wait.recv_timeout(Duration::from_secs(10));
+        drop(publisher);
+    });
+
+    let communication =
+        SocketCommunication::connect(&endpoint, ClientConnectMessage::new(ClientConnectMode::Any))
+            .unwrap();
+    done.send(()).unwrap();
+    server.join().unwrap();
+
+    //Returns once the reader notices the closed stream
+    let (sender, _received) = mpsc::channel();
+    assert!(matches!(
+        communication.process_blocking(Counter(sender)),
+        Err(CommunicationError::Disconnected)
+    ));
+
+    assert!(communication
+        .send_message_blocking(&UmSendMessage::GetExeName(1))
+        .is_err());
+}
+
+#[test]
+fn requests_are_answered_while_batches_pile_up() {
+    const FLOOD: u64 = 200;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = SocketEndpoint::Tcp(listener.local_addr().unwrap().to_string());
+
+    let (flooded, wait_flooded) = mpsc::channel();
+    let (answered, wait_answered) = mpsc::channel::<()>();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let publisher = SocketPublisher::accept(stream.into(), StandIn).unwrap();
+
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut builder = BatchBuilder::new();
+        let mut send = |sequence: u64| {
+            builder.try_push(&mut buffer, &event(sequence)).unwrap();
+            let batch = builder.finish(&mut buffer, 0, sequence, 0);
+            publisher.send_batch(batch).unwrap();
+        };
+
+        for sequence in 0..FLOOD {
+            send(sequence);
+        }
+        flooded.send(()).unwrap();
+
+        //A later batch lets the client notice the ones it dropped
+        wait_answered.recv_timeout(Duration::from_secs(10)).unwrap();
+        send(FLOOD);
+        publisher
+    });
+
+    let communication = Arc::new(
+        SocketCommunication::connect(&endpoint, ClientConnectMessage::new(ClientConnectMode::Any))
+            .unwrap(),
+    );
+
+    //Nobody processes batches yet, the reply must not wait behind them
+    wait_flooded.recv_timeout(Duration::from_secs(10)).unwrap();
+    let asked = std::time::Instant::now();
+    assert!(matches!(
+        communication.send_message_blocking(&UmSendMessage::GetExeName(42)),
+        Ok(Some(KmReplyMessage::ExeName(_)))
+    ));
+    assert!(asked.elapsed() < Duration::from_secs(5));
+    answered.send(()).unwrap();
+
+    let (sender, received) = mpsc::channel();
+    let processing = {
+        let communication = communication.clone();
+        thread::spawn(move || communication.process_blocking(Counter(sender)))
+    };
+
+    //The last batch comes after every queued one
+    while received.recv_timeout(Duration::from_secs(10)).unwrap() != FLOOD {}
+
+    communication.stop();
+    processing.join().unwrap().unwrap();
+    drop(server.join().unwrap());
+
+    let statistics = communication.statistics().snapshot();
+    assert!(statistics.lost_batches > 0);
+    assert_eq!(statistics.batches + statistics.lost_batches, FLOOD + 1);
+}
+
+#[test]
+fn endpoints_parse() {
+    assert_eq!(
+        "tcp://127.0.0.1:9000".parse::<SocketEndpoint>(),
+        Ok(SocketEndpoint::Tcp("127.0.0.1:9000".to_string()))
+    );
+    assert_eq!(
+        "agent.local:9000".parse::<SocketEndpoint>(),
+        Ok(SocketEndpoint::Tcp("agent.local:9000".to_string()))
+    );
+    assert!("http://agent.local".parse::<SocketEndpoint>().is_err());
+}
@@ -0,0 +1,14 @@
+//!
+//! Kernel side of the port protocol without the minifilter.
+//!
+//! A `Simulator` accepts clients over the socket transport, answers their requests
+//! from a scripted process table and streams the events of a scripted scenario
+//! through workers that batch exactly like the driver does
+//!
+
+pub mod messaging;
+pub mod responder;
+pub mod scenario;
+pub mod simulator;
+
+pub use simulator::{Simulator, SimulatorOptions};
@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use kmum_common::handshake::EventCapabilities;
+use procmon_core::communication::socket::SocketEndpoint;
+use procmon_simulator::{
+    messaging::DEFAULT_WORKERS, scenario::Scenario, Simulator, SimulatorOptions,
+};
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Capability {
+    Process,
+    FileSystem,
+    Registry,
+    Network,
+}
+
+impl Capability {
+    fn flag(&self) -> EventCapabilities {
+        match self {
+            Capability::Process => EventCapabilities::PROCESS,
+            Capability::FileSystem => EventCapabilities::FILE_SYSTEM,
+            Capability::Registry => EventCapabilities::REGISTRY,
+            Capability::Network => EventCapabilities::NETWORK,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Serves a scripted scenario over the driver protocol", long_about = None)]
+struct SimulatorArgs {
+    /// Scenario to play to every client
+    scenario: PathBuf,
+
+    /// Where clients connect, tcp://host:port or unix:///path
+    #[arg(short, long, default_value = "tcp://127.0.0.1:9700")]
+    listen: SocketEndpoint,
+
+    #[arg(short, long, default_value_t = DEFAULT_WORKERS)]
+    workers: usize,
+
+    /// Event classes offered to clients, everything by default
+    #[arg(long, value_delimiter = ',')]
+    capabilities: Vec<Capability>,
+
+    /// Events queued per worker before new ones are dropped
+    #[arg(long)]
+    queue_limit: Option<usize>,
+
+    /// Start the scenario over once it ended
+    #[arg(long = "loop")]
+    looped: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = SimulatorArgs::parse();
+
+    let sub = tracing_subscriber::fmt().with_ansi(false).finish();
+    tracing::subscriber::set_global_default(sub).expect("Failed to sent global tracing subscriber");
+
+    let scenario = Scenario::load(&args.scenario)?;
+    info!(
+        "Loaded {} processes and {} events from {}",
+        scenario.processes().len(),
+        scenario.event_count(),
+        args.scenario.display()
+    );
+
+    let mut options = SimulatorOptions {
+        workers: args.workers,
+        looped: args.looped,
+        ..SimulatorOptions::default()
+    };
+    if !args.capabilities.is_empty() {
+        options.capabilities = args
+            .capabilities
+            .iter()
+            .fold(EventCapabilities::empty(), |all, capability| {
+                all | capability.flag()
+            });
+    }
+    if let Some(queue_limit) = args.queue_limit {
+        options.queue_limit = queue_limit;
+    }
+
+    let listener = args
+        .listen
+        .bind()
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", args.listen, e))?;
+    info!("Listening on {}", args.listen);
+
+    Simulator::new(scenario, options).serve(&listener)?;
+    Ok(())
+}
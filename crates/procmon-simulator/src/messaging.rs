@@ -0,0 +1,213 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use kmum_common::{batch::BatchBuilder, KmMessage, MAX_KM_MESSAGE_RECEIVE_SIZE};
+use procmon_core::communication::{BatchHandler, CommunicationError};
+
+/// Same cadence as the driver workers
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(15);
+
+/// Workers created by the driver
+pub const DEFAULT_WORKERS: usize = 4;
+
+///
+/// User mode copy of the driver `AsyncMessaging`.
+///
+/// Events are routed to a worker by their thread, every worker wakes up each
+/// `FLUSH_INTERVAL`, serializes what was queued into a `MAX_KM_MESSAGE_RECEIVE_SIZE`
+/// buffer and hands the batch over as soon as it is full or the queue is empty
+///
+pub struct SimulatedMessaging {
+    num_workers: usize,
+    workers: Vec<Worker>,
+}
+
+impl SimulatedMessaging {
+    ///
+    /// `queue_limit` bounds the events waiting in each worker, the ones past it are
+    /// dropped and counted the same way the driver counts failed allocations
+    ///
+    pub fn new<H: BatchHandler + Send + Sync + 'static>(
+        num_workers: usize,
+        handler: Arc<H>,
+        queue_limit: usize,
+    ) -> Self {
+        let num_workers = num_workers.max(1);
+        let workers = (0..num_workers)
+            .map(|worker_id| Worker::new(worker_id as _, handler.clone(), queue_limit))
+            .collect();
+
+        Self {
+            num_workers,
+            workers,
+        }
+    }
+
+    pub fn try_emplace_event(&self, message: KmMessage) -> anyhow::Result<(), CommunicationError> {
+        let worker_id = (message.event.thread as usize) % self.num_workers;
+
+        self.workers[worker_id].try_push_event(message)
+    }
+
+    /// Total number of events dropped by every worker
+    pub fn dropped(&self) -> u64 {
+        self.workers
+            .iter()
+            .map(|worker| worker.internal.dropped.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    ///
+    /// Events that were queued but not yet serialized are lost, like in the driver
+    ///
+    pub fn stop(&self) {
+        self.workers.iter().for_each(|worker| {
+            worker.stop();
+        });
+    }
+}
+
+impl Drop for SimulatedMessaging {
+    fn drop(&mut self) {
+        self.stop();
+
+        for worker in &mut self.workers {
+            if let Some(handle) = worker.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+struct WorkerInternal {
+    items: Mutex<VecDeque<KmMessage>>,
+    queue_limit: usize,
+    id: u16,
+    //Reported to the client in every batch header
+    dropped: AtomicU64,
+}
+
+struct Worker {
+    handle: Option<JoinHandle<()>>,
+    stop_event: Mutex<Option<Sender<()>>>,
+    internal: Arc<WorkerInternal>,
+}
+
+impl Worker {
+    fn new<H: BatchHandler + Send + Sync + 'static>(
+        id: u16,
+        handler: Arc<H>,
+        queue_limit: usize,
+    ) -> Self {
+        let internal = Arc::new(WorkerInternal {
+            items: Mutex::new(VecDeque::new()),
+            queue_limit,
+            id,
+            dropped: AtomicU64::new(0),
+        });
+        let (stop_event, stop_wait) = mpsc::channel();
+
+        let internal_clone = internal.clone();
+        let handle = std::thread::spawn(move || {
+            Worker::worker_routine(handler.as_ref(), &internal_clone, stop_wait);
+        });
+
+        Self {
+            handle: Some(handle),
+            stop_event: Mutex::new(Some(stop_event)),
+            internal,
+        }
+    }
+
+    fn stop(&self) {
+        //Dropping the sender wakes up the worker
+        self.stop_event.lock().unwrap().take();
+        self.internal.items.lock().unwrap().clear();
+    }
+
+    fn worker_routine<H: BatchHandler>(
+        handler: &H,
+        internal: &WorkerInternal,
+        stop_wait: Receiver<()>,
+    ) {
+        let mut buffer = vec![0u8; MAX_KM_MESSAGE_RECEIVE_SIZE];
+
+        //Send every 15ms or as soon as the buffer is full
+        let mut sequence = 0;
+        let mut builder = BatchBuilder::new();
+        let mut items = VecDeque::new();
+        loop {
+            match stop_wait.recv_timeout(FLUSH_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            std::mem::swap(&mut *internal.items.lock().unwrap(), &mut items);
+
+            for item in items.drain(..) {
+                if builder.try_push(&mut buffer, &item).is_ok() {
+                    continue;
+                }
+
+                if !builder.is_empty() {
+                    tracing::trace!(
+                        "Flushing {} serialized items to the client",
+                        builder.event_count()
+                    );
+                    Self::send_batch(handler, internal, &mut builder, &mut buffer, sequence);
+                    sequence += 1;
+                }
+
+                if builder.try_push(&mut buffer, &item).is_err() {
+                    tracing::error!("Double serialization error for item");
+                    internal.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+
+            if !builder.is_empty() {
+                tracing::trace!(
+                    "Send {} remaining serialized items to the client",
+                    builder.event_count()
+                );
+                Self::send_batch(handler, internal, &mut builder, &mut buffer, sequence);
+                sequence += 1;
+            }
+        }
+    }
+
+    fn send_batch<H: BatchHandler>(
+        handler: &H,
+        internal: &WorkerInternal,
+        builder: &mut BatchBuilder,
+        buffer: &mut [u8],
+        sequence: u64,
+    ) {
+        let batch = builder.finish(
+            buffer,
+            internal.id,
+            sequence,
+            internal.dropped.load(Ordering::Relaxed),
+        );
+
+        let _ = handler.handle_batch(batch);
+    }
+
+    fn try_push_event(&self, message: KmMessage) -> anyhow::Result<(), CommunicationError> {
+        let mut items = self.internal.items.lock().unwrap();
+        if items.len() >= self.internal.queue_limit {
+            self.internal.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(CommunicationError::NoMemory);
+        }
+
+        items.push_back(message);
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use kmum_common::{
+    handshake::{negotiate, BufferSizes, DriverHandshake, EventCapabilities, HandshakeError},
+    process::{ProcessInformation, UniqueProcessId},
+    ClientConnectMessage, KmReplyMessage, UmSendMessage,
+};
+use procmon_core::communication::{socket::RequestResponder, CommunicationError};
+
+use crate::scenario::ProcessTable;
+
+///
+/// Answers client requests the way the driver `CommunicationCallback` does,
+/// with the scripted process table standing in for the process collector
+///
+pub struct SimulatedDriver {
+    processes: Arc<ProcessTable>,
+    capabilities: EventCapabilities,
+    /// FILETIME the scripted process times are relative to
+    base_time: u64,
+}
+
+impl SimulatedDriver {
+    pub fn new(
+        processes: Arc<ProcessTable>,
+        capabilities: EventCapabilities,
+        base_time: u64,
+    ) -> Self {
+        Self {
+            processes,
+            capabilities,
+            base_time,
+        }
+    }
+
+    pub fn handshake(
+        &self,
+        connect: &ClientConnectMessage,
+    ) -> Result<DriverHandshake, HandshakeError> {
+        negotiate(connect, self.capabilities, BufferSizes::current())
+    }
+
+    /// Scripted process times are moved to the start of the session
+    fn process_info(&self, unique_id: UniqueProcessId) -> Option<ProcessInformation> {
+        self.processes.get(unique_id).cloned().map(|mut info| {
+            info.start_time += self.base_time;
+            info.end_time = info.end_time.map(|end_time| end_time + self.base_time);
+            info
+        })
+    }
+}
+
+impl RequestResponder for SimulatedDriver {
+    fn respond(
+        &self,
+        connect: &ClientConnectMessage,
+        message: &UmSendMessage,
+    ) -> anyhow::Result<Option<KmReplyMessage>, CommunicationError> {
+        tracing::debug!("OnMessage received: {:?}", message);
+
+        match message {
+            UmSendMessage::GetProcessInfo(unique_id) => Ok(self
+                .process_info(*unique_id)
+                .map(KmReplyMessage::ProcessInfo)),
+            UmSendMessage::GetExeName(unique_id) => Ok(self
+                .processes
+                .exe_name(*unique_id)
+                .map(KmReplyMessage::ExeName)),
+            UmSendMessage::GetProcessInfoBatch(unique_ids) => {
+                Ok(Some(KmReplyMessage::ProcessInfoBatch(
+                    unique_ids.iter().map(|id| self.process_info(*id)).collect(),
+                )))
+            }
+            UmSendMessage::GetExeNameBatch(unique_ids) => Ok(Some(KmReplyMessage::ExeNameBatch(
+                unique_ids
+                    .iter()
+                    .map(|id| self.processes.exe_name(*id))
+                    .collect(),
+            ))),
+            UmSendMessage::Handshake => {
+                Ok(Some(KmReplyMessage::Handshake(self.handshake(connect))))
+            }
+        }
+    }
+}
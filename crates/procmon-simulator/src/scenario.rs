@@ -0,0 +1,744 @@
+//!
+//! Scripted input of the simulator, a text file with one directive per line.
+//!
+//! ```text
+//! # Processes answered to GetProcessInfo/GetExeName, times are in ms since the capture started
+//! process uid=2 pid=1234 parent=4 start=0 path="C:\Windows\explorer.exe" cmd="explorer.exe"
+//!
+//! # Events, sent `at` after the client connected
+//! event at=5ms uid=2 thread=7 op=Create path="C:\temp\a.txt" open_action=2
+//! event at=10ms uid=2 thread=7 op=Write path="C:\temp\a.txt" length=4096 repeat=100 every=1ms
+//! event at=20ms uid=2 thread=7 op=Close path="C:\temp\a.txt" result=ACCESS_DENIED
+//! ```
+//!
+//! Operation fields are named after the fields of the `EventClass` variant and default
+//! to zero when left out. Every event also accepts `path`, `result` (anything
+//! `NtStatus::parse` understands), `duration`, `stack` (comma separated return
+//! addresses, innermost first) and `repeat`/`every` to emit it several times
+//!
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    net::SocketAddr,
+    path::Path,
+    time::Duration,
+};
+
+use kmum_common::{
+    event::*,
+    ntstatus::NtStatus,
+    process::{ProcessInformation, UniqueProcessId},
+    serializable_ntstring::SerializableNtString,
+    KmMessage, MAX_KM_MESSAGE_RECEIVE_SIZE,
+};
+use nt_string::unicode_string::NtUnicodeString;
+
+/// Return addresses at or above this belong to the kernel
+const KERNEL_ADDRESS_START: u64 = 0xFFFF_8000_0000_0000;
+
+/// FILETIME ticks per millisecond
+const TICKS_PER_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioError {
+    /// One based, zero for errors that are not about a single line
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+///
+/// Processes the simulator knows about, keyed by their unique id
+///
+#[derive(Debug, Default, Clone)]
+pub struct ProcessTable {
+    processes: HashMap<UniqueProcessId, ProcessInformation>,
+}
+
+impl ProcessTable {
+    pub fn insert(&mut self, process: ProcessInformation) {
+        self.processes.insert(process.unique_id, process);
+    }
+
+    pub fn get(&self, unique_id: UniqueProcessId) -> Option<&ProcessInformation> {
+        self.processes.get(&unique_id)
+    }
+
+    ///
+    /// Last segment of the process path, what the driver answers to `GetExeName`
+    ///
+    pub fn exe_name(&self, unique_id: UniqueProcessId) -> Option<SerializableNtString> {
+        let path = self.get(unique_id)?.path.to_string();
+        let name = path.rsplit(['\\', '/']).next().unwrap_or(&path);
+
+        NtUnicodeString::try_from(name)
+            .ok()
+            .map(SerializableNtString::new)
+    }
+
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProcessInformation> {
+        self.processes.values()
+    }
+}
+
+///
+/// One `event` line, kept serialized since `KmMessage` can not be cloned
+///
+#[derive(Debug, Clone)]
+pub struct ScriptedEvent {
+    pub at: Duration,
+    /// Number of times the event is sent, at least one
+    pub repeat: u32,
+    pub every: Duration,
+    message: Vec<u8>,
+}
+
+impl ScriptedEvent {
+    ///
+    /// A fresh copy of the event, `date` is the FILETIME it happened at
+    ///
+    pub fn message(&self, date: u64) -> KmMessage {
+        let mut message: KmMessage =
+            postcard::from_bytes(&self.message).expect("Scripted events are checked when parsed");
+        message.event.date = date;
+
+        message
+    }
+
+    /// Time of every copy of the event
+    pub fn times(&self) -> impl Iterator<Item = Duration> + '_ {
+        (0..self.repeat).map(|index| self.at + self.every * index)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Scenario {
+    processes: ProcessTable,
+    events: Vec<ScriptedEvent>,
+}
+
+impl Scenario {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+        Self::parse(&source).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(source: &str) -> Result<Self, ScenarioError> {
+        let mut scenario = Scenario::default();
+
+        for (index, line) in source.lines().enumerate() {
+            scenario.parse_line(line).map_err(|message| ScenarioError {
+                line: index + 1,
+                message,
+            })?;
+        }
+
+        Ok(scenario)
+    }
+
+    pub fn processes(&self) -> &ProcessTable {
+        &self.processes
+    }
+
+    pub fn events(&self) -> &[ScriptedEvent] {
+        &self.events
+    }
+
+    /// Number of messages sent by one run of the scenario, repeats included
+    pub fn event_count(&self) -> u64 {
+        self.events.iter().map(|event| event.repeat as u64).sum()
+    }
+
+    /// Time of the last event
+    pub fn duration(&self) -> Duration {
+        self.events
+            .iter()
+            .filter_map(|event| event.times().last())
+            .max()
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Every message of one run in the order it is sent, as the time it is due
+    /// and the index of its event. Events due at the same time keep their file order
+    ///
+    pub fn timeline(&self) -> Vec<(Duration, usize)> {
+        let mut timeline: Vec<_> = self
+            .events
+            .iter()
+            .enumerate()
+            .flat_map(|(index, event)| event.times().map(move |at| (at, index)))
+            .collect();
+        timeline.sort_by_key(|(at, _)| *at);
+
+        timeline
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let (directive, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut fields = Fields::parse(rest)?;
+
+        match directive {
+            "process" => {
+                let process = parse_process(&mut fields)?;
+                fields.finish()?;
+                self.processes.insert(process);
+            }
+            "event" => {
+                let event = self.parse_event(&mut fields)?;
+                fields.finish()?;
+                self.events.push(event);
+            }
+            other => return Err(format!("unknown directive `{}`", other)),
+        }
+
+        Ok(())
+    }
+
+    fn parse_event(&self, fields: &mut Fields) -> Result<ScriptedEvent, String> {
+        let at = fields.duration("at")?.unwrap_or_default();
+        let repeat = fields.number::<u32>("repeat")?.unwrap_or(1);
+        let every = fields.duration("every")?.unwrap_or_default();
+        if repeat == 0 {
+            return Err("`repeat` must be at least 1".to_string());
+        }
+
+        let unique_id = fields.required_number::<u64>("uid")?;
+        let process = self
+            .processes
+            .get(unique_id)
+            .ok_or_else(|| format!("unknown process uid {}", unique_id))?;
+
+        let op = fields.required_string("op")?;
+        let operation = parse_operation(&op, fields)?;
+
+        let message = KmMessage {
+            event: EventCompoent {
+                date: 0,
+                thread: fields.number("thread")?.unwrap_or_default(),
+                operation,
+                result: fields.status("result")?.unwrap_or(NtStatus::SUCCESS).0,
+                path: fields
+                    .nt_string("path")?
+                    .unwrap_or_else(SerializableNtString::empty),
+                duration: fields
+                    .duration("duration")?
+                    .map(|duration| (duration.as_nanos() / 100) as u64)
+                    .unwrap_or_default(),
+            },
+            process: SimpleProcessDetails {
+                pid: process.pid,
+                unique_id,
+            },
+            stack: fields.stack("stack")?.unwrap_or_default(),
+        };
+
+        let mut buffer = vec![0u8; MAX_KM_MESSAGE_RECEIVE_SIZE];
+        let message = postcard::to_slice(&message, &mut buffer)
+            .map_err(|_| "event does not fit in a batch".to_string())?
+            .to_vec();
+
+        Ok(ScriptedEvent {
+            at,
+            repeat,
+            every,
+            message,
+        })
+    }
+}
+
+fn parse_process(fields: &mut Fields) -> Result<ProcessInformation, String> {
+    let ms_to_ticks = |duration: Duration| duration.as_millis() as u64 * TICKS_PER_MS;
+
+    Ok(ProcessInformation {
+        unique_id: fields.required_number("uid")?,
+        pid: fields.required_number("pid")?,
+        parent_pid: fields.number("parent")?.unwrap_or_default(),
+        start_time: fields
+            .duration("start")?
+            .map(ms_to_ticks)
+            .unwrap_or_default(),
+        end_time: fields.duration("end")?.map(ms_to_ticks),
+        path: fields
+            .nt_string("path")?
+            .ok_or_else(|| "missing `path`".to_string())?,
+        cmd: fields.nt_string("cmd")?,
+    })
+}
+
+fn parse_operation(op: &str, fields: &mut Fields) -> Result<EventClass, String> {
+    let operation = match op {
+        "ProcessCreate" => EventClass::Process(EventProcessOperation::ProcessCreate {
+            pid: fields.number("pid")?.unwrap_or_default(),
+            cmd: fields.nt_string("cmd")?,
+        }),
+        "ProcessDestroy" => EventClass::Process(EventProcessOperation::ProcessDestroy {
+            pid: fields.number("pid")?.unwrap_or_default(),
+            exit_status: fields.status("exit_status")?.unwrap_or(NtStatus::SUCCESS).0,
+        }),
+        "ThreadCreate" => EventClass::Process(EventProcessOperation::ThreadCreate {
+            tid: fields.number("tid")?.unwrap_or_default(),
+            start_address: fields.number("start_address")?.unwrap_or_default(),
+        }),
+        "ThreadExit" => EventClass::Process(EventProcessOperation::ThreadExit {
+            tid: fields.number("tid")?.unwrap_or_default(),
+        }),
+        "ImageLoad" => EventClass::Process(EventProcessOperation::ImageLoad {
+            base: fields.number("base")?.unwrap_or_default(),
+            size: fields.number("size")?.unwrap_or_default(),
+        }),
+
+        "Create" => EventClass::FileSystem(EventFileSystemOperation::Create {
+            desired_access: fields.number("desired_access")?.unwrap_or_default(),
+            share_mode: fields.number("share_mode")?.unwrap_or_default(),
+            disposition: fields.number("disposition")?.unwrap_or_default(),
+            options: fields.number("options")?.unwrap_or_default(),
+            attribute: fields.number("attribute")?.unwrap_or_default(),
+            open_action: fields.number("open_action")?.unwrap_or_default(),
+        }),
+        "Read" => EventClass::FileSystem(EventFileSystemOperation::Read {
+            length: fields.number("length")?.unwrap_or_default(),
+            offset: fields.number("offset")?.unwrap_or_default(),
+        }),
+        "Write" => EventClass::FileSystem(EventFileSystemOperation::Write {
+            length: fields.number("length")?.unwrap_or_default(),
+            offset: fields.number("offset")?.unwrap_or_default(),
+        }),
+        "Close" => EventClass::FileSystem(EventFileSystemOperation::Close {}),
+        "Cleanup" => EventClass::FileSystem(EventFileSystemOperation::Cleanup {}),
+        "FlushBuffers" => EventClass::FileSystem(EventFileSystemOperation::FlushBuffers {}),
+        "QueryInformation" => EventClass::FileSystem(EventFileSystemOperation::QueryInformation {
+            information_class: fields.number("information_class")?.unwrap_or_default(),
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "SetBasicInformation" => set_information(FileSetInformation::Basic {
+            creation_time: fields.number("creation_time")?.unwrap_or_default(),
+            last_access_time: fields.number("last_access_time")?.unwrap_or_default(),
+            last_write_time: fields.number("last_write_time")?.unwrap_or_default(),
+            change_time: fields.number("change_time")?.unwrap_or_default(),
+            attributes: fields.number("attributes")?.unwrap_or_default(),
+        }),
+        "SetRenameInformation" => set_information(FileSetInformation::Rename {
+            target: fields
+                .nt_string("target")?
+                .unwrap_or_else(SerializableNtString::empty),
+            replace_if_exists: fields.bool("replace_if_exists")?.unwrap_or_default(),
+        }),
+        "SetDispositionInformation" => set_information(FileSetInformation::Disposition {
+            delete: fields.bool("delete")?.unwrap_or(true),
+        }),
+        "SetEndOfFileInformation" => set_information(FileSetInformation::EndOfFile {
+            end_of_file: fields.number("end_of_file")?.unwrap_or_default(),
+        }),
+        "SetAllocationInformation" => set_information(FileSetInformation::Allocation {
+            allocation_size: fields.number("allocation_size")?.unwrap_or_default(),
+        }),
+        "SetInformation" => set_information(FileSetInformation::Other {
+            information_class: fields.number("information_class")?.unwrap_or_default(),
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "QueryDirectory" => EventClass::FileSystem(EventFileSystemOperation::QueryDirectory {
+            pattern: fields
+                .nt_string("pattern")?
+                .unwrap_or_else(SerializableNtString::empty),
+            information_class: fields.number("information_class")?.unwrap_or_default(),
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "FileSystemControl" => {
+            EventClass::FileSystem(EventFileSystemOperation::FileSystemControl {
+                control_code: fields.number("control_code")?.unwrap_or_default(),
+                input_length: fields.number("input_length")?.unwrap_or_default(),
+                output_length: fields.number("output_length")?.unwrap_or_default(),
+            })
+        }
+        "LockControl" => EventClass::FileSystem(EventFileSystemOperation::LockControl {
+            operation: fields.lock_operation("operation")?,
+            offset: fields.number("offset")?.unwrap_or_default(),
+            length: fields.number("length")?.unwrap_or_default(),
+            exclusive: fields.bool("exclusive")?.unwrap_or_default(),
+        }),
+
+        "CreateKey" => EventClass::Registry(EventRegistryOperation::CreateKey {
+            desired_access: fields.number("desired_access")?.unwrap_or_default(),
+            disposition: fields.number("disposition")?.unwrap_or_default(),
+        }),
+        "OpenKey" => EventClass::Registry(EventRegistryOperation::OpenKey {
+            desired_access: fields.number("desired_access")?.unwrap_or_default(),
+        }),
+        "QueryKey" => EventClass::Registry(EventRegistryOperation::QueryKey {
+            information_class: fields.number("information_class")?.unwrap_or_default(),
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "SetValue" => {
+            let value_type = fields.value_type("value_type")?;
+            let data = fields.value_data("data", value_type)?;
+            EventClass::Registry(EventRegistryOperation::SetValue {
+                value_name: fields
+                    .nt_string("value_name")?
+                    .unwrap_or_else(SerializableNtString::empty),
+                value_type,
+                data_size: fields.number("data_size")?.unwrap_or(data.len() as u32),
+                preview: RegistryDataPreview::new(&data),
+            })
+        }
+        "QueryValue" => EventClass::Registry(EventRegistryOperation::QueryValue {
+            value_name: fields
+                .nt_string("value_name")?
+                .unwrap_or_else(SerializableNtString::empty),
+            information_class: fields.number("information_class")?.unwrap_or_default(),
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "DeleteKey" => EventClass::Registry(EventRegistryOperation::DeleteKey {}),
+        "DeleteValue" => EventClass::Registry(EventRegistryOperation::DeleteValue {
+            value_name: fields
+                .nt_string("value_name")?
+                .unwrap_or_else(SerializableNtString::empty),
+        }),
+        "EnumerateKey" => EventClass::Registry(EventRegistryOperation::EnumerateKey {
+            index: fields.number("index")?.unwrap_or_default(),
+            information_class: fields.number("information_class")?.unwrap_or_default(),
+        }),
+        "EnumerateValue" => EventClass::Registry(EventRegistryOperation::EnumerateValue {
+            index: fields.number("index")?.unwrap_or_default(),
+            information_class: fields.number("information_class")?.unwrap_or_default(),
+        }),
+        "RenameKey" => EventClass::Registry(EventRegistryOperation::RenameKey {
+            new_name: fields
+                .nt_string("new_name")?
+                .unwrap_or_else(SerializableNtString::empty),
+        }),
+        "FlushKey" => EventClass::Registry(EventRegistryOperation::Flush {}),
+
+        "TcpConnect" => EventClass::Network(EventNetworkOperation::TcpConnect {
+            endpoints: fields.endpoints()?,
+        }),
+        "TcpAccept" => EventClass::Network(EventNetworkOperation::TcpAccept {
+            endpoints: fields.endpoints()?,
+        }),
+        "TcpSend" => EventClass::Network(EventNetworkOperation::TcpSend {
+            endpoints: fields.endpoints()?,
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "TcpReceive" => EventClass::Network(EventNetworkOperation::TcpReceive {
+            endpoints: fields.endpoints()?,
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "TcpDisconnect" => EventClass::Network(EventNetworkOperation::TcpDisconnect {
+            endpoints: fields.endpoints()?,
+        }),
+        "UdpSend" => EventClass::Network(EventNetworkOperation::UdpSend {
+            endpoints: fields.endpoints()?,
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+        "UdpReceive" => EventClass::Network(EventNetworkOperation::UdpReceive {
+            endpoints: fields.endpoints()?,
+            length: fields.number("length")?.unwrap_or_default(),
+        }),
+
+        other => return Err(format!("unknown operation `{}`", other)),
+    };
+
+    Ok(operation)
+}
+
+fn set_information(information: FileSetInformation) -> EventClass {
+    EventClass::FileSystem(EventFileSystemOperation::SetInformation { information })
+}
+
+///
+/// `key=value` pairs of a line, values with spaces are quoted. Every
+/// lookup consumes its key so leftovers can be reported as unknown
+///
+struct Fields<'a> {
+    values: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Fields<'a> {
+    fn parse(mut rest: &'a str) -> Result<Self, String> {
+        let mut values = HashMap::new();
+
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with('#') {
+                break;
+            }
+
+            let (key, after_key) = rest
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, found `{}`", rest))?;
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return Err(format!("invalid field name `{}`", key));
+            }
+
+            let (value, after_value) = if let Some(quoted) = after_key.strip_prefix('"') {
+                quoted
+                    .split_once('"')
+                    .ok_or_else(|| format!("unterminated quote in `{}`", key))?
+            } else {
+                after_key
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((after_key, ""))
+            };
+
+            if values.insert(key, value).is_some() {
+                return Err(format!("`{}` is set more than once", key));
+            }
+            rest = after_value;
+        }
+
+        Ok(Self { values })
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.values.keys().next() {
+            Some(key) => Err(format!("unknown field `{}`", key)),
+            None => Ok(()),
+        }
+    }
+
+    fn string(&mut self, key: &str) -> Option<&'a str> {
+        self.values.remove(key)
+    }
+
+    fn required_string(&mut self, key: &str) -> Result<String, String> {
+        self.string(key)
+            .map(str::to_string)
+            .ok_or_else(|| format!("missing `{}`", key))
+    }
+
+    fn nt_string(&mut self, key: &str) -> Result<Option<SerializableNtString>, String> {
+        self.string(key)
+            .map(|value| {
+                NtUnicodeString::try_from(value)
+                    .map(SerializableNtString::new)
+                    .map_err(|_| format!("`{}` is too long", key))
+            })
+            .transpose()
+    }
+
+    fn number<T: TryFrom<i128>>(&mut self, key: &str) -> Result<Option<T>, String> {
+        self.string(key)
+            .map(|value| {
+                parse_integer(value)
+                    .and_then(|number| T::try_from(number).ok())
+                    .ok_or_else(|| format!("invalid number `{}` for `{}`", value, key))
+            })
+            .transpose()
+    }
+
+    fn required_number<T: TryFrom<i128>>(&mut self, key: &str) -> Result<T, String> {
+        self.number(key)?
+            .ok_or_else(|| format!("missing `{}`", key))
+    }
+
+    fn bool(&mut self, key: &str) -> Result<Option<bool>, String> {
+        self.string(key)
+            .map(|value| match value {
+                "true" | "1" => Ok(true),
+                "false" | "0" => Ok(false),
+                _ => Err(format!("invalid boolean `{}` for `{}`", value, key)),
+            })
+            .transpose()
+    }
+
+    fn duration(&mut self, key: &str) -> Result<Option<Duration>, String> {
+        self.string(key)
+            .map(|value| {
+                parse_duration(value)
+                    .ok_or_else(|| format!("invalid duration `{}` for `{}`", value, key))
+            })
+            .transpose()
+    }
+
+    fn status(&mut self, key: &str) -> Result<Option<NtStatus>, String> {
+        self.string(key)
+            .map(|value| {
+                NtStatus::parse(value).ok_or_else(|| format!("unknown status `{}`", value))
+            })
+            .transpose()
+    }
+
+    fn stack(&mut self, key: &str) -> Result<Option<EventStack>, String> {
+        let Some(value) = self.string(key) else {
+            return Ok(None);
+        };
+
+        let mut stack = EventStack::new();
+        for address in value.split(',') {
+            let address = parse_integer(address.trim())
+                .and_then(|address| u64::try_from(address).ok())
+                .ok_or_else(|| format!("invalid return address `{}`", address))?;
+            let mode = if address >= KERNEL_ADDRESS_START {
+                FrameMode::Kernel
+            } else {
+                FrameMode::User
+            };
+
+            if !stack.try_push(StackFrame { address, mode }) {
+                return Err(format!("more than {} frames", MAX_STACK_FRAMES));
+            }
+        }
+
+        Ok(Some(stack))
+    }
+
+    fn lock_operation(&mut self, key: &str) -> Result<FileLockOperation, String> {
+        match self.string(key) {
+            None | Some("Lock") => Ok(FileLockOperation::Lock),
+            Some("UnlockSingle") => Ok(FileLockOperation::UnlockSingle),
+            Some("UnlockAll") => Ok(FileLockOperation::UnlockAll),
+            Some("UnlockAllByKey") => Ok(FileLockOperation::UnlockAllByKey),
+            Some(other) => Err(format!("unknown lock operation `{}`", other)),
+        }
+    }
+
+    ///
+    /// Either a `REG_*` name or the raw type
+    ///
+    fn value_type(&mut self, key: &str) -> Result<RegistryValueType, String> {
+        let Some(value) = self.string(key) else {
+            return Ok(RegistryValueType::None);
+        };
+
+        if let Some(raw) = parse_integer(value).and_then(|raw| u32::try_from(raw).ok()) {
+            return Ok(RegistryValueType::from_raw(raw));
+        }
+
+        (0..=11)
+            .map(RegistryValueType::from_raw)
+            .find(|value_type| value_type.name().eq_ignore_ascii_case(value))
+            .ok_or_else(|| format!("unknown value type `{}`", value))
+    }
+
+    ///
+    /// Numbers for the integer types, UTF-16 for strings and hex bytes for everything else
+    ///
+    fn value_data(&mut self, key: &str, value_type: RegistryValueType) -> Result<Vec<u8>, String> {
+        let Some(value) = self.string(key) else {
+            return Ok(Vec::new());
+        };
+        let invalid = || format!("invalid {} data `{}`", value_type.name(), value);
+
+        match value_type {
+            RegistryValueType::Dword => parse_integer(value)
+                .and_then(|number| u32::try_from(number).ok())
+                .map(|number| number.to_le_bytes().to_vec())
+                .ok_or_else(invalid),
+            RegistryValueType::DwordBigEndian => parse_integer(value)
+                .and_then(|number| u32::try_from(number).ok())
+                .map(|number| number.to_be_bytes().to_vec())
+                .ok_or_else(invalid),
+            RegistryValueType::Qword => parse_integer(value)
+                .and_then(|number| u64::try_from(number).ok())
+                .map(|number| number.to_le_bytes().to_vec())
+                .ok_or_else(invalid),
+            RegistryValueType::String
+            | RegistryValueType::ExpandString
+            | RegistryValueType::Link => Ok(value
+                .encode_utf16()
+                .chain([0])
+                .flat_map(u16::to_le_bytes)
+                .collect()),
+            _ => {
+                let hex = value.strip_prefix("0x").unwrap_or(value);
+                if !hex.len().is_multiple_of(2) {
+                    return Err(invalid());
+                }
+
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(invalid)
+            }
+        }
+    }
+
+    fn endpoints(&mut self) -> Result<NetworkEndpoints, String> {
+        let mut address = |key: &str| -> Result<SocketAddress, String> {
+            let value = self
+                .string(key)
+                .ok_or_else(|| format!("missing `{}`", key))?;
+            let address: SocketAddr = value
+                .parse()
+                .map_err(|_| format!("invalid address `{}` for `{}`", value, key))?;
+
+            Ok(SocketAddress {
+                ip: match address {
+                    SocketAddr::V4(address) => IpAddress::V4(address.ip().octets()),
+                    SocketAddr::V6(address) => IpAddress::V6(address.ip().octets()),
+                },
+                port: address.port(),
+            })
+        };
+
+        Ok(NetworkEndpoints {
+            local: address("local")?,
+            remote: address("remote")?,
+        })
+    }
+}
+
+fn parse_integer(value: &str) -> Option<i128> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+
+    let number = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i128::from_str_radix(&hex.replace('_', ""), 16).ok()?,
+        None => digits.replace('_', "").parse::<i128>().ok()?,
+    };
+
+    Some(if negative { -number } else { number })
+}
+
+///
+/// `15ms`, `2s`, `250us`, a bare number is taken as milliseconds
+///
+fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+
+    match unit {
+        "" | "ms" => Some(Duration::from_millis(number)),
+        "s" => Some(Duration::from_secs(number)),
+        "us" => Some(Duration::from_micros(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        _ => None,
+    }
+}
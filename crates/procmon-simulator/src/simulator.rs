@@ -0,0 +1,208 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use kmum_common::{
+    handshake::{negotiate, BufferSizes, EventCapabilities, HandshakeError, PROTOCOL_MAGIC},
+    ClientConnectMode,
+};
+use procmon_core::communication::{
+    filetime_now,
+    socket::{SocketListener, SocketPublisher, SocketStream},
+    CommunicationError,
+};
+
+use crate::{
+    messaging::{SimulatedMessaging, DEFAULT_WORKERS, FLUSH_INTERVAL},
+    responder::SimulatedDriver,
+    scenario::Scenario,
+};
+
+/// How often a waiting session checks whether the client went away
+const CLOSED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct SimulatorOptions {
+    pub workers: usize,
+    /// Event classes the simulated driver offers during the handshake
+    pub capabilities: EventCapabilities,
+    /// Events queued per worker before new ones are dropped
+    pub queue_limit: usize,
+    /// Starts the scenario over once it ended instead of idling until the client leaves
+    pub looped: bool,
+}
+
+impl Default for SimulatorOptions {
+    fn default() -> Self {
+        Self {
+            workers: DEFAULT_WORKERS,
+            capabilities: EventCapabilities::all(),
+            queue_limit: usize::MAX,
+            looped: false,
+        }
+    }
+}
+
+///
+/// What happened during one client connection
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SessionSummary {
+    /// Events handed to the workers
+    pub sent: u64,
+    /// Events the negotiated capabilities or the testing pid filtered out
+    pub filtered: u64,
+    /// Events the workers had to drop
+    pub dropped: u64,
+}
+
+pub struct Simulator {
+    scenario: Arc<Scenario>,
+    options: SimulatorOptions,
+}
+
+impl Simulator {
+    pub fn new(scenario: Scenario, options: SimulatorOptions) -> Self {
+        Self {
+            scenario: Arc::new(scenario),
+            options,
+        }
+    }
+
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
+    }
+
+    ///
+    /// Serves clients one after the other, the driver port also
+    /// accepts a single connection at a time
+    ///
+    pub fn serve(&self, listener: &SocketListener) -> std::io::Result<()> {
+        loop {
+            let stream = listener.accept()?;
+
+            match self.run_session(stream) {
+                Ok(summary) => tracing::info!("Client disconnected: {:?}", summary),
+                Err(e) => tracing::warn!("Client session failed: {:?}", e),
+            }
+        }
+    }
+
+    ///
+    /// Plays the scenario to a freshly connected client and returns once it went away
+    ///
+    pub fn run_session(
+        &self,
+        stream: SocketStream,
+    ) -> anyhow::Result<SessionSummary, CommunicationError> {
+        let base_time = filetime_now();
+        let responder = SimulatedDriver::new(
+            Arc::new(self.scenario.processes().clone()),
+            self.options.capabilities,
+            base_time,
+        );
+
+        let publisher = Arc::new(SocketPublisher::accept(stream, responder)?);
+        let connect = *publisher.connect_message();
+
+        //Clients that don't start with our magic are refused
+        if connect.preamble.magic != PROTOCOL_MAGIC {
+            tracing::error!("Refusing client with magic {:x}", connect.preamble.magic);
+            publisher.close();
+            return Err(CommunicationError::Handshake(HandshakeError::InvalidMagic(
+                connect.preamble.magic,
+            )));
+        }
+
+        let mut summary = SessionSummary::default();
+        let capabilities =
+            match negotiate(&connect, self.options.capabilities, BufferSizes::current()) {
+                Ok(handshake) => handshake.capabilities,
+                Err(e) => {
+                    //The client reads the error from its handshake request and leaves
+                    tracing::error!("Client handshake failed: {:?}", e);
+                    wait_closed(&publisher);
+                    return Ok(summary);
+                }
+            };
+        let filter_pid = match connect.mode {
+            ClientConnectMode::Testing { filter_pid } => filter_pid,
+            ClientConnectMode::Any => 0,
+        };
+        tracing::info!(
+            "Client connected with {:?}, mode {:?}",
+            capabilities,
+            connect.mode
+        );
+
+        let messaging = SimulatedMessaging::new(
+            self.options.workers,
+            publisher.clone(),
+            self.options.queue_limit,
+        );
+
+        let timeline = self.scenario.timeline();
+        let period = self.scenario.duration().max(FLUSH_INTERVAL);
+        let start = Instant::now();
+        let mut offset = Duration::ZERO;
+
+        'session: loop {
+            for (at, index) in &timeline {
+                let due = offset + *at;
+                if !sleep_until(&publisher, start + due) {
+                    break 'session;
+                }
+
+                let date = base_time + (due.as_nanos() / 100) as u64;
+                let message = self.scenario.events()[*index].message(date);
+
+                let wanted = capabilities.contains(message.event.operation.capability())
+                    && (filter_pid == 0 || filter_pid == message.process.pid);
+                if !wanted {
+                    summary.filtered += 1;
+                    continue;
+                }
+
+                if messaging.try_emplace_event(message).is_ok() {
+                    summary.sent += 1;
+                }
+            }
+
+            if !self.options.looped || timeline.is_empty() {
+                wait_closed(&publisher);
+                break;
+            }
+            offset += period;
+        }
+
+        summary.dropped = messaging.dropped();
+        messaging.stop();
+
+        Ok(summary)
+    }
+}
+
+///
+/// Returns false if the client went away before `deadline`
+///
+fn sleep_until(publisher: &SocketPublisher, deadline: Instant) -> bool {
+    loop {
+        if publisher.is_closed() {
+            return false;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+
+        std::thread::sleep((deadline - now).min(CLOSED_POLL_INTERVAL));
+    }
+}
+
+fn wait_closed(publisher: &SocketPublisher) {
+    while !publisher.is_closed() {
+        std::thread::sleep(CLOSED_POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use kmum_common::{
+    batch::BatchHeader,
+    event::{
+        EventClass, EventCompoent, EventFileSystemOperation, EventStack, SimpleProcessDetails,
+    },
+    serializable_ntstring::SerializableNtString,
+    ClientConnectMessage, ClientConnectMode, KmMessage, KmMessageRef, KmReplyMessage,
+    UmSendMessage, MAX_KM_MESSAGE_RECEIVE_SIZE,
+};
+use nt_string::unicode_string::NtUnicodeString;
+use procmon_core::communication::{
+    socket::{SocketCommunication, SocketEndpoint},
+    BatchHandler, CommunicationError, CommunicationInterface, EventProcessor,
+};
+use procmon_simulator::{
+    messaging::SimulatedMessaging, scenario::Scenario, Simulator, SimulatorOptions,
+};
+
+const TOOL_PID: u64 = 5120;
+/// Messages of the shipped scenario reported for `TOOL_PID`
+const TOOL_EVENTS: u64 = 257;
+
+fn basic_scenario() -> Scenario {
+    Scenario::load(concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios/basic.scn")).unwrap()
+}
+
+#[derive(Default)]
+struct Collector {
+    batches: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Collector {
+    fn wait_for_events(&self, count: u64) -> Vec<Vec<u8>> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let batches = self.batches.lock().unwrap().clone();
+            let received: u64 = batches
+                .iter()
+                .map(|batch| BatchHeader::split(batch).unwrap().0.event_count as u64)
+                .sum();
+            if received >= count {
+                return batches;
+            }
+
+            assert!(
+                Instant::now() < deadline,
+                "only {} events arrived",
+                received
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+impl BatchHandler for Collector {
+    fn handle_batch(&self, batch: &[u8]) -> Result<(), CommunicationError> {
+        self.batches.lock().unwrap().push(batch.to_vec());
+        Ok(())
+    }
+}
+
+fn event(thread: u64, index: u64) -> KmMessage {
+    let path = format!(
+        "\\Device\\HarddiskVolume3\\Users\\Public\\Documents\\{:08}.txt",
+        index
+    );
+
+    KmMessage {
+        event: EventCompoent {
+            date: index,
+            thread,
+            operation: EventClass::FileSystem(EventFileSystemOperation::Write {
+                length: 512,
+                offset: 0,
+            }),
+            result: 0,
+            path: SerializableNtString::new(NtUnicodeString::try_from(path.as_str()).unwrap()),
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid: 4,
+            unique_id: 1,
+        },
+        stack: EventStack::new(),
+    }
+}
+
+#[test]
+fn shipped_scenario_parses() {
+    let scenario = basic_scenario();
+
+    assert_eq!(scenario.processes().len(), 4);
+    assert_eq!(scenario.event_count(), 265);
+    assert_eq!(scenario.duration(), Duration::from_millis(900));
+    assert_eq!(
+        scenario.processes().exe_name(4).unwrap().to_string(),
+        "tool.exe"
+    );
+
+    let timeline = scenario.timeline();
+    assert_eq!(timeline.len() as u64, scenario.event_count());
+    assert!(timeline.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+}
+
+#[test]
+fn errors_point_at_the_line() {
+    let process = "process uid=1 pid=4 path=\"C:\\a.exe\"";
+    let cases = [
+        ("event uid=1 op=Read colour=blue", "unknown field `colour`"),
+        ("event uid=2 op=Read", "unknown process uid 2"),
+        ("event uid=1 op=Teleport", "unknown operation `Teleport`"),
+        (
+            "event uid=1 op=Read result=NOT_A_STATUS",
+            "unknown status `NOT_A_STATUS`",
+        ),
+        (
+            "event uid=1 op=Read at=5h",
+            "invalid duration `5h` for `at`",
+        ),
+        (
+            "event uid=1 op=Read path=\"C:\\a",
+            "unterminated quote in `path`",
+        ),
+    ];
+
+    for (line, message) in cases {
+        let error = Scenario::parse(&format!("{}\n# comment\n{}", process, line)).unwrap_err();
+        assert_eq!(error.line, 3, "{}", line);
+        assert_eq!(error.message, message);
+    }
+}
+
+#[test]
+fn batches_follow_driver_semantics() {
+    const WORKERS: usize = 4;
+    const EVENTS: u64 = 3000;
+
+    let collector = Arc::new(Collector::default());
+    let messaging = SimulatedMessaging::new(WORKERS, collector.clone(), usize::MAX);
+    for index in 0..EVENTS {
+        messaging
+            .try_emplace_event(event(index % 7, index))
+            .unwrap();
+    }
+
+    let batches = collector.wait_for_events(EVENTS);
+    drop(messaging);
+
+    let mut sequences: HashMap<u16, u64> = HashMap::new();
+    for batch in &batches {
+        //Many events are queued before the first flush, batches have to be split
+        assert!(batch.len() <= MAX_KM_MESSAGE_RECEIVE_SIZE);
+
+        let (header, mut payload) = BatchHeader::split(batch).unwrap();
+        assert!((header.worker as usize) < WORKERS);
+        assert_eq!(header.dropped, 0);
+
+        let next = sequences.entry(header.worker).or_default();
+        assert_eq!(header.sequence, *next);
+        *next += 1;
+
+        for _ in 0..header.event_count {
+            let (message, rest) = KmMessageRef::take_from_bytes(payload).unwrap();
+            assert_eq!(
+                message.event.thread as usize % WORKERS,
+                header.worker as usize
+            );
+            payload = rest;
+        }
+        assert!(payload.is_empty());
+    }
+
+    assert!(batches.len() > WORKERS);
+}
+
+#[test]
+fn queue_limit_drops_are_reported() {
+    let collector = Arc::new(Collector::default());
+    let messaging = SimulatedMessaging::new(1, collector.clone(), 10);
+
+    let accepted = (0..100)
+        .filter(|index| messaging.try_emplace_event(event(1, *index)).is_ok())
+        .count() as u64;
+    let dropped = 100 - accepted;
+    assert!(dropped > 0);
+    assert_eq!(messaging.dropped(), dropped);
+
+    collector.wait_for_events(accepted);
+    messaging.try_emplace_event(event(1, 100)).unwrap();
+    let batches = collector.wait_for_events(accepted + 1);
+
+    let (header, _) = BatchHeader::split(batches.last().unwrap()).unwrap();
+    assert_eq!(header.dropped, dropped);
+}
+
+struct Forward(mpsc::Sender<(u64, u64)>);
+
+impl EventProcessor for Forward {
+    fn process<I>(&self, iter: &mut I) -> Result<(), CommunicationError>
+    where
+        I: Iterator<Item = KmMessage>,
+    {
+        for event in iter {
+            let _ = self.0.send((event.process.pid, event.event.date));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn session_streams_scenario() {
+    let listener = "tcp://127.0.0.1:0"
+        .parse::<SocketEndpoint>()
+        .unwrap()
+        .bind()
+        .unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+
+    let simulator = Simulator::new(basic_scenario(), SimulatorOptions::default());
+    let server = thread::spawn(move || simulator.run_session(listener.accept().unwrap()));
+
+    let communication = Arc::new(
+        SocketCommunication::connect(
+            &endpoint,
+            ClientConnectMessage::new(ClientConnectMode::Testing {
+                filter_pid: TOOL_PID,
+            }),
+        )
+        .unwrap(),
+    );
+
+    match communication.send_message_blocking(&UmSendMessage::GetProcessInfo(4)) {
+        Ok(Some(KmReplyMessage::ProcessInfo(info))) => {
+            assert_eq!(info.pid, TOOL_PID);
+            assert_eq!(info.parent_pid, 688);
+            assert!(info.end_time.unwrap() > info.start_time);
+        }
+        other => panic!("unexpected reply {:?}", other),
+    }
+    match communication.send_message_blocking(&UmSendMessage::GetExeName(3)) {
+        Ok(Some(KmReplyMessage::ExeName(name))) => assert_eq!(name.to_string(), "svchost.exe"),
+        other => panic!("unexpected reply {:?}", other),
+    }
+    assert!(matches!(
+        communication.send_message_blocking(&UmSendMessage::GetProcessInfo(99)),
+        Ok(None)
+    ));
+
+    let (sender, received) = mpsc::channel();
+    let processing = {
+        let communication = communication.clone();
+        thread::spawn(move || communication.process_blocking(Forward(sender)))
+    };
+
+    let events: Vec<_> = (0..TOOL_EVENTS)
+        .map(|_| received.recv_timeout(Duration::from_secs(10)).unwrap())
+        .collect();
+    assert!(events.iter().all(|(pid, _)| *pid == TOOL_PID));
+    assert!(received.recv_timeout(Duration::from_millis(100)).is_err());
+
+    communication.stop();
+    processing.join().unwrap().unwrap();
+
+    let statistics = communication.statistics().snapshot();
+    assert_eq!(statistics.events, TOOL_EVENTS);
+    assert_eq!(statistics.lost_batches, 0);
+    assert_eq!(statistics.dropped_events, 0);
+
+    drop(communication);
+    let summary = server.join().unwrap().unwrap();
+    assert_eq!(summary.sent, TOOL_EVENTS);
+    assert_eq!(summary.filtered, 265 - TOOL_EVENTS);
+}
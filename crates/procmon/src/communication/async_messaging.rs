@@ -1,7 +1,14 @@
-use core::{ops::DerefMut, time::Duration};
+use core::{
+    ops::DerefMut,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 use kmum_common::{
-    ClientConnectMessage, KmMessage, KmReplyMessage, UmSendMessage, MAX_KM_MESSAGE_RECEIVE_SIZE,
+    batch::BatchBuilder,
+    handshake::{HandshakeError, ProtocolPreamble, PROTOCOL_MAGIC},
+    ClientConnectMessage, KmMessage, KmReply, KmReplyMessage, RequestError, UmRequest,
+    UmSendMessage, MAX_KM_MESSAGE_RECEIVE_SIZE,
 };
 use nt_string::unicode_string::NtUnicodeStr;
 use wdrf::minifilter::communication::client_communication::{
@@ -30,7 +37,10 @@ use wdrf_std::{
 use super::CommunicationError;
 
 pub trait MessagingCallback {
-    fn on_client(&self, data: Option<ClientConnectMessage>) -> anyhow::Result<()>;
+    fn on_client(
+        &self,
+        data: Option<Result<ClientConnectMessage, HandshakeError>>,
+    ) -> anyhow::Result<()>;
     fn on_message(
         &self,
         message: &UmSendMessage,
@@ -72,8 +82,8 @@ impl AsyncMessaging {
             .try_reserve(num_workers)
             .map_err(|_| CommunicationError::NotEnoughMemory)?;
 
-        for _ in 0..num_workers {
-            let worker = Worker::try_create(communication.clone())?;
+        for worker_id in 0..num_workers {
+            let worker = Worker::try_create(worker_id as _, communication.clone())?;
 
             workers.push(worker);
         }
@@ -114,15 +124,33 @@ impl MessagingPortCallback {
 
         Ok(Self { callback })
     }
+
+    ///
+    /// Clients that don't start with our magic are refused, clients from another
+    /// protocol version are accepted so they can read the mismatch from the handshake
+    ///
+    fn parse_connect_message(
+        buffer: &[u8],
+    ) -> anyhow::Result<Result<ClientConnectMessage, HandshakeError>> {
+        let (preamble, _) = postcard::take_from_bytes::<ProtocolPreamble>(buffer)
+            .map_err(|_| anyhow::Error::msg("Connect message is missing the preamble"))?;
+
+        if preamble.magic != PROTOCOL_MAGIC {
+            maple::error!("Refusing client with magic {:x}", preamble.magic);
+            anyhow::bail!("Invalid connect message magic");
+        }
+
+        if let Err(e) = preamble.check() {
+            return Ok(Err(e));
+        }
+
+        Ok(postcard::from_bytes(buffer).map_err(|_| HandshakeError::Malformed))
+    }
 }
 
 impl FltCommunicationCallback for MessagingPortCallback {
     fn connect(&self, buffer: Option<&[u8]>) -> anyhow::Result<()> {
-        let connect_data = if let Some(buffer) = buffer {
-            postcard::from_bytes(buffer).ok()
-        } else {
-            None
-        };
+        let connect_data = buffer.map(Self::parse_connect_message).transpose()?;
 
         self.callback.on_client(connect_data)
     }
@@ -136,31 +164,32 @@ impl FltCommunicationCallback for MessagingPortCallback {
         input: &[u8],
         output: Option<&mut wdrf_std::slice::tracked_slice::TrackedSlice>,
     ) -> anyhow::Result<()> {
-        let result = &postcard::from_bytes(input);
-
-        match result {
-            Ok(receive) => {
-                let reply = self
-                    .callback
-                    .on_message(receive)
-                    .map_err(|_| anyhow::Error::msg("On message failed"))?;
-
-                if output.is_none() || reply.is_none() {
-                    Ok(())
-                } else {
-                    let reply = reply.unwrap();
-                    let output = output.unwrap();
-
-                    let len = postcard::to_slice(&reply, output.as_slice_mut())
-                        .map_err(|_| anyhow::Error::msg("Failed to trasform output to slice"))
-                        .map(|buf| buf.len())?;
-
-                    output.seek(SeekFrom::Start(len));
-                    Ok(())
-                }
+        //Without an id there is nobody to answer
+        let id =
+            UmRequest::peek_id(input).ok_or_else(|| anyhow::Error::msg("Failed to parse input"))?;
+
+        let result = match postcard::from_bytes::<UmRequest>(input) {
+            Ok(request) => self.callback.on_message(&request.message).map_err(|e| {
+                maple::error!("Failed to answer request {}: {:#?}", id, e);
+                RequestError::Failed
+            }),
+            Err(_) => {
+                maple::error!("Failed to parse request {}", id);
+                Err(RequestError::Malformed)
             }
-            Err(_) => Err(anyhow::Error::msg("Failed to parse input")),
-        }
+        };
+
+        let Some(output) = output else {
+            return Ok(());
+        };
+
+        //Batch replies are cut down to the reply buffer, the client asks again for the rest
+        let len = KmReply::new(id, result)
+            .serialize_fitting(output.as_slice_mut())
+            .map_err(|_| anyhow::Error::msg("Failed to serialize the reply"))?;
+
+        output.seek(SeekFrom::Start(len));
+        Ok(())
     }
 }
 
@@ -172,6 +201,9 @@ struct WorkerInternal {
     //Todo: Maybe try make it a Box for faster performance
     items: StackSpinMutex<VecDeque<DummyKmMessage>>,
     stop_event: KeEvent,
+    id: u16,
+    //Reported to usermode in every batch header
+    dropped: AtomicU64,
 }
 unsafe impl Send for WorkerInternal {}
 unsafe impl Sync for WorkerInternal {}
@@ -188,11 +220,14 @@ impl TaggedObject for Worker {}
 
 impl Worker {
     pub fn try_create(
+        id: u16,
         communication: Arc<FltClientCommunication<MessagingPortCallback>>,
     ) -> anyhow::Result<Self, CommunicationError> {
         let internal = Arc::try_create(WorkerInternal {
             items: StackSpinMutex::new(VecDeque::create()),
             stop_event: unsafe { KeEvent::new() },
+            id,
+            dropped: AtomicU64::new(0),
         })
         .map_err(|_| CommunicationError::NotEnoughMemory)?;
 
@@ -226,8 +261,9 @@ impl Worker {
             .try_resize(MAX_KM_MESSAGE_RECEIVE_SIZE, 0)
             .expect("Failed to resize worker buffer");
 
-        //Send only every 30ms or when the buffer is 75% full
-
+        //Send every 15ms or as soon as the buffer is full
+        let mut sequence = 0;
+        let mut builder = BatchBuilder::new();
         let mut items: VecDeque<DummyKmMessage> = VecDeque::create();
         loop {
             let result = internal.stop_event.wait_for(Duration::from_millis(15));
@@ -246,37 +282,54 @@ impl Worker {
                 core::mem::swap(guard.deref_mut(), &mut items);
             }
 
-            let mut offset = 0;
-            let mut serliazed_items_count = 0;
             for item in items.drain(..).map(|item| item.0) {
-                match postcard::to_slice(&item, &mut buffer[offset..]) {
-                    Ok(serialize_slice) => {
-                        offset += serialize_slice.len();
-                        serliazed_items_count += 1;
-                    }
-                    Err(_) => {
-                        maple::info!(
-                            "Flushing {serliazed_items_count} serialized items to usermode"
-                        );
-                        let _ = communication.send_message(&buffer[..offset], Timeout::infinite());
-                        offset = 0;
-
-                        if let Ok(serialized_slice) = postcard::to_slice(&item, &mut buffer) {
-                            offset += serialized_slice.len();
-                        } else {
-                            maple::error!("Double serialization error for item");
-                        }
-                    }
+                if builder.try_push(&mut buffer, &item).is_ok() {
+                    continue;
+                }
+
+                if !builder.is_empty() {
+                    maple::info!(
+                        "Flushing {} serialized items to usermode",
+                        builder.event_count()
+                    );
+                    Self::send_batch(communication, internal, &mut builder, &mut buffer, sequence);
+                    sequence += 1;
+                }
+
+                if builder.try_push(&mut buffer, &item).is_err() {
+                    maple::error!("Double serialization error for item");
+                    internal.dropped.fetch_add(1, Ordering::Relaxed);
                 }
             }
 
-            if offset != 0 {
-                maple::info!("Send {serliazed_items_count} remaining serialized items to usermode");
-                let _ = communication.send_message(&buffer[..offset], Timeout::infinite());
+            if !builder.is_empty() {
+                maple::info!(
+                    "Send {} remaining serialized items to usermode",
+                    builder.event_count()
+                );
+                Self::send_batch(communication, internal, &mut builder, &mut buffer, sequence);
+                sequence += 1;
             }
         }
     }
 
+    fn send_batch(
+        communication: &FltClientCommunication<MessagingPortCallback>,
+        internal: &WorkerInternal,
+        builder: &mut BatchBuilder,
+        buffer: &mut [u8],
+        sequence: u64,
+    ) {
+        let batch = builder.finish(
+            buffer,
+            internal.id,
+            sequence,
+            internal.dropped.load(Ordering::Relaxed),
+        );
+
+        let _ = communication.send_message(batch, Timeout::infinite());
+    }
+
     #[inline]
     fn try_push_event(&self, message: KmMessage) -> anyhow::Result<(), CommunicationError> {
         let handle = InStackLockHandle::new();
@@ -286,7 +339,10 @@ impl Worker {
             .lock(&handle)
             .try_push_back(DummyKmMessage(message));
 
-        result.map_err(|_| CommunicationError::NotEnoughMemory)
+        result.map_err(|_| {
+            self.internal.dropped.fetch_add(1, Ordering::Relaxed);
+            CommunicationError::NotEnoughMemory
+        })
     }
 }
 
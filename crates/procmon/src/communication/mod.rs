@@ -1,16 +1,23 @@
 use core::{
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
     u64,
 };
 
+use alloc::vec::Vec;
 use async_messaging::{AsyncMessaging, MessagingCallback};
 use kmum_common::{
-    get_communication_port_name, serializable_ntstring::SerializableNtString, ClientConnectMessage,
-    KmMessage, KmReplyMessage,
+    get_communication_port_name,
+    handshake::{negotiate, BufferSizes, DriverHandshake, EventCapabilities, HandshakeError},
+    serializable_ntstring::SerializableNtString,
+    ClientConnectMessage, ClientConnectMode, KmMessage, KmReplyMessage,
 };
 use maple::{error, info};
 use nt_string::unicode_string::{NtUnicodeStr, NtUnicodeString};
-use wdrf_std::kmalloc::TaggedObject;
+use wdrf_std::{
+    kmalloc::TaggedObject,
+    sync::{InStackLockHandle, StackSpinMutex},
+    traits::DispatchSafe,
+};
 
 use crate::global::DRIVER_CONTEXT;
 
@@ -26,9 +33,18 @@ pub enum CommunicationError {
     PortError,
 }
 
+/// Event classes this driver is able to produce
+pub const DRIVER_CAPABILITIES: EventCapabilities =
+    EventCapabilities::PROCESS.union(EventCapabilities::FILE_SYSTEM);
+
+struct NegotiatedHandshake(Option<Result<DriverHandshake, HandshakeError>>);
+unsafe impl DispatchSafe for NegotiatedHandshake {}
+
 pub struct Communication {
     messaging: AsyncMessaging,
     filter_test_pid: AtomicU64,
+    capabilities: AtomicU32,
+    handshake: StackSpinMutex<NegotiatedHandshake>,
 }
 
 struct CommunicationCallback {}
@@ -47,10 +63,18 @@ impl Communication {
         Ok(Self {
             messaging,
             filter_test_pid: AtomicU64::new(u64::MAX),
+            capabilities: AtomicU32::new(0),
+            handshake: StackSpinMutex::new(NegotiatedHandshake(None)),
         })
     }
 
     pub fn try_send_event(&self, message: KmMessage) -> anyhow::Result<(), CommunicationError> {
+        let capabilities =
+            EventCapabilities::from_bits_retain(self.capabilities.load(Ordering::Acquire));
+        if !capabilities.contains(message.event.operation.capability()) {
+            return Ok(());
+        }
+
         let filter_pid = self.filter_test_pid.load(Ordering::Acquire);
         if filter_pid != 0 && filter_pid != message.process.pid {
             Ok(())
@@ -59,30 +83,60 @@ impl Communication {
         }
     }
 
+    fn set_handshake(&self, handshake: Option<Result<DriverHandshake, HandshakeError>>) {
+        let capabilities = match &handshake {
+            Some(Ok(handshake)) => handshake.capabilities.bits(),
+            _ => 0,
+        };
+
+        let lock = InStackLockHandle::new();
+        self.handshake.lock(&lock).0 = handshake;
+        self.capabilities.store(capabilities, Ordering::Release);
+    }
+
+    fn handshake(&self) -> Result<DriverHandshake, HandshakeError> {
+        let lock = InStackLockHandle::new();
+        let guard = self.handshake.lock(&lock);
+
+        guard.0.unwrap_or(Err(HandshakeError::Malformed))
+    }
+
     pub fn stop(&self) {
         self.messaging.stop();
     }
 }
 
 impl MessagingCallback for CommunicationCallback {
-    fn on_client(&self, data: Option<ClientConnectMessage>) -> anyhow::Result<()> {
+    fn on_client(
+        &self,
+        data: Option<Result<ClientConnectMessage, HandshakeError>>,
+    ) -> anyhow::Result<()> {
         info!("Client connected {:?}", data);
 
-        if let Some(data) = data {
-            match data {
-                ClientConnectMessage::Testing { filter_pid } => {
-                    DRIVER_CONTEXT
-                        .get()
-                        .communication
-                        .filter_test_pid
-                        .store(filter_pid, Ordering::Release);
-                }
-                ClientConnectMessage::Any => DRIVER_CONTEXT
-                    .get()
-                    .communication
+        let communication = &DRIVER_CONTEXT.get().communication;
+        let handshake = data.map(|data| {
+            data.and_then(|data| {
+                negotiate(&data, DRIVER_CAPABILITIES, BufferSizes::current())
+                    .map(|handshake| (data.mode, handshake))
+            })
+        });
+
+        match handshake {
+            Some(Ok((mode, handshake))) => {
+                let filter_pid = match mode {
+                    ClientConnectMode::Testing { filter_pid } => filter_pid,
+                    ClientConnectMode::Any => 0,
+                };
+                communication
                     .filter_test_pid
-                    .store(0, Ordering::Release),
+                    .store(filter_pid, Ordering::Release);
+                communication.set_handshake(Some(Ok(handshake)));
+            }
+            Some(Err(e)) => {
+                error!("Client handshake failed: {:?}", e);
+                communication.set_handshake(Some(Err(e)));
             }
+            None => communication.set_handshake(None),
         }
 
         Ok(())
@@ -104,55 +158,81 @@ impl MessagingCallback for CommunicationCallback {
                 Ok(process_info.map(|info| KmReplyMessage::ProcessInfo(info)))
             }
             kmum_common::UmSendMessage::GetExeName(unique_id) => {
-                let process_info = DRIVER_CONTEXT
-                    .get()
-                    .process_cache
-                    .get_process_info_from_uid(*unique_id);
-
-                maple::info!("GetExeName for uid: {unique_id} -> {:?}", process_info);
-
-                if process_info.is_none() {
-                    Ok(None)
-                } else {
-                    let process_info = process_info.unwrap();
-                    let nt_string = process_info.path.0.as_u16str();
-
-                    let mut pos = None;
-                    for (i, c) in nt_string.char_indices_lossy() {
-                        if c == '\\' || c == '/' {
-                            pos = Some(i);
-                        }
-                    }
-
-                    let path = if let Some(pos) = pos {
-                        nt_string.split_at(pos + 1).1
-                    } else {
-                        nt_string
-                    };
-
-                    let process_name = NtUnicodeString::try_from(path);
-
-                    match process_name {
-                        Ok(name) => Ok(Some(KmReplyMessage::ExeName(SerializableNtString::new(
-                            name,
-                        )))),
-                        Err(_) => Ok(None),
-                    }
-                }
+                Ok(exe_name(*unique_id).map(KmReplyMessage::ExeName))
+            }
+            kmum_common::UmSendMessage::GetProcessInfoBatch(unique_ids) => {
+                let process_cache = &DRIVER_CONTEXT.get().process_cache;
+
+                let mut infos = Vec::new();
+                infos
+                    .try_reserve_exact(unique_ids.len())
+                    .map_err(|_| CommunicationError::NotEnoughMemory)?;
+                infos.extend(
+                    unique_ids
+                        .iter()
+                        .map(|unique_id| process_cache.get_process_info_from_uid(*unique_id)),
+                );
+
+                Ok(Some(KmReplyMessage::ProcessInfoBatch(infos)))
             }
+            kmum_common::UmSendMessage::GetExeNameBatch(unique_ids) => {
+                let mut names = Vec::new();
+                names
+                    .try_reserve_exact(unique_ids.len())
+                    .map_err(|_| CommunicationError::NotEnoughMemory)?;
+                names.extend(unique_ids.iter().map(|unique_id| exe_name(*unique_id)));
+
+                Ok(Some(KmReplyMessage::ExeNameBatch(names)))
+            }
+            kmum_common::UmSendMessage::Handshake => Ok(Some(KmReplyMessage::Handshake(
+                DRIVER_CONTEXT.get().communication.handshake(),
+            ))),
         }
     }
 
     fn on_disconnect(&self) {
         info!("Client disconnected");
-        DRIVER_CONTEXT
-            .get()
-            .communication
+        let communication = &DRIVER_CONTEXT.get().communication;
+
+        communication
             .filter_test_pid
             .store(u64::MAX, Ordering::Release);
+        communication.set_handshake(None);
     }
 }
 
+///
+/// File name part of the image path of a process
+///
+fn exe_name(unique_id: u64) -> Option<SerializableNtString> {
+    let process_info = DRIVER_CONTEXT
+        .get()
+        .process_cache
+        .get_process_info_from_uid(unique_id);
+
+    maple::info!("GetExeName for uid: {unique_id} -> {:?}", process_info);
+
+    let process_info = process_info?;
+    let nt_string = process_info.path.0.as_u16str();
+
+    let mut pos = None;
+    for (i, c) in nt_string.char_indices_lossy() {
+        if c == '\\' || c == '/' {
+            pos = Some(i);
+        }
+    }
+
+    let path = if let Some(pos) = pos {
+        nt_string.split_at(pos + 1).1
+    } else {
+        nt_string
+    };
+
+    NtUnicodeString::try_from(path)
+        .ok()
+        .map(SerializableNtString::new)
+}
+
 impl TaggedObject for CommunicationCallback {
     fn tag() -> wdrf_std::kmalloc::MemoryTag {
         wdrf_std::kmalloc::MemoryTag::new_from_bytes(b"cocb")
@@ -26,10 +26,23 @@ pub const SYSTEM_PROCESS_INFORMATION_CLASS: u32 = 5;
 type ZwQuerySystemInformationFn =
     unsafe extern "system" fn(u32, *mut core::ffi::c_void, u32, *mut u32) -> NTSTATUS;
 
+type ZwQueryInformationThreadFn =
+    unsafe extern "system" fn(HANDLE, u32, *mut core::ffi::c_void, u32, *mut u32) -> NTSTATUS;
+
+/// Start address the thread was created with, as passed to CreateThread
+pub const THREAD_QUERY_SET_WIN32_START_ADDRESS: u32 = 9;
+
+type RtlWalkFrameChainFn = unsafe extern "system" fn(*mut *mut core::ffi::c_void, u32, u32) -> u32;
+
+/// Walks the user mode stack of the current thread instead of the kernel one
+pub const RTL_WALK_USER_MODE_STACK: u32 = 1;
+
 pub struct DynFncImports {
     fn_zw_query_information_process: ZwQueryInformationProcessFn,
     fn_ps_get_process_inherited_from_unique_process_id: PsGetProcessInheritedFromUniqueProcessIdFn,
     fn_zw_query_system_information: ZwQuerySystemInformationFn,
+    fn_rtl_walk_frame_chain: RtlWalkFrameChainFn,
+    fn_zw_query_information_thread: ZwQueryInformationThreadFn,
 }
 
 #[repr(i32)]
@@ -116,10 +129,32 @@ impl DynFncImports {
             }
         };
 
+        let rtl_walk_frame_chain = {
+            let fnc_ptr = Self::load_fnc(widestring::u16cstr!("RtlWalkFrameChain"));
+
+            if let Some(ptr) = fnc_ptr {
+                unsafe { core::mem::transmute::<*mut c_void, RtlWalkFrameChainFn>(ptr) }
+            } else {
+                return Err(anyhow::anyhow!("Failed to load RtlWalkFrameChain")).into();
+            }
+        };
+
+        let zw_query_information_thread = {
+            let fnc_ptr = Self::load_fnc(widestring::u16cstr!("ZwQueryInformationThread"));
+
+            if let Some(ptr) = fnc_ptr {
+                unsafe { core::mem::transmute::<*mut c_void, ZwQueryInformationThreadFn>(ptr) }
+            } else {
+                return Err(anyhow::anyhow!("Failed to load ZwQueryInformationThread")).into();
+            }
+        };
+
         DYN_IMPORTS.init(registry, move || DynFncImports {
             fn_zw_query_information_process: zw_query_info,
             fn_ps_get_process_inherited_from_unique_process_id: ps_inherited_process_id,
             fn_zw_query_system_information: zw_query_system_information,
+            fn_rtl_walk_frame_chain: rtl_walk_frame_chain,
+            fn_zw_query_information_thread: zw_query_information_thread,
         })
     }
 
@@ -161,4 +196,30 @@ impl DynFncImports {
             return_lenght,
         )
     }
+
+    pub unsafe fn rtl_walk_frame_chain(
+        &self,
+        callers: *mut *mut core::ffi::c_void,
+        count: u32,
+        flags: u32,
+    ) -> u32 {
+        (self.fn_rtl_walk_frame_chain)(callers, count, flags)
+    }
+
+    pub unsafe fn zw_query_information_thread(
+        &self,
+        thread_handle: HANDLE,
+        thread_information_class: u32,
+        thread_information: *mut core::ffi::c_void,
+        thread_information_length: u32,
+        return_length: *mut u32,
+    ) -> NTSTATUS {
+        (self.fn_zw_query_information_thread)(
+            thread_handle,
+            thread_information_class,
+            thread_information,
+            thread_information_length,
+            return_length,
+        )
+    }
 }
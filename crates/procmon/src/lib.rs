@@ -2,6 +2,8 @@
 #![feature(let_chains)]
 #![allow(unused_attributes)]
 
+extern crate alloc;
+
 use communication::Communication;
 use global::{DriverContext, CONTEXT_REGISTRY, DBGPRINT_LOGGER, DRIVER_CONTEXT};
 use imports::DynFncImports;
@@ -31,6 +33,7 @@ pub mod imports;
 pub mod minifilter;
 pub mod panic;
 pub mod pscollector;
+pub mod stack;
 
 fn driver_main(driver: &mut DRIVER_OBJECT, _registry_path: &UNICODE_STRING) -> anyhow::Result<()> {
     dbg_break();
@@ -77,6 +80,13 @@ fn setup_filter(driver: &mut DRIVER_OBJECT) -> anyhow::Result<()> {
         FltOperationEntry::new(FltOperationType::Write, 0),
         FltOperationEntry::new(FltOperationType::Read, 0),
         FltOperationEntry::new(FltOperationType::Close, 0),
+        FltOperationEntry::new(FltOperationType::QueryInformation, 0),
+        FltOperationEntry::new(FltOperationType::SetInformation, 0),
+        FltOperationEntry::new(FltOperationType::DirectoryControl, 0),
+        FltOperationEntry::new(FltOperationType::FileSystemControl, 0),
+        FltOperationEntry::new(FltOperationType::LockControl, 0),
+        FltOperationEntry::new(FltOperationType::Cleanup, 0),
+        FltOperationEntry::new(FltOperationType::FlushBuffers, 0),
     ];
 
     MinifilterFrameworkBuilder::new_with_context(
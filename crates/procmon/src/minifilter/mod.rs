@@ -1,3 +1,4 @@
+mod parameters;
 mod preop;
 
 pub use preop::*;
@@ -0,0 +1,199 @@
+use core::mem::{offset_of, size_of};
+
+use kmum_common::{
+    event::{EventFileSystemOperation, FileLockOperation, FileSetInformation},
+    serializable_ntstring::SerializableNtString,
+};
+use nt_string::unicode_string::NtUnicodeString;
+use windows_sys::{
+    Wdk::{
+        Storage::FileSystem::{
+            Minifilters::{FLT_CALLBACK_DATA, FLT_PARAMETERS_27},
+            FILE_ALLOCATION_INFORMATION, FILE_BASIC_INFORMATION, FILE_DISPOSITION_DELETE,
+            FILE_DISPOSITION_INFORMATION, FILE_DISPOSITION_INFORMATION_EX, FILE_RENAME_INFORMATION,
+            FILE_RENAME_REPLACE_IF_EXISTS,
+        },
+        System::SystemServices::{
+            FILE_END_OF_FILE_INFORMATION, IRP_MJ_CLEANUP, IRP_MJ_CREATE, IRP_MJ_DIRECTORY_CONTROL,
+            IRP_MJ_FILE_SYSTEM_CONTROL, IRP_MJ_FLUSH_BUFFERS, IRP_MJ_LOCK_CONTROL,
+            IRP_MJ_QUERY_INFORMATION, IRP_MJ_SET_INFORMATION, IRP_MN_QUERY_DIRECTORY,
+        },
+    },
+    Win32::System::WindowsProgramming::FILE_INFORMATION_CLASS,
+};
+
+const FILE_BASIC_INFORMATION_CLASS: FILE_INFORMATION_CLASS = 4;
+const FILE_RENAME_INFORMATION_CLASS: FILE_INFORMATION_CLASS = 10;
+const FILE_DISPOSITION_INFORMATION_CLASS: FILE_INFORMATION_CLASS = 13;
+const FILE_ALLOCATION_INFORMATION_CLASS: FILE_INFORMATION_CLASS = 19;
+const FILE_END_OF_FILE_INFORMATION_CLASS: FILE_INFORMATION_CLASS = 20;
+const FILE_DISPOSITION_INFORMATION_EX_CLASS: FILE_INFORMATION_CLASS = 64;
+const FILE_RENAME_INFORMATION_EX_CLASS: FILE_INFORMATION_CLASS = 65;
+
+///
+/// Decodes the operations `FltParameters` does not expose straight from the callback data.
+///
+/// Must be called from the post operation, the open action of a create is only known once it completed.
+/// Returns `None` for operations that are not reported, like directory change notifications
+///
+/// # Safety
+///
+/// `data` must be the callback data of the operation being completed
+///
+pub unsafe fn map_raw_operation(data: &FLT_CALLBACK_DATA) -> Option<EventFileSystemOperation> {
+    let iopb = &*data.Iopb;
+    let parameters = &iopb.Parameters;
+
+    let operation = match iopb.MajorFunction as u32 {
+        IRP_MJ_CREATE => {
+            let create = &parameters.Create;
+            let desired_access = create
+                .SecurityContext
+                .as_ref()
+                .map_or(0, |context| context.DesiredAccess);
+
+            EventFileSystemOperation::Create {
+                desired_access,
+                share_mode: create.ShareAccess,
+                disposition: create.Options >> 24,
+                options: create.Options & 0x00FF_FFFF,
+                attribute: create.FileAttributes,
+                open_action: data.IoStatus.Information as _,
+            }
+        }
+        IRP_MJ_QUERY_INFORMATION => EventFileSystemOperation::QueryInformation {
+            information_class: parameters.QueryFileInformation.FileInformationClass as _,
+            length: parameters.QueryFileInformation.Length,
+        },
+        IRP_MJ_SET_INFORMATION => EventFileSystemOperation::SetInformation {
+            information: map_set_information(&parameters.SetFileInformation),
+        },
+        IRP_MJ_DIRECTORY_CONTROL if iopb.MinorFunction as u32 == IRP_MN_QUERY_DIRECTORY => {
+            let query = &parameters.DirectoryControl.QueryDirectory;
+            let pattern = query
+                .FileName
+                .as_ref()
+                .filter(|name| !name.Buffer.is_null())
+                .and_then(|name| {
+                    let name = core::slice::from_raw_parts(name.Buffer, name.Length as usize / 2);
+                    NtUnicodeString::try_from_u16(name).ok()
+                })
+                .map_or_else(SerializableNtString::empty, SerializableNtString::new);
+
+            EventFileSystemOperation::QueryDirectory {
+                pattern,
+                information_class: query.FileInformationClass as _,
+                length: query.Length,
+            }
+        }
+        IRP_MJ_FILE_SYSTEM_CONTROL => {
+            let control = &parameters.FileSystemControl.Common;
+
+            EventFileSystemOperation::FileSystemControl {
+                control_code: control.FsControlCode,
+                input_length: control.InputBufferLength,
+                output_length: control.OutputBufferLength,
+            }
+        }
+        IRP_MJ_LOCK_CONTROL => {
+            let lock = &parameters.LockControl;
+
+            EventFileSystemOperation::LockControl {
+                operation: FileLockOperation::from_minor_function(iopb.MinorFunction),
+                offset: lock.ByteOffset,
+                length: lock.Length.as_ref().copied().unwrap_or(0),
+                exclusive: lock.ExclusiveLock != 0,
+            }
+        }
+        IRP_MJ_CLEANUP => EventFileSystemOperation::Cleanup {},
+        IRP_MJ_FLUSH_BUFFERS => EventFileSystemOperation::FlushBuffers {},
+        _ => return None,
+    };
+
+    Some(operation)
+}
+
+///
+/// The information buffer is the system buffer of the request, it is only read
+/// when it is large enough for the structure of its class
+///
+unsafe fn map_set_information(parameters: &FLT_PARAMETERS_27) -> FileSetInformation {
+    let class = parameters.FileInformationClass;
+    let length = parameters.Length as usize;
+    let buffer = parameters.InfoBuffer;
+
+    let other = FileSetInformation::Other {
+        information_class: class as _,
+        length: parameters.Length,
+    };
+
+    let fits = |size: usize| !buffer.is_null() && length >= size;
+
+    match class {
+        FILE_BASIC_INFORMATION_CLASS if fits(size_of::<FILE_BASIC_INFORMATION>()) => {
+            let basic = &*(buffer as *const FILE_BASIC_INFORMATION);
+
+            FileSetInformation::Basic {
+                creation_time: basic.CreationTime,
+                last_access_time: basic.LastAccessTime,
+                last_write_time: basic.LastWriteTime,
+                change_time: basic.ChangeTime,
+                attributes: basic.FileAttributes,
+            }
+        }
+        FILE_RENAME_INFORMATION_CLASS | FILE_RENAME_INFORMATION_EX_CLASS
+            if fits(offset_of!(FILE_RENAME_INFORMATION, FileName)) =>
+        {
+            let rename = &*(buffer as *const FILE_RENAME_INFORMATION);
+            let name_offset = offset_of!(FILE_RENAME_INFORMATION, FileName);
+            let name_length = rename.FileNameLength as usize;
+
+            if length - name_offset < name_length {
+                return other;
+            }
+
+            let replace_if_exists = if class == FILE_RENAME_INFORMATION_EX_CLASS {
+                rename.Anonymous.Flags & FILE_RENAME_REPLACE_IF_EXISTS != 0
+            } else {
+                rename.Anonymous.ReplaceIfExists != 0
+            };
+
+            let name = core::slice::from_raw_parts(
+                (buffer as *const u8).add(name_offset) as *const u16,
+                name_length / 2,
+            );
+            let target = NtUnicodeString::try_from_u16(name)
+                .map_or_else(|_| SerializableNtString::empty(), SerializableNtString::new);
+
+            FileSetInformation::Rename {
+                target,
+                replace_if_exists,
+            }
+        }
+        FILE_DISPOSITION_INFORMATION_CLASS if fits(size_of::<FILE_DISPOSITION_INFORMATION>()) => {
+            FileSetInformation::Disposition {
+                delete: (*(buffer as *const FILE_DISPOSITION_INFORMATION)).DeleteFile != 0,
+            }
+        }
+        FILE_DISPOSITION_INFORMATION_EX_CLASS
+            if fits(size_of::<FILE_DISPOSITION_INFORMATION_EX>()) =>
+        {
+            let flags = (*(buffer as *const FILE_DISPOSITION_INFORMATION_EX)).Flags;
+
+            FileSetInformation::Disposition {
+                delete: flags & FILE_DISPOSITION_DELETE != 0,
+            }
+        }
+        FILE_END_OF_FILE_INFORMATION_CLASS if fits(size_of::<FILE_END_OF_FILE_INFORMATION>()) => {
+            FileSetInformation::EndOfFile {
+                end_of_file: (*(buffer as *const FILE_END_OF_FILE_INFORMATION)).EndOfFile,
+            }
+        }
+        FILE_ALLOCATION_INFORMATION_CLASS if fits(size_of::<FILE_ALLOCATION_INFORMATION>()) => {
+            FileSetInformation::Allocation {
+                allocation_size: (*(buffer as *const FILE_ALLOCATION_INFORMATION)).AllocationSize,
+            }
+        }
+        _ => other,
+    }
+}
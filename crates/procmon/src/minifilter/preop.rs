@@ -7,19 +7,18 @@ use kmum_common::{
 };
 use nt_string::unicode_string::NtUnicodeString;
 use wdrf::minifilter::filter::{
-    params::FltParameters, FileNameInformation, FltPostOpCallback, FltPreOpCallback, PostOpContext,
-    PostOpStatus, PreOpStatus,
+    params::FltParameters, FileNameInformation, FltCallbackData, FltPostOpCallback,
+    FltPreOpCallback, PostOpContext, PostOpStatus, PreOpStatus,
 };
 use wdrf_std::{kmalloc::TaggedObject, time::SystemTime};
-use windows_sys::{
-    Wdk::{
-        Storage::FileSystem::Minifilters::FltGetRequestorProcessId,
-        System::SystemServices::PsGetCurrentThreadId,
-    },
-    Win32::Foundation::STATUS_SUCCESS,
+use windows_sys::Wdk::{
+    Storage::FileSystem::Minifilters::FltGetRequestorProcessId,
+    System::SystemServices::PsGetCurrentThreadId,
 };
 
-use crate::global::DRIVER_CONTEXT;
+use crate::{global::DRIVER_CONTEXT, stack::capture_current_stack};
+
+use super::parameters::map_raw_operation;
 
 pub struct ProcmonMinifilterCallback;
 
@@ -32,6 +31,8 @@ pub struct PostCallbackContext {
     pre_time: SystemTime,
     path: NtUnicodeString,
     uid: u64,
+    //Post operations may run on an arbitrary thread
+    stack: EventStack,
 }
 
 unsafe impl Send for PostCallbackContext {}
@@ -62,6 +63,7 @@ impl<'a> FltPreOpCallback<'a> for ProcmonMinifilterCallback {
                 uid,
                 pre_time: SystemTime::new(),
                 path,
+                stack: capture_current_stack(),
             })
         {
             PreOpStatus::SuccessWithCallback(Some(context))
@@ -86,17 +88,20 @@ impl<'a> FltPostOpCallback<'a> for ProcmonMinifilterCallback {
             uid: uid,
             pre_time: preop_time,
             path: path,
+            stack: stack,
         } = context.unwrap().unwrap();
 
         let pid = unsafe { FltGetRequestorProcessId(data.raw_struct()) } as u64;
-        let op = Self::map_minifilter_param_to_event_op(&params);
+        let Some(op) = Self::map_minifilter_param_to_event_op(&data, &params) else {
+            return PostOpStatus::FinishProcessing;
+        };
 
         let event = KmMessage {
             event: EventCompoent {
                 date: SystemTime::new().raw_time(),
                 thread: unsafe { PsGetCurrentThreadId() as _ },
                 operation: EventClass::FileSystem(op),
-                result: STATUS_SUCCESS,
+                result: unsafe { (*data.raw_struct()).IoStatus.Anonymous.Status },
                 path: SerializableNtString::new(path),
                 duration: SystemTime::new().raw_time() - preop_time.raw_time(),
             },
@@ -104,7 +109,7 @@ impl<'a> FltPostOpCallback<'a> for ProcmonMinifilterCallback {
                 pid,
                 unique_id: uid,
             },
-            stack: EventStack::new(),
+            stack,
         };
 
         let _ = communication.try_send_event(event);
@@ -114,20 +119,21 @@ impl<'a> FltPostOpCallback<'a> for ProcmonMinifilterCallback {
 }
 
 impl ProcmonMinifilterCallback {
-    fn map_minifilter_param_to_event_op(param: &FltParameters) -> EventFileSystemOperation {
+    fn map_minifilter_param_to_event_op(
+        data: &FltCallbackData,
+        param: &FltParameters,
+    ) -> Option<EventFileSystemOperation> {
         match param {
-            FltParameters::Create(flt_create_request) => EventFileSystemOperation::Create {
-                attribute: flt_create_request.attributes(),
-            },
-            FltParameters::Read(flt_read_file_request) => EventFileSystemOperation::Read {
+            FltParameters::Read(flt_read_file_request) => Some(EventFileSystemOperation::Read {
                 length: flt_read_file_request.len() as _,
                 offset: flt_read_file_request.offset(),
-            },
-            FltParameters::Write(flt_write_file_request) => EventFileSystemOperation::Write {
+            }),
+            FltParameters::Write(flt_write_file_request) => Some(EventFileSystemOperation::Write {
                 length: flt_write_file_request.len() as _,
                 offset: flt_write_file_request.offset(),
-            },
-            FltParameters::Close(flt_close_file_request) => EventFileSystemOperation::Close {},
+            }),
+            FltParameters::Close(_) => Some(EventFileSystemOperation::Close {}),
+            _ => unsafe { map_raw_operation(&*data.raw_struct()) },
         }
     }
 }
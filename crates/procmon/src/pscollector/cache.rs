@@ -1,5 +1,5 @@
 use kmum_common::{
-    event::{EventClass, EventCompoent, EventProcessOperation, EventStack, SimpleProcessDetails},
+    event::{EventClass, EventCompoent, EventProcessOperation, SimpleProcessDetails},
     process::{ProcessInformation, UniqueProcessId},
     serializable_ntstring::SerializableNtString,
     KmMessage,
@@ -19,7 +19,7 @@ use wdrf_std::{
     vec::{Vec, VecCreate, VecExt},
 };
 use windows_sys::{
-    Wdk::System::SystemServices::PsGetCurrentThreadId,
+    Wdk::System::SystemServices::{PsGetCurrentThreadId, PsGetProcessExitStatus},
     Win32::{
         Foundation::{STATUS_INFO_LENGTH_MISMATCH, STATUS_SUCCESS},
         System::WindowsProgramming::SYSTEM_PROCESS_INFORMATION,
@@ -29,13 +29,18 @@ use windows_sys::{
 use crate::{
     global::DRIVER_CONTEXT,
     imports::{DYN_IMPORTS, SYSTEM_PROCESS_INFORMATION_CLASS},
+    stack::capture_current_stack,
 };
 
-use super::{collection::PsInfoContainer, proc_info_factory::ProcessInformationFactory};
+use super::{
+    collection::PsInfoContainer, notifiers::ThreadImageNotifier,
+    proc_info_factory::ProcessInformationFactory,
+};
 
 pub struct ProcessCollectorCache {
     container: PsInfoContainer,
     ps_notifier: PsNotifierRegistration<CacheNotifierCallback>,
+    thread_image_notifier: ThreadImageNotifier,
 }
 
 unsafe impl Send for ProcessCollectorCache {}
@@ -49,6 +54,7 @@ impl ProcessCollectorCache {
         Ok(Self {
             container: PsInfoContainer::create(),
             ps_notifier,
+            thread_image_notifier: ThreadImageNotifier::new(),
         })
     }
 
@@ -57,10 +63,15 @@ impl ProcessCollectorCache {
             .try_start()
             .map_err(|_| anyhow::Error::msg("Failed to start process collector cache"))?;
 
-        self.scan_unmonitored()
+        self.scan_unmonitored()?;
+        self.thread_image_notifier.start();
+
+        Ok(())
     }
 
     pub fn try_stop(&self) -> anyhow::Result<()> {
+        self.thread_image_notifier.stop();
+
         self.ps_notifier
             .try_stop()
             .map_err(|_| anyhow::Error::msg("Failed to stop process collector cache"))
@@ -230,7 +241,7 @@ impl PsCreateNotifyCallback for CacheNotifierCallback {
                     pid,
                     unique_id: uid,
                 },
-                stack: EventStack::new(),
+                stack: capture_current_stack(),
             };
 
             let _ = DRIVER_CONTEXT.get().communication.try_send_event(event);
@@ -252,7 +263,13 @@ impl PsCreateNotifyCallback for CacheNotifierCallback {
             let process_info = cache.get_process_info_from_uid(uid);
 
             if let Some(process_info) = process_info {
-                let op: EventProcessOperation = EventProcessOperation::ProcessDestroy { pid };
+                //The process object is still alive while its exit is being notified
+                let exit_status = ps_lookup_by_process_id(pid as _)
+                    .map(|eprocess| unsafe { PsGetProcessExitStatus(eprocess.as_raw_obj() as _) })
+                    .unwrap_or(STATUS_SUCCESS);
+
+                let op: EventProcessOperation =
+                    EventProcessOperation::ProcessDestroy { pid, exit_status };
                 let event = KmMessage {
                     event: EventCompoent {
                         date: SystemTime::new().raw_time(),
@@ -266,7 +283,7 @@ impl PsCreateNotifyCallback for CacheNotifierCallback {
                         pid,
                         unique_id: uid,
                     },
-                    stack: EventStack::new(),
+                    stack: capture_current_stack(),
                 };
 
                 let _ = DRIVER_CONTEXT.get().communication.try_send_event(event);
@@ -1,5 +1,6 @@
 mod cache;
 mod collection;
+mod notifiers;
 mod proc_info_factory;
 
 pub use cache::*;
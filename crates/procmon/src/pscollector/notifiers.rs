@@ -0,0 +1,164 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kmum_common::{
+    event::{EventClass, EventCompoent, EventProcessOperation, SimpleProcessDetails},
+    serializable_ntstring::SerializableNtString,
+    KmMessage,
+};
+use nt_string::unicode_string::NtUnicodeString;
+use wdrf_std::{nt_success, time::SystemTime};
+use windows_sys::{
+    Wdk::System::SystemServices::{
+        PsCreateThreadNotifyNonSystem, PsGetCurrentThreadId, PsRemoveCreateThreadNotifyRoutine,
+        PsRemoveLoadImageNotifyRoutine, PsSetCreateThreadNotifyRoutineEx,
+        PsSetLoadImageNotifyRoutine, IMAGE_INFO,
+    },
+    Win32::Foundation::{BOOLEAN, HANDLE, STATUS_SUCCESS, UNICODE_STRING},
+};
+
+use crate::{
+    global::DRIVER_CONTEXT,
+    imports::{DYN_IMPORTS, THREAD_QUERY_SET_WIN32_START_ADDRESS},
+    stack::capture_current_stack,
+};
+
+//Kernel images are reported with a null process id
+const SYSTEM_PROCESS_ID: u64 = 4;
+const NT_CURRENT_THREAD: HANDLE = -2;
+
+///
+/// Thread and image load notifications, both are optional so a failure
+/// to register one of them does not stop the process collector
+///
+pub struct ThreadImageNotifier {
+    thread_registered: AtomicBool,
+    image_registered: AtomicBool,
+}
+
+impl ThreadImageNotifier {
+    pub const fn new() -> Self {
+        Self {
+            thread_registered: AtomicBool::new(false),
+            image_registered: AtomicBool::new(false),
+        }
+    }
+
+    pub fn start(&self) {
+        //Non system notifications run on the new thread, so its start address can be queried
+        let status = unsafe {
+            PsSetCreateThreadNotifyRoutineEx(
+                PsCreateThreadNotifyNonSystem,
+                on_thread_notify as *const core::ffi::c_void,
+            )
+        };
+        if nt_success(status) {
+            self.thread_registered.store(true, Ordering::Release);
+        } else {
+            maple::error!("Failed to register the thread notify routine: {:x}", status);
+        }
+
+        let status = unsafe { PsSetLoadImageNotifyRoutine(Some(on_image_load)) };
+        if nt_success(status) {
+            self.image_registered.store(true, Ordering::Release);
+        } else {
+            maple::error!("Failed to register the image notify routine: {:x}", status);
+        }
+    }
+
+    pub fn stop(&self) {
+        if self.thread_registered.swap(false, Ordering::AcqRel) {
+            unsafe { PsRemoveCreateThreadNotifyRoutine(Some(on_thread_notify)) };
+        }
+
+        if self.image_registered.swap(false, Ordering::AcqRel) {
+            unsafe { PsRemoveLoadImageNotifyRoutine(Some(on_image_load)) };
+        }
+    }
+}
+
+fn send_process_event(
+    pid: u64,
+    thread: u64,
+    op: EventProcessOperation,
+    path: SerializableNtString,
+) {
+    let Some(uid) = DRIVER_CONTEXT.get().process_cache.pid_to_unique_id(pid) else {
+        return;
+    };
+
+    let event = KmMessage {
+        event: EventCompoent {
+            date: SystemTime::new().raw_time(),
+            thread,
+            operation: EventClass::Process(op),
+            result: STATUS_SUCCESS,
+            path,
+            duration: 0,
+        },
+        process: SimpleProcessDetails {
+            pid,
+            unique_id: uid,
+        },
+        stack: capture_current_stack(),
+    };
+
+    let _ = DRIVER_CONTEXT.get().communication.try_send_event(event);
+}
+
+unsafe extern "system" fn on_thread_notify(process_id: HANDLE, thread_id: HANDLE, create: BOOLEAN) {
+    let tid = thread_id as u64;
+
+    let op = if create != 0 {
+        let mut start_address = 0usize;
+        let status = DYN_IMPORTS.get().zw_query_information_thread(
+            NT_CURRENT_THREAD,
+            THREAD_QUERY_SET_WIN32_START_ADDRESS,
+            &mut start_address as *mut usize as _,
+            core::mem::size_of::<usize>() as _,
+            core::ptr::null_mut(),
+        );
+        if !nt_success(status) {
+            start_address = 0;
+        }
+
+        EventProcessOperation::ThreadCreate {
+            tid,
+            start_address: start_address as _,
+        }
+    } else {
+        EventProcessOperation::ThreadExit { tid }
+    };
+
+    send_process_event(process_id as _, tid, op, SerializableNtString::empty());
+}
+
+unsafe extern "system" fn on_image_load(
+    full_image_name: *const UNICODE_STRING,
+    process_id: HANDLE,
+    image_info: *const IMAGE_INFO,
+) {
+    let Some(image_info) = image_info.as_ref() else {
+        return;
+    };
+
+    let pid = match process_id as u64 {
+        0 => SYSTEM_PROCESS_ID,
+        pid => pid,
+    };
+
+    let path = full_image_name
+        .as_ref()
+        .filter(|name| !name.Buffer.is_null())
+        .and_then(|name| {
+            let name = core::slice::from_raw_parts(name.Buffer, name.Length as usize / 2);
+            NtUnicodeString::try_from_u16(name).ok()
+        })
+        .map_or_else(SerializableNtString::empty, SerializableNtString::new);
+
+    let op = EventProcessOperation::ImageLoad {
+        base: image_info.ImageBase as _,
+        size: image_info.ImageSize as _,
+    };
+
+    send_process_event(pid, PsGetCurrentThreadId() as _, op, path);
+}
@@ -0,0 +1,55 @@
+use core::ffi::c_void;
+
+use kmum_common::event::{EventStack, FrameMode, StackFrame, MAX_STACK_FRAMES};
+use windows_sys::Wdk::System::SystemServices::KeGetCurrentIrql;
+
+use crate::imports::{DYN_IMPORTS, RTL_WALK_USER_MODE_STACK};
+
+const PASSIVE_LEVEL: u8 = 0;
+
+//Frames to skip are stored starting from the second byte of the flags
+const FRAMES_TO_SKIP_SHIFT: u32 = 8;
+
+///
+/// Captures the stack of the current thread, kernel frames first.
+///
+/// The user part is only walked at PASSIVE_LEVEL as it may page fault,
+/// frames of this module are skipped
+///
+pub fn capture_current_stack() -> EventStack {
+    let mut stack = EventStack::new();
+
+    walk_frames(&mut stack, FrameMode::Kernel, 1 << FRAMES_TO_SKIP_SHIFT);
+
+    if unsafe { KeGetCurrentIrql() } == PASSIVE_LEVEL {
+        walk_frames(&mut stack, FrameMode::User, RTL_WALK_USER_MODE_STACK);
+    }
+
+    stack
+}
+
+#[inline(never)]
+fn walk_frames(stack: &mut EventStack, mode: FrameMode, flags: u32) {
+    let remaining = MAX_STACK_FRAMES - stack.len();
+    if remaining == 0 {
+        return;
+    }
+
+    let mut callers = [core::ptr::null_mut::<c_void>(); MAX_STACK_FRAMES];
+    let captured = unsafe {
+        DYN_IMPORTS
+            .get()
+            .rtl_walk_frame_chain(callers.as_mut_ptr(), remaining as _, flags)
+    } as usize;
+
+    for caller in &callers[..captured.min(remaining)] {
+        let frame = StackFrame {
+            address: *caller as u64,
+            mode,
+        };
+
+        if !stack.try_push(frame) {
+            break;
+        }
+    }
+}